@@ -1,19 +1,19 @@
 use crossterm::{
     cursor,
-    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    event::{self, DisableFocusChange, EnableFocusChange, Event, KeyCode, KeyEvent, KeyModifiers},
     execute, queue,
     style::{Color, Print, ResetColor, SetForegroundColor},
     terminal::{self, ClearType},
 };
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     env,
     fs,
     io::{self, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     thread,
-    time::{Duration, SystemTime, UNIX_EPOCH},
-    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex},
 };
 use syntect::{
     easy::HighlightLines,
@@ -22,13 +22,13 @@ use syntect::{
     util::as_24_bit_terminal_escaped,
 };
 use viuer::{Config as ViuerConfig, print_from_file};
-use pdf_extract::extract_text;
+use base64::Engine;
 use serde::{Deserialize, Serialize};
 
 fn install() -> io::Result<()> {
     println!("Installing ils...\n");
 
-    let home = env::var("HOME").map_err(|_| io::Error::new(io::ErrorKind::Other, "HOME not set"))?;
+    let home = env::var("HOME").map_err(|_| io::Error::other("HOME not set"))?;
     let config_dir = PathBuf::from(&home).join(".config/ils");
 
     // Create config directory
@@ -76,8 +76,6 @@ fn install() -> io::Result<()> {
         writeln!(file, "        rm /tmp/ils_cd")?;
         writeln!(file, "        if [ -d \"$target\" ]; then")?;
         writeln!(file, "            cd \"$target\"")?;
-        writeln!(file, "        else")?;
-        writeln!(file, "            echo \"$target\"")?;
         writeln!(file, "        fi")?;
         writeln!(file, "    fi")?;
         writeln!(file, "}}")?;
@@ -99,2881 +97,12922 @@ fn print_shell_function() {
         rm /tmp/ils_cd
         if [ -d "$target" ]; then
             cd "$target"
-        else
-            echo "$target"
         fi
     fi
 }}
 "#);
 }
 
-fn check_wrapper_installed() -> bool {
-    // Check if running from shell wrapper by checking if we're called as 'ils' or 'ils-bin'
-    if let Ok(exe_path) = env::current_exe() {
-        if let Some(exe_name) = exe_path.file_name() {
-            // If called as 'ils-bin', we might be running from shell wrapper or directly
-            // Check for ILS_WRAPPER_ACTIVE env var that the wrapper should set
-            if exe_name == "ils-bin" && env::var("ILS_WRAPPER_ACTIVE").is_ok() {
-                return true;
+/// Expands a leading `~` and `$VAR` / `${VAR}` references in path input.
+/// Unknown variables are left untouched so the typed text is never silently lost.
+fn expand_path_vars(input: &str) -> String {
+    let mut result = String::new();
+    let mut rest = input;
+
+    if rest == "~" || rest.starts_with("~/") {
+        if let Ok(home) = env::var("HOME") {
+            result.push_str(&home);
+            rest = &rest[1..];
+        }
+    }
+
+    while let Some(pos) = rest.find('$') {
+        result.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+
+        let (name, consumed) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
             }
+        } else {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            (&after[..end], end)
+        };
+
+        match env::var(name) {
+            Ok(value) if !name.is_empty() => result.push_str(&value),
+            _ => result.push_str(&rest[pos..pos + 1 + consumed]),
         }
+        rest = &after[consumed..];
     }
+    result.push_str(rest);
+    result
+}
 
-    // Alternative check: Look for the wrapper function in common shell configs
-    let home = match env::var("HOME") {
-        Ok(h) => h,
-        Err(_) => return false,
-    };
+// A sed-style `s/pattern/replacement/flags` rename, parsed once and applied to each name
+struct Substitution {
+    regex: regex::Regex,
+    replacement: String,
+    global: bool, // The g flag: replace every match, not only the first
+}
 
-    let rc_files = vec![
-        PathBuf::from(&home).join(".zshrc"),
-        PathBuf::from(&home).join(".bashrc"),
-        PathBuf::from(&home).join(".config/fish/config.fish"),
-    ];
+impl Substitution {
+    /// Parse `s/pattern/replacement/` with any punctuation as the delimiter and
+    /// the flags g (every match) and i (ignore case). `\1` and `&` in the
+    /// replacement stand for a group and the whole match, as in sed.
+    fn parse(expr: &str) -> Result<Self, String> {
+        let mut chars = expr.chars();
+        if chars.next() != Some('s') {
+            return Err("Start with s, as in s/old/new/".to_string());
+        }
+        let delimiter = match chars.next() {
+            Some(c) if !c.is_alphanumeric() && !c.is_whitespace() && c != '\\' => c,
+            _ => return Err("Follow s with a delimiter, as in s/old/new/".to_string()),
+        };
 
-    for rc_file in rc_files {
-        if rc_file.exists() {
-            if let Ok(content) = fs::read_to_string(&rc_file) {
-                // Check for the ils wrapper function (must be on non-commented line)
-                for line in content.lines() {
-                    let trimmed = line.trim();
-                    // Skip comment lines
-                    if trimmed.starts_with('#') {
-                        continue;
-                    }
-                    // Check for ils() function definition
-                    if trimmed.contains("ils()") || trimmed.contains("function ils") {
-                        // Also verify the function contains /tmp/ils_cd logic
-                        if content.contains("/tmp/ils_cd") {
-                            return true;
-                        }
-                    }
+        // Split on unescaped delimiters; an escaped one is taken literally
+        let mut parts = vec![String::new()];
+        let mut escaped = false;
+        for c in chars {
+            let part = parts.last_mut().unwrap();
+            if escaped {
+                if c != delimiter {
+                    part.push('\\');
                 }
+                part.push(c);
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == delimiter {
+                parts.push(String::new());
+            } else {
+                part.push(c);
+            }
+        }
+        if escaped {
+            parts.last_mut().unwrap().push('\\');
+        }
+        let (pattern, replacement, flags) = match parts.as_slice() {
+            [pattern, replacement] => (pattern, replacement, ""),
+            [pattern, replacement, flags] => (pattern, replacement, flags.as_str()),
+            [_] => return Err(format!("Add the replacement: s{0}old{0}new{0}", delimiter)),
+            _ => return Err(format!("Too many {}s: escape one in a pattern as \\{}", delimiter, delimiter)),
+        };
+        if pattern.is_empty() {
+            return Err("The pattern is empty".to_string());
+        }
+        if let Some(flag) = flags.chars().find(|flag| !matches!(flag, 'g' | 'i')) {
+            return Err(format!("Unknown flag '{}' (use g or i)", flag));
+        }
+        let regex = regex::RegexBuilder::new(pattern)
+            .case_insensitive(flags.contains('i'))
+            .build()
+            .map_err(|e| match e {
+                regex::Error::Syntax(message) => message.lines().last().unwrap_or("Invalid pattern").trim().to_string(),
+                e => e.to_string(),
+            })?;
+
+        // sed's \1 and & become the regex crate's ${1} and ${0}; a literal $ is $$
+        let mut converted = String::new();
+        let mut rest = replacement.chars().peekable();
+        while let Some(c) = rest.next() {
+            match c {
+                '\\' => match rest.next() {
+                    Some(digit) if digit.is_ascii_digit() => converted.push_str(&format!("${{{}}}", digit)),
+                    Some(other) => converted.push(other),
+                    None => converted.push('\\'),
+                },
+                '&' => converted.push_str("${0}"),
+                '$' => converted.push_str("$$"),
+                c => converted.push(c),
             }
         }
+        Ok(Substitution { regex, replacement: converted, global: flags.contains('g') })
     }
 
-    false
+    fn apply(&self, name: &str) -> String {
+        let limit = if self.global { 0 } else { 1 };
+        self.regex.replacen(name, limit, self.replacement.as_str()).into_owned()
+    }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-struct Keybindings {
-    up: Vec<char>,
-    down: Vec<char>,
-    left: Vec<char>,
-    right: Vec<char>,
-    open: Vec<char>,
-    back: Vec<char>,
-    home: Vec<char>,
-    quit: Vec<char>,
-    quit_then_open_in_finder: Vec<char>,
-    help: Vec<char>,
-    preview_toggle: Vec<char>,
-    preview_up: Vec<char>,
-    preview_down: Vec<char>,
-    preview_height_decrease: Vec<char>,
-    preview_height_increase: Vec<char>,
-    toggle_hidden: Vec<char>,
-    fuzzy_find: Vec<char>,
-    fuzzy_back: Vec<char>,
-    fuzzy_home: Vec<char>,
-    history_jump: Vec<char>,
-    toggle_mode: Vec<char>,
-    rename: Vec<char>,
-    next_sibling: Vec<char>,
-    prev_sibling: Vec<char>,
-    copy: Vec<char>,
-    paste: Vec<char>,
-    trash: Vec<char>,
-    delete: Vec<char>,
-    undo: Vec<char>,
-    redo: Vec<char>,
-    create: Vec<char>,
-    jump_up: Vec<char>,
-    jump_down: Vec<char>,
-    jump_left: Vec<char>,
-    jump_right: Vec<char>,
-    quicklook: Vec<char>,
+/// Check a set of renames before touching anything. Returns the index of each
+/// offending rename with a description: invalid names, two entries renamed to
+/// the same target, or a target that already exists and isn't itself being
+/// renamed away.
+fn rename_conflicts(plan: &[(PathBuf, PathBuf)]) -> Vec<(usize, String)> {
+    let mut conflicts = Vec::new();
+    let mut targets: HashMap<&Path, usize> = HashMap::new();
+    let sources: HashSet<&Path> = plan.iter().map(|(old, _)| old.as_path()).collect();
+
+    for (i, (old, new)) in plan.iter().enumerate() {
+        let name = new.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        if name.is_empty() || name == "." || name == ".." {
+            conflicts.push((i, format!("'{}' is not a valid name", new.display())));
+            continue;
+        }
+        if name.chars().any(|c| c.is_control()) {
+            conflicts.push((i, format!("'{}' contains invalid characters", name.escape_debug())));
+        }
+        if let Some(&other) = targets.get(new.as_path()) {
+            let other_name = plan[other].0.file_name().unwrap_or_default().to_string_lossy().to_string();
+            conflicts.push((i, format!("'{}' is also the new name of '{}'", name, other_name)));
+        } else {
+            targets.insert(new.as_path(), i);
+        }
+        // On case-insensitive filesystems "a" -> "A" finds itself, which is fine
+        let same_file = fs::canonicalize(old).ok().is_some_and(|o| fs::canonicalize(new).ok() == Some(o));
+        if new.symlink_metadata().is_ok() && !sources.contains(new.as_path()) && !same_file {
+            conflicts.push((i, format!("'{}' already exists", name)));
+        }
+    }
+    conflicts
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-struct ColorConfig {
-    #[serde(default = "default_path_fg")]
-    path_fg: String,
-    #[serde(default = "default_path_bg")]
-    path_bg: String,
-    #[serde(default = "default_selected_fg")]
-    selected_fg: String,
-    #[serde(default = "default_selected_bg")]
-    selected_bg: String,
-    #[serde(default = "default_directory_fg")]
-    directory_fg: String,
-    #[serde(default = "default_directory_bg")]
-    directory_bg: String,
-    #[serde(default = "default_file_fg")]
-    file_fg: String,
-    #[serde(default = "default_file_bg")]
-    file_bg: String,
-    #[serde(default = "default_preview_border_fg")]
-    preview_border_fg: String,
-    #[serde(default = "default_cursor_fg")]
-    cursor_fg: String,
-    #[serde(default = "default_cursor_bg")]
-    cursor_bg: String,
-    #[serde(default = "default_fuzzy_highlight_fg")]
-    fuzzy_highlight_fg: String,
-    #[serde(default = "default_fuzzy_highlight_bg")]
-    fuzzy_highlight_bg: String,
-    #[serde(default = "default_line_number_fg")]
-    line_number_fg: String,
-    #[serde(default = "default_help_menu_fg")]
-    help_menu_fg: String,
-    #[serde(default = "default_help_menu_bg")]
-    help_menu_bg: String,
-    #[serde(default = "default_history_query_fg")]
-    history_query_fg: String,
-    #[serde(default = "default_history_path_fg")]
-    history_path_fg: String,
-    #[serde(default = "default_history_count_fg")]
-    history_count_fg: String,
-}
+/// Free and total bytes of the filesystem containing `path`.
+#[cfg(unix)]
+fn disk_space(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::ffi::OsStrExt;
 
-fn default_path_fg() -> String {
-    "white".to_string()
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is a valid NUL-terminated string and stat is a valid out pointer
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let block_size = stat.f_frsize as u64;
+    Some((stat.f_bavail as u64 * block_size, stat.f_blocks as u64 * block_size))
 }
 
-fn default_path_bg() -> String {
-    "#333333".to_string()
+#[cfg(not(unix))]
+fn disk_space(_path: &Path) -> Option<(u64, u64)> {
+    None
 }
 
-fn default_selected_fg() -> String {
-    "none".to_string()
+// Where the current directory is mounted, for the filesystem info panel
+struct MountInfo {
+    mount_point: String,
+    fs_type: String,
+    device: String,
+    options: String,
 }
 
-fn default_selected_bg() -> String {
-    "none".to_string()
+impl MountInfo {
+    /// Network and FUSE-over-network filesystems, where reading ahead costs more than it saves.
+    fn is_network(&self) -> bool {
+        const NETWORK: &[&str] = &["nfs", "nfs4", "cifs", "smbfs", "smb3", "afpfs", "webdav", "davfs", "9p", "fuse.sshfs", "sshfs", "fuse.rclone", "osxfuse", "macfuse"];
+        NETWORK.contains(&self.fs_type.as_str())
+    }
 }
 
-fn default_directory_fg() -> String {
-    "cyan".to_string()
-}
+/// Every mount in /proc/self/mounts, in the order they were made.
+#[cfg(target_os = "linux")]
+fn mounts() -> Vec<MountInfo> {
+    // Mount fields escape spaces, tabs, newlines and backslashes as octal
+    fn unescape(field: &str) -> String {
+        field.replace("\\040", " ").replace("\\011", "\t").replace("\\012", "\n").replace("\\134", "\\")
+    }
 
-fn default_directory_bg() -> String {
-    "none".to_string()
+    let Ok(mounts) = fs::read_to_string("/proc/self/mounts") else {
+        return Vec::new();
+    };
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [device, mount_point, fs_type, options, ..] = fields.as_slice() else {
+                return None;
+            };
+            Some(MountInfo {
+                mount_point: unescape(mount_point),
+                fs_type: fs_type.to_string(),
+                device: unescape(device),
+                options: options.to_string(),
+            })
+        })
+        .collect()
 }
 
-fn default_file_fg() -> String {
-    "none".to_string()
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn mounts() -> Vec<MountInfo> {
+    Vec::new()
 }
 
-fn default_file_bg() -> String {
-    "none".to_string()
+/// Mount that contains `path`: the longest matching mount point in /proc/self/mounts.
+#[cfg(target_os = "linux")]
+fn mount_info(path: &Path) -> Option<MountInfo> {
+    let path = fs::canonicalize(path).ok()?;
+    mounts()
+        .into_iter()
+        .filter(|mount| path.starts_with(&mount.mount_point))
+        // Later entries shadow earlier ones mounted at the same place
+        .max_by_key(|mount| mount.mount_point.len())
 }
 
-fn default_preview_border_fg() -> String {
-    "darkgrey".to_string()
-}
+#[cfg(target_os = "macos")]
+fn mount_info(path: &Path) -> Option<MountInfo> {
+    use std::ffi::CStr;
+    use std::os::unix::ffi::OsStrExt;
 
-fn default_cursor_fg() -> String {
-    "green".to_string()
-}
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is a valid NUL-terminated string and stat is a valid out pointer
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    // SAFETY: statfs fills these with NUL-terminated strings
+    let text = |chars: &[libc::c_char]| unsafe { CStr::from_ptr(chars.as_ptr()) }.to_string_lossy().to_string();
+
+    let flags = [
+        (libc::MNT_RDONLY, "read-only"),
+        (libc::MNT_LOCAL, "local"),
+        (libc::MNT_JOURNALED, "journaled"),
+        (libc::MNT_NOSUID, "nosuid"),
+        (libc::MNT_NODEV, "nodev"),
+        (libc::MNT_NOEXEC, "noexec"),
+        (libc::MNT_DONTBROWSE, "nobrowse"),
+        (libc::MNT_AUTOMOUNTED, "automounted"),
+        (libc::MNT_QUARANTINE, "quarantine"),
+    ];
+    let mut options: Vec<&str> = flags
+        .iter()
+        .filter(|(flag, _)| stat.f_flags & *flag as u32 != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    if stat.f_flags & libc::MNT_RDONLY as u32 == 0 {
+        options.insert(0, "read-write");
+    }
 
-fn default_cursor_bg() -> String {
-    "none".to_string()
+    Some(MountInfo {
+        mount_point: text(&stat.f_mntonname),
+        fs_type: text(&stat.f_fstypename),
+        device: text(&stat.f_mntfromname),
+        options: options.join(", "),
+    })
 }
 
-fn default_fuzzy_highlight_fg() -> String {
-    "#88cc88".to_string()  // Soft green
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn mount_info(_path: &Path) -> Option<MountInfo> {
+    None
 }
 
-fn default_fuzzy_highlight_bg() -> String {
-    "#323232".to_string()
-}
+/// Why `name` would be invalid on FAT/exFAT/NTFS, e.g. once copied to a USB stick.
+fn portability_problem(name: &str) -> Option<String> {
+    const RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '\\', '|', '?', '*'];
+    const RESERVED_NAMES: &[&str] = &[
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+        "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
 
-fn default_line_number_fg() -> String {
-    "darkgrey".to_string()
+    if let Some(c) = name.chars().find(|c| RESERVED_CHARS.contains(c) || c.is_control()) {
+        return Some(format!("'{}' contains '{}', which Windows filesystems don't allow", name, c.escape_debug()));
+    }
+    if name.ends_with(' ') || name.ends_with('.') {
+        return Some(format!("'{}' ends with a space or dot, which Windows filesystems strip", name));
+    }
+    // "nul.txt" is just as reserved as "NUL"
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    if RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(stem)) {
+        return Some(format!("'{}' is a reserved device name on Windows", name));
+    }
+    if name.encode_utf16().count() > 255 {
+        return Some(format!("'{}…' is longer than the 255 characters FAT/exFAT/NTFS allow", name.chars().take(20).collect::<String>()));
+    }
+    None
 }
 
-fn default_help_menu_fg() -> String {
-    "white".to_string()
+/// The user's trash on the home filesystem: the folder Finder keeps, or the
+/// freedesktop.org home trash with its `files` and `info` folders.
+fn home_trash() -> Option<PathBuf> {
+    if cfg!(target_os = "macos") {
+        return env::var_os("HOME").map(|home| PathBuf::from(home).join(".Trash"));
+    }
+    env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .map(|data_home| data_home.join("Trash"))
 }
 
-fn default_help_menu_bg() -> String {
-    "#1e1e1e".to_string()
+/// The folder holding the trashed files themselves, as the trash browser lists it.
+fn trashed_files_dir() -> Option<PathBuf> {
+    let trash = home_trash()?;
+    Some(if cfg!(target_os = "macos") { trash } else { trash.join("files") })
 }
 
-fn default_history_query_fg() -> String {
-    "cyan".to_string()
+/// Permanently delete everything in the trash, through Finder so every volume's
+/// trash is emptied.
+#[cfg(target_os = "macos")]
+fn empty_system_trash() -> io::Result<()> {
+    let output = std::process::Command::new("osascript")
+        .arg("-e")
+        .arg("tell application \"Finder\" to empty trash")
+        .stderr(std::process::Stdio::null())
+        .output()
+        .map_err(|e| io::Error::new(io::ErrorKind::Unsupported, format!("needs osascript: {}", e)))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other("Finder refused"))
+    }
 }
 
-fn default_history_path_fg() -> String {
-    "cyan".to_string()
+/// Permanently delete the files in the home trash and every volume's trash
+/// along with their .trashinfo records. Everything is attempted; the first
+/// failure is returned.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn empty_system_trash() -> io::Result<()> {
+    let home = home_trash().ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "no home directory"))?;
+    let mut first_error = None;
+    for trash in std::iter::once(home).chain(volume_trashes()) {
+        for dir in ["files", "info"] {
+            let Ok(entries) = fs::read_dir(trash.join(dir)) else { continue };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let result = if path.is_dir() && !path.is_symlink() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+                if let Err(e) = result {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        // Sizes file managers cached for the trashed directories
+        let _ = fs::remove_file(trash.join("directorysizes"));
+    }
+    first_error.map_or(Ok(()), Err)
 }
 
-fn default_history_count_fg() -> String {
-    "darkgrey".to_string()
+#[cfg(not(unix))]
+fn empty_system_trash() -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "no trash on this system"))
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-struct Settings {
-    #[serde(default = "default_exit_after_edit")]
-    exit_after_edit: bool,
-    #[serde(default = "default_preview_scroll_amount")]
-    preview_scroll_amount: usize,
-    #[serde(default = "default_show_hidden")]
-    show_hidden: bool,
-    #[serde(default = "default_preview_on_start")]
-    preview_on_start: bool,
-    #[serde(default = "default_preview_split_ratio")]
-    preview_split_ratio: f32,
-    #[serde(default = "default_case_sensitive_search")]
-    case_sensitive_search: bool,
-    #[serde(default = "default_show_dir_slash")]
-    show_dir_slash: bool,
-    #[serde(default = "default_jump_amount")]
-    jump_amount: usize,
-    #[serde(default = "default_show_tilde_for_home")]
-    show_tilde_for_home: bool,
-    #[serde(default = "default_verbose_dates")]
-    verbose_dates: bool,
-    #[serde(default = "default_debug_show_welcome")]
-    debug_show_welcome: bool,
-    #[serde(default = "default_wrapper_validation_cache_valid")]
-    wrapper_validation_cache_valid: bool,
-    #[serde(default = "default_show_help_on_start")]
-    show_help_on_start: bool,
-    #[serde(default = "default_show_history_visit_count")]
-    show_history_visit_count: bool,
-    #[serde(default = "default_history_max_results")]
-    history_max_results: usize,
-    #[serde(default = "default_history_max_storage")]
-    history_max_storage: usize,
-    #[serde(default = "default_history_prioritize_basename")]
-    history_prioritize_basename: bool,
+/// The trashes at the top of other filesystems, `.Trash-<uid>`, that hold what
+/// was trashed from outside the home filesystem.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn volume_trashes() -> Vec<PathBuf> {
+    // SAFETY: getuid has no preconditions and can't fail
+    let uid = unsafe { libc::getuid() };
+    let mut trashes: Vec<PathBuf> = mounts().into_iter().map(|mount| Path::new(&mount.mount_point).join(format!(".Trash-{}", uid))).collect();
+    trashes.sort();
+    trashes.dedup();
+    trashes.retain(|trash| trash.is_dir());
+    trashes
 }
 
-fn default_exit_after_edit() -> bool {
-    false
+/// Hand the terminal over to `f`, for programs that run in the foreground:
+/// raw mode off and the cursor shown, then both put back afterwards, also when
+/// `f` couldn't be started.
+fn suspended<T>(f: impl FnOnce() -> T) -> io::Result<T> {
+    terminal::disable_raw_mode()?;
+    let result = execute!(io::stdout(), cursor::Show, DisableFocusChange).map(|_| f());
+    let restored = execute!(io::stdout(), cursor::Hide, EnableFocusChange).and_then(|_| terminal::enable_raw_mode());
+    let result = result?;
+    restored.map(|_| result)
 }
 
-fn default_jump_amount() -> usize {
-    5
+/// Run `command` in the foreground with the terminal suspended around it.
+fn run_suspended(command: &mut std::process::Command) -> io::Result<std::process::ExitStatus> {
+    suspended(|| command.status())?
 }
 
-fn default_preview_scroll_amount() -> usize {
-    10
+/// The words of `command` quoted as a shell line, for showing what runs.
+fn shell_line(command: &[&std::ffi::OsStr]) -> String {
+    command.iter().map(|word| shell_quote(Path::new(word))).collect::<Vec<_>>().join(" ")
 }
 
-fn default_show_hidden() -> bool {
-    false
+/// `time` as an ISO 8601 UTC timestamp, like 2024-05-01T12:30:00Z.
+fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (mut days, seconds) = (secs / 86400, secs % 86400);
+    let is_leap = |y: u64| (y.is_multiple_of(4) && !y.is_multiple_of(100)) || y.is_multiple_of(400);
+    let mut year = 1970;
+    while days >= if is_leap(year) { 366 } else { 365 } {
+        days -= if is_leap(year) { 366 } else { 365 };
+        year += 1;
+    }
+    let days_in_months = [31, if is_leap(year) { 29 } else { 28 }, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    let mut month = 0;
+    while days >= days_in_months[month] {
+        days -= days_in_months[month];
+        month += 1;
+    }
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month + 1, days + 1, seconds / 3600, seconds % 3600 / 60, seconds % 60)
 }
 
-fn default_preview_on_start() -> bool {
-    false
+/// Append a line for a command run as administrator to ~/.config/ils/elevated.log,
+/// so what was done with raised privileges can be looked back on.
+fn log_elevated(line: &str, outcome: &str) -> io::Result<()> {
+    let home = env::var("HOME").map_err(|_| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+    let dir = PathBuf::from(home).join(".config/ils");
+    fs::create_dir_all(&dir)?;
+    let mut log = fs::OpenOptions::new().create(true).append(true).open(dir.join("elevated.log"))?;
+    writeln!(log, "{}\t{}\t{}", utc_timestamp(SystemTime::now()), outcome, line)
 }
 
-fn default_show_tilde_for_home() -> bool {
-    true
+/// Run `command` as administrator through the system's password dialog.
+#[cfg(target_os = "macos")]
+fn run_elevated(command: &[&std::ffi::OsStr]) -> io::Result<()> {
+    let script = shell_line(command).replace('\\', "\\\\").replace('"', "\\\"");
+    let output = std::process::Command::new("osascript")
+        .arg("-e")
+        .arg(format!("do shell script \"{}\" with administrator privileges", script))
+        .output()
+        .map_err(|e| io::Error::new(io::ErrorKind::Unsupported, format!("needs osascript: {}", e)))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let error = String::from_utf8_lossy(&output.stderr);
+        Err(io::Error::other(error.trim().strip_prefix("execution error: ").unwrap_or(error.trim()).to_string()))
+    }
 }
 
-fn default_verbose_dates() -> bool {
-    false
+/// Run `command` as root through sudo, which asks for a password on the
+/// terminal, so raw mode has to be off around it.
+#[cfg(not(target_os = "macos"))]
+fn run_elevated(command: &[&std::ffi::OsStr]) -> io::Result<()> {
+    let status = std::process::Command::new("sudo")
+        .arg("--")
+        .args(command)
+        .status()
+        .map_err(|e| io::Error::new(io::ErrorKind::Unsupported, format!("needs sudo: {}", e)))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("sudo {}", status)))
+    }
 }
 
-fn default_debug_show_welcome() -> bool {
-    false
+/// Move `path` to the Finder trash, through osascript.
+#[cfg(target_os = "macos")]
+fn trash_path(path: &Path) -> io::Result<()> {
+    // Quoted as an AppleScript string, so no name can end it early
+    let file = path.display().to_string().replace('\\', "\\\\").replace('"', "\\\"");
+    // stderr is dropped to keep the trash sound's noise out of the terminal
+    let output = std::process::Command::new("osascript")
+        .arg("-e")
+        .arg(format!("tell application \"Finder\" to delete POSIX file \"{}\"", file))
+        .stderr(std::process::Stdio::null())
+        .output()
+        .map_err(|e| io::Error::new(io::ErrorKind::Unsupported, format!("needs osascript: {}", e)))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other("Finder refused"))
+    }
 }
 
-fn default_wrapper_validation_cache_valid() -> bool {
-    false
+/// Move `path` to the freedesktop.org trash, so file managers can list and
+/// restore it: the home trash for files on the home filesystem, otherwise
+/// `.Trash-<uid>` at the top of the file's own filesystem.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn trash_path(path: &Path) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    let path = std::path::absolute(path)?;
+    let device = path.symlink_metadata()?.dev();
+    let home_trash = home_trash().ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "no home directory to keep it in"))?;
+    let home_device = home_trash.ancestors().find_map(|dir| fs::metadata(dir).ok()).map(|m| m.dev());
+    let trash = if home_device == Some(device) {
+        home_trash
+    } else {
+        // SAFETY: getuid has no preconditions and can't fail
+        let uid = unsafe { libc::getuid() };
+        let top = mount_info(path.parent().unwrap_or(&path)).map(|mount| PathBuf::from(mount.mount_point));
+        top.ok_or_else(|| io::Error::other("can't find the top of its filesystem"))?.join(format!(".Trash-{}", uid))
+    };
+    let (files, info) = (trash.join("files"), trash.join("info"));
+    // Only its owner may look into a trash
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true).mode(0o700);
+    builder.create(&files)?;
+    builder.create(&info)?;
+
+    // Path is percent-encoded like a URL; DeletionDate is local time
+    let mut encoded = String::new();
+    for &byte in path.as_os_str().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) as libc::time_t;
+    // SAFETY: an all-zero tm is valid, and localtime_r only writes into it
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&now, &mut tm) };
+    let info_text = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={:04}-{:02}-{:02}T{:02}:{:02}:{:02}\n",
+        encoded,
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    );
+
+    // The .trashinfo file is created first, exclusively, to claim a name in the trash
+    let name = path.file_name().ok_or_else(|| io::Error::other("nothing to trash"))?.to_string_lossy().into_owned();
+    for n in 1.. {
+        let trashed_name = if n == 1 { name.clone() } else { format!("{}.{}", name, n) };
+        let info_path = info.join(format!("{}.trashinfo", trashed_name));
+        let mut info_file = match fs::OpenOptions::new().write(true).create_new(true).open(&info_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        };
+        let moved = info_file.write_all(info_text.as_bytes()).and_then(|_| fs::rename(&path, files.join(&trashed_name)));
+        if moved.is_err() {
+            let _ = fs::remove_file(&info_path);
+        }
+        return moved;
+    }
+    unreachable!("the trash always has a free name")
 }
 
-fn default_show_help_on_start() -> bool {
-    true
+#[cfg(not(unix))]
+fn trash_path(_path: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "no trash on this system"))
 }
 
-fn default_show_history_visit_count() -> bool {
-    false
+/// Show `dir` in the system file manager: Finder, or whatever xdg-open picks.
+fn open_in_file_manager(dir: &Path) {
+    let opener = if cfg!(target_os = "macos") { "open" } else { "xdg-open" };
+    let _ = std::process::Command::new(opener)
+        .arg(dir)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
 }
 
-fn default_history_max_results() -> usize {
-    12
+/// Pop up a system notification, without waiting for the notifier to exit.
+fn send_desktop_notification(title: &str, body: &str) {
+    let mut command = if cfg!(target_os = "macos") {
+        let quote = |text: &str| text.replace('\\', "\\\\").replace('"', "\\\"");
+        let mut command = std::process::Command::new("osascript");
+        command.arg("-e").arg(format!("display notification \"{}\" with title \"{}\"", quote(body), quote(title)));
+        command
+    } else {
+        let mut command = std::process::Command::new("notify-send");
+        command.arg(title).arg(body);
+        command
+    };
+    command
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    // Reap the child in the background so it doesn't linger as a zombie
+    if let Ok(mut child) = command.spawn() {
+        thread::spawn(move || child.wait());
+    }
 }
 
-fn default_history_max_storage() -> usize {
-    1000
-}
+/// How much of a binary file the strings preview scans.
+const STRINGS_MAX_BYTES: u64 = 32 * 1024 * 1024;
 
-fn default_history_prioritize_basename() -> bool {
-    true
-}
+/// Files larger than this get no line count in the preview header.
+const PREVIEW_LINE_COUNT_MAX_BYTES: u64 = 64 * 1024 * 1024;
 
-fn default_preview_split_ratio() -> f32 {
-    0.5
-}
+type LineCounts = Arc<Mutex<HashMap<PathBuf, (Option<SystemTime>, Option<usize>)>>>;
 
-fn default_case_sensitive_search() -> bool {
-    false
-}
+type PreviewDiff = Arc<Mutex<Option<(PathBuf, Option<SystemTime>, Option<Vec<String>>)>>>;
 
-fn default_show_dir_slash() -> bool {
-    true
+/// Unstaged `git diff` of `path` from its first hunk on, tabs expanded; empty if
+/// there's none or git fails.
+fn git_diff_lines(path: &Path) -> Vec<String> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Vec::new();
+    };
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["diff", "--no-color", "--no-ext-diff", "--"])
+        .arg(name)
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success());
+    let text = output.map(|output| String::from_utf8_lossy(&output.stdout).into_owned()).unwrap_or_default();
+    let mut lines: Vec<String> = text.lines().map(|line| line.replace('\t', "    ")).collect();
+    // The diff --git, index and ---/+++ lines repeat what the separator says
+    if let Some(first_hunk) = lines.iter().position(|line| line.starts_with("@@")) {
+        lines.drain(..first_hunk);
+    }
+    lines
 }
 
-// Directory history for frecency-based navigation
-#[derive(Serialize, Deserialize, Clone, Debug)]
-struct DirectoryEntry {
-    path: String,
-    visit_count: usize,
-    last_accessed: u64,  // Unix timestamp
-    first_accessed: u64, // Unix timestamp
+/// Number of lines in a file, counting a last line without a newline.
+fn count_lines(path: &Path) -> io::Result<usize> {
+    use io::BufRead;
+    let mut reader = io::BufReader::new(fs::File::open(path)?);
+    let mut lines = 0;
+    let mut last = b'\n';
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            break;
+        }
+        lines += buffer.iter().filter(|&&b| b == b'\n').count();
+        last = buffer[buffer.len() - 1];
+        let len = buffer.len();
+        reader.consume(len);
+    }
+    if last != b'\n' {
+        lines += 1;
+    }
+    Ok(lines)
 }
 
-impl DirectoryEntry {
-    fn new(path: String) -> Self {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+/// The sort modes cycled by the sort key, in order.
+const SORT_MODES: &[&str] = &["name", "modified", "size", "allocated", "extension", "natural"];
 
-        DirectoryEntry {
-            path,
-            visit_count: 1,
-            last_accessed: now,
-            first_accessed: now,
-        }
-    }
+/// Groups of a listing sorted by extension, in the order they're shown.
+const FILE_GROUPS: [&str; 8] = ["Folders", "Images", "Video", "Audio", "Documents", "Code", "Archives", "Other"];
 
-    fn update_visit(&mut self) {
-        self.visit_count += 1;
-        self.last_accessed = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+/// Which of `FILE_GROUPS` an entry falls in, by its extension.
+fn file_group(path: &Path, is_dir: bool) -> usize {
+    if is_dir {
+        return 0;
     }
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    match extension.as_str() {
+        "png" | "jpg" | "jpeg" | "gif" | "bmp" | "webp" | "svg" | "ico" | "tif" | "tiff" | "heic" | "avif" | "psd" => 1,
+        "mp4" | "m4v" | "mkv" | "mov" | "avi" | "webm" | "wmv" | "flv" => 2,
+        "mp3" | "wav" | "flac" | "aac" | "ogg" | "oga" | "opus" | "m4a" | "aiff" => 3,
+        "pdf" | "doc" | "docx" | "odt" | "rtf" | "txt" | "md" | "markdown" | "rst" | "tex" | "epub" | "xls" | "xlsx"
+        | "ods" | "csv" | "tsv" | "ppt" | "pptx" | "odp" | "pages" | "numbers" | "key" => 4,
+        "rs" | "py" | "js" | "mjs" | "ts" | "jsx" | "tsx" | "c" | "h" | "cpp" | "hpp" | "cc" | "go" | "java" | "kt"
+        | "swift" | "rb" | "php" | "cs" | "sh" | "bash" | "zsh" | "fish" | "lua" | "pl" | "scala" | "hs" | "ml" | "ex"
+        | "exs" | "clj" | "dart" | "zig" | "vue" | "svelte" | "html" | "css" | "scss" | "sql" | "json" | "yaml" | "yml"
+        | "toml" | "xml" | "ini" | "ipynb" => 5,
+        "zip" | "tar" | "gz" | "tgz" | "bz2" | "xz" | "zst" | "7z" | "rar" | "dmg" | "iso" | "deb" | "rpm" | "jar" => 6,
+        _ => 7,
+    }
+}
 
-    fn frecency_score(&self) -> f64 {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+// A row of the entry listing: a run of `entries` from the first index to the
+// last (exclusive), or the header of a group with how many entries it holds
+#[derive(Clone, Copy)]
+enum ListingRow {
+    Entries(usize, usize),
+    Group(usize, usize),
+}
 
-        let age_seconds = now.saturating_sub(self.last_accessed) as f64;
-        let age_hours = age_seconds / 3600.0;
+/// A size both as the bytes of content and as the blocks allocated on disk,
+/// which differ a lot for sparse files, clones and many small files.
+#[derive(Clone, Copy, Default)]
+struct DiskUsage {
+    apparent: u64,
+    allocated: u64,
+}
 
-        // Decay function: recent visits are weighted more heavily
-        let recency_weight = if age_hours < 1.0 {
-            4.0
-        } else if age_hours < 24.0 {
-            2.0
-        } else if age_hours < 168.0 { // 1 week
-            1.0
-        } else {
-            0.5
+impl DiskUsage {
+    fn of(metadata: &fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let allocated = {
+            use std::os::unix::fs::MetadataExt;
+            // st_blocks is always in 512-byte units
+            metadata.blocks() * 512
         };
-
-        (self.visit_count as f64) * recency_weight
+        #[cfg(not(unix))]
+        let allocated = metadata.len();
+        DiskUsage { apparent: metadata.len(), allocated }
     }
-}
 
-#[derive(Serialize, Deserialize, Default)]
-struct DirectoryHistory {
-    entries: HashMap<String, DirectoryEntry>,
+    fn add(&mut self, other: DiskUsage) {
+        self.apparent += other.apparent;
+        self.allocated += other.allocated;
+    }
 }
 
-impl DirectoryHistory {
-    fn load() -> Self {
-        if let Ok(home) = env::var("HOME") {
-            let history_path = PathBuf::from(home).join(".config/ils/history.json");
-            if let Ok(content) = fs::read_to_string(&history_path) {
-                if let Ok(history) = serde_json::from_str(&content) {
-                    return history;
+/// Compare names with runs of digits taken as numbers, so "file2" sorts before "file10".
+fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return std::cmp::Ordering::Equal,
+            (None, Some(_)) => return std::cmp::Ordering::Less,
+            (Some(_), None) => return std::cmp::Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let take_number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(c) = chars.peek().copied().filter(char::is_ascii_digit) {
+                        digits.push(c);
+                        chars.next();
+                    }
+                    digits
+                };
+                let (x, y) = (take_number(&mut a), take_number(&mut b));
+                let (x_trimmed, y_trimmed) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                let ordering = x_trimmed.len().cmp(&y_trimmed.len()).then_with(|| x_trimmed.cmp(y_trimmed));
+                if ordering != std::cmp::Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != std::cmp::Ordering::Equal {
+                    return ordering;
                 }
+                a.next();
+                b.next();
             }
         }
-        DirectoryHistory::default()
     }
+}
 
-    fn save(&self) -> io::Result<()> {
-        if let Ok(home) = env::var("HOME") {
-            let config_dir = PathBuf::from(&home).join(".config/ils");
-            fs::create_dir_all(&config_dir)?;
-            let history_path = config_dir.join("history.json");
-            let content = serde_json::to_string_pretty(self)
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-            fs::write(history_path, content)?;
+/// Score `name` as a fuzzy match for `query`, fzf-style: every query character
+/// must appear in order; matches at the start, after a separator or on a
+/// camelCase hump, and consecutive runs score higher, while gaps cost a little.
+/// Returns the score and the matched character positions.
+fn fuzzy_score(query: &str, name: &str, case_sensitive: bool) -> Option<(i64, Vec<usize>)> {
+    let same = |a: char, b: char| if case_sensitive { a == b } else { a.to_lowercase().eq(b.to_lowercase()) };
+    let query: Vec<char> = query.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let first = *query.first()?;
+    let bonus = |i: usize| match i.checked_sub(1).map(|prev| name[prev]) {
+        None => 12,
+        Some('.' | '_' | '-' | ' ') => 10,
+        Some(prev) if prev.is_lowercase() && name[i].is_uppercase() => 8,
+        Some(_) => 0,
+    };
+
+    // Try each place the first character matches and keep the best greedy run
+    let mut best: Option<(i64, Vec<usize>)> = None;
+    for start in (0..name.len()).filter(|&i| same(name[i], first)) {
+        let mut positions = vec![start];
+        for &q in &query[1..] {
+            let from = positions[positions.len() - 1] + 1;
+            match (from..name.len()).find(|&i| same(name[i], q)) {
+                Some(i) => positions.push(i),
+                None => break,
+            }
+        }
+        if positions.len() < query.len() {
+            // Starting later can only leave fewer characters to match
+            break;
+        }
+        let mut score = -(start.min(5) as i64);
+        for (n, &pos) in positions.iter().enumerate() {
+            score += 16 + bonus(pos);
+            if n > 0 {
+                let gap = pos - positions[n - 1] - 1;
+                score += if gap == 0 { 8 } else { -(gap.min(10) as i64) };
+            }
+        }
+        if best.as_ref().is_none_or(|(best_score, _)| score > *best_score) {
+            best = Some((score, positions));
         }
-        Ok(())
     }
+    best
+}
 
-    fn record_visit(&mut self, path: &PathBuf, max_storage: usize) {
-        let path_str = path.to_string_lossy().to_string();
+/// A filter term that matches on size or modification time instead of the name:
+/// `>100MB`, `<10k`, `within:7d`, `after:2024-01-31`, `before:30d`.
+#[derive(Clone, Copy)]
+enum FilterPredicate {
+    LargerThan(u64),
+    SmallerThan(u64),
+    ModifiedAfter(SystemTime),
+    ModifiedBefore(SystemTime),
+}
 
-        if let Some(entry) = self.entries.get_mut(&path_str) {
-            entry.update_visit();
-        } else {
-            self.entries.insert(path_str.clone(), DirectoryEntry::new(path_str));
+impl FilterPredicate {
+    fn parse(term: &str) -> Option<Self> {
+        if let Some(size) = term.strip_prefix('>') {
+            return parse_size(size).map(Self::LargerThan);
         }
-
-        // Cleanup old entries every 100 visits (probabilistic)
-        if self.entries.len() % 100 == 0 {
-            self.cleanup_old_entries(max_storage);
+        if let Some(size) = term.strip_prefix('<') {
+            return parse_size(size).map(Self::SmallerThan);
+        }
+        let (key, value) = term.split_once(':')?;
+        // A date, or an age counted back from now
+        let time = || {
+            parse_date(value)
+                .or_else(|| parse_age(value).and_then(|age| SystemTime::now().checked_sub(age)))
+        };
+        match key.to_lowercase().as_str() {
+            "within" | "last" => parse_age(value)
+                .and_then(|age| SystemTime::now().checked_sub(age))
+                .map(Self::ModifiedAfter),
+            "after" | "since" => time().map(Self::ModifiedAfter),
+            "before" => time().map(Self::ModifiedBefore),
+            _ => None,
         }
     }
 
-    fn get_sorted_by_frecency(&self) -> Vec<DirectoryEntry> {
-        let mut entries: Vec<DirectoryEntry> = self.entries.values().cloned().collect();
-        entries.sort_by(|a, b| {
-            b.frecency_score().partial_cmp(&a.frecency_score()).unwrap()
-        });
-        // Limit to top 1000 entries for performance
-        entries.truncate(1000);
-        entries
+    /// Sizes only match files, as a directory's size isn't known up front.
+    fn matches(&self, metadata: &fs::Metadata) -> bool {
+        let modified = || metadata.modified().ok();
+        match *self {
+            Self::LargerThan(size) => metadata.is_file() && metadata.len() > size,
+            Self::SmallerThan(size) => metadata.is_file() && metadata.len() < size,
+            Self::ModifiedAfter(time) => modified().is_some_and(|m| m >= time),
+            Self::ModifiedBefore(time) => modified().is_some_and(|m| m < time),
+        }
     }
+}
 
-    fn cleanup_old_entries(&mut self, max_storage: usize) {
-        // Remove entries older than 90 days with no recent visits
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        let ninety_days = 90 * 24 * 60 * 60;
+/// Parse a size like "512", "10k", "1.5MB" or "2G" (binary units) into bytes.
+fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim().to_lowercase();
+    let split = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let scale: u64 = match unit.trim_end_matches("ib").trim_end_matches('b') {
+        "" => 1,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        "t" => 1 << 40,
+        _ => return None,
+    };
+    Some((number * scale as f64) as u64)
+}
 
-        self.entries.retain(|_, entry| {
-            let age = now.saturating_sub(entry.last_accessed);
-            age < ninety_days || entry.visit_count > 5
-        });
+/// Parse an age like "3h", "7d", "2w", "6m" (months of 30 days) or "1y".
+fn parse_age(text: &str) -> Option<Duration> {
+    let unit = text.chars().last()?;
+    let count: u64 = text[..text.len() - unit.len_utf8()].parse().ok()?;
+    let hours = match unit.to_ascii_lowercase() {
+        'h' => 1,
+        'd' => 24,
+        'w' => 24 * 7,
+        'm' => 24 * 30,
+        'y' => 24 * 365,
+        _ => return None,
+    };
+    Some(Duration::from_secs(count.checked_mul(hours * 3600)?))
+}
 
-        // If still too many entries, keep only top N by frecency
-        if self.entries.len() > max_storage {
-            let mut sorted: Vec<_> = self.entries.iter().collect();
-            sorted.sort_by(|a, b| {
-                b.1.frecency_score().partial_cmp(&a.1.frecency_score()).unwrap()
-            });
+/// Parse a YYYY-MM-DD date as midnight UTC.
+fn parse_date(text: &str) -> Option<SystemTime> {
+    let mut parts = text.splitn(3, '-').map(|part| part.parse::<u64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let is_leap = |y: u64| (y.is_multiple_of(4) && !y.is_multiple_of(100)) || y.is_multiple_of(400);
+    let days_in_months = [31, if is_leap(year) { 29 } else { 28 }, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    let days = (1970..year).map(|y| if is_leap(y) { 366 } else { 365 }).sum::<u64>()
+        + days_in_months[..month as usize - 1].iter().sum::<u64>()
+        + day
+        - 1;
+    UNIX_EPOCH.checked_add(Duration::from_secs(days * 86400))
+}
 
-            let to_keep: std::collections::HashSet<String> = sorted
-                .iter()
-                .take(max_storage)
-                .map(|(k, _)| k.to_string())
-                .collect();
+/// Files that mark a project directory, with the badge shown for it. The first
+/// match wins, so a Rust project with a package.json for its web UI shows "rs".
+const PROJECT_MARKERS: &[(&str, &str)] = &[
+    ("Cargo.toml", "rs"),
+    ("go.mod", "go"),
+    ("pyproject.toml", "py"),
+    ("setup.py", "py"),
+    ("Package.swift", "swift"),
+    ("mix.exs", "ex"),
+    ("Gemfile", "rb"),
+    ("pom.xml", "java"),
+    ("build.gradle", "java"),
+    ("build.gradle.kts", "kt"),
+    ("composer.json", "php"),
+    ("CMakeLists.txt", "cmake"),
+    ("package.json", "js"),
+];
+
+/// Largest file the content search reads.
+const GREP_MAX_FILE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Search the text files below `root` for lines containing `query`, streaming
+/// matches into `results` until done, `max_results` is reached or `cancel` is set.
+fn grep_tree(root: &Path, query: &str, options: SearchOptions, results: &Mutex<GrepResults>, cancel: &AtomicBool) {
+    let SearchOptions { case_sensitive, show_hidden, max_results, .. } = options;
+    let query = if case_sensitive { query.to_string() } else { query.to_lowercase() };
+    let mut dirs = vec![root.to_path_buf()];
+    'walk: while let Some(dir) = dirs.pop() {
+        let Ok(read_dir) = fs::read_dir(&dir) else { continue };
+        let mut children: Vec<fs::DirEntry> = read_dir.flatten().collect();
+        // Depth first in name order, so the results read like a listing
+        children.sort_by_key(|child| std::cmp::Reverse(child.file_name()));
+        let mut files = Vec::new();
+        for child in children {
+            if !show_hidden && child.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            // Symlinks aren't followed, so loops can't trap the search
+            match child.file_type() {
+                Ok(kind) if kind.is_dir() => dirs.push(child.path()),
+                Ok(kind) if kind.is_file() => files.push(child.path()),
+                _ => {}
+            }
+        }
 
-            self.entries.retain(|k, _| to_keep.contains(k));
+        for path in files.into_iter().rev() {
+            if cancel.load(Ordering::Relaxed) {
+                return;
+            }
+            if fs::metadata(&path).map_or(true, |meta| meta.len() > GREP_MAX_FILE_BYTES) {
+                continue;
+            }
+            let Ok(bytes) = fs::read(&path) else { continue };
+            if bytes[..bytes.len().min(8000)].contains(&0) {
+                continue; // Binary
+            }
+            let text = String::from_utf8_lossy(&bytes);
+            let mut found = Vec::new();
+            for (idx, line) in text.lines().enumerate() {
+                let hit = if case_sensitive { line.contains(&query) } else { line.to_lowercase().contains(&query) };
+                if hit {
+                    found.push(GrepMatch { path: path.clone(), line: idx + 1, text: line.trim().chars().take(300).collect() });
+                }
+            }
+            let Ok(mut results) = results.lock() else { return };
+            results.files += 1;
+            for found in found {
+                if results.matches.len() >= max_results {
+                    break 'walk;
+                }
+                results.matches.push(found);
+            }
         }
     }
+    if let Ok(mut results) = results.lock() {
+        results.done = true;
+    }
 }
 
-impl Default for Settings {
-    fn default() -> Self {
-        Settings {
-            exit_after_edit: default_exit_after_edit(),
-            preview_scroll_amount: default_preview_scroll_amount(),
-            show_hidden: default_show_hidden(),
-            preview_on_start: default_preview_on_start(),
-            preview_split_ratio: default_preview_split_ratio(),
-            case_sensitive_search: default_case_sensitive_search(),
-            show_dir_slash: default_show_dir_slash(),
-            jump_amount: default_jump_amount(),
-            show_tilde_for_home: default_show_tilde_for_home(),
-            verbose_dates: default_verbose_dates(),
-            debug_show_welcome: default_debug_show_welcome(),
-            wrapper_validation_cache_valid: default_wrapper_validation_cache_valid(),
-            show_help_on_start: default_show_help_on_start(),
-            show_history_visit_count: default_show_history_visit_count(),
-            history_max_results: default_history_max_results(),
-            history_max_storage: default_history_max_storage(),
-            history_prioritize_basename: default_history_prioritize_basename(),
+/// Collect the paths up to `max_depth` levels below `root` whose names contain
+/// `query`, streaming them into `results` like grep_tree.
+fn find_names(root: &Path, query: &str, options: SearchOptions, results: &Mutex<GrepResults>, cancel: &AtomicBool) {
+    let SearchOptions { case_sensitive, show_hidden, max_depth, max_results } = options;
+    let query = if case_sensitive { query.to_string() } else { query.to_lowercase() };
+    let mut dirs = vec![(root.to_path_buf(), 0)];
+    'walk: while let Some((dir, depth)) = dirs.pop() {
+        if cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        let Ok(read_dir) = fs::read_dir(&dir) else { continue };
+        let mut children: Vec<fs::DirEntry> = read_dir.flatten().collect();
+        children.sort_by_key(|child| child.file_name());
+        let mut found = Vec::new();
+        let mut subdirs = Vec::new();
+        for child in children {
+            let name = child.file_name().to_string_lossy().to_string();
+            if !show_hidden && name.starts_with('.') {
+                continue;
+            }
+            let hit = if case_sensitive { name.contains(&query) } else { name.to_lowercase().contains(&query) };
+            if hit {
+                found.push(GrepMatch { path: child.path(), line: 0, text: String::new() });
+            }
+            if depth < max_depth && child.file_type().is_ok_and(|kind| kind.is_dir()) {
+                subdirs.push((child.path(), depth + 1));
+            }
+        }
+        // Breadth first, so the shallowest matches come first
+        dirs.splice(0..0, subdirs.into_iter().rev());
+
+        let Ok(mut results) = results.lock() else { return };
+        results.files += 1;
+        for found in found {
+            if results.matches.len() >= max_results {
+                break 'walk;
+            }
+            results.matches.push(found);
         }
     }
+    if let Ok(mut results) = results.lock() {
+        results.done = true;
+    }
 }
 
-// Unified config structure
-#[derive(Serialize, Deserialize, Clone)]
-struct Config {
-    #[serde(default)]
-    keybindings: Keybindings,
-    #[serde(default)]
-    colors: ColorConfig,
-    #[serde(default)]
-    settings: Settings,
+/// The top of the git work tree containing `dir`, if any.
+fn git_root(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().find(|ancestor| ancestor.join(".git").exists()).map(Path::to_path_buf)
 }
 
-impl Config {
-    fn load() -> (Self, Option<String>) {
-        if let Ok(home) = env::var("HOME") {
-            let config_path = PathBuf::from(home).join(".config/ils/config.toml");
-            if let Ok(content) = fs::read_to_string(&config_path) {
-                match toml::from_str(&content) {
-                    Ok(config) => return (config, None),
-                    Err(e) => {
-                        let error_msg = format!("Config error: {} - Using defaults. Press '?' for help.", e);
-                        eprintln!("\x1b[31mError loading config from {}: {}\x1b[0m", config_path.display(), e);
-                        eprintln!("\x1b[33mUsing default configuration. Run 'ils config' to fix.\x1b[0m");
-                        return (Config::default(), Some(error_msg));
-                    }
-                }
+/// Which of `paths` git ignores, by .gitignore files, .git/info/exclude and the
+/// global excludes file. Tracked files are never reported. Empty outside a work tree.
+#[cfg(unix)]
+fn gitignored(dir: &Path, paths: &[PathBuf]) -> HashSet<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+    let child = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["check-ignore", "-z", "--stdin"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+    let Ok(mut child) = child else {
+        return HashSet::new();
+    };
+    // Feed the names from another thread so a full stdout pipe can't stall git
+    let mut input = Vec::new();
+    for path in paths {
+        input.extend_from_slice(path.as_os_str().as_bytes());
+        input.push(0);
+    }
+    let stdin = child.stdin.take();
+    let writer = thread::spawn(move || {
+        if let Some(mut stdin) = stdin {
+            let _ = stdin.write_all(&input);
+        }
+    });
+    let output = child.wait_with_output();
+    let _ = writer.join();
+    // check-ignore exits with 1 when nothing is ignored
+    let Ok(output) = output else {
+        return HashSet::new();
+    };
+    output
+        .stdout
+        .split(|&b| b == 0)
+        .filter(|path| !path.is_empty())
+        .map(|path| dir.join(std::ffi::OsStr::from_bytes(path)))
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn gitignored(_dir: &Path, _paths: &[PathBuf]) -> HashSet<PathBuf> {
+    HashSet::new()
+}
+
+/// Whether `name` matches `pattern`, where `*` stands for any run of characters
+/// and `?` for one. Case is ignored, as file names are often capitalised either way.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    // Where the last * started and how much of the name it has taken, to backtrack to
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
             }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
         }
-        (Config::default(), None)
     }
+    pattern[p..].iter().all(|&c| c == '*')
+}
 
-    fn path() -> Option<PathBuf> {
-        if let Ok(home) = env::var("HOME") {
-            Some(PathBuf::from(home).join(".config/ils/config.toml"))
+/// Quote a path for pasting into a POSIX shell. Plain paths are left as they
+/// are; anything else is wrapped in single quotes.
+fn shell_quote(path: &Path) -> String {
+    let text = path.to_string_lossy();
+    let plain = |c: char| c.is_ascii_alphanumeric() || "/._-+,:@%=".contains(c);
+    if !text.is_empty() && text.chars().all(plain) {
+        text.into_owned()
+    } else {
+        format!("'{}'", text.replace('\'', "'\\''"))
+    }
+}
+
+/// Fill in a shell command's placeholders: `{}` becomes the selected path and
+/// `{+}` the marked paths (the selected one when nothing is marked), quoted.
+fn expand_placeholders(command: &str, selected: Option<&Path>, marked: &[PathBuf]) -> String {
+    let selected_quoted = selected.map(shell_quote).unwrap_or_default();
+    let marked_quoted = match marked {
+        [] => selected_quoted.clone(),
+        _ => marked.iter().map(|path| shell_quote(path)).collect::<Vec<_>>().join(" "),
+    };
+    // One pass, so text from a substituted path is never expanded again
+    let mut expanded = String::with_capacity(command.len());
+    let mut rest = command;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("{+}") {
+            expanded.push_str(&marked_quoted);
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("{}") {
+            expanded.push_str(&selected_quoted);
+            rest = after;
         } else {
-            None
+            expanded.push('{');
+            rest = &rest[1..];
         }
     }
+    expanded.push_str(rest);
+    expanded
+}
 
-    fn save(&self) -> io::Result<()> {
-        if let Some(config_path) = Config::path() {
-            if let Some(parent) = config_path.parent() {
-                fs::create_dir_all(parent)?;
-            }
-            let content = toml::to_string_pretty(self).map_err(|e| {
-                io::Error::new(io::ErrorKind::Other, e)
-            })?;
-            fs::write(config_path, content)?;
+/// Run a user-configured exit command through sh, with the directory as $1
+/// and as the working directory.
+fn run_exit_command(command: &str, dir: &Path) {
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .arg("ils")
+        .arg(dir)
+        .current_dir(dir)
+        .status();
+    match status {
+        Ok(status) if !status.success() => eprintln!("ils: `{}` exited with {}", command, status),
+        Err(e) => eprintln!("ils: couldn't run `{}`: {}", command, e),
+        _ => {}
+    }
+}
+
+/// Put text on the system clipboard with pbcopy, wl-copy, xclip or xsel,
+/// whichever is available. Returns false when none of them worked.
+fn copy_to_system_clipboard(text: &str) -> bool {
+    let mut candidates: Vec<(&str, &[&str])> = Vec::new();
+    if cfg!(target_os = "macos") {
+        candidates.push(("pbcopy", &[]));
+    } else {
+        if env::var_os("WAYLAND_DISPLAY").is_some() {
+            candidates.push(("wl-copy", &[]));
         }
-        Ok(())
+        candidates.push(("xclip", &["-selection", "clipboard"]));
+        candidates.push(("xsel", &["--clipboard", "--input"]));
     }
 
-    fn create_default() -> io::Result<()> {
-        if let Some(config_path) = Config::path() {
-            if let Some(parent) = config_path.parent() {
-                fs::create_dir_all(parent)?;
+    for (program, args) in candidates {
+        let child = std::process::Command::new(program)
+            .args(args)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn();
+        if let Ok(mut child) = child {
+            let written = child.stdin.take().is_some_and(|mut stdin| stdin.write_all(text.as_bytes()).is_ok());
+            if child.wait().is_ok_and(|status| status.success()) && written {
+                return true;
             }
+        }
+    }
+    false
+}
 
-            let default_config = r##"# ILS Configuration File
-# This file contains all configuration for the ils file browser
-
-# ============================================================================
-# KEYBINDINGS
-# ============================================================================
-[keybindings]
-# Navigation
-up = ['w']
-down = ['s']
-left = ['a']
-right = ['d']
-jump_up = ['W']
-jump_down = ['S']
-jump_left = ['A']
-jump_right = ['D']
-
-# Actions
-open = ['l']                    # Open file/directory
-back = ['j', 'b']              # Go back up one directory
-home = ['h']                    # Go to home directory
-quit = ['q']                    # Quit without cd
-quit_then_open_in_finder = ['Q'] # Quit and open current directory in Finder (Shift+q)
-help = ['!']                    # Show help screen
-
-# Preview controls
-preview_toggle = ['P']          # Toggle preview pane (Shift+p)
-preview_up = ['i']             # Scroll preview up
-preview_down = ['o']           # Scroll preview down
-preview_height_decrease = ['-'] # Decrease preview pane height
-preview_height_increase = ['+'] # Increase preview pane height
-
-# Other
-toggle_hidden = ['.']          # Toggle hidden files
-fuzzy_find = ['/']             # Enter fuzzy find mode
-history_jump = ['f']           # Jump to frequently visited directory
-
-# Fuzzy find mode controls
-fuzzy_back = ['/']             # Go back one directory (in fuzzy mode)
-fuzzy_home = ['?']             # Go to home directory (in fuzzy mode)
-
-# Other
-toggle_mode = ['m']            # Toggle between list and grid mode
-rename = ['r']                 # Rename selected file
-next_sibling = ['n']           # Go to next sibling directory
-prev_sibling = ['N']           # Go to previous sibling directory (Shift+n)
-copy = ['c']                   # Copy selected file to clipboard
-paste = ['v']                  # Paste from clipboard
-trash = ['x']                  # Move to trash
-delete = ['X']                 # Permanently delete (Shift+x)
-undo = ['z']                   # Undo last action
-redo = ['Z']                   # Redo last undone action (Shift+z)
-create = ['y']                 # Create new file or directory
-quicklook = [' ']              # Open selected file in macOS Quick Look (Space)
-
-# ============================================================================
-# COLORS
-# ============================================================================
-[colors]
-# Color values can be:
-#   - Named colors: "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"
-#   - Dark variants: "darkgrey", "darkred", etc.
-#   - Hex colors: "#RRGGBB" (e.g., "#333333")
-#   - Special: "reverse" (inverted default color), "none" (no color)
-
-# Path bar at top
-path_fg = "white"
-path_bg = "#333333"
-
-# Selected item
-selected_fg = "none"
-selected_bg = "none"
-
-# Directory names
-directory_fg = "cyan"
-directory_bg = "none"
-
-# File names
-file_fg = "none"
-file_bg = "none"
-
-# Preview pane border
-preview_border_fg = "darkgrey"
-
-# Cursor (">")
-cursor_fg = "green"
-cursor_bg = "none"
-
-# Fuzzy find highlighting
-fuzzy_highlight_fg = "#ffff00"
-fuzzy_highlight_bg = "#323232"
-
-# Line numbers in preview
-line_number_fg = "darkgrey"
-
-# Help menu (bottom of screen)
-help_menu_fg = "white"
-help_menu_bg = "#1e1e1e"
-
-# ============================================================================
-# SETTINGS
-# ============================================================================
-[settings]
-# Exit after editing a file (default: false)
-exit_after_edit = false
-
-# Number of lines to scroll in preview mode (default: 10)
-# Shift+i/o will scroll by visible lines instead
-preview_scroll_amount = 10
-
-# Show hidden files by default (default: false)
-show_hidden = false
-
-# Show preview pane on start (default: false)
-preview_on_start = false
-
-# Preview pane height ratio (0.0-1.0, default: 0.5)
-preview_split_ratio = 0.5
-
-# Case sensitive fuzzy find search (default: false)
-case_sensitive_search = false
-
-# Show trailing slash on directories (default: true)
-show_dir_slash = true
-
-# Jump amount for W/A/S/D navigation (default: 5)
-jump_amount = 5
-
-# Show ~ for home directory in path (default: true)
-show_tilde_for_home = true
-
-# Always show relative dates (default: false, uses "Aug '25" format for dates >3mo)
-verbose_dates = false
-
-# Debug: Force show welcome wizard on every run (default: false)
-debug_show_welcome = false
-
-# Show help menu on first start (default: true)
-show_help_on_start = true
+/// Apply renames, going through temporary names when there is more than one so
+/// swaps and chains (a -> b, b -> c) don't overwrite each other. On failure the
+/// entries already moved are put back.
+fn rename_all(plan: &[(PathBuf, PathBuf)]) -> io::Result<()> {
+    if let [(old, new)] = plan {
+        return fs::rename(old, new);
+    }
 
-# Internal: Cache for shell wrapper validation (automatically set, do not modify)
-wrapper_validation_cache_valid = false
-"##;
+    let temps: Vec<PathBuf> = plan
+        .iter()
+        .enumerate()
+        .map(|(i, (old, _))| old.with_file_name(format!(".ils-rename-{}-{}", std::process::id(), i)))
+        .collect();
 
-            fs::write(&config_path, default_config)?;
+    for (i, (old, _)) in plan.iter().enumerate() {
+        if let Err(e) = fs::rename(old, &temps[i]) {
+            for j in 0..i {
+                let _ = fs::rename(&temps[j], &plan[j].0);
+            }
+            return Err(e);
+        }
+    }
+    for (i, (_, new)) in plan.iter().enumerate() {
+        if let Err(e) = fs::rename(&temps[i], new) {
+            for j in 0..i {
+                let _ = fs::rename(&plan[j].1, &temps[j]);
+            }
+            for j in 0..plan.len() {
+                let _ = fs::rename(&temps[j], &plan[j].0);
+            }
+            return Err(e);
         }
-        Ok(())
     }
+    Ok(())
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Config {
-            keybindings: Keybindings::default(),
-            colors: ColorConfig::default(),
-            settings: Settings::default(),
+/// Create a file only the user can read, under a random name in the temp
+/// directory, so no one else can read it or put a symlink in its place first.
+fn create_temp_file(prefix: &str, extension: &str) -> io::Result<(PathBuf, fs::File)> {
+    use std::hash::{BuildHasher, RandomState};
+    loop {
+        let random = RandomState::new().hash_one(std::process::id());
+        let path = env::temp_dir().join(format!("{}-{:016x}.{}", prefix, random, extension));
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
         }
     }
 }
 
-impl Settings {
-    #[allow(dead_code)]
-    fn save(&self) -> io::Result<()> {
-        if let Ok(home) = env::var("HOME") {
-            let config_dir = PathBuf::from(home).join(".config/ils");
-            fs::create_dir_all(&config_dir)?;
-            let config_path = config_dir.join("settings.toml");
-            let content = toml::to_string_pretty(self).map_err(|e| {
-                io::Error::new(io::ErrorKind::Other, e)
-            })?;
-            fs::write(config_path, content)?;
+/// Replace `path` with `contents` by way of a temporary file in the same directory,
+/// so an interrupted save leaves the old file whole. A symlinked file is written
+/// through to its target and keeps its permissions.
+fn write_atomic(path: &Path, contents: &str) -> io::Result<()> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+    let result = (|| {
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(contents.as_bytes())?;
+        if let Ok(metadata) = fs::metadata(&path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()?;
+        fs::rename(&temp, &path)?;
+        // Make the rename itself durable
+        if let Some(dir) = path.parent().and_then(|parent| fs::File::open(parent).ok()) {
+            let _ = dir.sync_all();
         }
         Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
     }
+    result
 }
 
-impl Default for ColorConfig {
-    fn default() -> Self {
-        ColorConfig {
-            path_fg: default_path_fg(),
-            path_bg: default_path_bg(),
-            selected_fg: default_selected_fg(),
-            selected_bg: default_selected_bg(),
-            directory_fg: default_directory_fg(),
-            directory_bg: default_directory_bg(),
-            file_fg: default_file_fg(),
-            file_bg: default_file_bg(),
-            preview_border_fg: default_preview_border_fg(),
-            cursor_fg: default_cursor_fg(),
-            cursor_bg: default_cursor_bg(),
-            fuzzy_highlight_fg: default_fuzzy_highlight_fg(),
-            fuzzy_highlight_bg: default_fuzzy_highlight_bg(),
-            line_number_fg: default_line_number_fg(),
-            help_menu_fg: default_help_menu_fg(),
-            help_menu_bg: default_help_menu_bg(),
-            history_query_fg: default_history_query_fg(),
-            history_path_fg: default_history_path_fg(),
-            history_count_fg: default_history_count_fg(),
+/// Rewrite `[name]` in the TOML `content` from `old` to `new`, touching only the
+/// keys that changed so the user's comments, order and formatting survive.
+fn edit_toml_table(content: &str, name: &str, old: &toml::Table, new: &toml::Table) -> io::Result<String> {
+    let mut doc: toml_edit::DocumentMut = content.parse().map_err(io::Error::other)?;
+    if !doc.contains_key(name) {
+        doc[name] = toml_edit::table();
+    }
+    let Some(table) = doc[name].as_table_like_mut() else {
+        return Err(io::Error::other(format!("[{}] in the config is not a table", name)));
+    };
+    for key in old.keys().filter(|key| !new.contains_key(*key)) {
+        table.remove(key);
+    }
+    for (key, value) in new {
+        if old.get(key) == Some(value) {
+            continue;
+        }
+        let mut edited: toml_edit::Value = value.to_string().parse().map_err(io::Error::other)?;
+        // Keep a comment written after the old value
+        if let Some(existing) = table.get(key).and_then(|item| item.as_value()) {
+            *edited.decor_mut() = existing.decor().clone();
         }
+        table.insert(key, toml_edit::Item::Value(edited));
     }
+    Ok(doc.to_string())
 }
 
-impl ColorConfig {
-    #[allow(dead_code)]
-    fn load() -> Self {
-        if let Ok(home) = env::var("HOME") {
-            let config_path = PathBuf::from(home).join(".config/ils/colors.toml");
-            if let Ok(content) = fs::read_to_string(&config_path) {
-                if let Ok(config) = toml::from_str(&content) {
-                    return config;
-                }
-            }
+/// Serialize `base`, replace any keys present in `user`, and read it back.
+fn overlay_table<T: Serialize + serde::de::DeserializeOwned>(
+    base: &T,
+    user: Option<&toml::Value>,
+) -> Result<T, toml::de::Error> {
+    let mut merged = toml::Table::try_from(base).map_err(|e| serde::de::Error::custom(e.to_string()))?;
+    if let Some(toml::Value::Table(user)) = user {
+        for (key, value) in user {
+            merged.insert(key.clone(), value.clone());
         }
-        Self::default()
     }
+    toml::Value::Table(merged).try_into()
+}
 
-    #[allow(dead_code)]
-    fn save(&self) -> io::Result<()> {
-        if let Ok(home) = env::var("HOME") {
-            let config_dir = PathBuf::from(home).join(".config/ils");
-            fs::create_dir_all(&config_dir)?;
-            let config_path = config_dir.join("colors.toml");
-            let content = toml::to_string_pretty(self).map_err(|e| {
-                io::Error::new(io::ErrorKind::Other, e)
-            })?;
-            fs::write(config_path, content)?;
+fn check_wrapper_installed() -> bool {
+    // Check if running from shell wrapper by checking if we're called as 'ils' or 'ils-bin'
+    if let Ok(exe_path) = env::current_exe() {
+        if let Some(exe_name) = exe_path.file_name() {
+            // If called as 'ils-bin', we might be running from shell wrapper or directly
+            // Check for ILS_WRAPPER_ACTIVE env var that the wrapper should set
+            if exe_name == "ils-bin" && env::var("ILS_WRAPPER_ACTIVE").is_ok() {
+                return true;
+            }
         }
-        Ok(())
     }
 
-    fn parse_fg_color(&self) -> Option<Color> {
-        Self::parse_color_string(&self.path_fg)
-    }
+    // Alternative check: Look for the wrapper function in common shell configs
+    let home = match env::var("HOME") {
+        Ok(h) => h,
+        Err(_) => return false,
+    };
 
-    fn parse_bg_color(&self) -> Option<Color> {
-        Self::parse_color_string(&self.path_bg)
-    }
+    let rc_files = vec![
+        PathBuf::from(&home).join(".zshrc"),
+        PathBuf::from(&home).join(".bashrc"),
+        PathBuf::from(&home).join(".config/fish/config.fish"),
+    ];
 
-    fn parse_selected_fg(&self) -> Option<Color> {
-        Self::parse_color_string(&self.selected_fg)
+    for rc_file in rc_files {
+        if rc_file.exists() {
+            if let Ok(content) = fs::read_to_string(&rc_file) {
+                // Check for the ils wrapper function (must be on non-commented line)
+                for line in content.lines() {
+                    let trimmed = line.trim();
+                    // Skip comment lines
+                    if trimmed.starts_with('#') {
+                        continue;
+                    }
+                    // Check for ils() function definition
+                    if trimmed.contains("ils()") || trimmed.contains("function ils") {
+                        // Also verify the function contains /tmp/ils_cd logic
+                        if content.contains("/tmp/ils_cd") {
+                            return true;
+                        }
+                    }
+                }
+            }
+        }
     }
 
-    fn parse_selected_bg(&self) -> Option<Color> {
-        Self::parse_color_string(&self.selected_bg)
-    }
-
-    fn parse_directory_fg(&self) -> Option<Color> {
-        Self::parse_color_string(&self.directory_fg)
-    }
-
-    fn parse_directory_bg(&self) -> Option<Color> {
-        Self::parse_color_string(&self.directory_bg)
-    }
-
-    fn parse_file_fg(&self) -> Option<Color> {
-        Self::parse_color_string(&self.file_fg)
-    }
+    false
+}
 
-    fn parse_file_bg(&self) -> Option<Color> {
-        Self::parse_color_string(&self.file_bg)
-    }
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+struct Keybindings {
+    up: Vec<String>,
+    down: Vec<String>,
+    left: Vec<String>,
+    right: Vec<String>,
+    open: Vec<String>,
+    back: Vec<String>,
+    home: Vec<String>,
+    quit: Vec<String>,
+    quit_then_open_in_finder: Vec<String>,
+    help: Vec<String>,
+    preview_toggle: Vec<String>,
+    preview_up: Vec<String>,
+    preview_down: Vec<String>,
+    preview_left: Vec<String>,
+    preview_right: Vec<String>,
+    preview_height_decrease: Vec<String>,
+    preview_height_increase: Vec<String>,
+    toggle_hidden: Vec<String>,
+    toggle_gitignored: Vec<String>,
+    toggle_markdown: Vec<String>,
+    fold_preview: Vec<String>,
+    sort: Vec<String>,
+    sort_reverse: Vec<String>,
+    collapse_group: Vec<String>,
+    expand_groups: Vec<String>,
+    fuzzy_find: Vec<String>,
+    fuzzy_back: Vec<String>,
+    fuzzy_home: Vec<String>,
+    history_jump: Vec<String>,
+    grep: Vec<String>,
+    find_files: Vec<String>,
+    toggle_mode: Vec<String>,
+    rename: Vec<String>,
+    bulk_rename: Vec<String>,
+    pattern_rename: Vec<String>,
+    edit_tags: Vec<String>,
+    next_sibling: Vec<String>,
+    match_next: Vec<String>,
+    match_prev: Vec<String>,
+    prev_sibling: Vec<String>,
+    copy: Vec<String>,
+    mark: Vec<String>,
+    paste: Vec<String>,
+    trash: Vec<String>,
+    trash_browse: Vec<String>,
+    empty_trash: Vec<String>,
+    delete: Vec<String>,
+    extract: Vec<String>,
+    compress: Vec<String>,
+    undo: Vec<String>,
+    redo: Vec<String>,
+    create: Vec<String>,
+    jump_up: Vec<String>,
+    jump_down: Vec<String>,
+    jump_left: Vec<String>,
+    jump_right: Vec<String>,
+    quicklook: Vec<String>,
+    open_in_ide: Vec<String>,
+    pager: Vec<String>,
+    open_with: Vec<String>,
+    shell_command: Vec<String>,
+    refresh: Vec<String>,
+    retry_load: Vec<String>,
+    path_jump: Vec<String>,
+    gitignore: Vec<String>,
+    git_stage: Vec<String>,
+    git_unstage: Vec<String>,
+    git_diff: Vec<String>,
+    ancestor_jump: Vec<String>,
+    edit_path: Vec<String>,
+    find_char: Vec<String>,
+    bookmark_add: Vec<String>,
+    bookmarks: Vec<String>,
+    tab_new: Vec<String>,
+    tab_close: Vec<String>,
+    tab_next: Vec<String>,
+    tab_prev: Vec<String>,
+    dual_pane: Vec<String>,
+    pane_switch: Vec<String>,
+    pane_copy: Vec<String>,
+    pane_move: Vec<String>,
+    pane_sync: Vec<String>,
+    filter: Vec<String>,
+    help_screen: Vec<String>,
+    first: Vec<String>,
+    last: Vec<String>,
+    cancel: Vec<String>,
+    select: Vec<String>,
+    list_action: Vec<String>,
+    dir_size: Vec<String>,
+    preview_page_up: Vec<String>,
+    preview_page_down: Vec<String>,
+    clear_copy: Vec<String>,
+    messages: Vec<String>,
+    gallery: Vec<String>,
+    next_image: Vec<String>,
+    prev_image: Vec<String>,
+    filesystem_info: Vec<String>,
+    file_info: Vec<String>,
+    diagnostics: Vec<String>,
+    print_path: Vec<String>,
+    #[serde(skip)]
+    commands: BTreeMap<String, String>, // Shell commands from [commands], by the keys they're bound to
+}
 
-    fn parse_preview_border_fg(&self) -> Option<Color> {
-        Self::parse_color_string(&self.preview_border_fg)
-    }
+/// Build a binding list. Each entry is a single key or a sequence typed in
+/// order, like "gg" or "<C-x><C-f>".
+fn keys(list: &[&str]) -> Vec<String> {
+    list.iter().map(|k| k.to_string()).collect()
+}
 
-    fn parse_cursor_fg(&self) -> Option<Color> {
-        Self::parse_color_string(&self.cursor_fg)
-    }
+#[derive(Serialize, Deserialize, Clone)]
+struct ColorConfig {
+    #[serde(default = "default_path_fg")]
+    path_fg: String,
+    #[serde(default = "default_path_bg")]
+    path_bg: String,
+    #[serde(default = "default_selected_fg")]
+    selected_fg: String,
+    #[serde(default = "default_selected_bg")]
+    selected_bg: String,
+    #[serde(default = "default_directory_fg")]
+    directory_fg: String,
+    #[serde(default = "default_directory_bg")]
+    directory_bg: String,
+    #[serde(default = "default_file_fg")]
+    file_fg: String,
+    #[serde(default = "default_file_bg")]
+    file_bg: String,
+    #[serde(default = "default_preview_border_fg")]
+    preview_border_fg: String,
+    #[serde(default = "default_cursor_fg")]
+    cursor_fg: String,
+    #[serde(default = "default_cursor_bg")]
+    cursor_bg: String,
+    #[serde(default = "default_fuzzy_highlight_fg")]
+    fuzzy_highlight_fg: String,
+    #[serde(default = "default_fuzzy_highlight_bg")]
+    fuzzy_highlight_bg: String,
+    #[serde(default = "default_line_number_fg")]
+    line_number_fg: String,
+    #[serde(default = "default_help_menu_fg")]
+    help_menu_fg: String,
+    #[serde(default = "default_help_menu_bg")]
+    help_menu_bg: String,
+    #[serde(default = "default_history_query_fg")]
+    history_query_fg: String,
+    #[serde(default = "default_history_path_fg")]
+    history_path_fg: String,
+    #[serde(default = "default_history_count_fg")]
+    history_count_fg: String,
+}
 
-    fn parse_cursor_bg(&self) -> Option<Color> {
-        Self::parse_color_string(&self.cursor_bg)
-    }
+fn default_path_fg() -> String {
+    "white".to_string()
+}
 
-    fn parse_fuzzy_highlight_fg(&self) -> Option<Color> {
-        Self::parse_color_string(&self.fuzzy_highlight_fg)
-    }
+fn default_path_bg() -> String {
+    "#333333".to_string()
+}
 
-    fn parse_fuzzy_highlight_bg(&self) -> Option<Color> {
-        Self::parse_color_string(&self.fuzzy_highlight_bg)
-    }
+fn default_selected_fg() -> String {
+    "none".to_string()
+}
 
-    fn parse_line_number_fg(&self) -> Option<Color> {
-        Self::parse_color_string(&self.line_number_fg)
-    }
+fn default_selected_bg() -> String {
+    "none".to_string()
+}
 
-    fn parse_history_query_fg(&self) -> Option<Color> {
-        Self::parse_color_string(&self.history_query_fg)
-    }
+fn default_directory_fg() -> String {
+    "cyan".to_string()
+}
 
-    fn parse_history_path_fg(&self) -> Option<Color> {
-        Self::parse_color_string(&self.history_path_fg)
-    }
+fn default_directory_bg() -> String {
+    "none".to_string()
+}
 
-    fn parse_history_count_fg(&self) -> Option<Color> {
-        Self::parse_color_string(&self.history_count_fg)
-    }
+fn default_file_fg() -> String {
+    "none".to_string()
+}
 
-    fn parse_color_string(color_str: &str) -> Option<Color> {
-        let color_str = color_str.trim().to_lowercase();
+fn default_file_bg() -> String {
+    "none".to_string()
+}
 
-        // Check for hex color (#RRGGBB or #RGB)
-        if color_str.starts_with('#') {
-            return Self::parse_hex_color(&color_str);
-        }
+fn default_preview_border_fg() -> String {
+    "darkgrey".to_string()
+}
 
-        // Named colors
-        match color_str.as_str() {
-            "black" => Some(Color::Black),
-            "red" => Some(Color::Red),
-            "green" => Some(Color::Green),
-            "yellow" => Some(Color::Yellow),
-            "blue" => Some(Color::Blue),
-            "magenta" => Some(Color::Magenta),
-            "cyan" => Some(Color::Cyan),
-            "white" => Some(Color::White),
-            "darkgrey" | "darkgray" => Some(Color::DarkGrey),
-            "darkred" => Some(Color::DarkRed),
-            "darkgreen" => Some(Color::DarkGreen),
-            "darkyellow" => Some(Color::DarkYellow),
-            "darkblue" => Some(Color::DarkBlue),
-            "darkmagenta" => Some(Color::DarkMagenta),
-            "darkcyan" => Some(Color::DarkCyan),
-            "grey" | "gray" => Some(Color::Grey),
-            "none" | "reverse" => None,
-            _ => None,
-        }
-    }
+fn default_cursor_fg() -> String {
+    "green".to_string()
+}
 
-    fn parse_hex_color(hex: &str) -> Option<Color> {
-        let hex = hex.trim_start_matches('#');
+fn default_cursor_bg() -> String {
+    "none".to_string()
+}
 
-        let (r, g, b) = if hex.len() == 6 {
-            // #RRGGBB format
-            let r = u8::from_str_radix(&hex[0..2], 16).ok()?;
-            let g = u8::from_str_radix(&hex[2..4], 16).ok()?;
-            let b = u8::from_str_radix(&hex[4..6], 16).ok()?;
-            (r, g, b)
-        } else if hex.len() == 3 {
-            // #RGB format - expand to #RRGGBB
-            let r = u8::from_str_radix(&hex[0..1], 16).ok()?;
-            let g = u8::from_str_radix(&hex[1..2], 16).ok()?;
-            let b = u8::from_str_radix(&hex[2..3], 16).ok()?;
-            (r * 17, g * 17, b * 17) // 0xF -> 0xFF
-        } else {
-            return None;
-        };
+fn default_fuzzy_highlight_fg() -> String {
+    "#88cc88".to_string()  // Soft green
+}
 
-        Some(Color::Rgb { r, g, b })
-    }
+fn default_fuzzy_highlight_bg() -> String {
+    "#323232".to_string()
 }
 
-impl Default for Keybindings {
-    fn default() -> Self {
-        Keybindings {
-            up: vec!['w'],
-            down: vec!['s'],
-            left: vec!['a'],
-            right: vec!['d'],
-            open: vec!['l'],
-            back: vec!['j', 'b'],
-            home: vec!['h'],
-            quit: vec!['q'],
-            quit_then_open_in_finder: vec!['Q'],
-            help: vec!['?'],
-            preview_toggle: vec!['P'],
-            preview_up: vec!['i'],
-            preview_down: vec!['o'],
-            preview_height_decrease: vec!['-'],
-            preview_height_increase: vec!['+'],
-            toggle_hidden: vec!['.'],
-            fuzzy_find: vec!['/'],
-            fuzzy_back: vec!['/'],
-            fuzzy_home: vec!['?'],
-            history_jump: vec!['f'],
-            toggle_mode: vec!['m'],
-            rename: vec!['r'],
-            next_sibling: vec!['n'],
-            prev_sibling: vec!['N'],
-            copy: vec!['c'],
-            paste: vec!['v'],
-            trash: vec!['x'],
-            delete: vec!['X'],
-            undo: vec!['z'],
-            redo: vec!['Z'],
-            create: vec!['y'],
-            jump_up: vec!['W'],
-            jump_down: vec!['S'],
-            jump_left: vec!['A'],
-            jump_right: vec!['D'],
-            quicklook: vec![' '],
-        }
-    }
+fn default_line_number_fg() -> String {
+    "darkgrey".to_string()
 }
 
-impl Keybindings {
-    #[allow(dead_code)]
-    fn load() -> Self {
-        if let Ok(home) = env::var("HOME") {
-            let config_path = PathBuf::from(home).join(".config/ils/keybindings.toml");
-            if let Ok(content) = fs::read_to_string(&config_path) {
-                if let Ok(bindings) = toml::from_str(&content) {
-                    return bindings;
-                }
-            }
-        }
-        Self::default()
-    }
+fn default_help_menu_fg() -> String {
+    "white".to_string()
+}
 
-    #[allow(dead_code)]
-    fn save(&self) -> io::Result<()> {
-        if let Ok(home) = env::var("HOME") {
-            let config_dir = PathBuf::from(home).join(".config/ils");
-            fs::create_dir_all(&config_dir)?;
-            let config_path = config_dir.join("keybindings.toml");
-            let content = toml::to_string_pretty(self).map_err(|e| {
-                io::Error::new(io::ErrorKind::Other, e)
-            })?;
-            fs::write(config_path, content)?;
-        }
-        Ok(())
-    }
+fn default_help_menu_bg() -> String {
+    "#1e1e1e".to_string()
+}
 
-    fn contains(&self, key_list: &[char], ch: char) -> bool {
-        key_list.contains(&ch)
-    }
+fn default_history_query_fg() -> String {
+    "cyan".to_string()
 }
 
-// Action for undo/redo
-#[derive(Clone)]
-#[allow(dead_code)]
-enum UndoAction {
-    Copy { src: PathBuf, dest: PathBuf },
-    Move { src: PathBuf, dest: PathBuf },
-    Delete { path: PathBuf, was_dir: bool },
-    Rename { old_path: PathBuf, new_path: PathBuf },
-    Create { path: PathBuf, was_dir: bool },
+fn default_history_path_fg() -> String {
+    "cyan".to_string()
 }
 
-#[derive(Clone)]
-enum PreviewState {
-    NotLoaded,
-    Loading,
-    Loaded(Vec<String>),
-    Error(String),
+fn default_history_count_fg() -> String {
+    "darkgrey".to_string()
 }
 
-struct FileBrowser {
-    current_dir: PathBuf,
-    entries: Vec<PathBuf>,
-    selected: usize,
-    scroll_offset: usize,
-    num_cols: usize,
-    start_row: u16, // The row where the content starts drawing
-    breadcrumbs: Vec<String>, // Track folders we've navigated into
-    show_dir_slash: bool, // Whether to show trailing slash for directories
-    preview_mode: bool, // Whether preview pane is active
-    preview_scroll_map: HashMap<PathBuf, usize>, // Per-file scroll positions
-    preview_split_ratio: f32, // Ratio of screen for preview (0.0-1.0)
-    show_help: bool, // Whether to show help screen
-    show_hidden: bool, // Whether to show hidden files
-    fuzzy_mode: bool, // Whether fuzzy find mode is active
-    fuzzy_query: String, // Current fuzzy search query
-    fuzzy_prev_count: usize, // Previous match count for fuzzy finder
-    fuzzy_jump_mode: bool, // Whether fuzzy mode should auto-exit on selection
-    list_mode: bool, // Whether to show in list mode (vs grid mode)
-    list_info_mode: u8, // 0 = none, 1 = modified date, 2 = permissions, 3 = size
-    show_line_numbers: bool, // Whether to show line numbers in preview
-    clipboard: Option<PathBuf>, // Copied file/directory path (single copy, deprecated)
-    clipboard_selection: Vec<PathBuf>, // Multi-select clipboard for copy operations
-    copy_in_progress: bool, // Whether a paste operation is currently in progress
-    copy_progress_current: usize, // Current item being copied
-    copy_progress_total: usize, // Total items to copy
-    undo_stack: Vec<UndoAction>, // Undo history
-    redo_stack: Vec<UndoAction>, // Redo history
-    keybindings: Keybindings,
-    color_config: ColorConfig,
-    settings: Settings,
-    preview_cache: Arc<Mutex<HashMap<PathBuf, PreviewState>>>, // Cache preview content with loading state
-    syntax_set: Option<SyntaxSet>,  // Lazy-loaded on first preview
-    theme_set: Option<ThemeSet>,    // Lazy-loaded on first preview
-    config_error: Option<String>,   // Config loading error message
-    dir_size_cache: HashMap<PathBuf, u64>, // Cache directory sizes
-    calculating_sizes: bool, // Whether we're currently calculating sizes
-    show_created_date: bool, // Toggle between modified and created date
-    error_message: Option<String>, // Error message to display
-    input_block_until: Option<std::time::Instant>, // Block input until this time
-    wrapper_warning: bool, // Whether to show wrapper not installed warning
-    dir_history: DirectoryHistory, // Directory visit history for frecency
-    history_mode: bool, // Whether we're in history navigation mode
-    history_query: String, // Query for history search
-    history_filtered: Vec<DirectoryEntry>, // Filtered history results
+#[derive(Serialize, Deserialize, Clone)]
+struct Settings {
+    #[serde(default = "default_exit_after_edit")]
+    exit_after_edit: bool,
+    #[serde(default = "default_editor_confirm_mb")]
+    editor_confirm_mb: u64, // Ask before opening files this large or binary in $EDITOR; 0 never asks
+    #[serde(default = "default_preview_scroll_amount")]
+    preview_scroll_amount: usize,
+    #[serde(default = "default_show_hidden")]
+    show_hidden: bool,
+    #[serde(default = "default_hide_gitignored")]
+    hide_gitignored: bool, // Leave entries git ignores out of listings inside a repository
+    #[serde(default = "default_preview_on_start")]
+    preview_on_start: bool,
+    #[serde(default = "default_view_on_start")]
+    view_on_start: String, // "grid", "list" or "gallery"
+    #[serde(default = "default_list_column_on_start")]
+    list_column_on_start: String, // List mode's info column: "none", "modified", "permissions", "size" or "duration"
+    #[serde(default = "default_line_numbers_on_start")]
+    line_numbers_on_start: bool, // Number the lines of text previews
+    #[serde(default = "default_preview_split_ratio")]
+    preview_split_ratio: f32,
+    #[serde(default = "default_case_sensitive_search")]
+    case_sensitive_search: bool,
+    #[serde(default = "default_show_dir_slash")]
+    show_dir_slash: bool,
+    #[serde(default = "default_jump_amount")]
+    jump_amount: usize,
+    #[serde(default = "default_show_tilde_for_home")]
+    show_tilde_for_home: bool,
+    #[serde(default = "default_verbose_dates")]
+    verbose_dates: bool,
+    #[serde(default = "default_debug_show_welcome")]
+    debug_show_welcome: bool,
+    #[serde(default = "default_wrapper_validation_cache_valid")]
+    wrapper_validation_cache_valid: bool,
+    #[serde(default = "default_show_help_on_start")]
+    show_help_on_start: bool,
+    #[serde(default = "default_show_history_visit_count")]
+    show_history_visit_count: bool,
+    #[serde(default = "default_history_max_results")]
+    history_max_results: usize,
+    #[serde(default = "default_history_max_storage")]
+    history_max_storage: usize,
+    #[serde(default = "default_history_prioritize_basename")]
+    history_prioritize_basename: bool,
+    #[serde(default = "default_keymap")]
+    keymap: String, // Keybinding preset: "wasd", "vim", "emacs" or "arrows"
+    #[serde(default = "default_theme")]
+    theme: String, // Color preset: "default", "light", "mono", "high-contrast" or "colorblind"
+    #[serde(default = "default_ascii_mode")]
+    ascii_mode: bool, // Plain ASCII and text markers for screen readers and limited terminals
+    #[serde(default = "default_locale")]
+    locale: String, // Language for month names and decimal separator; empty reads LC_ALL/LC_TIME/LANG
+    #[serde(default = "default_show_item_counts")]
+    show_item_counts: bool, // Show the number of entries after each directory in list mode
+    #[serde(default = "default_mark_empty_and_unreadable")]
+    mark_empty_and_unreadable: bool, // Dim empty directories and mark unreadable ones with a lock
+    #[serde(default = "default_sort_by")]
+    sort_by: String, // "name", "modified", "size", "allocated", "extension" or "natural"; directories always come first
+    #[serde(default)]
+    sort_reverse: bool, // Flip the order of sort_by
+    #[serde(default = "default_group_by_type")]
+    group_by_type: bool, // Sorted by extension, list images, documents, code, ... under a header each
+    #[serde(default = "default_show_project_badges")]
+    show_project_badges: bool, // Tag directories holding a Cargo.toml, package.json, go.mod, ... with their language
+    #[serde(default = "default_show_git_status")]
+    show_git_status: bool, // Show branch and uncommitted changes of git repositories, and the status of files inside one
+    #[serde(default = "default_filename_check")]
+    filename_check: String, // Names invalid on FAT/exFAT/NTFS: "off", "warn" or "strict" (refuse)
+    #[serde(default = "default_show_disk_space")]
+    show_disk_space: bool, // Show free/total space of the current filesystem next to the path
+    #[serde(default = "default_auto_refresh")]
+    auto_refresh: bool, // Reload the listing when another program adds, removes or renames entries
+    #[serde(default = "default_preview_cache_entries")]
+    preview_cache_entries: usize, // Most previews kept in memory before the least recently used is dropped
+    #[serde(default = "default_preview_cache_mb")]
+    preview_cache_mb: usize, // Memory limit for cached previews, in megabytes
+    #[serde(default = "default_dir_size_cache_entries")]
+    dir_size_cache_entries: usize, // Most calculated directory sizes kept
+    #[serde(default = "default_preview_prefetch")]
+    preview_prefetch: usize, // Entries above and below the selection to preload while idle; 0 disables
+    #[serde(default = "default_image_protocol")]
+    image_protocol: String, // "auto", "kitty", "iterm", "sixel" or "blocks": how images are drawn in the terminal
+    #[serde(default = "default_scrolloff")]
+    scrolloff: usize, // Rows of context kept visible above and below the selection when scrolling
+    #[serde(default = "default_sequence_timeout_ms")]
+    sequence_timeout_ms: u64, // How long a key bound alone and as a sequence start waits for the rest; 0 waits forever
+    #[serde(default = "default_enter_selects")]
+    enter_selects: String, // Entry selected on entering a directory: "first", "first_file", "last" or name patterns
+    #[serde(default = "default_preview_readme")]
+    preview_readme: bool, // Select and preview README.md/README.txt when entering a directory
+    #[serde(default = "default_strings_min_length")]
+    strings_min_length: usize, // Shortest run of printable characters the binary preview lists
+    #[serde(default = "default_desktop_notifications")]
+    desktop_notifications: bool, // Notify the desktop when a long job finishes while the terminal is unfocused
+    #[serde(default = "default_desktop_notify_after_secs")]
+    desktop_notify_after_secs: u64, // Shortest job, in seconds, worth a desktop notification
+    #[serde(default = "default_path_output")]
+    path_output: String, // Where print_path sends the selected path: "print" or "clipboard"
+    #[serde(default = "default_quit_action")]
+    quit_action: String, // What quit (q) does: "cd", "print", "finder", "none" or "!command"
+    #[serde(default = "default_alt_quit_action")]
+    alt_quit_action: String, // What quit_then_open_in_finder (Q) does, same choices
+    #[serde(default = "default_escape_action")]
+    escape_action: String, // What Esc does outside of modes, same choices
+    #[serde(default)]
+    on_exit: String, // Shell command run on every exit with the final directory as $1
+    #[serde(default = "default_grep_max_results")]
+    grep_max_results: usize, // Content search stops after this many matching lines
+    #[serde(default = "default_find_max_depth")]
+    find_max_depth: usize, // Directory levels find_files descends below the current one
+    #[serde(default = "default_ide_command")]
+    ide_command: String, // Program (and arguments) open_in_ide runs on the current directory
 }
 
-impl FileBrowser {
-    fn wrap_text(text: &str, max_width: usize) -> Vec<String> {
-        if max_width == 0 {
-            return vec![text.to_string()];
-        }
-
-        let mut lines = Vec::new();
-        let mut current_line = String::new();
-        let mut current_width = 0;
+fn default_exit_after_edit() -> bool {
+    false
+}
 
-        for ch in text.chars() {
-            let ch_width = if ch.is_ascii() { 1 } else { 1 }; // Simplified: treat all chars as width 1
+fn default_editor_confirm_mb() -> u64 {
+    100
+}
 
-            if current_width + ch_width > max_width {
-                // Start new line
-                if !current_line.is_empty() {
-                    lines.push(current_line.clone());
-                    current_line.clear();
-                    current_width = 0;
-                }
-            }
+fn default_jump_amount() -> usize {
+    5
+}
 
-            current_line.push(ch);
-            current_width += ch_width;
-        }
+fn default_preview_scroll_amount() -> usize {
+    10
+}
 
-        if !current_line.is_empty() {
-            lines.push(current_line);
-        }
+fn default_show_hidden() -> bool {
+    false
+}
 
-        if lines.is_empty() {
-            vec![String::new()]
-        } else {
-            lines
-        }
-    }
+fn default_hide_gitignored() -> bool {
+    false
+}
 
-    fn truncate_string_safe(s: &str, max_width: usize) -> String {
-        if max_width <= 3 {
-            return "...".to_string();
-        }
+fn default_preview_on_start() -> bool {
+    false
+}
 
-        let char_count: usize = s.chars().count();
-        if char_count <= max_width {
-            return s.to_string();
-        }
+fn default_view_on_start() -> String {
+    "grid".to_string()
+}
 
-        // Take max_width - 3 characters and add "..."
-        let truncated: String = s.chars().take(max_width.saturating_sub(3)).collect();
-        format!("{}...", truncated)
-    }
+fn default_list_column_on_start() -> String {
+    "none".to_string()
+}
 
-    fn format_path_display(&self) -> String {
-        if self.settings.show_tilde_for_home {
-            if let Some(home) = env::var("HOME").ok() {
-                let home_path = PathBuf::from(home);
-                if let Ok(relative) = self.current_dir.strip_prefix(&home_path) {
-                    if relative.as_os_str().is_empty() {
-                        return "~".to_string();
-                    } else {
-                        return format!("~/{}", relative.display());
-                    }
-                }
-            }
-        }
-        self.current_dir.display().to_string()
-    }
+fn default_line_numbers_on_start() -> bool {
+    true
+}
 
-    fn new(start_dir: PathBuf) -> io::Result<Self> {
-        let (_, row) = cursor::position()?;
+fn default_show_tilde_for_home() -> bool {
+    true
+}
 
-        // Load unified config or create default if not exists
-        let (config, config_error) = if let Some(config_path) = Config::path() {
-            if config_path.exists() {
-                Config::load()
-            } else {
-                let _ = Config::create_default();
-                (Config::default(), None)
-            }
-        } else {
-            (Config::default(), None)
-        };
+fn default_verbose_dates() -> bool {
+    false
+}
 
-        let keybindings = config.keybindings;
-        let color_config = config.colors;
-        let settings = config.settings;
+fn default_debug_show_welcome() -> bool {
+    false
+}
 
-        // Use show_help setting from config
-        let show_help = settings.show_help_on_start;
+fn default_wrapper_validation_cache_valid() -> bool {
+    false
+}
 
-        // Use preview split ratio from config
-        let preview_split_ratio = settings.preview_split_ratio;
+fn default_show_help_on_start() -> bool {
+    true
+}
 
-        // start drawing content on the row *after* the initial position
-        let mut browser = FileBrowser {
-            current_dir: start_dir.clone(),
-            entries: Vec::new(),
-            selected: 0,
-            scroll_offset: 0,
-            num_cols: 1,
-            start_row: row,
-            breadcrumbs: Vec::new(),
-            show_dir_slash: settings.show_dir_slash,
-            preview_mode: settings.preview_on_start,
-            preview_scroll_map: HashMap::new(),
-            preview_split_ratio,
-            show_help,
-            show_hidden: settings.show_hidden,
-            fuzzy_mode: false,
-            fuzzy_query: String::new(),
-            fuzzy_prev_count: 0,
-            fuzzy_jump_mode: false,
-            list_mode: false,
-            list_info_mode: 0,
-            show_line_numbers: true,
-            clipboard: None,
-            clipboard_selection: Vec::new(),
-            copy_in_progress: false,
-            copy_progress_current: 0,
-            copy_progress_total: 0,
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
-            keybindings,
-            color_config,
-            settings,
-            preview_cache: Arc::new(Mutex::new(HashMap::new())),
-            syntax_set: None,  // Lazy-loaded
-            theme_set: None,   // Lazy-loaded
-            config_error,
-            dir_size_cache: HashMap::new(),
-            calculating_sizes: false,
-            show_created_date: false,
-            error_message: None,
-            input_block_until: None,
-            wrapper_warning: false,
-            dir_history: DirectoryHistory::load(),
-            history_mode: false,
-            history_query: String::new(),
-            history_filtered: Vec::new(),
-        };
-        browser.load_entries()?;
+fn default_show_history_visit_count() -> bool {
+    false
+}
 
-        // Record initial directory visit
-        browser.dir_history.record_visit(&start_dir, browser.settings.history_max_storage);
-        let _ = browser.dir_history.save();
-        // Don't calculate layout here - will be done on first draw for faster startup
+fn default_history_max_results() -> usize {
+    12
+}
 
-        Ok(browser)
-    }
+fn default_history_max_storage() -> usize {
+    1000
+}
 
-    fn ensure_syntax_loaded(&mut self) {
-        if self.syntax_set.is_none() {
-            self.syntax_set = Some(SyntaxSet::load_defaults_newlines());
-            self.theme_set = Some(ThemeSet::load_defaults());
-        }
-    }
+fn default_keymap() -> String {
+    "wasd".to_string()
+}
 
-    fn calculate_dir_size(dir: &PathBuf) -> u64 {
-        let mut total = 0u64;
-        if let Ok(entries) = fs::read_dir(dir) {
-            for entry in entries.filter_map(|e| e.ok()) {
-                if let Ok(metadata) = entry.metadata() {
-                    if metadata.is_file() {
-                        total += metadata.len();
-                    } else if metadata.is_dir() {
-                        total += Self::calculate_dir_size(&entry.path());
-                    }
-                }
-            }
-        }
-        total
-    }
+fn default_theme() -> String {
+    "default".to_string()
+}
 
-    fn calculate_all_dir_sizes(&mut self) -> io::Result<()> {
-        self.calculating_sizes = true;
-        self.draw()?; // Show "Calculating..." tooltip
-        for entry in &self.entries {
-            if entry.is_dir() && !self.dir_size_cache.contains_key(entry) {
-                let size = Self::calculate_dir_size(entry);
-                self.dir_size_cache.insert(entry.clone(), size);
-            }
-        }
-        self.calculating_sizes = false;
-        Ok(())
-    }
+fn default_ascii_mode() -> bool {
+    false
+}
 
-    fn start_preview_load(&self, path: PathBuf) {
-        let cache = Arc::clone(&self.preview_cache);
-        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+fn default_locale() -> String {
+    String::new()
+}
 
-        // Mark as loading
-        if let Ok(mut cache_lock) = cache.lock() {
-            cache_lock.insert(path.clone(), PreviewState::Loading);
-        }
+fn default_show_item_counts() -> bool {
+    true
+}
 
-        thread::spawn(move || {
-            let result = if matches!(extension.as_str(), "png" | "jpg" | "jpeg" | "gif" | "bmp" | "webp") {
-                // For images, we can't really cache rendered output easily
-                // Just mark as loaded with placeholder
-                PreviewState::Loaded(vec!["[Image Preview]".to_string()])
-            } else if extension == "pdf" {
-                // Extract PDF text
-                match extract_text(&path) {
-                    Ok(text) => {
-                        let lines: Vec<String> = text.lines().map(|s| s.to_string()).collect();
-                        PreviewState::Loaded(lines)
-                    }
-                    Err(_) => PreviewState::Error("Cannot extract PDF text".to_string())
-                }
-            } else {
-                PreviewState::NotLoaded
-            };
+fn default_mark_empty_and_unreadable() -> bool {
+    true
+}
 
-            if let Ok(mut cache_lock) = cache.lock() {
-                cache_lock.insert(path, result);
-            }
-        });
-    }
+fn default_sort_by() -> String {
+    "name".to_string()
+}
 
-    #[allow(dead_code)]
-    fn config_exists() -> bool {
-        if let Ok(home) = env::var("HOME") {
-            let config_path = PathBuf::from(home).join(".config/ils/preview_ratio");
-            config_path.exists()
-        } else {
-            false
-        }
-    }
+fn default_group_by_type() -> bool {
+    true
+}
 
-    #[allow(dead_code)]
-    fn color_config_exists() -> bool {
-        if let Ok(home) = env::var("HOME") {
-            let config_path = PathBuf::from(home).join(".config/ils/colors.toml");
-            config_path.exists()
-        } else {
-            false
-        }
-    }
+fn default_show_project_badges() -> bool {
+    true
+}
 
-    fn save_preview_ratio(&self) -> io::Result<()> {
-        // Load current config, update preview_split_ratio, and save
-        let (mut config, _) = Config::load();
-        config.settings.preview_split_ratio = self.preview_split_ratio;
-        config.save()
-    }
+fn default_show_git_status() -> bool {
+    true
+}
 
-    fn save_show_help_state(&self) -> io::Result<()> {
-        // Load current config, update show_help_on_start, and save
-        let (mut config, _) = Config::load();
-        config.settings.show_help_on_start = self.show_help;
-        config.save()
-    }
+fn default_filename_check() -> String {
+    "warn".to_string()
+}
 
-    fn save_show_hidden(&self) -> io::Result<()> {
-        // Load current config, update show_hidden, and save
-        let (mut config, _) = Config::load();
-        config.settings.show_hidden = self.show_hidden;
-        config.save()
-    }
+fn default_show_disk_space() -> bool {
+    true
+}
 
-    fn load_entries(&mut self) -> io::Result<()> {
-        self.entries.clear();
-        self.selected = 0;
-        self.scroll_offset = 0;
+fn default_auto_refresh() -> bool {
+    true
+}
 
-        let mut entries: Vec<PathBuf> = fs::read_dir(&self.current_dir)?
-            .filter_map(|e| e.ok())
-            .map(|e| e.path())
-            .collect();
+fn default_preview_cache_entries() -> usize {
+    200
+}
 
-        // Filter out hidden files (starting with '.') if show_hidden is false
-        if !self.show_hidden {
-            entries.retain(|path| {
-                path.file_name()
-                    .and_then(|n| n.to_str())
-                    .map(|n| !n.starts_with('.'))
-                    .unwrap_or(true)
-            });
-        }
+fn default_preview_cache_mb() -> usize {
+    64
+}
 
-        // Sort: directories first, then alphabetically
-        entries.sort_by(|a, b| {
-            let a_is_dir = a.is_dir();
-            let b_is_dir = b.is_dir();
-            if a_is_dir == b_is_dir {
-                a.file_name().cmp(&b.file_name())
-            } else {
-                b_is_dir.cmp(&a_is_dir)
-            }
-        });
+fn default_dir_size_cache_entries() -> usize {
+    5000
+}
 
-        self.entries = entries;
-        self.update_layout()?; // Recalculate layout after loading new directory entries
-        Ok(())
-    }
+fn default_preview_prefetch() -> usize {
+    1
+}
 
-    /// Recalculates the number of columns and adjusts selected/scroll indices based on current terminal size.
-    fn update_layout(&mut self) -> io::Result<()> {
-        let (width, height) = terminal::size()?;
-        let term_width = width as usize;
-        let term_height = height as usize;
+fn default_image_protocol() -> String {
+    "auto".to_string()
+}
 
-        // Fixed cell width: 20 characters for name + 2 for prefix
-        const CELL_WIDTH: usize = 22;
+fn default_scrolloff() -> usize {
+    0
+}
 
-        // Calculate available rows for content (subtract header and footer)
-        let available_rows = term_height.saturating_sub(self.start_row as usize).saturating_sub(2);
+fn default_sequence_timeout_ms() -> u64 {
+    500
+}
 
-        // Layout method: Square
-        // Distribute into a square-like layout (cols ≈ rows)
-        let num_entries = self.entries.len().max(1);
+fn default_enter_selects() -> String {
+    "first".to_string()
+}
 
-        // In list mode, always use 1 column
-        if self.list_mode {
-            self.num_cols = 1;
-        } else {
-            // Calculate max columns that can fit
-            let max_cols = (term_width / CELL_WIDTH).max(1);
+fn default_preview_readme() -> bool {
+    false
+}
 
-            // Start with square root as ideal column count
-            let ideal_cols = (num_entries as f64).sqrt().ceil() as usize;
-            let ideal_cols = ideal_cols.clamp(1, max_cols);
+fn default_strings_min_length() -> usize {
+    4
+}
 
-            let mut best_cols = ideal_cols;
-            let mut best_score = usize::MAX;
+fn default_desktop_notifications() -> bool {
+    true
+}
 
-            // Try column counts around the ideal, find the one closest to square that fits
-            for cols in 1..=max_cols {
-                let rows_needed = (num_entries + cols - 1) / cols;
-                if rows_needed <= available_rows {
-                    // Score based on how close to square (minimize abs difference between cols and rows)
-                    let score = if cols > rows_needed {
-                        cols - rows_needed
-                    } else {
-                        rows_needed - cols
-                    };
+fn default_desktop_notify_after_secs() -> u64 {
+    10
+}
 
-                    if score < best_score {
-                        best_score = score;
-                        best_cols = cols;
-                    }
-                }
-            }
+fn default_path_output() -> String {
+    "print".to_string()
+}
 
-            self.num_cols = best_cols;
-        }
+fn default_quit_action() -> String {
+    "cd".to_string()
+}
 
-        // Ensure selected index is valid after layout change
-        if !self.entries.is_empty() {
-            self.selected = self.selected.min(self.entries.len().saturating_sub(1));
-        }
+fn default_alt_quit_action() -> String {
+    "finder".to_string()
+}
 
-        Ok(())
-    }
+fn default_escape_action() -> String {
+    "none".to_string()
+}
 
-    fn draw(&mut self) -> io::Result<()> {
-        let mut stdout = io::stdout();
+fn default_grep_max_results() -> usize {
+    1000
+}
 
-        let (width, height) = terminal::size()?;
+fn default_find_max_depth() -> usize {
+    6
+}
 
-        // Ensure layout is calculated (deferred from new() for faster startup)
-        self.update_layout()?;
+fn default_ide_command() -> String {
+    "code".to_string()
+}
 
+fn default_history_prioritize_basename() -> bool {
+    true
+}
 
-        // Calculate split if in preview mode
-        let split_line = if self.preview_mode {
-            self.start_row + ((height - self.start_row) as f32 * (1.0 - self.preview_split_ratio)) as u16
-        } else {
-            height
-        };
-        let display_height = split_line;
+fn default_preview_split_ratio() -> f32 {
+    0.5
+}
 
-        // 1. Clear from start row downward (execute for immediate effect to reduce flicker)
-        execute!(stdout, cursor::MoveTo(0, self.start_row))?;
-        execute!(stdout, terminal::Clear(ClearType::FromCursorDown))?;
+fn default_case_sensitive_search() -> bool {
+    false
+}
 
-        // Display directory path with color config
-        queue!(stdout, cursor::MoveTo(0, self.start_row))?;
+fn default_show_dir_slash() -> bool {
+    true
+}
 
-        let fg_color = self.color_config.parse_fg_color();
-        let bg_color = self.color_config.parse_bg_color();
+// Directory history for frecency-based navigation
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct DirectoryEntry {
+    path: String,
+    visit_count: usize,
+    last_accessed: u64,  // Unix timestamp
+    first_accessed: u64, // Unix timestamp
+}
 
-        let display_path = self.format_path_display();
+impl DirectoryEntry {
+    fn new(path: String) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
 
-        if fg_color.is_none() && bg_color.is_none() {
-            // Use reverse attribute (default)
-            queue!(
-                stdout,
-                crossterm::style::SetAttribute(crossterm::style::Attribute::Reverse),
-                Print(format!(" {} ", display_path)),
-                crossterm::style::SetAttribute(crossterm::style::Attribute::Reset)
-            )?;
-        } else {
-            // Use specified colors
-            if let Some(fg) = fg_color {
-                queue!(stdout, SetForegroundColor(fg))?;
-            }
-            if let Some(bg) = bg_color {
-                queue!(stdout, crossterm::style::SetBackgroundColor(bg))?;
-            }
-            queue!(stdout, Print(format!(" {} ", display_path)))?;
-            queue!(stdout, ResetColor)?;
+        DirectoryEntry {
+            path,
+            visit_count: 1,
+            last_accessed: now,
+            first_accessed: now,
         }
+    }
 
-        // Explicitly move to next line
-        queue!(stdout, cursor::MoveTo(0, self.start_row + 1))?;
-
-        // Display breadcrumbs (currently hidden)
-        // for folder in &self.breadcrumbs {
-        //     queue!(
-        //         stdout,
-        //         SetForegroundColor(Color::DarkGrey),
-        //         Print(format!("← {}/", folder)),
-        //         ResetColor,
-        //         Print("\n")
-        //     )?;
-        // }
+    fn update_visit(&mut self) {
+        self.visit_count += 1;
+        self.last_accessed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+    }
 
-        let start_content_row = self.start_row + 1; // + self.breadcrumbs.len() as u16;
-        // Move cursor to where file list starts.
-        queue!(stdout, cursor::MoveTo(0, start_content_row))?;
+    fn frecency_score(&self) -> f64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
 
-        // Display entries - either history mode or normal file browsing
-        if self.history_mode {
-            // History mode - zoxide-style directory jump
-            // Show query prompt
-            let query_color = self.color_config.parse_history_query_fg().unwrap_or(Color::Cyan);
-            queue!(
-                stdout,
-                SetForegroundColor(query_color),
-                Print(format!("Jump to: {}", self.history_query)),
-                Print("█"), // Cursor
-                ResetColor,
-                Print("\r\n\r\n")
-            )?;
+        let age_seconds = now.saturating_sub(self.last_accessed) as f64;
+        let age_hours = age_seconds / 3600.0;
 
-            // Show filtered results
-            if !self.history_filtered.is_empty() {
-                let max_display_rows = (display_height as usize).saturating_sub(self.start_row as usize).saturating_sub(4);
-                let start_idx = self.scroll_offset;
-                let end_idx = (start_idx + max_display_rows).min(self.history_filtered.len());
+        // Decay function: recent visits are weighted more heavily
+        let recency_weight = if age_hours < 1.0 {
+            4.0
+        } else if age_hours < 24.0 {
+            2.0
+        } else if age_hours < 168.0 { // 1 week
+            1.0
+        } else {
+            0.5
+        };
 
-                for idx in start_idx..end_idx {
-                    let entry = &self.history_filtered[idx];
-                    let is_selected = idx == self.selected;
+        (self.visit_count as f64) * recency_weight
+    }
+}
 
-                    let prefix = if is_selected { "> " } else { "  " };
+#[derive(Serialize, Deserialize, Default)]
+struct DirectoryHistory {
+    entries: HashMap<String, DirectoryEntry>,
+}
 
-                    // Print prefix with cursor color
-                    if is_selected {
-                        if let Some(cursor_color) = self.color_config.parse_cursor_fg() {
-                            queue!(stdout, SetForegroundColor(cursor_color))?;
-                        } else {
-                            queue!(stdout, SetForegroundColor(Color::Green))?;
-                        }
-                        if let Some(cursor_bg) = self.color_config.parse_cursor_bg() {
-                            queue!(stdout, crossterm::style::SetBackgroundColor(cursor_bg))?;
-                        }
-                    }
-                    queue!(stdout, Print(prefix))?;
+impl DirectoryHistory {
+    fn load() -> Self {
+        if let Ok(home) = env::var("HOME") {
+            let history_path = PathBuf::from(home).join(".config/ils/history.json");
+            if let Ok(content) = fs::read_to_string(&history_path) {
+                if let Ok(history) = serde_json::from_str(&content) {
+                    return history;
+                }
+            }
+        }
+        DirectoryHistory::default()
+    }
 
-                    // Display path with highlighting
-                    let path_color = if is_selected {
-                        self.color_config.parse_selected_fg().unwrap_or(Color::Green)
-                    } else {
-                        self.color_config.parse_history_path_fg().unwrap_or(Color::Cyan)
-                    };
+    fn save(&self) -> io::Result<()> {
+        if let Ok(home) = env::var("HOME") {
+            let config_dir = PathBuf::from(&home).join(".config/ils");
+            fs::create_dir_all(&config_dir)?;
+            let history_path = config_dir.join("history.json");
+            let content = serde_json::to_string_pretty(self)
+                .map_err(io::Error::other)?;
+            write_atomic(&history_path, &content)?;
+        }
+        Ok(())
+    }
 
-                    // Find and highlight the query match
-                    let query_lower = self.history_query.to_lowercase();
-                    let path_lower = entry.path.to_lowercase();
+    fn record_visit(&mut self, path: &Path, max_storage: usize) {
+        let path_str = path.to_string_lossy().to_string();
 
-                    if let Some(match_pos) = path_lower.find(&query_lower) {
-                        // Print before match
-                        queue!(stdout, SetForegroundColor(path_color), Print(&entry.path[..match_pos]))?;
+        if let Some(entry) = self.entries.get_mut(&path_str) {
+            entry.update_visit();
+        } else {
+            self.entries.insert(path_str.clone(), DirectoryEntry::new(path_str));
+        }
 
-                        // Print matched part with highlight
-                        let match_end = match_pos + self.history_query.len();
-                        queue!(
-                            stdout,
-                            crossterm::style::SetAttribute(crossterm::style::Attribute::Bold),
-                            SetForegroundColor(self.color_config.parse_fuzzy_highlight_fg().unwrap_or(Color::Yellow)),
-                            Print(&entry.path[match_pos..match_end]),
-                            crossterm::style::SetAttribute(crossterm::style::Attribute::Reset)
-                        )?;
+        // Cleanup old entries every 100 visits (probabilistic)
+        if self.entries.len().is_multiple_of(100) {
+            self.cleanup_old_entries(max_storage);
+        }
+    }
 
-                        // Print after match
-                        queue!(stdout, SetForegroundColor(path_color), Print(&entry.path[match_end..]))?;
-                    } else {
-                        queue!(stdout, SetForegroundColor(path_color), Print(&entry.path))?;
-                    }
+    fn get_sorted_by_frecency(&self) -> Vec<DirectoryEntry> {
+        let mut entries: Vec<DirectoryEntry> = self.entries.values().cloned().collect();
+        entries.sort_by(|a, b| {
+            b.frecency_score().partial_cmp(&a.frecency_score()).unwrap()
+        });
+        // Limit to top 1000 entries for performance
+        entries.truncate(1000);
+        entries
+    }
 
-                    // Show visit count if setting is enabled
-                    if self.settings.show_history_visit_count {
-                        let count_color = self.color_config.parse_history_count_fg().unwrap_or(Color::DarkGrey);
-                        queue!(
-                            stdout,
-                            ResetColor,
-                            SetForegroundColor(count_color),
-                            Print(format!(" ({})", entry.visit_count)),
-                            ResetColor
-                        )?;
-                    }
+    fn cleanup_old_entries(&mut self, max_storage: usize) {
+        // Remove entries older than 90 days with no recent visits
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let ninety_days = 90 * 24 * 60 * 60;
 
-                    queue!(stdout, ResetColor, Print("\r\n"))?;
-                }
-            }
-        } else if self.entries.is_empty() {
-            queue!(
-                stdout,
-                SetForegroundColor(Color::Yellow),
-                Print("  (empty directory)\n"),
-                ResetColor
-            )?;
-        } else {
-            // Fixed dimensions for grid
-            #[allow(dead_code)]
-            const CELL_WIDTH: usize = 22;
-            const NAME_WIDTH: usize = 20;
+        self.entries.retain(|_, entry| {
+            let age = now.saturating_sub(entry.last_accessed);
+            age < ninety_days || entry.visit_count > 5
+        });
 
-            let max_display_rows = (display_height as usize).saturating_sub(self.start_row as usize).saturating_sub(2); // + self.breadcrumbs.len());
-            let total_rows = (self.entries.len() + self.num_cols - 1) / self.num_cols;
+        // If still too many entries, keep only top N by frecency
+        if self.entries.len() > max_storage {
+            let mut sorted: Vec<_> = self.entries.iter().collect();
+            sorted.sort_by(|a, b| {
+                b.1.frecency_score().partial_cmp(&a.1.frecency_score()).unwrap()
+            });
 
-            // Use scroll_offset to show the right portion (works for both list and grid mode)
-            let start_row = self.scroll_offset;
-            let end_row = (start_row + max_display_rows).min(total_rows);
-            let num_rows = end_row - start_row;
+            let to_keep: std::collections::HashSet<String> = sorted
+                .iter()
+                .take(max_storage)
+                .map(|(k, _)| k.to_string())
+                .collect();
 
-            for row in start_row..end_row {
-                for col in 0..self.num_cols {
-                    let idx = row * self.num_cols + col;
+            self.entries.retain(|k, _| to_keep.contains(k));
+        }
+    }
+}
 
-                    if idx >= self.entries.len() {
-                        // Don't print padding for empty cells, just break
-                        break;
-                    }
+/// Where a browser tab is and what it shows, kept while another tab is active.
+/// Clipboard, marks and undo stay shared, so copying in one tab and pasting in
+/// another works.
+#[derive(Clone)]
+struct Tab {
+    dir: PathBuf,
+    selected: Option<PathBuf>,
+    scroll_offset: usize,
+    preview_mode: bool,
+}
 
-                    let entry = &self.entries[idx];
-                    let is_selected = idx == self.selected;
-                    let is_dir = entry.is_dir();
+/// Directories bookmarked under a single letter, kept in ~/.config/ils/bookmarks.toml
+#[derive(Serialize, Deserialize, Default)]
+struct Bookmarks {
+    #[serde(default)]
+    bookmarks: BTreeMap<String, String>,
+}
 
-                    let name = entry.file_name()
-                        .and_then(|n| n.to_str())
-                        .unwrap_or("?");
+impl Bookmarks {
+    fn path() -> Option<PathBuf> {
+        env::var("HOME").ok().map(|home| PathBuf::from(home).join(".config/ils/bookmarks.toml"))
+    }
 
-                    // Truncate name if needed
-                    let mut display_name = if is_dir && self.show_dir_slash {
-                        format!("{}/", name)
-                    } else {
-                        name.to_string()
-                    };
+    fn load() -> Self {
+        Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| toml::from_str(&content).ok())
+            .unwrap_or_default()
+    }
 
-                    // In grid mode or list mode with info, truncate to NAME_WIDTH
-                    // In list mode without info, don't truncate
-                    if (!self.list_mode || (self.list_mode && self.list_info_mode > 0)) && display_name.len() > NAME_WIDTH {
-                        // Find a valid UTF-8 boundary for truncation
-                        let mut truncate_at = NAME_WIDTH - 1;
-                        while truncate_at > 0 && !display_name.is_char_boundary(truncate_at) {
-                            truncate_at -= 1;
-                        }
-                        display_name.truncate(truncate_at);
-                        display_name.push('~');
-                    }
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = Self::path() else { return Ok(()) };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomic(&path, &toml::to_string(self).map_err(io::Error::other)?)
+    }
+}
 
-                    let prefix = if is_selected { "> " } else { "  " };
+/// Labels given to files and directories with the edit_tags key, kept in
+/// ~/.config/ils/tags.toml by path
+#[derive(Serialize, Deserialize, Default)]
+struct Tags {
+    #[serde(default)]
+    tags: BTreeMap<String, Vec<String>>,
+}
 
-                    // Check if this entry matches the fuzzy query
-                    let query_len = if self.fuzzy_mode && !self.fuzzy_query.is_empty() {
-                        let (query_cmp, name_cmp) = if self.settings.case_sensitive_search {
-                            (self.fuzzy_query.clone(), name.to_string())
-                        } else {
-                            (self.fuzzy_query.to_lowercase(), name.to_lowercase())
-                        };
-                        if name_cmp.starts_with(&query_cmp) {
-                            self.fuzzy_query.len().min(display_name.len())
-                        } else {
-                            0
-                        }
-                    } else {
-                        0
-                    };
+impl Tags {
+    fn path() -> Option<PathBuf> {
+        env::var("HOME").ok().map(|home| PathBuf::from(home).join(".config/ils/tags.toml"))
+    }
 
-                    // Print prefix with cursor color
-                    if is_selected {
-                        // Apply cursor colors for the ">" prefix
-                        if let Some(cursor_color) = self.color_config.parse_cursor_fg() {
-                            queue!(stdout, SetForegroundColor(cursor_color))?;
-                        } else {
-                            queue!(stdout, SetForegroundColor(Color::Green))?;
-                        }
-                        if let Some(cursor_bg) = self.color_config.parse_cursor_bg() {
-                            queue!(stdout, crossterm::style::SetBackgroundColor(cursor_bg))?;
-                        }
-                    }
-                    queue!(stdout, Print(prefix))?;
+    fn load() -> Self {
+        Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| toml::from_str(&content).ok())
+            .unwrap_or_default()
+    }
 
-                    // Now set the colors for the filename
-                    if is_selected {
-                        // Apply selected colors
-                        if let Some(fg) = self.color_config.parse_selected_fg() {
-                            queue!(stdout, SetForegroundColor(fg))?;
-                        } else {
-                            queue!(stdout, SetForegroundColor(Color::Green))?;
-                        }
-                        if let Some(bg) = self.color_config.parse_selected_bg() {
-                            queue!(stdout, crossterm::style::SetBackgroundColor(bg))?;
-                        }
-                    } else if is_dir {
-                        // Apply directory colors
-                        if let Some(fg) = self.color_config.parse_directory_fg() {
-                            queue!(stdout, SetForegroundColor(fg))?;
-                        } else {
-                            queue!(stdout, SetForegroundColor(Color::Blue))?;
-                        }
-                        if let Some(bg) = self.color_config.parse_directory_bg() {
-                            queue!(stdout, crossterm::style::SetBackgroundColor(bg))?;
-                        }
-                    } else {
-                        // Apply file colors
-                        if let Some(fg) = self.color_config.parse_file_fg() {
-                            queue!(stdout, SetForegroundColor(fg))?;
-                        } else {
-                            queue!(stdout, ResetColor)?;
-                        }
-                        if let Some(bg) = self.color_config.parse_file_bg() {
-                            queue!(stdout, crossterm::style::SetBackgroundColor(bg))?;
-                        }
-                    }
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = Self::path() else { return Ok(()) };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomic(&path, &toml::to_string(self).map_err(io::Error::other)?)
+    }
 
-                    // Print name with fuzzy match highlighting
-                    if query_len > 0 {
-                        let highlight_len = query_len.min(display_name.len());
+    fn of(&self, path: &Path) -> &[String] {
+        self.tags.get(path.to_string_lossy().as_ref()).map_or(&[], |tags| tags.as_slice())
+    }
 
-                        // Print matching part with fuzzy highlight colors
-                        queue!(stdout, crossterm::style::SetAttribute(crossterm::style::Attribute::Bold))?;
-                        if let Some(fg) = self.color_config.parse_fuzzy_highlight_fg() {
-                            queue!(stdout, SetForegroundColor(fg))?;
-                        } else {
-                            queue!(stdout, SetForegroundColor(Color::Rgb { r: 255, g: 255, b: 0 }))?;
-                        }
-                        if let Some(bg) = self.color_config.parse_fuzzy_highlight_bg() {
-                            queue!(stdout, crossterm::style::SetBackgroundColor(bg))?;
-                        } else {
-                            queue!(stdout, crossterm::style::SetBackgroundColor(Color::Rgb { r: 50, g: 50, b: 50 }))?;
-                        }
-                        queue!(stdout, Print(&display_name[..highlight_len]))?;
-                        queue!(stdout, crossterm::style::SetAttribute(crossterm::style::Attribute::Reset))?;
+    fn set(&mut self, path: &Path, tags: Vec<String>) {
+        let key = path.to_string_lossy().to_string();
+        if tags.is_empty() {
+            self.tags.remove(&key);
+        } else {
+            self.tags.insert(key, tags);
+        }
+    }
 
-                        // Reset to original color for rest
-                        if is_selected {
-                            if let Some(fg) = self.color_config.parse_selected_fg() {
-                                queue!(stdout, SetForegroundColor(fg))?;
-                            } else {
-                                queue!(stdout, SetForegroundColor(Color::Green))?;
-                            }
-                            if let Some(bg) = self.color_config.parse_selected_bg() {
-                                queue!(stdout, crossterm::style::SetBackgroundColor(bg))?;
-                            }
-                        } else if is_dir {
-                            if let Some(fg) = self.color_config.parse_directory_fg() {
-                                queue!(stdout, SetForegroundColor(fg))?;
-                            } else {
-                                queue!(stdout, SetForegroundColor(Color::Blue))?;
-                            }
-                        } else {
-                            queue!(stdout, ResetColor)?;
-                        }
+    /// Carry the tags of renamed or moved entries, and of everything inside a
+    /// renamed directory, over to their new paths. Returns whether any moved.
+    fn follow_renames(&mut self, plan: &[(PathBuf, PathBuf)]) -> bool {
+        // Take every old key out before putting any back, so swapped names keep their own tags
+        let mut moved = Vec::new();
+        for (old, new) in plan {
+            let old = old.to_string_lossy().to_string();
+            let inside = format!("{}/", old);
+            let keys: Vec<String> = self.tags.keys().filter(|key| **key == old || key.starts_with(&inside)).cloned().collect();
+            for key in keys {
+                if let Some(tags) = self.tags.remove(&key) {
+                    moved.push((format!("{}{}", new.to_string_lossy(), &key[old.len()..]), tags));
+                }
+            }
+        }
+        let any = !moved.is_empty();
+        self.tags.extend(moved);
+        any
+    }
 
-                        // Print rest of name, padded
-                        let rest = &display_name[highlight_len..];
-                        let padding = NAME_WIDTH - display_name.len();
-                        queue!(stdout, Print(format!("{}{}", rest, " ".repeat(padding))))?;
-                    } else {
-                        // No match, print normally with padding
-                        queue!(stdout, Print(format!("{:<width$}", display_name, width = NAME_WIDTH)))?;
-                    }
+    /// Drop the tags of deleted entries and of everything inside them.
+    /// Returns whether any were dropped.
+    fn forget(&mut self, paths: &[PathBuf]) -> bool {
+        let before = self.tags.len();
+        for path in paths {
+            let path = path.to_string_lossy().to_string();
+            let inside = format!("{}/", path);
+            self.tags.retain(|key, _| *key != path && !key.starts_with(&inside));
+        }
+        self.tags.len() != before
+    }
+}
 
-                    queue!(stdout, ResetColor)?;
+/// The color a tag is shown in, the same for a name wherever it appears.
+fn tag_color(tag: &str) -> Color {
+    const COLORS: [Color; 6] = [Color::Red, Color::Green, Color::Yellow, Color::Blue, Color::Magenta, Color::Cyan];
+    let hash = tag.bytes().fold(0usize, |hash, byte| hash.wrapping_mul(31).wrapping_add(byte as usize));
+    COLORS[hash % COLORS.len()]
+}
 
-                    // In list mode, show info after the name
-                    if self.list_mode && self.list_info_mode > 0 {
-                        if self.list_info_mode == 1 {
-                            // Show date (modified or created based on toggle)
-                            if let Ok(metadata) = entry.metadata() {
-                                let time_result = if self.show_created_date {
-                                    metadata.created()
-                                } else {
-                                    metadata.modified()
-                                };
+/// Bookmarks and shortcuts as written by `ils bookmarks export`, with paths under
+/// the home directory as ~/... so they carry over to another machine
+#[derive(Serialize, Deserialize, Default)]
+struct BookmarkExport {
+    #[serde(default)]
+    bookmarks: BTreeMap<String, String>,
+    #[serde(default)]
+    shortcuts: BTreeMap<String, String>,
+}
 
-                                if let Ok(time) = time_result {
-                                    use std::time::SystemTime;
-                                    if let Ok(elapsed) = SystemTime::now().duration_since(time) {
-                                        let secs = elapsed.as_secs();
-                                        let three_months = 3 * 30 * 24 * 60 * 60; // ~3 months in seconds
+/// Home directory paths written as ~/..., for files meant for other machines.
+fn home_relative(path: &str) -> String {
+    match env::var("HOME") {
+        Ok(home) if !home.is_empty() && (path == home || path.starts_with(&format!("{}/", home))) => format!("~{}", &path[home.len()..]),
+        _ => path.to_string(),
+    }
+}
 
-                                        let date_str = if self.settings.verbose_dates || secs < three_months {
-                                            // Use relative format
-                                            if secs < 60 {
-                                                format!("{:>9}s", secs)
-                                            } else if secs < 3600 {
-                                                format!("{:>8}min", secs / 60)
-                                            } else if secs < 86400 {
-                                                format!("{:>8}hrs", secs / 3600)
-                                            } else if secs < 2592000 { // 30 days
-                                                format!("{:>7}days", secs / 86400)
-                                            } else if secs < 31536000 { // 365 days
-                                                format!("{:>6}month", secs / 2592000)
-                                            } else {
-                                                format!("{:>7}year", secs / 31536000)
-                                            }
-                                        } else {
-                                            // Use "Aug '25" format for dates > 3 months
-                                            use std::time::UNIX_EPOCH;
-                                            if let Ok(duration) = time.duration_since(UNIX_EPOCH) {
-                                                let timestamp = duration.as_secs();
+/// Merge `incoming` entries into `existing`. An entry already there under the same
+/// name, or the same path under another name, is a duplicate and skipped; a name
+/// taken by a different path keeps the local one. Returns what was added and
+/// the names that clashed.
+fn merge_named_paths(existing: &mut BTreeMap<String, String>, incoming: BTreeMap<String, String>) -> (usize, Vec<String>) {
+    let mut added = 0;
+    let mut clashes = Vec::new();
+    for (name, path) in incoming {
+        match existing.get(&name) {
+            Some(local) if *local == path => {}
+            Some(local) => clashes.push(format!("{} (here {}, imported {})", name, local, path)),
+            None if existing.values().any(|local| *local == path) => {}
+            None => {
+                existing.insert(name, path);
+                added += 1;
+            }
+        }
+    }
+    (added, clashes)
+}
 
-                                                // Special case: Unix epoch (likely means unknown/unset date)
-                                                if timestamp == 0 {
-                                                    format!("{:>9}", "unknown")
-                                                } else {
-                                                    // Proper date calculation accounting for leap years
-                                                let mut year = 1970;
-                                                let mut remaining_secs = timestamp;
+impl BookmarkExport {
+    /// Write the bookmarks and config shortcuts to `file`, or print them.
+    fn export(file: Option<&str>) -> io::Result<()> {
+        let (config, _) = Config::load();
+        let export = BookmarkExport {
+            bookmarks: Bookmarks::load().bookmarks.into_iter().map(|(letter, path)| (letter, home_relative(&path))).collect(),
+            shortcuts: config.shortcuts,
+        };
+        let content = toml::to_string_pretty(&export).map_err(io::Error::other)?;
+        match file {
+            Some(file) => {
+                fs::write(file, content)?;
+                println!("Exported {} bookmarks and {} shortcuts to {}", export.bookmarks.len(), export.shortcuts.len(), file);
+            }
+            None => print!("{}", content),
+        }
+        Ok(())
+    }
 
-                                                // Skip through years
-                                                loop {
-                                                    let year_secs = if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 {
-                                                        366 * 86400 // leap year
-                                                    } else {
-                                                        365 * 86400 // normal year
-                                                    };
+    /// Merge the bookmarks and shortcuts of an exported file into this machine's.
+    fn import(file: &str) -> io::Result<()> {
+        let content = fs::read_to_string(file)?;
+        let import: BookmarkExport = toml::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
 
-                                                    if remaining_secs < year_secs {
-                                                        break;
-                                                    }
-                                                    remaining_secs -= year_secs;
-                                                    year += 1;
-                                                }
+        let mut bookmarks = Bookmarks::load();
+        let incoming = import.bookmarks.into_iter().map(|(letter, path)| (letter, expand_path_vars(&path))).collect();
+        let (bookmarks_added, mut clashes) = merge_named_paths(&mut bookmarks.bookmarks, incoming);
+        if bookmarks_added > 0 {
+            bookmarks.save()?;
+        }
 
-                                                // Calculate month
-                                                let is_leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
-                                                let days_in_months = if is_leap {
-                                                    [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31]
-                                                } else {
-                                                    [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31]
-                                                };
+        let (mut shortcuts_added, mut shortcut_clashes) = (0, Vec::new());
+        Config::update_shortcuts(|shortcuts| (shortcuts_added, shortcut_clashes) = merge_named_paths(shortcuts, import.shortcuts))?;
+        clashes.extend(shortcut_clashes);
 
-                                                let mut remaining_days = (remaining_secs / 86400) as usize;
-                                                let mut month_idx = 0;
+        println!("Imported {} bookmarks and {} shortcuts from {}", bookmarks_added, shortcuts_added, file);
+        if !clashes.is_empty() {
+            println!("\nKept these, which are set differently here:");
+            for clash in clashes {
+                println!("  {}", clash);
+            }
+        }
+        Ok(())
+    }
+}
 
-                                                for (i, &days) in days_in_months.iter().enumerate() {
-                                                    if remaining_days < days {
-                                                        month_idx = i;
-                                                        break;
-                                                    }
-                                                    remaining_days -= days;
-                                                }
+/// Most preview scroll positions kept between sessions; the least recently
+/// changed are dropped first.
+const PREVIEW_POSITIONS_MAX: usize = 500;
 
-                                                let months = ["Jan", "Feb", "Mar", "Apr", "May", "Jun",
-                                                             "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
-                                                format!("{:>9}", format!("{} '{:02}", months[month_idx], year % 100))
-                                                }
-                                            } else {
-                                                format!("{:>9}s", secs)
-                                            }
-                                        };
+/// Preview scroll positions saved across sessions, keyed by file path, with when
+/// each was last changed
+#[derive(Serialize, Deserialize, Default)]
+struct PreviewPositions {
+    files: HashMap<String, (usize, u64)>,
+}
 
-                                        let date_color = if self.show_created_date {
-                                            Color::DarkCyan // Created date
-                                        } else {
-                                            Color::DarkGrey // Modified date
-                                        };
-                                        queue!(
-                                            stdout,
-                                            SetForegroundColor(date_color),
-                                            Print(format!("  {}", date_str)),
-                                            ResetColor
-                                        )?;
-                                    }
-                                }
-                            }
-                        } else if self.list_info_mode == 2 {
-                            // Show permissions
-                            #[cfg(unix)]
-                            {
-                                use std::os::unix::fs::PermissionsExt;
-                                if let Ok(metadata) = entry.metadata() {
-                                    let mode = metadata.permissions().mode();
-                                    let perms = format!(
-                                        "{}{}{}{}{}{}{}{}{}",
-                                        if mode & 0o400 != 0 { 'r' } else { '-' },
-                                        if mode & 0o200 != 0 { 'w' } else { '-' },
-                                        if mode & 0o100 != 0 { 'x' } else { '-' },
-                                        if mode & 0o040 != 0 { 'r' } else { '-' },
-                                        if mode & 0o020 != 0 { 'w' } else { '-' },
-                                        if mode & 0o010 != 0 { 'x' } else { '-' },
-                                        if mode & 0o004 != 0 { 'r' } else { '-' },
-                                        if mode & 0o002 != 0 { 'w' } else { '-' },
-                                        if mode & 0o001 != 0 { 'x' } else { '-' },
-                                    );
-                                    queue!(
-                                        stdout,
-                                        SetForegroundColor(Color::DarkGrey),
-                                        Print(format!("  {}", perms)),
-                                        ResetColor
-                                    )?;
-                                }
-                            }
-                        } else if self.list_info_mode == 3 {
-                            // Show size (with cached dir size)
-                            if let Ok(metadata) = entry.metadata() {
-                                let size = if is_dir {
-                                    // Use cached size or show loading
-                                    if let Some(&dir_size) = self.dir_size_cache.get(entry) {
-                                        if dir_size < 1024 {
-                                            format!("{:>8} B", dir_size)
-                                        } else if dir_size < 1024 * 1024 {
-                                            format!("{:>7.1} K", dir_size as f64 / 1024.0)
-                                        } else if dir_size < 1024 * 1024 * 1024 {
-                                            format!("{:>7.1} M", dir_size as f64 / (1024.0 * 1024.0))
-                                        } else {
-                                            format!("{:>7.1} G", dir_size as f64 / (1024.0 * 1024.0 * 1024.0))
-                                        }
-                                    } else if self.calculating_sizes {
-                                        String::from("  calc...")
-                                    } else {
-                                        String::from("    <DIR>")
-                                    }
-                                } else {
-                                    let len = metadata.len();
-                                    if len < 1024 {
-                                        format!("{:>8} B", len)
-                                    } else if len < 1024 * 1024 {
-                                        format!("{:>7.1} K", len as f64 / 1024.0)
-                                    } else if len < 1024 * 1024 * 1024 {
-                                        format!("{:>7.1} M", len as f64 / (1024.0 * 1024.0))
-                                    } else {
-                                        format!("{:>7.1} G", len as f64 / (1024.0 * 1024.0 * 1024.0))
-                                    }
-                                };
-                                queue!(
-                                    stdout,
-                                    SetForegroundColor(Color::DarkGrey),
-                                    Print(format!("  {}", size)),
-                                    ResetColor
-                                )?;
-                            }
-                        }
-                    }
-                }
-                queue!(stdout, Print("\r\n"))?;
-            }
+impl PreviewPositions {
+    fn path() -> Option<PathBuf> {
+        env::var("HOME").ok().map(|home| PathBuf::from(home).join(".config/ils/preview_positions.json"))
+    }
 
-            // Display config error if present (below the entries)
-            if let Some(error) = &self.config_error {
-                queue!(stdout, cursor::MoveTo(0, start_content_row + num_rows as u16 + 1))?;
-                queue!(
-                    stdout,
-                    SetForegroundColor(Color::Red),
-                    Print(format!("⚠ {}", error)),
-                    ResetColor
-                )?;
-            }
-        }
+    fn load() -> Self {
+        Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
 
-        // 3. Draw separator and preview if in preview mode
-        if self.preview_mode {
-            queue!(stdout, cursor::MoveTo(0, split_line))?;
+    fn lines(&self) -> HashMap<PathBuf, usize> {
+        self.files.iter().map(|(path, (line, _))| (PathBuf::from(path), *line)).collect()
+    }
 
-            if let Some(border_color) = self.color_config.parse_preview_border_fg() {
-                queue!(stdout, SetForegroundColor(border_color))?;
+    /// Merge the positions that changed since `before` into the stored ones and
+    /// write them back. Re-reading first keeps what other sessions saved meanwhile.
+    fn save_changes(before: &HashMap<PathBuf, usize>, after: &HashMap<PathBuf, usize>) -> io::Result<()> {
+        let changed: Vec<_> = after.iter().filter(|(path, line)| before.get(*path) != Some(*line)).collect();
+        if changed.is_empty() {
+            return Ok(());
+        }
+        let Some(path) = Self::path() else { return Ok(()) };
+
+        let mut stored = Self::load();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        for (file, &line) in changed {
+            let key = file.to_string_lossy().to_string();
+            if line == 0 {
+                stored.files.remove(&key);
             } else {
-                queue!(stdout, SetForegroundColor(Color::DarkGrey))?;
+                stored.files.insert(key, (line, now));
+            }
+        }
+        if stored.files.len() > PREVIEW_POSITIONS_MAX {
+            let mut by_age: Vec<_> = stored.files.iter().map(|(file, (_, time))| (*time, file.clone())).collect();
+            by_age.sort();
+            for (_, file) in by_age.into_iter().take(stored.files.len() - PREVIEW_POSITIONS_MAX) {
+                stored.files.remove(&file);
             }
-            queue!(stdout, Print("─".repeat(width as usize)), ResetColor)?;
+        }
 
-            // Draw preview
-            if let Some(selected) = self.get_selected_path() {
-                if selected.is_dir() {
-                    // Directory preview - show contents and stats
-                    let preview_lines = (height - split_line - 3) as usize;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomic(&path, &serde_json::to_string(&stored).map_err(io::Error::other)?)
+    }
+}
 
-                    if let Ok(entries) = fs::read_dir(&selected) {
-                        let mut dirs = 0;
-                        let mut files = 0;
-                        let mut total_size: u64 = 0;
-                        let mut has_uncached_dirs = false;
-                        let mut items: Vec<(String, bool)> = Vec::new();
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            exit_after_edit: default_exit_after_edit(),
+            editor_confirm_mb: default_editor_confirm_mb(),
+            preview_scroll_amount: default_preview_scroll_amount(),
+            show_hidden: default_show_hidden(),
+            hide_gitignored: default_hide_gitignored(),
+            preview_on_start: default_preview_on_start(),
+            view_on_start: default_view_on_start(),
+            list_column_on_start: default_list_column_on_start(),
+            line_numbers_on_start: default_line_numbers_on_start(),
+            preview_split_ratio: default_preview_split_ratio(),
+            case_sensitive_search: default_case_sensitive_search(),
+            show_dir_slash: default_show_dir_slash(),
+            jump_amount: default_jump_amount(),
+            show_tilde_for_home: default_show_tilde_for_home(),
+            verbose_dates: default_verbose_dates(),
+            debug_show_welcome: default_debug_show_welcome(),
+            wrapper_validation_cache_valid: default_wrapper_validation_cache_valid(),
+            show_help_on_start: default_show_help_on_start(),
+            show_history_visit_count: default_show_history_visit_count(),
+            history_max_results: default_history_max_results(),
+            history_max_storage: default_history_max_storage(),
+            history_prioritize_basename: default_history_prioritize_basename(),
+            keymap: default_keymap(),
+            theme: default_theme(),
+            ascii_mode: default_ascii_mode(),
+            locale: default_locale(),
+            show_item_counts: default_show_item_counts(),
+            mark_empty_and_unreadable: default_mark_empty_and_unreadable(),
+            show_project_badges: default_show_project_badges(),
+            sort_by: default_sort_by(),
+            sort_reverse: false,
+            group_by_type: default_group_by_type(),
+            show_git_status: default_show_git_status(),
+            filename_check: default_filename_check(),
+            show_disk_space: default_show_disk_space(),
+            auto_refresh: default_auto_refresh(),
+            preview_cache_entries: default_preview_cache_entries(),
+            preview_cache_mb: default_preview_cache_mb(),
+            dir_size_cache_entries: default_dir_size_cache_entries(),
+            preview_prefetch: default_preview_prefetch(),
+            image_protocol: default_image_protocol(),
+            scrolloff: default_scrolloff(),
+            sequence_timeout_ms: default_sequence_timeout_ms(),
+            enter_selects: default_enter_selects(),
+            preview_readme: default_preview_readme(),
+            strings_min_length: default_strings_min_length(),
+            desktop_notifications: default_desktop_notifications(),
+            desktop_notify_after_secs: default_desktop_notify_after_secs(),
+            path_output: default_path_output(),
+            quit_action: default_quit_action(),
+            alt_quit_action: default_alt_quit_action(),
+            escape_action: default_escape_action(),
+            on_exit: String::new(),
+            grep_max_results: default_grep_max_results(),
+            find_max_depth: default_find_max_depth(),
+            ide_command: default_ide_command(),
+        }
+    }
+}
 
-                        for entry in entries.filter_map(|e| e.ok()) {
-                            let path = entry.path();
-                            let is_dir = path.is_dir();
-                            let name = path.file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or("?")
-                                .to_string();
+// Unified config structure
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct Config {
+    #[serde(default)]
+    keybindings: Keybindings,
+    #[serde(default)]
+    colors: ColorConfig,
+    #[serde(default)]
+    settings: Settings,
+    #[serde(default)]
+    shortcuts: BTreeMap<String, String>, // Directory aliases (name -> path)
+    #[serde(default)]
+    openers: BTreeMap<String, String>, // Commands to open files with (glob or extension -> command)
+    #[serde(default)]
+    commands: BTreeMap<String, String>, // Shell commands bound to keys (key -> command)
+}
+
+impl Config {
+    fn load() -> (Self, Option<String>) {
+        if let Ok(home) = env::var("HOME") {
+            let config_path = PathBuf::from(home).join(".config/ils/config.toml");
+            if let Ok(content) = fs::read_to_string(&config_path) {
+                match Config::parse(&content) {
+                    Ok((config, warning)) => return (config, warning),
+                    Err(e) => {
+                        let error_msg = format!("Config error: {} - Using defaults. Press '?' for help.", e);
+                        eprintln!("\x1b[31mError loading config from {}: {}\x1b[0m", config_path.display(), e);
+                        eprintln!("\x1b[33mUsing default configuration. Run 'ils config' to fix.\x1b[0m");
+                        return (Config::default(), Some(error_msg));
+                    }
+                }
+            }
+        }
+        (Config::default(), None)
+    }
+
+    /// Parse config text, starting from the selected keymap and theme presets and
+    /// overlaying whatever the user set in [keybindings] and [colors].
+    fn parse(content: &str) -> Result<(Self, Option<String>), toml::de::Error> {
+        let raw: toml::Value = toml::from_str(content)?;
+        let mut config: Config = raw.clone().try_into()?;
+        let mut warning = None;
+
+        let keymap_base = Keybindings::preset(&config.settings.keymap).unwrap_or_else(|| {
+            warning = Some(format!("Unknown keymap '{}' - using wasd", config.settings.keymap));
+            Keybindings::default()
+        });
+        let theme_base = ColorConfig::preset(&config.settings.theme).unwrap_or_else(|| {
+            warning = Some(format!("Unknown theme '{}' - using default", config.settings.theme));
+            ColorConfig::default()
+        });
+
+        config.keybindings = overlay_table(&keymap_base, raw.get("keybindings"))?;
+        config.keybindings.commands = config.commands.clone();
+        config.colors = overlay_table(&theme_base, raw.get("colors"))?;
+        Ok((config, warning))
+    }
+
+    fn path() -> Option<PathBuf> {
+        if let Ok(home) = env::var("HOME") {
+            Some(PathBuf::from(home).join(".config/ils/config.toml"))
+        } else {
+            None
+        }
+    }
+
+    /// Change [settings] in place, leaving the user's other tables as written so
+    /// preset-derived keybindings and colors are not frozen into the file.
+    fn update_settings(update: impl FnOnce(&mut Settings)) -> io::Result<()> {
+        if let Some(config_path) = Config::path() {
+            if let Some(parent) = config_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let content = fs::read_to_string(&config_path).unwrap_or_default();
+            let raw: toml::Table = toml::from_str(&content).map_err(io::Error::other)?;
+
+            let mut settings: Settings = match raw.get("settings") {
+                Some(value) => value.clone().try_into().map_err(io::Error::other)?,
+                None => Settings::default(),
+            };
+            let old = toml::Table::try_from(&settings).map_err(io::Error::other)?;
+            update(&mut settings);
+            let new = toml::Table::try_from(&settings).map_err(io::Error::other)?;
+            if new == old {
+                return Ok(());
+            }
+
+            write_atomic(&config_path, &edit_toml_table(&content, "settings", &old, &new)?)?;
+        }
+        Ok(())
+    }
+
+    /// Change the [shortcuts] table of the saved config, leaving the rest as it is.
+    fn update_shortcuts(update: impl FnOnce(&mut BTreeMap<String, String>)) -> io::Result<()> {
+        let Some(config_path) = Config::path() else {
+            return Ok(());
+        };
+        if let Some(parent) = config_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = fs::read_to_string(&config_path).unwrap_or_default();
+        let raw: toml::Table = toml::from_str(&content).map_err(io::Error::other)?;
+
+        let mut shortcuts: BTreeMap<String, String> = match raw.get("shortcuts") {
+            Some(value) => value.clone().try_into().map_err(io::Error::other)?,
+            None => BTreeMap::new(),
+        };
+        let before = shortcuts.clone();
+        update(&mut shortcuts);
+        if shortcuts == before {
+            return Ok(());
+        }
+        let old = toml::Table::try_from(&before).map_err(io::Error::other)?;
+        let new = toml::Table::try_from(&shortcuts).map_err(io::Error::other)?;
+        write_atomic(&config_path, &edit_toml_table(&content, "shortcuts", &old, &new)?)
+    }
+
+    fn create_default() -> io::Result<()> {
+        Config::create_default_with("wasd", "default")
+    }
+
+    /// Write the commented default config. When a non-default preset is chosen,
+    /// the explicit [keybindings] / [colors] values are commented out so the
+    /// preset applies, and stay available as a reference for overrides.
+    fn create_default_with(keymap: &str, theme: &str) -> io::Result<()> {
+        if let Some(config_path) = Config::path() {
+            if let Some(parent) = config_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            let default_config = r##"# ILS Configuration File
+# This file contains all configuration for the ils file browser
+
+# ============================================================================
+# KEYBINDINGS
+# ============================================================================
+[keybindings]
+# Defaults come from the `keymap` preset in [settings]; values set here override it.
+
+# Navigation
+up = ['w']
+down = ['s']
+left = ['a']
+right = ['d']
+jump_up = ['W']
+jump_down = ['S']
+jump_left = ['A']
+jump_right = ['D']
+
+# Actions
+open = ['l']                    # Open file/directory
+select = ['<Enter>', 'k']       # Open file in $EDITOR, or cd into directory and exit
+back = ['j', 'b']              # Go back up one directory
+home = ['h']                    # Go to home directory
+quit = ['q']                    # Quit without cd
+quit_then_open_in_finder = ['Q'] # Quit and open current directory in Finder (Shift+q)
+print_path = ['Y']              # Quit and print the selected path, quoted for the shell
+help = ['?', '!']               # Toggle the help bar
+help_screen = ['H']             # Full help screen (scroll with arrows, type to search)
+messages = ['M']                # Recent messages and errors, including dismissed ones
+
+# Preview controls
+preview_toggle = ['P']          # Toggle preview pane (Shift+p)
+preview_up = ['i']             # Scroll preview up
+preview_down = ['o']           # Scroll preview down
+preview_left = ['<']           # Previewing a CSV or TSV table: scroll a column left
+preview_right = ['>']          # Previewing a CSV or TSV table: scroll a column right
+preview_page_up = ['I']        # Scroll preview up one page
+preview_page_down = ['O', ' '] # Scroll preview down one page (Space pages only while the preview is open)
+preview_height_decrease = ['-'] # Decrease preview pane height
+preview_height_increase = ['+'] # Increase preview pane height
+next_image = [']']             # Previewing an image: show the next image in the directory
+prev_image = ['[']             # Previewing an image: show the previous image
+
+# Other
+toggle_hidden = ['.']          # Toggle hidden files
+toggle_gitignored = ['G.']     # Toggle entries ignored by git
+toggle_markdown = ['<A-M>']    # Preview Markdown files rendered or as raw text
+fold_preview = ['<A-k>']       # Previewing JSON, YAML or TOML: fold or unfold the top-level entries
+sort = ['<C-s>']               # Cycle sorting: name, modified, size, size on disk, extension, natural
+sort_reverse = ['<C-r>']       # Reverse the sort order
+collapse_group = ['<A-t>']     # Sorted by extension: collapse the selected entry's group to its header
+expand_groups = ['<A-T>']      # Sorted by extension: expand the collapsed groups
+fuzzy_find = ['/']             # Enter fuzzy find mode
+filter = ['F']                 # Filter the list as you type (arrows move, Enter select, Esc cancel);
+                               # terms like >100MB, <10k, within:7d, after:2024-01-31 or before:30d
+                               # match on size and modification time, and tag:name on tags
+grep = ['R']                   # Search file contents below the current directory (Enter opens the match)
+find_files = ['L']             # Search file names in subdirectories too (Enter goes to the match)
+history_jump = ['f']           # Jump to frequently visited directory
+
+# Fuzzy find mode controls
+fuzzy_back = ['/']             # Go back one directory (in fuzzy mode)
+fuzzy_home = ['?']             # Go to home directory (in fuzzy mode)
+
+# Other
+toggle_mode = ['m']            # Toggle between list and grid mode
+filesystem_info = ['V']        # Show mount point, filesystem type, device and mount options
+file_info = ['u']              # Show the selected entry's size and size on disk
+diagnostics = ['`']            # Show cache usage
+gallery = ['T']                # Toggle the thumbnail gallery for image folders
+list_action = ['e']            # List mode: toggle date type, edit permissions, or calculate sizes
+dir_size = ['U']               # Calculate the size of just the selected directory, in the background
+rename = ['r']                 # Rename selected file
+bulk_rename = ['<A-r>']        # Rename the marked (or all) entries by editing their names in $EDITOR
+pattern_rename = ['<A-R>']     # Rename the marked (or selected) entries with s/pattern/replacement/
+edit_tags = ['#']              # Tag the selected or marked entries, space separated (saved in tags.toml)
+next_sibling = ['n']           # Go to next sibling directory
+match_next = ['n']             # After a find or filter, go to the next match instead
+match_prev = ['N']             # After a find or filter, go to the previous match instead
+prev_sibling = ['N']           # Go to previous sibling directory (Shift+n)
+copy = ['c']                   # Copy selected file to clipboard
+mark = ['t']                   # Mark entries for copy, trash and delete
+clear_copy = ['C']             # Clear the copy selection and marks
+paste = ['v']                  # Paste from clipboard
+trash = ['x']                  # Move to trash
+trash_browse = ['<A-x>']       # Browse the trash, with its total size in the path bar
+empty_trash = ['<A-X>']        # Permanently delete everything in the trash (asks first)
+delete = ['X']                 # Permanently delete (Shift+x)
+extract = ['<A-e>']            # Unpack the selected zip, tar or tar.gz here
+compress = ['<A-z>']           # Pack the selected or marked entries into a zip or tar.gz
+undo = ['z']                   # Undo last action
+redo = ['Z']                   # Redo last undone action (Shift+z)
+create = ['y']                 # Create new file or directory
+quicklook = [' ']              # Open selected file in macOS Quick Look (Space)
+open_in_ide = ['E']            # Open the current directory in ide_command (Shift+e)
+pager = ['<A-p>']              # Read the selected file in $PAGER (less if unset), without editing it
+open_with = ['<A-l>']          # Pick the program to open the selected entry with, from [openers] and common ones
+shell_command = [':']          # Run a shell command; {} is the selected path, {+} the marked ones
+refresh = ['<C-l>']            # Read the directory again, dropping its cached sizes, previews and git status
+retry_load = ['r']             # When the directory couldn't be opened, try listing it again
+gitignore = ['Gi']             # Add the selected entry (or a pattern) to the repository's .gitignore
+git_stage = ['Ga']             # git add the selected or marked entries
+git_unstage = ['Gu']           # Unstage the selected or marked entries
+git_diff = ['Gd']              # Preview the unstaged git diff of modified files instead of their contents
+ancestor_jump = ['g']          # Then a digit: jump up N directories (Alt+digit also works)
+path_jump = ['<A-g>']          # Hint every directory in the path bar; press a hint to go there
+edit_path = ['p']              # Edit the path bar and jump to the typed location
+find_char = [';']              # Then a letter: jump to next entry starting with it (unbound letters jump directly)
+bookmark_add = ['B']           # Then a letter: bookmark the current directory under it
+bookmarks = ["'"]              # Bookmarks picker: a letter jumps to its bookmark (saved in bookmarks.toml)
+tab_new = ['<C-t>']            # Open a tab in the current directory
+tab_close = ['<C-w>']          # Close the current tab
+tab_next = ['<C-n>']           # Switch to the next tab
+tab_prev = ['<C-p>']           # Switch to the previous tab
+dual_pane = ['<A-d>']          # Split into two panes side by side, each with its own directory
+pane_switch = ['<A-o>']        # Dual pane: make the other pane active
+pane_copy = ['<A-c>']          # Dual pane: copy the selected or marked entries into the other pane
+pane_move = ['<A-m>']          # Dual pane: move the selected or marked entries into the other pane
+pane_sync = ['<A-s>']          # Dual pane: make the other pane follow into and out of same-named directories
+first = []                     # Jump to first entry (vim preset: 'gg')
+last = []                      # Jump to last entry (vim preset: 'G')
+cancel = []                    # Leave the current mode like Esc (emacs preset: '<C-g>')
+
+# An entry with several characters is a key sequence typed in order, e.g. trash = ['dd'].
+# A key can also be bound alone: pressed once it runs after sequence_timeout_ms.
+# Ctrl and Alt are written <C-x> and <A-x>, e.g. down = ['<C-n>'], quit = ['<C-x><C-c>'].
+# <Enter> names the Enter key. An empty list [] disables an action.
+
+# ============================================================================
+# COLORS
+# ============================================================================
+[colors]
+# Defaults come from the `theme` preset in [settings]; values set here override it.
+# Color values can be:
+#   - Named colors: "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"
+#   - Dark variants: "darkgrey", "darkred", etc.
+#   - Hex colors: "#RRGGBB" (e.g., "#333333")
+#   - Special: "reverse" (inverted default color), "none" (no color)
+
+# Path bar at top
+path_fg = "white"
+path_bg = "#333333"
+
+# Selected item
+selected_fg = "none"
+selected_bg = "none"
+
+# Directory names
+directory_fg = "cyan"
+directory_bg = "none"
+
+# File names
+file_fg = "none"
+file_bg = "none"
+
+# Preview pane border
+preview_border_fg = "darkgrey"
+
+# Cursor (">")
+cursor_fg = "green"
+cursor_bg = "none"
+
+# Fuzzy find highlighting
+fuzzy_highlight_fg = "#ffff00"
+fuzzy_highlight_bg = "#323232"
+
+# Line numbers in preview
+line_number_fg = "darkgrey"
+
+# Help menu (bottom of screen)
+help_menu_fg = "white"
+help_menu_bg = "#1e1e1e"
+
+# ============================================================================
+# SETTINGS
+# ============================================================================
+[settings]
+# Keybinding preset: "wasd", "vim", "emacs" or "arrows" (default: "wasd")
+keymap = "wasd"
+
+# Color preset: "default", "light", "mono", "high-contrast" or "colorblind"
+# ("colorblind" avoids red/green cues, for deuteranopia and protanopia) (default: "default")
+theme = "default"
+
+# Plain ASCII rendering for screen readers and limited terminals: no box-drawing
+# or arrow glyphs, text markers instead of color-only cues, and the terminal
+# cursor parked on the selected entry (default: false)
+ascii_mode = false
+
+# Language for month names and the decimal separator in dates and sizes, e.g.
+# "de" or "fr_FR.UTF-8". Empty uses LC_ALL, LC_TIME or LANG (default: "")
+# Supported: en, de, fr, es, it, pt, nl, sv (others fall back to English)
+locale = ""
+
+# Show how many entries each directory holds in list mode, e.g. "src/ 14".
+# Counted in the background and cached until the directory changes (default: true)
+show_item_counts = true
+
+# Dim directories with nothing in them and mark the ones you can't open in
+# red with a lock, so you don't descend into either. Checked in the background
+# like the item counts, and skipped on network filesystems (default: true)
+mark_empty_and_unreadable = true
+
+# How entries are sorted, directories first: "name", "modified" (newest
+# first), "size" (largest first), "allocated" (most space on disk first),
+# "extension" or "natural" (file2 before file10). With the size sorts,
+# directories are sized in the background and reorder as their sizes
+# arrive (not on network filesystems). sort_reverse flips the order. Ctrl+S cycles and Ctrl+R reverses,
+# saving the choice here (defaults: "name", false)
+sort_by = "name"
+sort_reverse = false
+
+# Sorted by "extension", list entries under a header per kind: Folders,
+# Images, Video, Audio, Documents, Code, Archives and Other, each with its
+# count. Alt+t collapses the selected entry's group, Alt+T expands them all
+# (default: true)
+group_by_type = true
+
+# Tag project directories with their language after the name, e.g. "ils rs"
+# for a Cargo.toml, "js" for package.json, "py" for pyproject.toml and "go"
+# for go.mod. Skipped on network filesystems (default: true)
+show_project_badges = true
+
+# Mark git repositories in list info mode with their branch, plus " *" in yellow
+# when they have uncommitted work. Inside a repository, changed entries get
+# their status letter before the name: green when staged, red when not, and
+# ? when untracked. Checked in the background each time the directory is
+# loaded (default: true)
+show_git_status = true
+
+# Check new and renamed names against FAT/exFAT/NTFS rules (reserved characters
+# like : or ?, trailing spaces or dots, names like CON or NUL, over 255 characters)
+# so files can still be copied to a USB stick: "off", "warn" or "strict" to refuse
+# (default: "warn")
+filename_check = "warn"
+
+# Show free and total space of the current filesystem at the right of the path
+# bar, in yellow below 10% free and red below 5% (default: true)
+show_disk_space = true
+
+# Reload the listing when another program creates, deletes or renames entries in
+# the current directory, keeping the selection on the same entry. Checked about
+# once a second while nothing else is going on (default: true)
+auto_refresh = true
+
+# Cache limits for long sessions; the least recently used entries are dropped first.
+# Press ` to see cache usage (defaults: 200, 64, 5000)
+preview_cache_entries = 200
+preview_cache_mb = 64
+dir_size_cache_entries = 5000
+
+# While the preview is open and no key is pressed, preload PDF text and image
+# thumbnails for this many entries above and below the selection. Skipped on
+# network filesystems like NFS and SMB; 0 disables (default: 1)
+preview_prefetch = 1
+
+# How images are drawn: "kitty" (Kitty graphics protocol, also Ghostty),
+# "iterm" (iTerm2 inline images, also WezTerm), "sixel" (foot, xterm -ti vt340,
+# mlterm, Windows Terminal, tmux built with sixel) or "blocks" (colored
+# half-block characters, which work everywhere). "auto" picks by the terminal's
+# environment, asking it about sixel if nothing else fits (default: "auto")
+image_protocol = "auto"
+
+# Keep this many rows visible above and below the selection when scrolling,
+# like vim's scrolloff (default: 0)
+scrolloff = 0
+
+# A key bound on its own and as the start of a sequence (d to move, dd to
+# trash) runs on its own if the next key doesn't follow within this many
+# milliseconds; 0 waits for the next key however long it takes (default: 500)
+sequence_timeout_ms = 500
+
+# When a copy or size scan runs longer than desktop_notify_after_secs and the
+# terminal is in the background, send a desktop notification (osascript on
+# macOS, notify-send on Linux). Needs a terminal that reports focus changes
+# (defaults: true, 10)
+desktop_notifications = true
+desktop_notify_after_secs = 10
+
+# What print_path (Y) does with the selected path on exit: "print" writes it
+# to the terminal quoted for the shell, "clipboard" copies it instead
+# (default: "print")
+path_output = "print"
+
+# What quit (q), quit_then_open_in_finder (Q) and Esc do. Each is one of
+# "cd" (cd the shell to the current directory), "print" (print the selected
+# path, see path_output), "finder" (open the current directory in Finder),
+# "none" (just quit), or "!command" to run a shell command with the current
+# directory as $1, e.g. "!tmux new-window -c \"$1\""
+# (defaults: "cd", "finder", "none")
+quit_action = "cd"
+alt_quit_action = "finder"
+escape_action = "none"
+
+# Shell command run after every exit, with the final directory as $1 and the
+# working directory set to it. For example "zoxide add \"$1\"" (default: "")
+on_exit = ""
+
+# Content search (grep, R) stops after this many matching lines. It skips
+# binary files, files over 10 MB and, unless hidden files are shown, dotfiles
+# (default: 1000)
+grep_max_results = 1000
+
+# How many directory levels below the current one the file name search
+# (find_files, L) looks through. It shares grep_max_results (default: 6)
+find_max_depth = 6
+
+# Editor or IDE that open_in_ide (E) opens the current directory in, e.g.
+# "zed", "idea" or "code --new-window". The directory is passed as the last
+# argument (default: "code")
+ide_command = "code"
+
+# Exit after editing a file (default: false)
+exit_after_edit = false
+
+# Before Enter opens a file of at least this many megabytes, or a binary file,
+# in $EDITOR, ask whether to edit it, read it in the pager or look at a hex
+# dump instead. [openers] matches are opened without asking; 0 never asks
+# (default: 100)
+editor_confirm_mb = 100
+
+# Number of lines to scroll in preview mode (default: 10)
+# Shift+i/o will scroll by visible lines instead
+preview_scroll_amount = 10
+
+# Show hidden files by default (default: false)
+show_hidden = false
+
+# Inside a git repository, hide what .gitignore, .git/info/exclude and your
+# global excludes file ignore, like build output (default: false)
+hide_gitignored = false
+
+# Show preview pane on start (default: false)
+preview_on_start = false
+
+# How entries are laid out on start: "grid", "list" (one per row, with an
+# info column) or "gallery" (grid with image thumbnails) (default: "grid")
+view_on_start = "grid"
+
+# The info column list mode starts with: "none", "modified", "permissions",
+# "size" or "duration" (of media files). Tab cycles through them (default: "none")
+list_column_on_start = "none"
+
+# Number the lines of text previews on start; Tab toggles it in the preview
+# (default: true)
+line_numbers_on_start = true
+
+# Where the selection lands when entering a directory: "first" entry,
+# "first_file" after the directories, "last" entry selected there before, or
+# comma-separated name patterns to prefer, with * and ? wildcards, like
+# "README*, index.*". Without a match it falls back to the first entry
+# (default: "first")
+enter_selects = "first"
+
+# When entering a directory with a README.md or README.txt, select it and open
+# the preview pane on it, like a repository page on GitHub. The pane closes again
+# in directories without one (default: false)
+preview_readme = false
+
+# Binary files like executables are previewed as the printable strings found
+# in them, like the strings tool. Shorter runs of characters than this are
+# left out (default: 4)
+strings_min_length = 4
+
+# Preview pane height ratio (0.0-1.0, default: 0.5)
+preview_split_ratio = 0.5
+
+# Case sensitive fuzzy find search (default: false)
+case_sensitive_search = false
+
+# Show trailing slash on directories (default: true)
+show_dir_slash = true
+
+# Jump amount for W/A/S/D navigation (default: 5)
+jump_amount = 5
+
+# Show ~ for home directory in path (default: true)
+show_tilde_for_home = true
+
+# Always show relative dates (default: false, uses "Aug '25" format for dates >3mo)
+verbose_dates = false
+
+# Debug: Force show welcome wizard on every run (default: false)
+debug_show_welcome = false
+
+# Show help menu on first start (default: true)
+show_help_on_start = true
+
+# Internal: Cache for shell wrapper validation (automatically set, do not modify)
+wrapper_validation_cache_valid = false
+
+# ============================================================================
+# SHORTCUTS
+# ============================================================================
+# Directory aliases: type the name in the path bar (p), or press g then the name
+[shortcuts]
+# dl = "~/Downloads"
+# p = "~/dev/projects"
+
+# ============================================================================
+# OPENERS
+# ============================================================================
+# Commands Enter opens matching files with instead of $EDITOR; the file is
+# passed as the last argument. A bare extension stands for "*.ext", and the
+# longest matching pattern wins
+[openers]
+# "*.png" = "open -a Preview"
+# md = "glow"
+
+# ============================================================================
+# COMMANDS
+# ============================================================================
+# Shell commands bound to keys, run in the current directory. {} is the selected
+# path and {+} the marked paths (or the selected one), both quoted. Keys are
+# written like in [keybindings] and take over a built-in binding of the same key
+[commands]
+# "<A-o>" = "code {}"
+# G = "lazygit"
+"##;
+
+            let mut content = String::new();
+            let mut section = "";
+            for line in default_config.lines() {
+                if line.starts_with('[') {
+                    section = line;
+                }
+                let is_value = line.split_once(" = ").is_some_and(|(key, _)| {
+                    !key.is_empty() && key.chars().all(|c| c.is_ascii_lowercase() || c == '_')
+                });
+                let preset_section = (section == "[keybindings]" && keymap != "wasd")
+                    || (section == "[colors]" && theme != "default");
+
+                if line.starts_with("keymap = ") {
+                    content.push_str(&format!("keymap = \"{}\"", keymap));
+                } else if line.starts_with("theme = ") {
+                    content.push_str(&format!("theme = \"{}\"", theme));
+                } else if is_value && preset_section {
+                    content.push_str("# ");
+                    content.push_str(line);
+                } else {
+                    content.push_str(line);
+                }
+                content.push('\n');
+            }
+
+            write_atomic(&config_path, &content)?;
+        }
+        Ok(())
+    }
+}
+
+impl Settings {
+    #[allow(dead_code)]
+    fn save(&self) -> io::Result<()> {
+        if let Ok(home) = env::var("HOME") {
+            let config_dir = PathBuf::from(home).join(".config/ils");
+            fs::create_dir_all(&config_dir)?;
+            let config_path = config_dir.join("settings.toml");
+            let content = toml::to_string_pretty(self).map_err(io::Error::other)?;
+            fs::write(config_path, content)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for ColorConfig {
+    fn default() -> Self {
+        ColorConfig {
+            path_fg: default_path_fg(),
+            path_bg: default_path_bg(),
+            selected_fg: default_selected_fg(),
+            selected_bg: default_selected_bg(),
+            directory_fg: default_directory_fg(),
+            directory_bg: default_directory_bg(),
+            file_fg: default_file_fg(),
+            file_bg: default_file_bg(),
+            preview_border_fg: default_preview_border_fg(),
+            cursor_fg: default_cursor_fg(),
+            cursor_bg: default_cursor_bg(),
+            fuzzy_highlight_fg: default_fuzzy_highlight_fg(),
+            fuzzy_highlight_bg: default_fuzzy_highlight_bg(),
+            line_number_fg: default_line_number_fg(),
+            help_menu_fg: default_help_menu_fg(),
+            help_menu_bg: default_help_menu_bg(),
+            history_query_fg: default_history_query_fg(),
+            history_path_fg: default_history_path_fg(),
+            history_count_fg: default_history_count_fg(),
+        }
+    }
+}
+
+impl ColorConfig {
+    /// Built-in color scheme selected by `theme` in [settings].
+    fn preset(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::default()),
+            "light" => Some(ColorConfig {
+                path_fg: "black".to_string(),
+                path_bg: "#d0d0d0".to_string(),
+                directory_fg: "blue".to_string(),
+                preview_border_fg: "grey".to_string(),
+                cursor_fg: "darkgreen".to_string(),
+                fuzzy_highlight_fg: "#000000".to_string(),
+                fuzzy_highlight_bg: "#ffd75f".to_string(),
+                line_number_fg: "grey".to_string(),
+                help_menu_fg: "black".to_string(),
+                help_menu_bg: "#e4e4e4".to_string(),
+                ..Self::default()
+            }),
+            "mono" => Some(ColorConfig {
+                path_fg: "black".to_string(),
+                path_bg: "grey".to_string(),
+                directory_fg: "white".to_string(),
+                preview_border_fg: "grey".to_string(),
+                cursor_fg: "white".to_string(),
+                fuzzy_highlight_fg: "black".to_string(),
+                fuzzy_highlight_bg: "white".to_string(),
+                line_number_fg: "grey".to_string(),
+                help_menu_fg: "black".to_string(),
+                help_menu_bg: "grey".to_string(),
+                history_query_fg: "white".to_string(),
+                history_path_fg: "white".to_string(),
+                history_count_fg: "grey".to_string(),
+                ..Self::default()
+            }),
+            "high-contrast" => Some(ColorConfig {
+                path_fg: "#000000".to_string(),
+                path_bg: "#ffffff".to_string(),
+                selected_fg: "#000000".to_string(),
+                selected_bg: "#ffff00".to_string(),
+                directory_fg: "#00ffff".to_string(),
+                file_fg: "#ffffff".to_string(),
+                preview_border_fg: "#ffffff".to_string(),
+                cursor_fg: "#ffff00".to_string(),
+                fuzzy_highlight_fg: "#000000".to_string(),
+                fuzzy_highlight_bg: "#00ffff".to_string(),
+                line_number_fg: "#ffffff".to_string(),
+                help_menu_fg: "#ffffff".to_string(),
+                help_menu_bg: "#000000".to_string(),
+                history_query_fg: "#ffff00".to_string(),
+                history_path_fg: "#ffffff".to_string(),
+                history_count_fg: "#ffffff".to_string(),
+                ..Self::default()
+            }),
+            // Okabe-Ito colors: blue and orange stay distinct with red-green color blindness
+            "colorblind" | "deuteranopia" | "protanopia" => Some(ColorConfig {
+                selected_fg: "#e69f00".to_string(),
+                directory_fg: "#56b4e9".to_string(),
+                cursor_fg: "#e69f00".to_string(),
+                fuzzy_highlight_fg: "#f0e442".to_string(),
+                fuzzy_highlight_bg: "#0072b2".to_string(),
+                history_query_fg: "#56b4e9".to_string(),
+                history_path_fg: "#56b4e9".to_string(),
+                ..Self::default()
+            }),
+            _ => None,
+        }
+    }
+
+    #[allow(dead_code)]
+    fn load() -> Self {
+        if let Ok(home) = env::var("HOME") {
+            let config_path = PathBuf::from(home).join(".config/ils/colors.toml");
+            if let Ok(content) = fs::read_to_string(&config_path) {
+                if let Ok(config) = toml::from_str(&content) {
+                    return config;
+                }
+            }
+        }
+        Self::default()
+    }
+
+    #[allow(dead_code)]
+    fn save(&self) -> io::Result<()> {
+        if let Ok(home) = env::var("HOME") {
+            let config_dir = PathBuf::from(home).join(".config/ils");
+            fs::create_dir_all(&config_dir)?;
+            let config_path = config_dir.join("colors.toml");
+            let content = toml::to_string_pretty(self).map_err(io::Error::other)?;
+            fs::write(config_path, content)?;
+        }
+        Ok(())
+    }
+
+    fn parse_fg_color(&self) -> Option<Color> {
+        Self::parse_color_string(&self.path_fg)
+    }
+
+    fn parse_bg_color(&self) -> Option<Color> {
+        Self::parse_color_string(&self.path_bg)
+    }
+
+    fn parse_selected_fg(&self) -> Option<Color> {
+        Self::parse_color_string(&self.selected_fg)
+    }
+
+    fn parse_selected_bg(&self) -> Option<Color> {
+        Self::parse_color_string(&self.selected_bg)
+    }
+
+    fn parse_directory_fg(&self) -> Option<Color> {
+        Self::parse_color_string(&self.directory_fg)
+    }
+
+    fn parse_directory_bg(&self) -> Option<Color> {
+        Self::parse_color_string(&self.directory_bg)
+    }
+
+    fn parse_file_fg(&self) -> Option<Color> {
+        Self::parse_color_string(&self.file_fg)
+    }
+
+    fn parse_file_bg(&self) -> Option<Color> {
+        Self::parse_color_string(&self.file_bg)
+    }
+
+    fn parse_preview_border_fg(&self) -> Option<Color> {
+        Self::parse_color_string(&self.preview_border_fg)
+    }
+
+    fn parse_cursor_fg(&self) -> Option<Color> {
+        Self::parse_color_string(&self.cursor_fg)
+    }
+
+    fn parse_cursor_bg(&self) -> Option<Color> {
+        Self::parse_color_string(&self.cursor_bg)
+    }
+
+    fn parse_fuzzy_highlight_fg(&self) -> Option<Color> {
+        Self::parse_color_string(&self.fuzzy_highlight_fg)
+    }
+
+    fn parse_fuzzy_highlight_bg(&self) -> Option<Color> {
+        Self::parse_color_string(&self.fuzzy_highlight_bg)
+    }
+
+    fn parse_line_number_fg(&self) -> Option<Color> {
+        Self::parse_color_string(&self.line_number_fg)
+    }
+
+    fn parse_history_query_fg(&self) -> Option<Color> {
+        Self::parse_color_string(&self.history_query_fg)
+    }
+
+    fn parse_history_path_fg(&self) -> Option<Color> {
+        Self::parse_color_string(&self.history_path_fg)
+    }
+
+    fn parse_history_count_fg(&self) -> Option<Color> {
+        Self::parse_color_string(&self.history_count_fg)
+    }
+
+    fn parse_color_string(color_str: &str) -> Option<Color> {
+        let color_str = color_str.trim().to_lowercase();
+
+        // Check for hex color (#RRGGBB or #RGB)
+        if color_str.starts_with('#') {
+            return Self::parse_hex_color(&color_str);
+        }
+
+        // Named colors
+        match color_str.as_str() {
+            "black" => Some(Color::Black),
+            "red" => Some(Color::Red),
+            "green" => Some(Color::Green),
+            "yellow" => Some(Color::Yellow),
+            "blue" => Some(Color::Blue),
+            "magenta" => Some(Color::Magenta),
+            "cyan" => Some(Color::Cyan),
+            "white" => Some(Color::White),
+            "darkgrey" | "darkgray" => Some(Color::DarkGrey),
+            "darkred" => Some(Color::DarkRed),
+            "darkgreen" => Some(Color::DarkGreen),
+            "darkyellow" => Some(Color::DarkYellow),
+            "darkblue" => Some(Color::DarkBlue),
+            "darkmagenta" => Some(Color::DarkMagenta),
+            "darkcyan" => Some(Color::DarkCyan),
+            "grey" | "gray" => Some(Color::Grey),
+            "none" | "reverse" => None,
+            _ => None,
+        }
+    }
+
+    fn parse_hex_color(hex: &str) -> Option<Color> {
+        let hex = hex.trim_start_matches('#');
+
+        let (r, g, b) = if hex.len() == 6 {
+            // #RRGGBB format
+            let r = u8::from_str_radix(&hex[0..2], 16).ok()?;
+            let g = u8::from_str_radix(&hex[2..4], 16).ok()?;
+            let b = u8::from_str_radix(&hex[4..6], 16).ok()?;
+            (r, g, b)
+        } else if hex.len() == 3 {
+            // #RGB format - expand to #RRGGBB
+            let r = u8::from_str_radix(&hex[0..1], 16).ok()?;
+            let g = u8::from_str_radix(&hex[1..2], 16).ok()?;
+            let b = u8::from_str_radix(&hex[2..3], 16).ok()?;
+            (r * 17, g * 17, b * 17) // 0xF -> 0xFF
+        } else {
+            return None;
+        };
+
+        Some(Color::Rgb { r, g, b })
+    }
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        Keybindings {
+            up: keys(&["w"]),
+            down: keys(&["s"]),
+            left: keys(&["a"]),
+            right: keys(&["d"]),
+            open: keys(&["l"]),
+            back: keys(&["j", "b"]),
+            home: keys(&["h"]),
+            quit: keys(&["q"]),
+            quit_then_open_in_finder: keys(&["Q"]),
+            help: keys(&["?", "!"]),
+            preview_toggle: keys(&["P"]),
+            preview_up: keys(&["i"]),
+            preview_left: keys(&["<"]),
+            preview_right: keys(&[">"]),
+            preview_down: keys(&["o"]),
+            preview_height_decrease: keys(&["-"]),
+            preview_height_increase: keys(&["+"]),
+            toggle_hidden: keys(&["."]),
+            toggle_gitignored: keys(&["G."]),
+            toggle_markdown: keys(&["<A-M>"]),
+            fold_preview: keys(&["<A-k>"]),
+            sort: keys(&["<C-s>"]),
+            sort_reverse: keys(&["<C-r>"]),
+            collapse_group: keys(&["<A-t>"]),
+            expand_groups: keys(&["<A-T>"]),
+            fuzzy_find: keys(&["/"]),
+            fuzzy_back: keys(&["/"]),
+            fuzzy_home: keys(&["?"]),
+            history_jump: keys(&["f"]),
+            grep: keys(&["R"]),
+            find_files: keys(&["L"]),
+            toggle_mode: keys(&["m"]),
+            rename: keys(&["r"]),
+            bulk_rename: keys(&["<A-r>"]),
+            pattern_rename: keys(&["<A-R>"]),
+            edit_tags: keys(&["#"]),
+            next_sibling: keys(&["n"]),
+            match_next: keys(&["n"]),
+            match_prev: keys(&["N"]),
+            prev_sibling: keys(&["N"]),
+            copy: keys(&["c"]),
+            mark: keys(&["t"]),
+            paste: keys(&["v"]),
+            trash: keys(&["x"]),
+            trash_browse: keys(&["<A-x>"]),
+            empty_trash: keys(&["<A-X>"]),
+            delete: keys(&["X"]),
+            extract: keys(&["<A-e>"]),
+            compress: keys(&["<A-z>"]),
+            undo: keys(&["z"]),
+            redo: keys(&["Z"]),
+            create: keys(&["y"]),
+            jump_up: keys(&["W"]),
+            jump_down: keys(&["S"]),
+            jump_left: keys(&["A"]),
+            jump_right: keys(&["D"]),
+            quicklook: keys(&[" "]),
+            open_in_ide: keys(&["E"]),
+            pager: keys(&["<A-p>"]),
+            open_with: keys(&["<A-l>"]),
+            shell_command: keys(&[":"]),
+            refresh: keys(&["<C-l>"]),
+            retry_load: keys(&["r"]),
+            path_jump: keys(&["<A-g>"]),
+            gitignore: keys(&["Gi"]),
+            git_stage: keys(&["Ga"]),
+            git_unstage: keys(&["Gu"]),
+            git_diff: keys(&["Gd"]),
+            ancestor_jump: keys(&["g"]),
+            edit_path: keys(&["p"]),
+            find_char: keys(&[";"]),
+            bookmark_add: keys(&["B"]),
+            bookmarks: keys(&["'"]),
+            tab_new: keys(&["<C-t>"]),
+            tab_close: keys(&["<C-w>"]),
+            tab_next: keys(&["<C-n>"]),
+            tab_prev: keys(&["<C-p>"]),
+            dual_pane: keys(&["<A-d>"]),
+            pane_switch: keys(&["<A-o>"]),
+            pane_copy: keys(&["<A-c>"]),
+            pane_move: keys(&["<A-m>"]),
+            pane_sync: keys(&["<A-s>"]),
+            filter: keys(&["F"]),
+            help_screen: keys(&["H"]),
+            first: Vec::new(),
+            last: Vec::new(),
+            cancel: Vec::new(),
+            select: keys(&["<Enter>", "k"]),
+            list_action: keys(&["e"]),
+            dir_size: keys(&["U"]),
+            preview_page_up: keys(&["I"]),
+            preview_page_down: keys(&["O", " "]),
+            clear_copy: keys(&["C"]),
+            messages: keys(&["M"]),
+            gallery: keys(&["T"]),
+            next_image: keys(&["]"]),
+            prev_image: keys(&["["]),
+            filesystem_info: keys(&["V"]),
+            file_info: keys(&["u"]),
+            diagnostics: keys(&["`"]),
+            print_path: keys(&["Y"]),
+            commands: BTreeMap::new(),
+        }
+    }
+}
+
+impl Keybindings {
+    /// Built-in keymap selected by `keymap` in [settings].
+    fn preset(name: &str) -> Option<Self> {
+        match name {
+            "wasd" => Some(Self::default()),
+            "vim" => Some(Keybindings {
+                up: keys(&["k"]),
+                down: keys(&["j"]),
+                left: keys(&["b"]),
+                right: keys(&["w"]),
+                jump_up: keys(&["K"]),
+                jump_down: keys(&["J"]),
+                jump_left: keys(&["B"]),
+                jump_right: keys(&["W"]),
+                open: keys(&["l"]),
+                back: keys(&["h"]),
+                home: keys(&["~"]),
+                history_jump: keys(&["'"]),
+                edit_path: keys(&[":"]),
+                find_char: keys(&["f"]),
+                select: keys(&["<Enter>"]),
+                first: keys(&["gg"]),
+                last: keys(&["G"]),
+                copy: keys(&["yy"]),
+                paste: keys(&["p"]),
+                trash: keys(&["dd"]),
+                delete: keys(&["D"]),
+                undo: keys(&["u"]),
+                redo: keys(&["U"]),
+                create: keys(&["a"]),
+                gitignore: keys(&["gi"]),
+                toggle_gitignored: keys(&["g."]),
+                git_stage: keys(&["ga"]),
+                git_unstage: keys(&["gu"]),
+                git_diff: keys(&["gD"]),
+                file_info: keys(&["gd"]),
+                dir_size: keys(&["gs"]),
+                bookmark_add: keys(&["gm"]),
+                path_jump: keys(&["gp"]),
+                open_with: keys(&["go"]),
+                shell_command: keys(&["!"]),
+                help: keys(&["?"]), // "!" runs a shell command here
+                bookmarks: keys(&["g'"]),
+                tab_next: keys(&["gt"]),
+                tab_prev: keys(&["gT"]),
+                ..Self::default()
+            }),
+            "emacs" => Some(Keybindings {
+                up: keys(&["<C-p>"]),
+                down: keys(&["<C-n>"]),
+                left: keys(&["<C-b>"]),
+                right: keys(&["<C-f>"]),
+                jump_up: keys(&["<A-v>"]),
+                jump_down: keys(&["<C-v>"]),
+                jump_left: keys(&["<A-b>"]),
+                jump_right: keys(&["<A-f>"]),
+                first: keys(&["<A-<>"]),
+                last: keys(&["<A->>"]),
+                fuzzy_find: keys(&["<C-s>", "/"]),
+                filter: keys(&["<C-r>", "F"]),
+                sort: keys(&["<C-x>s"]),
+                sort_reverse: keys(&["<C-x>r"]),
+                tab_new: keys(&["<C-x>t2"]),
+                tab_close: keys(&["<C-x>t0"]),
+                tab_next: keys(&["<C-x>to"]),
+                tab_prev: keys(&["<C-x>tO"]),
+                edit_path: keys(&["<C-x><C-f>", "p"]),
+                undo: keys(&["<C-x>u", "z"]),
+                quit: keys(&["<C-x><C-c>", "q"]),
+                cancel: keys(&["<C-g>"]),
+                ..Self::default()
+            }),
+            "arrows" => Some(Keybindings {
+                // Arrow keys always move the selection; free the movement
+                // letters so they jump to matching entries instead
+                up: Vec::new(),
+                down: Vec::new(),
+                left: Vec::new(),
+                right: Vec::new(),
+                jump_up: Vec::new(),
+                jump_down: Vec::new(),
+                jump_left: Vec::new(),
+                jump_right: Vec::new(),
+                ..Self::default()
+            }),
+            _ => None,
+        }
+    }
+
+    #[allow(dead_code)]
+    fn load() -> Self {
+        if let Ok(home) = env::var("HOME") {
+            let config_path = PathBuf::from(home).join(".config/ils/keybindings.toml");
+            if let Ok(content) = fs::read_to_string(&config_path) {
+                if let Ok(bindings) = toml::from_str(&content) {
+                    return bindings;
+                }
+            }
+        }
+        Self::default()
+    }
+
+    #[allow(dead_code)]
+    fn save(&self) -> io::Result<()> {
+        if let Ok(home) = env::var("HOME") {
+            let config_dir = PathBuf::from(home).join(".config/ils");
+            fs::create_dir_all(&config_dir)?;
+            let config_path = config_dir.join("keybindings.toml");
+            let content = toml::to_string_pretty(self).map_err(io::Error::other)?;
+            fs::write(config_path, content)?;
+        }
+        Ok(())
+    }
+
+    fn contains(&self, key_list: &[String], ch: char) -> bool {
+        key_list.iter().any(|k| k.chars().eq(std::iter::once(ch)))
+    }
+
+    /// Binding notation for a key press: characters as typed, "<C-x>" with Ctrl
+    /// and "<A-x>" with Alt.
+    fn key_name(ch: char, modifiers: KeyModifiers) -> String {
+        let ctrl = modifiers.contains(KeyModifiers::CONTROL);
+        let alt = modifiers.contains(KeyModifiers::ALT);
+        match (ctrl, alt) {
+            (false, false) => ch.to_string(),
+            (true, false) => format!("<C-{}>", ch.to_ascii_lowercase()),
+            (false, true) => format!("<A-{}>", ch),
+            (true, true) => format!("<C-A-{}>", ch.to_ascii_lowercase()),
+        }
+    }
+
+    /// Whether `key` (a single key or a completed sequence) is in `key_list`.
+    fn matches(&self, key_list: &[String], key: &str) -> bool {
+        key_list.iter().any(|k| k == key)
+    }
+
+    /// Split a binding into its key presses: "<C-x>u" is ["<C-x>", "u"].
+    fn split_keys(sequence: &str) -> Vec<&str> {
+        let mut parts = Vec::new();
+        let mut rest = sequence;
+        while let Some(ch) = rest.chars().next() {
+            let named = rest.strip_prefix('<').and_then(|r| r.split_once('>')).filter(|(name, _)| {
+                name.len() > 1 && name.chars().all(|c| c.is_ascii_alphabetic())
+            });
+            let len = if rest.starts_with("<C-") || rest.starts_with("<A-") {
+                // The key itself may be '>' as in "<A->>", so search after it
+                rest.char_indices().skip(4).find(|&(_, c)| c == '>').map_or(rest.len(), |(i, _)| i + 1)
+            } else if let Some((name, _)) = named {
+                // Named keys like "<Enter>"
+                name.len() + 2
+            } else {
+                ch.len_utf8()
+            };
+            parts.push(&rest[..len]);
+            rest = &rest[len..];
+        }
+        parts
+    }
+
+    /// Whether some binding is a longer sequence starting with `typed`.
+    fn is_sequence_prefix(&self, typed: &str) -> bool {
+        let typed = Self::split_keys(typed);
+        self.bound_keys().map(|k| Self::split_keys(k)).any(|k| k.len() > typed.len() && k.starts_with(&typed))
+    }
+
+    /// Whether `key` is bound to any action or command exactly.
+    fn is_bound_key(&self, key: &str) -> bool {
+        self.bound_keys().any(|k| k == key)
+    }
+
+    /// Every key and sequence bound to an action or a [commands] entry.
+    fn bound_keys(&self) -> impl Iterator<Item = &String> {
+        self.actions().into_iter().flat_map(|(_, _, _, keys)| keys).chain(self.commands.keys())
+    }
+
+    /// Every configurable action as (config name, help category, description, keys).
+    /// The help screen and key lookups are generated from this table.
+    fn actions(&self) -> Vec<(&'static str, &'static str, &'static str, &Vec<String>)> {
+        vec![
+            ("up", "Navigation", "Move up", &self.up),
+            ("down", "Navigation", "Move down", &self.down),
+            ("left", "Navigation", "Move left", &self.left),
+            ("right", "Navigation", "Move right", &self.right),
+            ("jump_up", "Navigation", "Jump up several rows", &self.jump_up),
+            ("jump_down", "Navigation", "Jump down several rows", &self.jump_down),
+            ("jump_left", "Navigation", "Jump left several columns", &self.jump_left),
+            ("jump_right", "Navigation", "Jump right several columns", &self.jump_right),
+            ("select", "Navigation", "Open file in $EDITOR, or cd into directory and exit", &self.select),
+            ("open", "Navigation", "Enter directory", &self.open),
+            ("back", "Navigation", "Go to parent directory", &self.back),
+            ("home", "Navigation", "Go to home directory", &self.home),
+            ("next_sibling", "Navigation", "Next sibling directory", &self.next_sibling),
+            ("match_next", "Navigation", "Next match of the last find or filter", &self.match_next),
+            ("match_prev", "Navigation", "Previous match of the last find or filter", &self.match_prev),
+            ("prev_sibling", "Navigation", "Previous sibling directory", &self.prev_sibling),
+            ("ancestor_jump", "Navigation", "Then 1-9: jump up N levels, or a shortcut name", &self.ancestor_jump),
+            ("path_jump", "Navigation", "Then a hint from the path bar: go to that directory", &self.path_jump),
+            ("edit_path", "Navigation", "Edit the path bar (goto)", &self.edit_path),
+            ("first", "Navigation", "Jump to the first entry", &self.first),
+            ("last", "Navigation", "Jump to the last entry", &self.last),
+            ("cancel", "Navigation", "Cancel the current mode, like Esc", &self.cancel),
+            ("history_jump", "Navigation", "Jump to a frequently visited directory", &self.history_jump),
+            ("fuzzy_find", "Search", "Fuzzy find (jump mode)", &self.fuzzy_find),
+            ("fuzzy_back", "Search", "Parent directory while in fuzzy find", &self.fuzzy_back),
+            ("fuzzy_home", "Search", "Fuzzy find (continuous) / home while in fuzzy find", &self.fuzzy_home),
+            ("filter", "Search", "Filter the list as you type (>100MB, within:7d, before:2024-01-31, tag:name)", &self.filter),
+            ("grep", "Search", "Search file contents below the current directory", &self.grep),
+            ("find_files", "Search", "Search file names in subdirectories", &self.find_files),
+            ("find_char", "Search", "Then a letter: jump to the next entry starting with it", &self.find_char),
+            ("bookmark_add", "Navigation", "Then a letter: bookmark the current directory", &self.bookmark_add),
+            ("bookmarks", "Navigation", "Bookmarks picker: a letter jumps to its directory", &self.bookmarks),
+            ("tab_new", "Navigation", "Open a tab in the current directory", &self.tab_new),
+            ("tab_close", "Navigation", "Close the current tab", &self.tab_close),
+            ("tab_next", "Navigation", "Switch to the next tab", &self.tab_next),
+            ("tab_prev", "Navigation", "Switch to the previous tab", &self.tab_prev),
+            ("dual_pane", "View", "Toggle two side-by-side panes", &self.dual_pane),
+            ("pane_switch", "Navigation", "Dual pane: switch to the other pane", &self.pane_switch),
+            ("pane_copy", "File Operations", "Dual pane: copy selection into the other pane", &self.pane_copy),
+            ("pane_move", "File Operations", "Dual pane: move selection into the other pane", &self.pane_move),
+            ("pane_sync", "View", "Dual pane: toggle synchronized navigation", &self.pane_sync),
+            ("toggle_mode", "View", "Toggle list / grid mode", &self.toggle_mode),
+            ("gallery", "View", "Toggle image thumbnail gallery", &self.gallery),
+            ("toggle_hidden", "View", "Toggle hidden files", &self.toggle_hidden),
+            ("toggle_gitignored", "View", "Toggle entries ignored by git", &self.toggle_gitignored),
+            ("toggle_markdown", "View", "Preview Markdown rendered or as raw text", &self.toggle_markdown),
+            ("fold_preview", "View", "Fold or unfold the top-level entries of a JSON, YAML or TOML preview", &self.fold_preview),
+            ("sort", "View", "Cycle sort: name, modified, size, size on disk, extension, natural", &self.sort),
+            ("sort_reverse", "View", "Reverse the sort order", &self.sort_reverse),
+            ("collapse_group", "View", "Sorted by extension: collapse the group of the selected entry", &self.collapse_group),
+            ("expand_groups", "View", "Sorted by extension: expand the collapsed groups", &self.expand_groups),
+            ("preview_toggle", "View", "Toggle preview pane", &self.preview_toggle),
+            ("preview_up", "View", "Scroll preview up", &self.preview_up),
+            ("preview_down", "View", "Scroll preview down", &self.preview_down),
+            ("preview_left", "View", "Scroll a table preview a column left", &self.preview_left),
+            ("preview_right", "View", "Scroll a table preview a column right", &self.preview_right),
+            ("preview_page_up", "View", "Page preview up", &self.preview_page_up),
+            ("preview_page_down", "View", "Page preview down", &self.preview_page_down),
+            ("list_action", "View", "List info action (toggle date, edit permissions, calculate sizes)", &self.list_action),
+            ("dir_size", "View", "Calculate the selected directory's size in the background", &self.dir_size),
+            ("preview_height_decrease", "View", "Shrink preview pane", &self.preview_height_decrease),
+            ("preview_height_increase", "View", "Grow preview pane", &self.preview_height_increase),
+            ("next_image", "View", "Next image while previewing one (slideshow)", &self.next_image),
+            ("prev_image", "View", "Previous image while previewing one", &self.prev_image),
+            ("quicklook", "View", "Quick Look (macOS)", &self.quicklook),
+            ("help", "View", "Toggle the help bar", &self.help),
+            ("help_screen", "View", "Show this help screen", &self.help_screen),
+            ("messages", "View", "Show recent messages and errors", &self.messages),
+            ("filesystem_info", "View", "Show mount point, filesystem type and device", &self.filesystem_info),
+            ("file_info", "View", "Show size and size on disk of the selection", &self.file_info),
+            ("diagnostics", "View", "Show cache usage", &self.diagnostics),
+            ("open_in_ide", "File Operations", "Open the current directory in your IDE (ide_command)", &self.open_in_ide),
+            ("pager", "View", "Read the selected file in $PAGER", &self.pager),
+            ("open_with", "File Operations", "Choose a program to open the selection with", &self.open_with),
+            ("shell_command", "File Operations", "Run a shell command on the selected ({}) or marked ({+}) paths", &self.shell_command),
+            ("refresh", "View", "Reload the directory and what's cached about it", &self.refresh),
+            ("retry_load", "View", "List a directory that couldn't be opened again", &self.retry_load),
+            ("gitignore", "File Operations", "Add to the repository's .gitignore", &self.gitignore),
+            ("git_stage", "File Operations", "Stage (git add) selected or marked entries", &self.git_stage),
+            ("git_unstage", "File Operations", "Unstage selected or marked entries", &self.git_unstage),
+            ("git_diff", "View", "Preview the git diff of modified files", &self.git_diff),
+            ("create", "File Operations", "Create file or directory (end with /)", &self.create),
+            ("rename", "File Operations", "Rename or move (type a path)", &self.rename),
+            ("bulk_rename", "File Operations", "Rename marked or all entries in $EDITOR", &self.bulk_rename),
+            ("pattern_rename", "File Operations", "Rename marked entries with a s/pattern/replacement/ regex", &self.pattern_rename),
+            ("edit_tags", "File Operations", "Tag the selected or marked entries (filter them with tag:name)", &self.edit_tags),
+            ("copy", "File Operations", "Add to / remove from copy selection (or copy the marked entries)", &self.copy),
+            ("mark", "File Operations", "Mark / unmark for copy, trash and delete", &self.mark),
+            ("clear_copy", "File Operations", "Clear copy selection and marks", &self.clear_copy),
+            ("paste", "File Operations", "Paste copy selection here", &self.paste),
+            ("trash", "File Operations", "Move to trash", &self.trash),
+            ("trash_browse", "File Operations", "Browse the trash", &self.trash_browse),
+            ("empty_trash", "File Operations", "Empty the trash", &self.empty_trash),
+            ("delete", "File Operations", "Delete permanently", &self.delete),
+            ("extract", "File Operations", "Extract the selected archive here", &self.extract),
+            ("compress", "File Operations", "Compress selection into a zip or tar.gz", &self.compress),
+            ("undo", "File Operations", "Undo", &self.undo),
+            ("redo", "File Operations", "Redo", &self.redo),
+            ("quit", "Exit", "Quit and cd to the current directory", &self.quit),
+            ("quit_then_open_in_finder", "Exit", "Quit and open the current directory in Finder", &self.quit_then_open_in_finder),
+            ("print_path", "Exit", "Quit and print the selected path (or copy it, see path_output)", &self.print_path),
+        ]
+    }
+
+    /// Whether `ch` triggers anything in normal mode, alone or as the start of a sequence.
+    fn is_bound(&self, ch: char) -> bool {
+        self.bound_keys().any(|k| k.starts_with(ch))
+    }
+
+    fn format_keys(keys: &[String]) -> String {
+        keys.iter()
+            .map(|k| {
+                if k == " " {
+                    return "Space".to_string();
+                }
+                // "<C-x><C-f>" reads as "C-x C-f", while "gg" stays "gg"
+                let parts = Self::split_keys(k);
+                let separator = if parts.iter().any(|part| part.len() > 1) { " " } else { "" };
+                parts
+                    .iter()
+                    .map(|part| part.strip_prefix('<').and_then(|p| p.strip_suffix('>')).filter(|_| part.len() > 1).unwrap_or(part))
+                    .collect::<Vec<_>>()
+                    .join(separator)
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+// Action for undo/redo
+#[derive(Clone)]
+#[allow(dead_code)]
+enum UndoAction {
+    Copy { src: PathBuf, dest: PathBuf },
+    Move { src: PathBuf, dest: PathBuf },
+    Delete { path: PathBuf, was_dir: bool },
+    Rename { old_path: PathBuf, new_path: PathBuf },
+    RenameBatch { renames: Vec<(PathBuf, PathBuf)> }, // (old, new) pairs applied together
+    Create { path: PathBuf, was_dir: bool },
+    Extract { archive: PathBuf, dest: PathBuf, lifted: bool }, // dest is the archive's lone entry when lifted, else the directory around its entries
+    Compress { sources: Vec<PathBuf>, base: PathBuf, archive: PathBuf, format: &'static str },
+    Batch(Vec<UndoAction>), // Applied together, undone in reverse order
+    Edit { path: PathBuf, before: Option<String>, after: String }, // before is None if the file was created
+}
+
+impl UndoAction {
+    fn label(&self) -> &'static str {
+        match self {
+            UndoAction::Copy { .. } => "copy",
+            UndoAction::Move { .. } => "move",
+            UndoAction::Delete { .. } => "delete",
+            UndoAction::Rename { .. } => "rename",
+            UndoAction::RenameBatch { .. } => "renames",
+            UndoAction::Create { .. } => "create",
+            UndoAction::Extract { .. } => "extract",
+            UndoAction::Compress { .. } => "compress",
+            UndoAction::Edit { .. } => "edit",
+            UndoAction::Batch(actions) => match actions.first() {
+                Some(UndoAction::Copy { .. }) => "copies",
+                Some(UndoAction::Move { .. }) => "moves",
+                _ => "batch",
+            },
+        }
+    }
+
+    /// The (from, to) paths entries were moved along when the action was applied.
+    fn moves(&self) -> Vec<(PathBuf, PathBuf)> {
+        match self {
+            UndoAction::Move { src, dest } => vec![(src.clone(), dest.clone())],
+            UndoAction::Rename { old_path, new_path } => vec![(old_path.clone(), new_path.clone())],
+            UndoAction::RenameBatch { renames } => renames.clone(),
+            UndoAction::Batch(actions) => actions.iter().flat_map(UndoAction::moves).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Severity {
+    Info,
+    Success,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn color(self) -> Color {
+        match self {
+            Severity::Info => Color::Blue,
+            Severity::Success => Color::Green,
+            Severity::Warning => Color::Yellow,
+            Severity::Error => Color::Red,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Success => "ok",
+            Severity::Warning => "warn",
+            Severity::Error => "error",
+        }
+    }
+
+    /// How long a toast stays up; errors linger so they can be read.
+    fn lifetime(self) -> Duration {
+        match self {
+            Severity::Info | Severity::Success => Duration::from_secs(3),
+            Severity::Warning => Duration::from_secs(5),
+            Severity::Error => Duration::from_secs(8),
+        }
+    }
+}
+
+// Transient status message shown in the top-right corner
+struct Toast {
+    message: String,
+    severity: Severity,
+    expires_at: Instant,
+}
+
+// Single-line text input shared by the bottom-row prompt, the path bar and the filter
+#[derive(Default)]
+struct LineEditor {
+    text: String,
+    cursor: usize, // Position in text, in chars
+    history: Vec<String>, // Submitted entries, oldest first
+    history_pos: Option<usize>, // History entry shown while browsing with Up/Down
+    draft: String, // What was typed before browsing history
+}
+
+// What a key did to a LineEditor
+#[derive(PartialEq)]
+enum LineEdit {
+    Changed,
+    Moved,
+    Submit,
+    Cancel,
+    Complete, // Tab, for callers that offer completion
+    Ignored,
+}
+
+impl LineEditor {
+    /// Begin editing `text` with the cursor at its end. History is kept.
+    fn start(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.cursor = text.chars().count();
+        self.history_pos = None;
+    }
+
+    fn byte_idx(&self, chars: usize) -> usize {
+        self.text.char_indices().nth(chars).map(|(i, _)| i).unwrap_or(self.text.len())
+    }
+
+    fn before_cursor(&self) -> &str {
+        &self.text[..self.byte_idx(self.cursor)]
+    }
+
+    /// Replace the text before the cursor, e.g. with a completed path.
+    fn complete_with(&mut self, completed: String) {
+        let after = self.text[self.byte_idx(self.cursor)..].to_string();
+        self.cursor = completed.chars().count();
+        self.text = completed + &after;
+    }
+
+    /// Finish editing, remembering the text for Up/Down.
+    fn submit(&mut self) -> String {
+        if !self.text.trim().is_empty() && self.history.last() != Some(&self.text) {
+            self.history.push(self.text.clone());
+        }
+        self.history_pos = None;
+        self.text.clone()
+    }
+
+    fn show_history(&mut self, pos: Option<usize>) {
+        if self.history_pos.is_none() {
+            self.draft = self.text.clone();
+        }
+        self.history_pos = pos;
+        let text = match pos {
+            Some(pos) => self.history[pos].clone(),
+            None => std::mem::take(&mut self.draft),
+        };
+        self.text = text;
+        self.cursor = self.text.chars().count();
+    }
+
+    fn handle_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> LineEdit {
+        let ctrl = modifiers.contains(KeyModifiers::CONTROL);
+        let len = self.text.chars().count();
+        match code {
+            KeyCode::Esc => LineEdit::Cancel,
+            KeyCode::Enter => LineEdit::Submit,
+            KeyCode::Tab => LineEdit::Complete,
+            KeyCode::Left => {
+                self.cursor = self.cursor.saturating_sub(1);
+                LineEdit::Moved
+            }
+            KeyCode::Right => {
+                self.cursor = (self.cursor + 1).min(len);
+                LineEdit::Moved
+            }
+            KeyCode::Home => {
+                self.cursor = 0;
+                LineEdit::Moved
+            }
+            KeyCode::End => {
+                self.cursor = len;
+                LineEdit::Moved
+            }
+            KeyCode::Char('a') if ctrl => {
+                self.cursor = 0;
+                LineEdit::Moved
+            }
+            KeyCode::Char('e') if ctrl => {
+                self.cursor = len;
+                LineEdit::Moved
+            }
+            KeyCode::Up if !self.history.is_empty() => {
+                let pos = self.history_pos.map_or(self.history.len() - 1, |pos| pos.saturating_sub(1));
+                self.show_history(Some(pos));
+                LineEdit::Changed
+            }
+            KeyCode::Down if self.history_pos.is_some() => {
+                let next = self.history_pos.map(|pos| pos + 1).filter(|&pos| pos < self.history.len());
+                self.show_history(next);
+                LineEdit::Changed
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                let idx = self.byte_idx(self.cursor);
+                self.text.remove(idx);
+                LineEdit::Changed
+            }
+            KeyCode::Delete if self.cursor < len => {
+                let idx = self.byte_idx(self.cursor);
+                self.text.remove(idx);
+                LineEdit::Changed
+            }
+            KeyCode::Char('u') if ctrl => {
+                // Clear everything before the cursor
+                let idx = self.byte_idx(self.cursor);
+                self.text.replace_range(..idx, "");
+                self.cursor = 0;
+                LineEdit::Changed
+            }
+            KeyCode::Char('k') if ctrl => {
+                // Clear everything after the cursor
+                let idx = self.byte_idx(self.cursor);
+                self.text.truncate(idx);
+                LineEdit::Changed
+            }
+            KeyCode::Char('w') if ctrl => {
+                // Delete the word before the cursor
+                let before: Vec<char> = self.before_cursor().chars().collect();
+                let mut start = before.len();
+                while start > 0 && before[start - 1] == ' ' {
+                    start -= 1;
+                }
+                while start > 0 && before[start - 1] != ' ' && before[start - 1] != '/' {
+                    start -= 1;
+                }
+                let (from, to) = (self.byte_idx(start), self.byte_idx(self.cursor));
+                self.text.replace_range(from..to, "");
+                self.cursor = start;
+                LineEdit::Changed
+            }
+            KeyCode::Char(ch) if !ctrl => {
+                let idx = self.byte_idx(self.cursor);
+                self.text.insert(idx, ch);
+                self.cursor += 1;
+                LineEdit::Changed
+            }
+            _ => LineEdit::Ignored,
+        }
+    }
+}
+
+const MAX_TOASTS: usize = 4;
+const THUMBNAIL_SIZE: u32 = 1024; // Longest side of cached image thumbnails, in pixels
+const GALLERY_THUMBNAIL_SIZE: u32 = 256; // Smaller thumbnails for the gallery grid
+const GALLERY_ROW_HEIGHT: usize = 8; // Terminal rows per gallery cell: image, name and a gap
+const DIR_SIZE_WORKERS: usize = 4; // Directories sized at once; more mostly contend for the disk
+const SORT_SIZE_MAX_DIRS: usize = 256; // Subdirectories a size sort sizes by itself; the rest go after by name
+const PATH_JUMP_HINTS: &str = "123456789abcdefghijklmnopqrstuvwxyz"; // Path bar hints, nearest ancestor first
+
+fn is_image(path: &Path) -> bool {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    matches!(extension.as_str(), "png" | "jpg" | "jpeg" | "gif" | "bmp" | "webp")
+}
+
+/// Most decoded images kept in memory, and the memory they may take in megabytes.
+const IMAGE_CACHE_ENTRIES: usize = 64;
+const IMAGE_CACHE_MB: usize = 64;
+
+// How images are drawn in the terminal
+#[derive(Clone, Copy, PartialEq)]
+enum ImageProtocol {
+    Kitty,
+    Iterm,
+    Sixel,
+    Blocks, // Half-block characters, through viuer
+}
+
+impl ImageProtocol {
+    fn name(self) -> &'static str {
+        match self {
+            ImageProtocol::Kitty => "Kitty graphics",
+            ImageProtocol::Iterm => "iTerm2 inline images",
+            ImageProtocol::Sixel => "sixel",
+            ImageProtocol::Blocks => "text blocks",
+        }
+    }
+
+    /// The best protocol the terminal has. iTerm2, WezTerm and Kitty go by their
+    /// environment; under tmux, which passes neither through, only sixel is tried.
+    fn detect() -> Self {
+        let term = env::var("TERM").unwrap_or_default();
+        let program = env::var("TERM_PROGRAM").unwrap_or_default();
+        if env::var_os("TMUX").is_none() {
+            if viuer::is_iterm_supported() {
+                return ImageProtocol::Iterm;
+            }
+            if term == "xterm-kitty" || term == "xterm-ghostty" || program == "ghostty" || env::var_os("KITTY_WINDOW_ID").is_some() {
+                return ImageProtocol::Kitty;
+            }
+        }
+        if terminal_has_sixel() { ImageProtocol::Sixel } else { ImageProtocol::Blocks }
+    }
+}
+
+/// The attributes of the first well-formed primary device attributes reply in
+/// `bytes`, which looks like ESC [ ? 62 ; 4 ; 22 c, skipping anything around it.
+fn device_attributes(bytes: &[u8]) -> Option<Vec<u32>> {
+    let mut rest = bytes;
+    while let Some(start) = rest.windows(3).position(|window| window == b"\x1b[?") {
+        let body = &rest[start + 3..];
+        let len = body.iter().take_while(|&&b| b.is_ascii_digit() || b == b';').count();
+        if body.get(len) == Some(&b'c') {
+            return Some(
+                body[..len]
+                    .split(|&b| b == b';')
+                    .filter_map(|attribute| std::str::from_utf8(attribute).ok()?.parse().ok())
+                    .collect(),
+            );
+        }
+        rest = &rest[start + 1..];
+    }
+    None
+}
+
+/// Whether the terminal lists sixel graphics (attribute 4) in its answer to the
+/// primary device attributes query. Run once at startup in raw mode, before
+/// crossterm reads any input; keys typed meanwhile are dropped. A terminal that
+/// doesn't answer within half a second is taken to have none, and crossterm
+/// swallows an answer that turns up later.
+#[cfg(unix)]
+fn terminal_has_sixel() -> bool {
+    use std::os::unix::io::AsRawFd;
+    let mut stdout = io::stdout();
+    if stdout.write_all(b"\x1b[c").and_then(|_| stdout.flush()).is_err() {
+        return false;
+    }
+    let fd = io::stdin().as_raw_fd();
+    let deadline = Instant::now() + Duration::from_millis(500);
+    let mut answer = Vec::new();
+    while device_attributes(&answer).is_none() {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let mut poll = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+        if left.is_zero() || unsafe { libc::poll(&mut poll, 1, left.as_millis() as libc::c_int) } <= 0 {
+            break;
+        }
+        let mut buf = [0u8; 64];
+        let read = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+        if read <= 0 {
+            break;
+        }
+        answer.extend_from_slice(&buf[..read as usize]);
+    }
+    device_attributes(&answer).is_some_and(|attributes| attributes.contains(&4))
+}
+
+#[cfg(not(unix))]
+fn terminal_has_sixel() -> bool {
+    false
+}
+
+/// Pixels per terminal cell, from the window size the terminal reports,
+/// or a common 10 by 20 when it reports none.
+fn cell_pixels() -> (u32, u32) {
+    match terminal::window_size() {
+        Ok(size) if size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0 => {
+            ((size.width / size.columns).max(1) as u32, (size.height / size.rows).max(1) as u32)
+        }
+        _ => (10, 20),
+    }
+}
+
+/// The size in cells and in pixels of a `width` by `height` image scaled to fit
+/// into `cols` by `rows` cells, keeping its aspect.
+fn fit_image(width: u32, height: u32, cols: u16, rows: u16) -> ((u32, u32), (u32, u32)) {
+    let (cell_width, cell_height) = cell_pixels();
+    let scale = f64::min(
+        (cols as u32 * cell_width) as f64 / width.max(1) as f64,
+        (rows as u32 * cell_height) as f64 / height.max(1) as f64,
+    );
+    let pixels = |size: u32| ((size as f64 * scale).round() as u32).max(1);
+    let (pixel_width, pixel_height) = (pixels(width), pixels(height));
+    let cells = pixel_width.div_ceil(cell_width).min(cols as u32).max(1);
+    ((cells, pixel_height.div_ceil(cell_height).min(rows as u32).max(1)), (pixel_width, pixel_height))
+}
+
+/// A PNG sent with the Kitty graphics protocol, shown over `cols` by `rows`
+/// cells from the cursor, which stays put.
+fn kitty_graphics(png: &[u8], cols: u32, rows: u32) -> String {
+    let data = base64::engine::general_purpose::STANDARD.encode(png);
+    // Payloads go in chunks of at most 4096 bytes, each saying whether more follow
+    let chunks: Vec<&str> = data.as_bytes().chunks(4096).map(|chunk| std::str::from_utf8(chunk).unwrap_or("")).collect();
+    let mut out = String::with_capacity(data.len() + chunks.len() * 16);
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        if i == 0 {
+            out += &format!("\x1b_Ga=T,f=100,q=2,C=1,c={},r={},m={};{}\x1b\\", cols, rows, more, chunk);
+        } else {
+            out += &format!("\x1b_Gm={};{}\x1b\\", more, chunk);
+        }
+    }
+    out
+}
+
+/// A PNG as an iTerm2 inline image over `cols` by `rows` cells.
+fn iterm_graphics(png: &[u8], cols: u32, rows: u32) -> String {
+    let data = base64::engine::general_purpose::STANDARD.encode(png);
+    format!("\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=1:{}\x07", png.len(), cols, rows, data)
+}
+
+/// An image as sixel graphics, in up to 256 colors picked for it. Pixels that
+/// are mostly transparent are left to the background.
+fn sixel_graphics(image: &image::RgbaImage) -> String {
+    let (width, height) = image.dimensions();
+    let quantizer = color_quant::NeuQuant::new(10, 256, image.as_raw());
+    let indices: Vec<Option<u8>> = image.pixels().map(|pixel| (pixel[3] >= 128).then(|| quantizer.index_of(&pixel.0) as u8)).collect();
+
+    let mut out = format!("\x1bP0;1;0q\"1;1;{};{}", width, height);
+    for (i, rgb) in quantizer.color_map_rgb().chunks(3).enumerate() {
+        let percent = |value: u8| (value as u32 * 100 + 127) / 255;
+        out += &format!("#{};2;{};{};{}", i, percent(rgb[0]), percent(rgb[1]), percent(rgb[2]));
+    }
+    // Each band of six pixel rows is drawn once per color in it, a bit per row
+    let mut bands: Vec<Option<Vec<u8>>> = vec![None; 256];
+    for top in (0..height).step_by(6) {
+        for (bit, y) in (top..(top + 6).min(height)).enumerate() {
+            for x in 0..width {
+                if let Some(color) = indices[(y * width + x) as usize] {
+                    bands[color as usize].get_or_insert_with(|| vec![0; width as usize])[x as usize] |= 1 << bit;
+                }
+            }
+        }
+        let mut first = true;
+        for (color, band) in bands.iter_mut().enumerate() {
+            let Some(band) = band.take() else { continue };
+            if !first {
+                out.push('$');
+            }
+            first = false;
+            out += &format!("#{}", color);
+            // Runs of the same column pattern are written as !count
+            let mut x = 0;
+            while x < band.len() {
+                let run = band[x..].iter().take_while(|bits| **bits == band[x]).count();
+                let sixel = (63 + band[x]) as char;
+                if run > 3 {
+                    out += &format!("!{}{}", run, sixel);
+                } else {
+                    out.extend(std::iter::repeat_n(sixel, run));
+                }
+                x += run;
+            }
+        }
+        out.push('-');
+    }
+    out += "\x1b\\";
+    out
+}
+const MESSAGE_LOG_SIZE: usize = 200;
+
+// Month names and number style used for dates and sizes
+#[derive(Clone, Copy)]
+struct Locale {
+    months: [&'static str; 12],
+    decimal: char,
+}
+
+impl Locale {
+    const ENGLISH: Locale = Locale {
+        months: ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"],
+        decimal: '.',
+    };
+
+    /// Resolve the `locale` setting, falling back to the environment and then English.
+    fn from_setting(setting: &str) -> Self {
+        let name = if setting.is_empty() {
+            ["LC_ALL", "LC_TIME", "LANG"]
+                .iter()
+                .filter_map(|var| env::var(var).ok())
+                .find(|value| !value.is_empty())
+                .unwrap_or_default()
+        } else {
+            setting.to_string()
+        };
+        // "de_DE.UTF-8" and "de-AT" both select "de"
+        let lang = name.split(['_', '-', '.', '@']).next().unwrap_or("").to_lowercase();
+
+        let months = match lang.as_str() {
+            "de" => ["Jan", "Feb", "Mär", "Apr", "Mai", "Jun", "Jul", "Aug", "Sep", "Okt", "Nov", "Dez"],
+            "fr" => ["janv.", "févr.", "mars", "avr.", "mai", "juin", "juil.", "août", "sept.", "oct.", "nov.", "déc."],
+            "es" => ["ene", "feb", "mar", "abr", "may", "jun", "jul", "ago", "sep", "oct", "nov", "dic"],
+            "it" => ["gen", "feb", "mar", "apr", "mag", "giu", "lug", "ago", "set", "ott", "nov", "dic"],
+            "pt" => ["jan", "fev", "mar", "abr", "mai", "jun", "jul", "ago", "set", "out", "nov", "dez"],
+            "nl" => ["jan", "feb", "mrt", "apr", "mei", "jun", "jul", "aug", "sep", "okt", "nov", "dec"],
+            "sv" => ["jan", "feb", "mar", "apr", "maj", "jun", "jul", "aug", "sep", "okt", "nov", "dec"],
+            _ => return Locale::ENGLISH,
+        };
+        Locale { months, decimal: ',' }
+    }
+
+    fn month_year(&self, month_idx: usize, year: u64) -> String {
+        format!("{} '{:02}", self.months[month_idx], year % 100)
+    }
+
+    /// Human-readable size using the given unit names, bytes first, each 1024 times the last.
+    fn format_size(&self, bytes: u64, units: &[&str]) -> String {
+        let mut value = bytes as f64;
+        let mut unit = 0;
+        while value >= 1024.0 && unit < units.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+        if unit == 0 {
+            format!("{} {}", bytes, units[0])
+        } else {
+            let number = format!("{:.1}", value);
+            format!("{} {}", number.replace('.', &self.decimal.to_string()), units[unit])
+        }
+    }
+}
+
+// What a listed directory holds, when it's worth marking
+#[derive(Clone, Copy, PartialEq)]
+enum DirState {
+    Empty,
+    Unreadable,
+}
+
+// Entry count of a directory for the list-mode badge
+#[derive(Clone, Copy)]
+enum ItemCount {
+    Counting,
+    Counted {
+        modified: Option<SystemTime>, // Directory mtime when counted; a change means entries were added or removed
+        all: usize,
+        visible: usize, // Entries not starting with '.'
+    },
+    Unreadable,
+}
+
+// Git state of a repository directory, shown in list info mode
+#[derive(Clone)]
+enum GitStatus {
+    Scanning,
+    Scanned { branch: String, dirty: bool },
+    Failed, // git missing or the repository unreadable
+}
+
+// A line found by the content search, or a path found by the name search
+struct GrepMatch {
+    path: PathBuf,
+    line: usize, // 1-based; 0 for name matches
+    text: String,
+}
+
+// Settings a search thread works with
+#[derive(Clone, Copy)]
+struct SearchOptions {
+    case_sensitive: bool,
+    show_hidden: bool,
+    max_depth: usize, // Name search only
+    max_results: usize,
+}
+
+// Progress of a content search, filled in by its thread
+#[derive(Default)]
+struct GrepResults {
+    matches: Vec<GrepMatch>,
+    files: usize, // Files (or for name searches, directories) searched so far
+    done: bool,
+}
+
+// A directory, the index and work tree status codes (the "XY" of
+// `git status --porcelain`) of its changed entries, and which of its entries
+// git ignores when that was asked for
+type GitChanges = (PathBuf, HashMap<PathBuf, (char, char)>, Option<HashSet<PathBuf>>);
+
+// Map that drops the least recently used entries once it holds more than
+// `max_entries` values or more than `max_bytes` as measured by `weigh`
+struct LruCache<V> {
+    entries: HashMap<PathBuf, (V, u64)>, // Value and the tick it was last used
+    tick: u64,
+    max_entries: usize,
+    max_bytes: usize,
+    bytes: usize,
+    weigh: fn(&V) -> usize,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl<V> LruCache<V> {
+    fn new(max_entries: usize, max_bytes: usize, weigh: fn(&V) -> usize) -> Self {
+        LruCache {
+            entries: HashMap::new(),
+            tick: 0,
+            max_entries: max_entries.max(1),
+            max_bytes,
+            bytes: 0,
+            weigh,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    fn get(&mut self, key: &Path) -> Option<&V> {
+        self.tick += 1;
+        match self.entries.get_mut(key) {
+            Some((value, used)) => {
+                *used = self.tick;
+                self.hits += 1;
+                Some(value)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn contains_key(&self, key: &Path) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Look up without counting a use, for callers that only compare values.
+    fn peek(&self, key: &Path) -> Option<&V> {
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    fn insert(&mut self, key: PathBuf, value: V) {
+        self.tick += 1;
+        self.bytes += (self.weigh)(&value);
+        if let Some((old, _)) = self.entries.insert(key.clone(), (value, self.tick)) {
+            self.bytes -= (self.weigh)(&old);
+        }
+        // Never evict what was just inserted, even if it alone is over the limit
+        while self.entries.len() > self.max_entries || (self.bytes > self.max_bytes && self.entries.len() > 1) {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .filter(|(path, _)| **path != key)
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(path, _)| path.clone())
+            else {
+                break;
+            };
+            if let Some((old, _)) = self.entries.remove(&oldest) {
+                self.bytes -= (self.weigh)(&old);
+                self.evictions += 1;
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &Path) {
+        if let Some((old, _)) = self.entries.remove(key) {
+            self.bytes -= (self.weigh)(&old);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// One line for the diagnostics panel.
+    fn stats(&self, locale: &Locale) -> String {
+        let units = ["B", "KB", "MB", "GB"];
+        let size = if self.max_bytes == usize::MAX {
+            String::new()
+        } else {
+            format!(", {} of {}", locale.format_size(self.bytes as u64, &units), locale.format_size(self.max_bytes as u64, &units))
+        };
+        format!(
+            "{} of {} entries{}, {} hits, {} misses, {} evicted",
+            self.len(),
+            self.max_entries,
+            size,
+            self.hits,
+            self.misses,
+            self.evictions
+        )
+    }
+}
+
+#[derive(Clone)]
+enum PreviewState {
+    NotLoaded,
+    Loading,
+    Extracting { page: usize, pages: usize }, // PDF text extraction in progress
+    Loaded(Vec<String>),
+    Notebook { language: String, cells: Vec<NotebookCell> },
+    Error(String),
+}
+
+// One cell of a Jupyter notebook
+#[derive(Clone)]
+struct NotebookCell {
+    kind: String, // "code", "markdown" or "raw"
+    execution_count: Option<u64>,
+    source: Vec<String>,
+    outputs: Vec<String>, // Text of stream, result and error outputs
+}
+
+// A rendered line of a notebook preview, styled by where it came from
+enum NotebookLine<'a> {
+    Header(String),
+    Code(&'a str),
+    Markdown(&'a str),
+    Output(&'a str),
+    Blank,
+}
+
+/// Read the cells of an .ipynb file (nbformat 4) and the kernel's language.
+fn parse_notebook(text: &str) -> Result<(String, Vec<NotebookCell>), String> {
+    let json: serde_json::Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let cells = json.get("cells").and_then(|c| c.as_array()).ok_or("unsupported notebook format")?;
+    let metadata = &json["metadata"];
+    let language = metadata["language_info"]["name"]
+        .as_str()
+        .or(metadata["kernelspec"]["language"].as_str())
+        .unwrap_or("python")
+        .to_string();
+
+    // Multiline fields are either one string or a list of lines
+    let text_of = |value: &serde_json::Value| -> Vec<String> {
+        let joined = match value {
+            serde_json::Value::Array(parts) => parts.iter().filter_map(|p| p.as_str()).collect::<String>(),
+            serde_json::Value::String(text) => text.clone(),
+            _ => String::new(),
+        };
+        joined.lines().map(|line| line.to_string()).collect()
+    };
+
+    let cells = cells
+        .iter()
+        .map(|cell| {
+            let mut outputs = Vec::new();
+            for output in cell["outputs"].as_array().into_iter().flatten() {
+                match output["output_type"].as_str().unwrap_or("") {
+                    "stream" => outputs.extend(text_of(&output["text"])),
+                    "execute_result" | "display_data" => {
+                        let data = &output["data"];
+                        if data.get("text/plain").is_some() {
+                            outputs.extend(text_of(&data["text/plain"]));
+                        } else if let Some(kinds) = data.as_object() {
+                            outputs.push(format!("[{}]", kinds.keys().cloned().collect::<Vec<_>>().join(", ")));
+                        }
+                    }
+                    "error" => outputs.push(format!(
+                        "{}: {}",
+                        output["ename"].as_str().unwrap_or("Error"),
+                        output["evalue"].as_str().unwrap_or("")
+                    )),
+                    _ => {}
+                }
+            }
+            NotebookCell {
+                kind: cell["cell_type"].as_str().unwrap_or("raw").to_string(),
+                execution_count: cell["execution_count"].as_u64(),
+                source: text_of(&cell["source"]),
+                outputs,
+            }
+        })
+        .collect();
+    Ok((language, cells))
+}
+
+/// Whether a file is in one of Apple's encodings that the text preview would show
+/// as garbage: binary plists (also used for .strings), UTF-16 .strings and signed
+/// .mobileprovision profiles.
+fn is_apple_encoded(path: &Path, extension: &str) -> bool {
+    match extension {
+        "mobileprovision" => true,
+        "plist" | "strings" => {
+            let mut head = [0u8; 8];
+            let read = fs::File::open(path).and_then(|mut file| io::Read::read(&mut file, &mut head)).unwrap_or(0);
+            let head = &head[..read];
+            head.starts_with(b"bplist") || head.starts_with(&[0xFF, 0xFE]) || head.starts_with(&[0xFE, 0xFF])
+        }
+        _ => false,
+    }
+}
+
+/// Whether a file looks binary: a NUL byte somewhere in its first 8000 bytes,
+/// the same test content search uses to skip files.
+fn is_binary_file(path: &Path) -> bool {
+    let mut head = Vec::new();
+    match fs::File::open(path) {
+        Ok(file) => io::Read::read_to_end(&mut io::Read::take(file, 8000), &mut head).is_ok() && head.contains(&0),
+        Err(_) => false,
+    }
+}
+
+/// Runs of at least `min_length` printable ASCII characters in a file, one per
+/// line, like the `strings` tool. Only the first STRINGS_MAX_BYTES are read.
+fn extract_strings(path: &Path, min_length: usize) -> Result<Vec<String>, String> {
+    let file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut bytes = Vec::new();
+    io::Read::read_to_end(&mut io::Read::take(file, STRINGS_MAX_BYTES), &mut bytes).map_err(|e| e.to_string())?;
+
+    let min_length = min_length.max(1);
+    let mut strings = Vec::new();
+    let mut run = String::new();
+    for &byte in bytes.iter().chain(std::iter::once(&0)) {
+        if byte == b'\t' || (0x20..0x7f).contains(&byte) {
+            run.push(byte as char);
+        } else {
+            if run.len() >= min_length {
+                strings.push(std::mem::take(&mut run));
+            }
+            run.clear();
+        }
+    }
+    if strings.is_empty() {
+        strings.push(format!("(no strings of {} or more characters)", min_length));
+    } else if bytes.len() as u64 == STRINGS_MAX_BYTES {
+        strings.push(format!("(stopped after the first {} MB)", STRINGS_MAX_BYTES / (1024 * 1024)));
+    }
+    Ok(strings)
+}
+
+// One file or directory inside an archive
+struct ArchiveEntry {
+    name: String,
+    size: u64, // Uncompressed size
+    is_dir: bool,
+}
+
+/// The archive format a file's name says it has: "zip", "tar" or "tar.gz".
+fn archive_kind(path: &Path) -> Option<&'static str> {
+    let name = path.file_name()?.to_str()?.to_lowercase();
+    if name.ends_with(".zip") {
+        Some("zip")
+    } else if name.ends_with(".tar") {
+        Some("tar")
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some("tar.gz")
+    } else {
+        None
+    }
+}
+
+/// List the entries of a zip or (gzipped) tar archive, without extracting anything.
+fn list_archive(path: &Path) -> Result<Vec<ArchiveEntry>, String> {
+    let file = fs::File::open(path).map_err(|e| e.to_string())?;
+    match archive_kind(path) {
+        Some("zip") => list_zip(file),
+        Some("tar") => list_tar(io::BufReader::new(file)),
+        Some("tar.gz") => list_tar(io::BufReader::new(flate2::read::GzDecoder::new(io::BufReader::new(file)))),
+        _ => Err(String::from("not an archive")),
+    }
+}
+
+// A zip entry as its central directory record describes it
+struct ZipEntry {
+    name: String,
+    size: u64,
+    compressed_size: u64,
+    method: u16, // 0 = stored, 8 = deflated
+    encrypted: bool,
+    offset: u64, // Of its local header
+    mode: u32, // Unix file type and permissions, 0 if not made on unix
+}
+
+/// Read a zip's central directory, found through the end record at the back of the file.
+fn zip_directory(file: &mut fs::File) -> Result<Vec<ZipEntry>, String> {
+    use io::{Read, Seek, SeekFrom};
+    let u16_at = |bytes: &[u8], at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]) as u64;
+    let u32_at = |bytes: &[u8], at: usize| u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]) as u64;
+    let u64_at = |bytes: &[u8], at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+
+    // The end record is 22 bytes plus a comment of up to 64 KB
+    let len = file.metadata().map_err(|e| e.to_string())?.len();
+    let tail_len = len.min(22 + 65535);
+    file.seek(SeekFrom::Start(len - tail_len)).map_err(|e| e.to_string())?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).map_err(|e| e.to_string())?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&at| tail[at..].starts_with(&[0x50, 0x4b, 0x05, 0x06]))
+        .ok_or("no zip directory found")?;
+    let (mut count, mut size, mut offset) = (u16_at(&tail, end + 10), u32_at(&tail, end + 12), u32_at(&tail, end + 16));
+
+    // Zip64 archives keep the real values in a second end record just before
+    if offset == 0xFFFF_FFFF && end >= 20 && tail[end - 20..].starts_with(&[0x50, 0x4b, 0x06, 0x07]) {
+        let record_offset = u64_at(&tail, end - 20 + 8);
+        let mut record = [0u8; 56];
+        file.seek(SeekFrom::Start(record_offset)).and_then(|_| file.read_exact(&mut record)).map_err(|e| e.to_string())?;
+        count = u64_at(&record, 32);
+        size = u64_at(&record, 40);
+        offset = u64_at(&record, 48);
+    }
+
+    let mut directory = Vec::new();
+    file.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+    file.take(size).read_to_end(&mut directory).map_err(|e| e.to_string())?;
+
+    let mut entries = Vec::new();
+    let mut at = 0;
+    for _ in 0..count {
+        if at + 46 > directory.len() || !directory[at..].starts_with(&[0x50, 0x4b, 0x01, 0x02]) {
+            break;
+        }
+        let name_len = u16_at(&directory, at + 28) as usize;
+        let extra_len = u16_at(&directory, at + 30) as usize;
+        let comment_len = u16_at(&directory, at + 32) as usize;
+        let name_end = (at + 46 + name_len).min(directory.len());
+        let mut entry = ZipEntry {
+            name: String::from_utf8_lossy(&directory[at + 46..name_end]).into_owned(),
+            size: u32_at(&directory, at + 24),
+            compressed_size: u32_at(&directory, at + 20),
+            method: u16_at(&directory, at + 10) as u16,
+            encrypted: u16_at(&directory, at + 8) & 1 != 0,
+            offset: u32_at(&directory, at + 42),
+            mode: if u16_at(&directory, at + 4) >> 8 == 3 { (u32_at(&directory, at + 38) >> 16) as u32 } else { 0 },
+        };
+
+        // Values too big for 32 bits are in the zip64 extra field, in this order
+        let extra = &directory[name_end..(name_end + extra_len).min(directory.len())];
+        let mut field = 0;
+        while field + 4 <= extra.len() {
+            let field_len = u16_at(extra, field + 2) as usize;
+            if u16_at(extra, field) == 1 {
+                let mut value = field + 4;
+                for target in [&mut entry.size, &mut entry.compressed_size, &mut entry.offset] {
+                    if *target == 0xFFFF_FFFF && value + 8 <= extra.len() {
+                        *target = u64_at(extra, value);
+                        value += 8;
+                    }
+                }
+                break;
+            }
+            field += 4 + field_len;
+        }
+        entries.push(entry);
+        at += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+fn list_zip(mut file: fs::File) -> Result<Vec<ArchiveEntry>, String> {
+    Ok(zip_directory(&mut file)?
+        .into_iter()
+        .map(|entry| ArchiveEntry { is_dir: entry.name.ends_with('/'), name: entry.name, size: entry.size })
+        .collect())
+}
+
+// One entry of a tar stream as walk_tar hands it over
+struct TarEntry {
+    name: String,
+    size: u64,
+    kind: u8, // b'0' file, b'5' directory, b'2' symlink, b'1' hard link, ...
+    mode: u32,
+    link: String, // Target of a link
+}
+
+/// Walk the 512-byte headers of a tar stream, handing each entry and a reader of
+/// its data to `visit`. Whatever `visit` leaves unread is skipped.
+fn walk_tar(mut reader: impl io::Read, mut visit: impl FnMut(&TarEntry, &mut dyn io::Read) -> io::Result<()>) -> io::Result<()> {
+    use io::Read;
+    let field = |header: &[u8], range: std::ops::Range<usize>| {
+        let bytes = &header[range];
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    };
+    let octal = |header: &[u8], range: std::ops::Range<usize>| u64::from_str_radix(field(header, range).trim(), 8).unwrap_or(0);
+
+    let mut long_name = None;
+    let mut long_link = None;
+    let mut header = [0u8; 512];
+    loop {
+        if reader.read_exact(&mut header).is_err() || header.iter().all(|&b| b == 0) {
+            return Ok(());
+        }
+        // Sizes are octal text, or big-endian binary when the top bit is set
+        let size = if header[124] & 0x80 != 0 {
+            header[125..136].iter().fold(0u64, |size, &b| (size << 8) | b as u64)
+        } else {
+            octal(&header, 124..136)
+        };
+        let padding = size.div_ceil(512) * 512 - size;
+        let kind = header[156];
+
+        if matches!(kind, b'L' | b'K' | b'x' | b'g') {
+            // GNU long name or link, or pax attributes, for the next entry
+            let mut data = Vec::new();
+            reader.by_ref().take(size).read_to_end(&mut data)?;
+            let text = String::from_utf8_lossy(&data).trim_end_matches('\0').to_string();
+            match kind {
+                b'L' => long_name = Some(text),
+                b'K' => long_link = Some(text),
+                b'x' => {
+                    for record in text.lines() {
+                        if let Some((_, path)) = record.split_once(" path=") {
+                            long_name = Some(path.to_string());
+                        } else if let Some((_, link)) = record.split_once(" linkpath=") {
+                            long_link = Some(link.to_string());
+                        }
+                    }
+                }
+                _ => {}
+            }
+        } else {
+            let name = long_name.take().unwrap_or_else(|| {
+                let prefix = if &header[257..262] == b"ustar" { field(&header, 345..500) } else { String::new() };
+                let name = field(&header, 0..100);
+                if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) }
+            });
+            let entry = TarEntry {
+                kind: if kind == b'5' || name.ends_with('/') { b'5' } else { kind },
+                name,
+                size,
+                mode: octal(&header, 100..108) as u32,
+                link: long_link.take().unwrap_or_else(|| field(&header, 157..257)),
+            };
+            let mut data = reader.by_ref().take(size);
+            visit(&entry, &mut data)?;
+            io::copy(&mut data, &mut io::sink())?;
+        }
+        io::copy(&mut reader.by_ref().take(padding), &mut io::sink())?;
+    }
+}
+
+fn list_tar(reader: impl io::Read) -> Result<Vec<ArchiveEntry>, String> {
+    let mut entries = Vec::new();
+    walk_tar(reader, |entry, _| {
+        let is_dir = entry.kind == b'5';
+        entries.push(ArchiveEntry { name: entry.name.clone(), size: if is_dir { 0 } else { entry.size }, is_dir });
+        Ok(())
+    })
+    .map_err(|e| e.to_string())?;
+    Ok(entries)
+}
+
+// Counts the bytes read through it, to report progress through an archive
+struct CountingReader<'a, R> {
+    inner: R,
+    count: &'a std::cell::Cell<u64>,
+}
+
+impl<R: io::Read> io::Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count.set(self.count.get() + read as u64);
+        Ok(read)
+    }
+}
+
+// Counts the bytes written through it, for the offsets a zip records
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: io::Write> io::Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Where an archive entry lands under `dest`, or None for names that would
+/// escape it: absolute paths, "..", a parent that isn't a plain directory, or
+/// an existing symlink in the entry's place. Nothing is created here.
+fn archive_entry_path(dest: &Path, name: &str) -> Option<PathBuf> {
+    use std::path::Component;
+    let relative = Path::new(name);
+    if relative.components().next().is_none() || !relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return None;
+    }
+    // Walk down from `dest` without following links: each existing parent has
+    // to be a real directory and the entry itself must not be a symlink
+    let mut path = dest.to_path_buf();
+    let mut components = relative.components().filter(|c| matches!(c, Component::Normal(_))).peekable();
+    while let Some(component) = components.next() {
+        path.push(component);
+        match path.symlink_metadata() {
+            Ok(meta) if meta.file_type().is_symlink() => return None,
+            Ok(meta) if components.peek().is_some() && !meta.is_dir() => return None,
+            Ok(_) => {}
+            // Missing from here down, so nothing deeper can be a link yet
+            Err(_) => return Some(dest.join(relative)),
+        }
+    }
+    Some(path)
+}
+
+/// Write one extracted entry: a directory, a symlink or a file read from `data`.
+/// `path` comes from archive_entry_path, so no existing link is followed.
+/// Permission bits are kept, but never setuid, setgid or sticky, as with tar
+/// and unzip run by a normal user.
+fn write_archive_entry(path: &Path, is_dir: bool, symlink: bool, mode: u32, data: &mut dyn io::Read) -> io::Result<()> {
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if is_dir {
+        fs::create_dir_all(path)?;
+        #[cfg(unix)]
+        if mode & 0o777 != 0 {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))?;
+        }
+    } else if symlink {
+        let mut target = String::new();
+        data.read_to_string(&mut target)?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(target, path)?;
+    } else {
+        // A later entry with the same name replaces the earlier one
+        if path.symlink_metadata().is_ok_and(|meta| meta.is_file()) {
+            fs::remove_file(path)?;
+        }
+        let mut file = fs::OpenOptions::new().write(true).create_new(true).open(path)?;
+        io::copy(data, &mut file)?;
+        #[cfg(unix)]
+        if mode & 0o777 != 0 {
+            file.set_permissions(fs::Permissions::from_mode(mode & 0o777))?;
+        }
+    }
+    Ok(())
+}
+
+/// Unpack a zip or (gzipped) tar archive into the existing directory `dest`,
+/// calling `progress` with the bytes of the archive read so far and its size.
+/// Returns how many entries were written and how many were skipped: unsafe
+/// paths, encrypted or unsupported zip entries and special files.
+fn extract_archive(path: &Path, dest: &Path, progress: &mut dyn FnMut(u64, u64)) -> io::Result<(usize, usize)> {
+    use io::{Read, Seek, SeekFrom};
+    let mut file = fs::File::open(path)?;
+    let total = file.metadata()?.len();
+    let (mut written, mut skipped) = (0, 0);
+
+    if archive_kind(path) == Some("zip") {
+        let entries = zip_directory(&mut file).map_err(io::Error::other)?;
+        for entry in entries {
+            progress(entry.offset, total);
+            let Some(target) = archive_entry_path(dest, &entry.name).filter(|_| !entry.encrypted && matches!(entry.method, 0 | 8)) else {
+                skipped += 1;
+                continue;
+            };
+            // The data follows the local header, whose name and extra field lengths can differ from the directory's
+            let mut header = [0u8; 30];
+            file.seek(SeekFrom::Start(entry.offset))?;
+            file.read_exact(&mut header)?;
+            let skip = 30 + u16::from_le_bytes([header[26], header[27]]) as u64 + u16::from_le_bytes([header[28], header[29]]) as u64;
+            file.seek(SeekFrom::Start(entry.offset + skip))?;
+            let raw = (&mut file).take(entry.compressed_size);
+            let mut data: Box<dyn Read> = if entry.method == 8 { Box::new(flate2::read::DeflateDecoder::new(raw)) } else { Box::new(raw) };
+            let symlink = entry.mode & 0o170000 == 0o120000;
+            write_archive_entry(&target, entry.name.ends_with('/'), symlink, entry.mode, &mut data)?;
+            written += 1;
+        }
+    } else {
+        let read = std::cell::Cell::new(0);
+        let counted = CountingReader { inner: io::BufReader::new(file), count: &read };
+        let reader: Box<dyn Read> = if archive_kind(path) == Some("tar.gz") {
+            Box::new(io::BufReader::new(flate2::read::GzDecoder::new(counted)))
+        } else {
+            Box::new(counted)
+        };
+        walk_tar(reader, |entry, data| {
+            progress(read.get(), total);
+            let target = archive_entry_path(dest, &entry.name);
+            match (target, entry.kind) {
+                (Some(target), b'0' | 0 | b'7' | b'5' | b'2') => {
+                    let mut link = io::Cursor::new(entry.link.clone().into_bytes());
+                    let data: &mut dyn Read = if entry.kind == b'2' { &mut link } else { data };
+                    write_archive_entry(&target, entry.kind == b'5', entry.kind == b'2', entry.mode, data)?;
+                    written += 1;
+                }
+                (Some(target), b'1') => {
+                    // Hard link to an entry extracted earlier
+                    match archive_entry_path(dest, &entry.link) {
+                        Some(original) if original.symlink_metadata().is_ok_and(|meta| meta.is_file()) => {
+                            let mut source = fs::File::open(original)?;
+                            write_archive_entry(&target, false, false, entry.mode, &mut source)?;
+                            written += 1;
+                        }
+                        _ => skipped += 1,
+                    }
+                }
+                _ => skipped += 1,
+            }
+            Ok(())
+        })?;
+    }
+    progress(total, total);
+    Ok((written, skipped))
+}
+
+/// `dir/` + `stem` + `suffix`, or the first of "stem (1)suffix", "stem (2)suffix", ...
+/// that doesn't exist yet.
+/// Move what was unpacked into the hidden directory `unpacked` to `dest`: its
+/// lone entry when `lifted`, otherwise the whole directory.
+fn place_extracted(unpacked: &Path, dest: &Path, lifted: bool) -> io::Result<()> {
+    if !lifted {
+        return fs::rename(unpacked, dest);
+    }
+    let only = fs::read_dir(unpacked)?.next().ok_or_else(|| io::Error::other("nothing was extracted"))??;
+    fs::rename(only.path(), dest)?;
+    fs::remove_dir(unpacked)
+}
+
+fn numbered_path(dir: &Path, stem: &str, suffix: &str) -> PathBuf {
+    let mut path = dir.join(format!("{}{}", stem, suffix));
+    let mut counter = 1;
+    while path.symlink_metadata().is_ok() {
+        path = dir.join(format!("{} ({}){}", stem, counter, suffix));
+        counter += 1;
+    }
+    path
+}
+
+/// An MS-DOS date and time as zip stores them, taken as UTC.
+fn dos_datetime(time: SystemTime) -> (u16, u16) {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (mut days, seconds) = (secs / 86400, secs % 86400);
+    let is_leap = |y: u64| (y.is_multiple_of(4) && !y.is_multiple_of(100)) || y.is_multiple_of(400);
+    let mut year = 1970;
+    while days >= if is_leap(year) { 366 } else { 365 } {
+        days -= if is_leap(year) { 366 } else { 365 };
+        year += 1;
+    }
+    if year < 1980 {
+        return (0, (1 << 5) | 1); // Zip dates start at 1980
+    }
+    let days_in_months = [31, if is_leap(year) { 29 } else { 28 }, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    let mut month = 0;
+    while days >= days_in_months[month] {
+        days -= days_in_months[month];
+        month += 1;
+    }
+    let time = ((seconds / 3600) << 11) | ((seconds % 3600 / 60) << 5) | ((seconds % 60) / 2);
+    let date = ((year - 1980) << 9) | ((month as u64 + 1) << 5) | (days + 1);
+    (time as u16, date as u16)
+}
+
+/// A tar header for one entry, preceded by a GNU long name entry when the name
+/// does not fit in the header's 100 bytes.
+fn tar_header(name: &str, size: u64, mode: u32, mtime: u64, kind: u8, link: &str) -> Vec<u8> {
+    let mut blocks = Vec::new();
+    if name.len() > 100 {
+        let mut long = name.as_bytes().to_vec();
+        long.push(0);
+        blocks.extend(tar_header("././@LongLink", long.len() as u64, 0, 0, b'L', ""));
+        long.resize(long.len().div_ceil(512) * 512, 0);
+        blocks.extend(long);
+    }
+    let mut header = [0u8; 512];
+    let put = |header: &mut [u8; 512], at: usize, bytes: &[u8], len: usize| {
+        let bytes = &bytes[..bytes.len().min(len)];
+        header[at..at + bytes.len()].copy_from_slice(bytes);
+    };
+    put(&mut header, 0, name.as_bytes(), 100);
+    put(&mut header, 100, format!("{:07o}\0", mode & 0o7777).as_bytes(), 8);
+    put(&mut header, 108, b"0000000\0", 8);
+    put(&mut header, 116, b"0000000\0", 8);
+    if size < 0o77777777777 {
+        put(&mut header, 124, format!("{:011o}\0", size).as_bytes(), 12);
+    } else {
+        // Too big for octal: big-endian binary marked by the top bit
+        header[124] = 0x80;
+        header[128..136].copy_from_slice(&size.to_be_bytes());
+    }
+    put(&mut header, 136, format!("{:011o}\0", mtime.min(0o77777777777)).as_bytes(), 12);
+    header[156] = kind;
+    put(&mut header, 157, link.as_bytes(), 100);
+    put(&mut header, 257, b"ustar\x0000", 8);
+    // The checksum is taken with its own field as spaces
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    put(&mut header, 148, format!("{:06o}\0 ", checksum).as_bytes(), 8);
+    blocks.extend_from_slice(&header);
+    blocks
+}
+
+/// Pack `sources` into a new zip or tar.gz at `archive`, naming entries by their
+/// path below `base`, and calling `progress` with the bytes packed so far and
+/// the total. Returns how many files went in.
+fn write_archive(sources: &[PathBuf], base: &Path, archive: &Path, format: &str, progress: &mut dyn FnMut(u64, u64)) -> io::Result<usize> {
+    use io::{Read, Write};
+
+    // Everything to pack, parents before children
+    let mut items = Vec::new();
+    let mut stack: Vec<PathBuf> = sources.iter().rev().cloned().collect();
+    while let Some(path) = stack.pop() {
+        let metadata = path.symlink_metadata()?;
+        if metadata.is_dir() {
+            let mut children: Vec<PathBuf> = fs::read_dir(&path)?.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+            children.sort();
+            stack.extend(children.into_iter().rev());
+        }
+        let mut name = path.strip_prefix(base).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+        if metadata.is_dir() {
+            name.push('/');
+        }
+        items.push((path, name, metadata));
+    }
+    let total: u64 = items.iter().filter(|(_, _, m)| m.is_file()).map(|(_, _, m)| m.len()).sum();
+    #[cfg(unix)]
+    let mode_of = |metadata: &fs::Metadata| std::os::unix::fs::MetadataExt::mode(metadata);
+    #[cfg(not(unix))]
+    let mode_of = |metadata: &fs::Metadata| if metadata.is_dir() { 0o40755 } else { 0o100644 };
+
+    let mut done = 0;
+    let mut files = 0;
+    let mut buffer = vec![0u8; 64 * 1024];
+    if format == "zip" {
+        if items.len() >= 0xFFFF || items.iter().any(|(_, _, m)| m.len() >= 0xFFFF_FFFF) {
+            return Err(io::Error::other("too large for zip, use tar.gz"));
+        }
+        let mut out = CountingWriter { inner: io::BufWriter::new(fs::File::create(archive)?), count: 0 };
+        let mut directory = Vec::new();
+        for (path, name, metadata) in &items {
+            let offset = out.count;
+            let (time, date) = dos_datetime(metadata.modified().unwrap_or(UNIX_EPOCH));
+            let is_file = metadata.is_file();
+            let link = if metadata.file_type().is_symlink() { fs::read_link(path)?.to_string_lossy().into_owned() } else { String::new() };
+            // Files are deflated while read, so their crc and sizes follow the data
+            let (flags, method): (u16, u16) = if is_file { (0x0808, 8) } else { (0x0800, 0) };
+            let mut crc = flate2::Crc::new();
+            crc.update(link.as_bytes());
+            let mut local = Vec::new();
+            local.extend_from_slice(&[0x50, 0x4b, 0x03, 0x04, 20, 0]);
+            local.extend_from_slice(&flags.to_le_bytes());
+            local.extend_from_slice(&method.to_le_bytes());
+            local.extend_from_slice(&time.to_le_bytes());
+            local.extend_from_slice(&date.to_le_bytes());
+            for value in [if is_file { 0 } else { crc.sum() }, link.len() as u32, link.len() as u32] {
+                local.extend_from_slice(&value.to_le_bytes());
+            }
+            local.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            local.extend_from_slice(&[0, 0]);
+            local.extend_from_slice(name.as_bytes());
+            out.write_all(&local)?;
+
+            let (mut compressed, mut size) = (link.len() as u64, link.len() as u64);
+            if is_file {
+                let start = out.count;
+                let mut encoder = flate2::write::DeflateEncoder::new(&mut out, flate2::Compression::default());
+                let mut input = fs::File::open(path)?;
+                size = 0;
+                loop {
+                    let read = input.read(&mut buffer)?;
+                    if read == 0 {
+                        break;
+                    }
+                    crc.update(&buffer[..read]);
+                    encoder.write_all(&buffer[..read])?;
+                    size += read as u64;
+                    done += read as u64;
+                    progress(done, total);
+                }
+                encoder.finish()?;
+                compressed = out.count - start;
+                let mut descriptor = vec![0x50, 0x4b, 0x07, 0x08];
+                for value in [crc.sum(), compressed as u32, size as u32] {
+                    descriptor.extend_from_slice(&value.to_le_bytes());
+                }
+                out.write_all(&descriptor)?;
+                files += 1;
+            } else if !link.is_empty() {
+                out.write_all(link.as_bytes())?;
+            }
+            if out.count >= 0xFFFF_FFFF {
+                return Err(io::Error::other("too large for zip, use tar.gz"));
+            }
+
+            let mut record = vec![0x50, 0x4b, 0x01, 0x02, 20, 3, 20, 0];
+            record.extend_from_slice(&flags.to_le_bytes());
+            record.extend_from_slice(&method.to_le_bytes());
+            record.extend_from_slice(&time.to_le_bytes());
+            record.extend_from_slice(&date.to_le_bytes());
+            for value in [crc.sum(), compressed as u32, size as u32] {
+                record.extend_from_slice(&value.to_le_bytes());
+            }
+            record.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            record.extend_from_slice(&[0; 8]); // Extra field, comment, disk and internal attributes
+            let external = (mode_of(metadata) << 16) | if metadata.is_dir() { 0x10 } else { 0 };
+            record.extend_from_slice(&external.to_le_bytes());
+            record.extend_from_slice(&(offset as u32).to_le_bytes());
+            record.extend_from_slice(name.as_bytes());
+            directory.extend(record);
+        }
+        let directory_offset = out.count;
+        out.write_all(&directory)?;
+        let mut end = vec![0x50, 0x4b, 0x05, 0x06, 0, 0, 0, 0];
+        end.extend_from_slice(&(items.len() as u16).to_le_bytes());
+        end.extend_from_slice(&(items.len() as u16).to_le_bytes());
+        end.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        end.extend_from_slice(&(directory_offset as u32).to_le_bytes());
+        end.extend_from_slice(&[0, 0]);
+        out.write_all(&end)?;
+        out.flush()?;
+    } else {
+        let mut out = flate2::write::GzEncoder::new(io::BufWriter::new(fs::File::create(archive)?), flate2::Compression::default());
+        for (path, name, metadata) in &items {
+            let mtime = metadata.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs());
+            let mode = mode_of(metadata);
+            if metadata.file_type().is_symlink() {
+                out.write_all(&tar_header(name, 0, mode, mtime, b'2', &fs::read_link(path)?.to_string_lossy()))?;
+            } else if metadata.is_dir() {
+                out.write_all(&tar_header(name, 0, mode, mtime, b'5', ""))?;
+            } else if metadata.is_file() {
+                out.write_all(&tar_header(name, metadata.len(), mode, mtime, b'0', ""))?;
+                let mut input = fs::File::open(path)?.take(metadata.len());
+                let mut size = 0;
+                loop {
+                    let read = input.read(&mut buffer)?;
+                    if read == 0 {
+                        break;
+                    }
+                    out.write_all(&buffer[..read])?;
+                    size += read as u64;
+                    done += read as u64;
+                    progress(done, total);
+                }
+                if size < metadata.len() {
+                    return Err(io::Error::other(format!("{} shrank while being packed", name)));
+                }
+                out.write_all(&vec![0; (size.div_ceil(512) * 512 - size) as usize])?;
+                files += 1;
+            }
+        }
+        out.write_all(&[0; 1024])?;
+        out.finish()?.flush()?;
+    }
+    Ok(files)
+}
+
+/// Preview lines for an archive: a summary, then each entry with its size.
+fn archive_lines(entries: &[ArchiveEntry], locale: Locale) -> Vec<String> {
+    let units = ["B", "KB", "MB", "GB"];
+    let files = entries.iter().filter(|entry| !entry.is_dir).count();
+    let total: u64 = entries.iter().map(|entry| entry.size).sum();
+    let mut lines = vec![
+        format!(
+            "{} {}, {} {}, {} unpacked",
+            files,
+            if files == 1 { "file" } else { "files" },
+            entries.len() - files,
+            if entries.len() - files == 1 { "directory" } else { "directories" },
+            locale.format_size(total, &units)
+        ),
+        String::new(),
+    ];
+    lines.extend(entries.iter().map(|entry| {
+        let size = if entry.is_dir { String::new() } else { locale.format_size(entry.size, &units) };
+        format!("{:>10}  {}", size, entry.name)
+    }));
+    lines
+}
+
+// Duration and streams of an audio or video file, read from its container headers
+#[derive(Clone, Default)]
+struct MediaInfo {
+    duration: Option<f64>, // Seconds
+    streams: Vec<MediaStream>,
+}
+
+#[derive(Clone)]
+enum MediaStream {
+    Video { codec: String, width: u32, height: u32 },
+    Audio { codec: String, sample_rate: u32, channels: u32 },
+}
+
+impl MediaInfo {
+    fn resolution(&self) -> Option<(u32, u32)> {
+        self.streams.iter().find_map(|stream| match stream {
+            MediaStream::Video { width, height, .. } if *width > 0 => Some((*width, *height)),
+            _ => None,
+        })
+    }
+}
+
+/// The container format of a media file by its extension, and whether it is audio only.
+fn media_format(path: &Path) -> Option<(&'static str, bool)> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    Some(match extension.as_str() {
+        "mp4" | "m4v" => ("MP4", false),
+        "mov" => ("QuickTime", false),
+        "m4a" => ("MP4", true),
+        "mkv" => ("Matroska", false),
+        "mka" => ("Matroska", true),
+        "webm" => ("WebM", false),
+        "wav" => ("WAV", true),
+        "flac" => ("FLAC", true),
+        "mp3" => ("MP3", true),
+        _ => return None,
+    })
+}
+
+/// Read duration, codecs, resolution and sample rate from a media file's headers.
+/// No decoding is done and the parsers are built in, so nothing like ffmpeg is needed.
+fn read_media_info(path: &Path) -> Result<MediaInfo, String> {
+    let (format, _) = media_format(path).ok_or("not a media file")?;
+    let mut file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let info = match format {
+        "MP4" | "QuickTime" => mp4_info(&mut file),
+        "Matroska" | "WebM" => matroska_info(&mut file),
+        "WAV" => wav_info(&mut file),
+        "FLAC" => flac_info(&mut file),
+        _ => mp3_info(&mut file),
+    };
+    info.ok_or_else(|| format!("cannot read {} headers", format))
+}
+
+/// Child boxes of an MP4 box body as (type, body) pairs.
+fn mp4_boxes(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut boxes = Vec::new();
+    let mut at = 0;
+    while at + 8 <= data.len() {
+        let size = u32::from_be_bytes(data[at..at + 4].try_into().unwrap()) as usize;
+        let (header, size) = match size {
+            0 => (8, data.len() - at),
+            1 if at + 16 <= data.len() => (16, usize::try_from(u64::from_be_bytes(data[at + 8..at + 16].try_into().unwrap())).unwrap_or(usize::MAX)),
+            _ => (8, size),
+        };
+        if size < header || size > data.len() - at {
+            break;
+        }
+        boxes.push((&data[at + 4..at + 8], &data[at + header..at + size]));
+        at += size;
+    }
+    boxes
+}
+
+fn mp4_info(file: &mut (impl io::Read + io::Seek)) -> Option<MediaInfo> {
+    use io::{Read, SeekFrom};
+    let be16 = |bytes: &[u8], at: usize| bytes.get(at..at + 2).map(|b| u16::from_be_bytes([b[0], b[1]]) as u64);
+    let be32 = |bytes: &[u8], at: usize| bytes.get(at..at + 4).map(|b| u32::from_be_bytes(b.try_into().unwrap()) as u64);
+    let be64 = |bytes: &[u8], at: usize| bytes.get(at..at + 8).map(|b| u64::from_be_bytes(b.try_into().unwrap()));
+    let child = |data: &'_ [u8], kind: &[u8]| mp4_boxes(data).into_iter().find(|(k, _)| *k == kind).map(|(_, body)| body.to_vec());
+
+    // The movie box can come before or after the media data, so hop over the top-level boxes
+    let len = file.seek(SeekFrom::End(0)).ok()?;
+    let mut at = 0;
+    let moov = loop {
+        if at + 8 > len {
+            return None;
+        }
+        let mut header = [0u8; 16];
+        file.seek(SeekFrom::Start(at)).ok()?;
+        file.read_exact(&mut header[..8]).ok()?;
+        let mut size = be32(&header, 0)?;
+        let mut header_len = 8;
+        if size == 1 {
+            file.read_exact(&mut header[8..]).ok()?;
+            size = be64(&header, 8)?;
+            header_len = 16;
+        } else if size == 0 {
+            size = len - at;
+        }
+        if size < header_len {
+            return None;
+        }
+        if &header[4..8] == b"moov" {
+            let mut moov = Vec::new();
+            file.take((size - header_len).min(64 * 1024 * 1024)).read_to_end(&mut moov).ok()?;
+            break moov;
+        }
+        at = at.checked_add(size).filter(|&next| next <= len)?;
+    };
+
+    let mut info = MediaInfo::default();
+    if let Some(mvhd) = child(&moov, b"mvhd") {
+        let (timescale, duration) = if *mvhd.first()? == 1 { (be32(&mvhd, 20)?, be64(&mvhd, 24)?) } else { (be32(&mvhd, 12)?, be32(&mvhd, 16)?) };
+        if timescale > 0 {
+            info.duration = Some(duration as f64 / timescale as f64);
+        }
+    }
+    for (kind, trak) in mp4_boxes(&moov) {
+        if kind != b"trak" {
+            continue;
+        }
+        let Some(mdia) = child(trak, b"mdia") else { continue };
+        let handler = child(&mdia, b"hdlr").and_then(|hdlr| hdlr.get(8..12).map(|h| h.to_vec()));
+        let Some(stsd) = child(&mdia, b"minf").and_then(|minf| child(&minf, b"stbl")).and_then(|stbl| child(&stbl, b"stsd")) else { continue };
+        // The first sample entry: size, codec fourcc, then the codec's fields
+        let Some(entry) = stsd.get(8..) else { continue };
+        let Some(fourcc) = entry.get(4..8) else { continue };
+        let codec = match fourcc {
+            b"avc1" | b"avc3" => String::from("H.264"),
+            b"hvc1" | b"hev1" => String::from("HEVC"),
+            b"av01" => String::from("AV1"),
+            b"vp09" => String::from("VP9"),
+            b"mp4v" => String::from("MPEG-4"),
+            b"mp4a" => String::from("AAC"),
+            b"ac-3" => String::from("AC-3"),
+            b"ec-3" => String::from("E-AC-3"),
+            b"alac" => String::from("ALAC"),
+            b"Opus" => String::from("Opus"),
+            b"fLaC" => String::from("FLAC"),
+            other => String::from_utf8_lossy(other).trim().to_string(),
+        };
+        match handler.as_deref() {
+            Some(b"vide") => info.streams.push(MediaStream::Video {
+                codec,
+                width: be16(entry, 32).unwrap_or(0) as u32,
+                height: be16(entry, 34).unwrap_or(0) as u32,
+            }),
+            Some(b"soun") => info.streams.push(MediaStream::Audio {
+                codec,
+                sample_rate: (be32(entry, 32).unwrap_or(0) >> 16) as u32,
+                channels: be16(entry, 24).unwrap_or(0) as u32,
+            }),
+            _ => {}
+        }
+    }
+    Some(info)
+}
+
+/// An EBML variable-length number at `at`: its value and length. IDs keep their
+/// marker bit, sizes drop it.
+fn ebml_vint(data: &[u8], at: usize, keep_marker: bool) -> Option<(u64, usize)> {
+    let first = *data.get(at)?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 || at + len > data.len() {
+        return None;
+    }
+    let mut value = if keep_marker { first as u64 } else { (first as u64) & (0xFF >> len) };
+    for &byte in &data[at + 1..at + len] {
+        value = (value << 8) | byte as u64;
+    }
+    Some((value, len))
+}
+
+/// Matroska elements in `data` as (id, body) pairs. An element of unknown or
+/// overlong size runs to the end of the data.
+fn ebml_elements(data: &[u8]) -> Vec<(u64, &[u8])> {
+    let mut elements = Vec::new();
+    let mut at = 0;
+    while let Some((id, id_len)) = ebml_vint(data, at, true) {
+        let Some((size, size_len)) = ebml_vint(data, at + id_len, false) else { break };
+        let start = at + id_len + size_len;
+        let end = start.saturating_add(size as usize).min(data.len());
+        elements.push((id, &data[start..end]));
+        at = end;
+    }
+    elements
+}
+
+fn matroska_info(file: &mut impl io::Read) -> Option<MediaInfo> {
+    use io::Read;
+    let uint = |body: &[u8]| body.iter().fold(0u64, |value, &b| (value << 8) | b as u64);
+    let float = |body: &[u8]| match body.len() {
+        4 => Some(f32::from_be_bytes(body.try_into().unwrap()) as f64),
+        8 => Some(f64::from_be_bytes(body.try_into().unwrap())),
+        _ => None,
+    };
+
+    // Segment info and tracks come before the clusters of media data
+    let mut head = Vec::new();
+    file.take(4 * 1024 * 1024).read_to_end(&mut head).ok()?;
+    let top = ebml_elements(&head);
+    if top.first()?.0 != 0x1A45DFA3 {
+        return None;
+    }
+    let (_, segment) = top.into_iter().find(|(id, _)| *id == 0x18538067)?;
+
+    let mut info = MediaInfo::default();
+    for (id, body) in ebml_elements(segment) {
+        match id {
+            0x1549A966 => {
+                let mut scale = 1_000_000;
+                let mut duration = None;
+                for (id, body) in ebml_elements(body) {
+                    match id {
+                        0x2AD7B1 => scale = uint(body),
+                        0x4489 => duration = float(body),
+                        _ => {}
+                    }
+                }
+                info.duration = duration.map(|ticks| ticks * scale as f64 / 1e9);
+            }
+            0x1654AE6B => {
+                for (id, track) in ebml_elements(body) {
+                    if id != 0xAE {
+                        continue;
+                    }
+                    let mut kind = 0;
+                    let mut codec = String::new();
+                    let (mut width, mut height, mut sample_rate, mut channels) = (0, 0, 8000.0, 1);
+                    for (id, body) in ebml_elements(track) {
+                        match id {
+                            0x83 => kind = uint(body),
+                            0x86 => codec = String::from_utf8_lossy(body).trim_end_matches('\0').to_string(),
+                            0xE0 => {
+                                for (id, body) in ebml_elements(body) {
+                                    match id {
+                                        0xB0 => width = uint(body) as u32,
+                                        0xBA => height = uint(body) as u32,
+                                        _ => {}
+                                    }
+                                }
+                            }
+                            0xE1 => {
+                                for (id, body) in ebml_elements(body) {
+                                    match id {
+                                        0xB5 => sample_rate = float(body).unwrap_or(sample_rate),
+                                        0x9F => channels = uint(body) as u32,
+                                        _ => {}
+                                    }
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    let codec = match codec.as_str() {
+                        "V_MPEG4/ISO/AVC" => String::from("H.264"),
+                        "V_MPEGH/ISO/HEVC" => String::from("HEVC"),
+                        "A_AAC" => String::from("AAC"),
+                        "A_OPUS" => String::from("Opus"),
+                        "A_VORBIS" => String::from("Vorbis"),
+                        other => other.split_once('_').map_or(other, |(_, name)| name).to_string(),
+                    };
+                    match kind {
+                        1 => info.streams.push(MediaStream::Video { codec, width, height }),
+                        2 => info.streams.push(MediaStream::Audio { codec, sample_rate: sample_rate as u32, channels }),
+                        _ => {}
+                    }
+                }
+            }
+            0x1F43B675 => break, // First cluster
+            _ => {}
+        }
+    }
+    Some(info)
+}
+
+fn wav_info(file: &mut (impl io::Read + io::Seek)) -> Option<MediaInfo> {
+    use io::SeekFrom;
+    let mut riff = [0u8; 12];
+    file.read_exact(&mut riff).ok()?;
+    if &riff[..4] != b"RIFF" || &riff[8..] != b"WAVE" {
+        return None;
+    }
+    let (mut stream, mut byte_rate, mut data_len) = (None, 0, None);
+    let mut header = [0u8; 8];
+    while file.read_exact(&mut header).is_ok() {
+        let size = u32::from_le_bytes(header[4..].try_into().unwrap()) as u64;
+        match &header[..4] {
+            b"fmt " => {
+                let mut fmt = [0u8; 16];
+                file.read_exact(&mut fmt).ok()?;
+                let le16 = |at: usize| u16::from_le_bytes([fmt[at], fmt[at + 1]]) as u32;
+                let le32 = |at: usize| u32::from_le_bytes(fmt[at..at + 4].try_into().unwrap());
+                byte_rate = le32(8);
+                let codec = match le16(0) {
+                    1 | 0xFFFE => format!("PCM {}-bit", le16(14)),
+                    3 => format!("float {}-bit", le16(14)),
+                    6 => String::from("A-law"),
+                    7 => String::from("mu-law"),
+                    other => format!("format {:#x}", other),
+                };
+                stream = Some(MediaStream::Audio { codec, sample_rate: le32(4), channels: le16(2) });
+                // A chunk too short for its fields isn't read into twice
+                file.seek(SeekFrom::Current(((size + size % 2) as i64 - 16).max(0))).ok()?;
+            }
+            b"data" => {
+                data_len = Some(size);
+                break;
+            }
+            _ => {
+                file.seek(SeekFrom::Current((size + size % 2) as i64)).ok()?;
+            }
+        }
+    }
+    Some(MediaInfo {
+        duration: data_len.filter(|_| byte_rate > 0).map(|len| len as f64 / byte_rate as f64),
+        streams: stream.into_iter().collect(),
+    })
+}
+
+fn flac_info(file: &mut impl io::Read) -> Option<MediaInfo> {
+    // "fLaC", then the STREAMINFO block, which always comes first
+    let mut head = [0u8; 42];
+    file.read_exact(&mut head).ok()?;
+    if &head[..4] != b"fLaC" || head[4] & 0x7F != 0 {
+        return None;
+    }
+    let packed = u64::from_be_bytes(head[18..26].try_into().unwrap());
+    let sample_rate = (packed >> 44) as u32;
+    let channels = ((packed >> 41) & 0x7) as u32 + 1;
+    let samples = packed & 0xF_FFFF_FFFF;
+    Some(MediaInfo {
+        duration: (sample_rate > 0 && samples > 0).then(|| samples as f64 / sample_rate as f64),
+        streams: vec![MediaStream::Audio { codec: String::from("FLAC"), sample_rate, channels }],
+    })
+}
+
+fn mp3_info(file: &mut (impl io::Read + io::Seek)) -> Option<MediaInfo> {
+    use io::{Read, SeekFrom};
+    let len = file.seek(SeekFrom::End(0)).ok()?;
+    file.seek(SeekFrom::Start(0)).ok()?;
+    let mut head = Vec::new();
+    file.take(256 * 1024).read_to_end(&mut head).ok()?;
+
+    // Skip an ID3v2 tag, whose size is stored 7 bits per byte
+    let mut start = 0;
+    if head.starts_with(b"ID3") && head.len() >= 10 {
+        start = 10 + head[6..10].iter().fold(0usize, |size, &b| (size << 7) | (b & 0x7F) as usize);
+    }
+    let frame = (start..head.len().saturating_sub(4)).find(|&at| head[at] == 0xFF && head[at + 1] & 0xE6 == 0xE2)?;
+    let header = &head[frame..frame + 4];
+    let version = (header[1] >> 3) & 0x3; // 3 = MPEG-1, 2 = MPEG-2, 0 = MPEG-2.5
+    let mpeg1 = version == 3;
+    const BITRATES_V1: [u32; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+    const BITRATES_V2: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+    let bitrate = *(if mpeg1 { &BITRATES_V1 } else { &BITRATES_V2 }).get((header[2] >> 4) as usize)?;
+    let sample_rate = [44100, 48000, 32000].get(((header[2] >> 2) & 0x3) as usize)? >> match version {
+        3 => 0,
+        2 => 1,
+        _ => 2,
+    };
+    let channels = if header[3] >> 6 == 3 { 1 } else { 2 };
+
+    // A Xing or Info frame after the side information gives the frame count of
+    // variable bitrate files; otherwise the bitrate is constant
+    let samples_per_frame = if mpeg1 { 1152 } else { 576 };
+    let side_info = match (mpeg1, channels) {
+        (true, 1) | (false, 2) => 17,
+        (true, _) => 32,
+        (false, _) => 9,
+    };
+    let xing = frame + 4 + side_info;
+    let frames = head.get(xing..xing + 12).filter(|tag| (&tag[..4] == b"Xing" || &tag[..4] == b"Info") && tag[7] & 1 != 0)
+        .map(|tag| u32::from_be_bytes(tag[8..12].try_into().unwrap()));
+    let duration = match frames {
+        Some(frames) => Some(frames as f64 * samples_per_frame as f64 / sample_rate as f64),
+        None if bitrate > 0 => Some(len.saturating_sub(frame as u64) as f64 * 8.0 / (bitrate as f64 * 1000.0)),
+        None => None,
+    };
+    Some(MediaInfo {
+        duration,
+        streams: vec![MediaStream::Audio { codec: String::from("MP3"), sample_rate, channels }],
+    })
+}
+
+/// A duration as "3:07" or "1:23:45".
+fn format_duration(seconds: f64) -> String {
+    let total = seconds.round() as u64;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// Preview lines for a media file: its duration, then one line per stream.
+fn media_lines(info: &MediaInfo) -> Vec<String> {
+    let mut lines = vec![format!("Duration: {}", info.duration.map_or_else(|| String::from("unknown"), format_duration))];
+    for stream in &info.streams {
+        lines.push(match stream {
+            MediaStream::Video { codec, width, height } => format!("Video: {}, {}x{}", codec, width, height),
+            MediaStream::Audio { codec, sample_rate, channels } => {
+                let layout = match channels {
+                    1 => String::from("mono"),
+                    2 => String::from("stereo"),
+                    6 => String::from("5.1"),
+                    8 => String::from("7.1"),
+                    n => format!("{} channels", n),
+                };
+                format!("Audio: {}, {} Hz, {}", codec, sample_rate, layout)
+            }
+        });
+    }
+    lines
+}
+
+/// Decode a file accepted by `is_apple_encoded` into readable lines. Plists are
+/// shown as XML with binary data blobs summarized by size.
+fn decode_apple_file(path: &Path) -> Result<Vec<String>, String> {
+    fn summarize_data(value: &mut plist::Value) {
+        match value {
+            plist::Value::Data(data) => *value = plist::Value::String(format!("({} bytes of data)", data.len())),
+            plist::Value::Array(items) => items.iter_mut().for_each(summarize_data),
+            plist::Value::Dictionary(dict) => dict.values_mut().for_each(summarize_data),
+            _ => {}
+        }
+    }
+
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    let mut value = if bytes.starts_with(b"bplist") {
+        plist::Value::from_reader(io::Cursor::new(&bytes)).map_err(|e| e.to_string())?
+    } else if bytes.starts_with(&[0xFF, 0xFE]) || bytes.starts_with(&[0xFE, 0xFF]) {
+        // UTF-16 .strings file: already readable once decoded
+        let little_endian = bytes[0] == 0xFF;
+        let units: Vec<u16> = bytes[2..]
+            .chunks_exact(2)
+            .map(|pair| if little_endian { u16::from_le_bytes([pair[0], pair[1]]) } else { u16::from_be_bytes([pair[0], pair[1]]) })
+            .collect();
+        return Ok(String::from_utf16_lossy(&units).lines().map(|line| line.to_string()).collect());
+    } else {
+        // Provisioning profile: an XML plist wrapped in a CMS signature
+        let find = |bytes: &[u8], needle: &[u8]| bytes.windows(needle.len()).position(|window| window == needle);
+        let Some(start) = find(&bytes, b"<?xml") else {
+            return Err(String::from("no property list found"));
+        };
+        let Some(end) = find(&bytes[start..], b"</plist>").map(|end| start + end + b"</plist>".len()) else {
+            return Err(String::from("no property list found"));
+        };
+        plist::Value::from_reader_xml(&bytes[start..end]).map_err(|e| e.to_string())?
+    };
+    summarize_data(&mut value);
+
+    let mut xml = Vec::new();
+    value.to_writer_xml(&mut xml).map_err(|e| e.to_string())?;
+    Ok(String::from_utf8_lossy(&xml).lines().map(|line| line.to_string()).collect())
+}
+
+/// Lay out notebook cells for the preview, along with the line each cell starts on.
+fn notebook_lines(cells: &[NotebookCell]) -> (Vec<NotebookLine<'_>>, Vec<usize>) {
+    let mut lines = Vec::new();
+    let mut cell_starts = Vec::new();
+    for cell in cells {
+        cell_starts.push(lines.len());
+        let header = match (cell.kind.as_str(), cell.execution_count) {
+            ("code", Some(count)) => format!("In [{}]", count),
+            ("code", None) => String::from("In [ ]"),
+            ("markdown", _) => String::from("Markdown"),
+            (kind, _) => kind.to_string(),
+        };
+        lines.push(NotebookLine::Header(header));
+        for line in &cell.source {
+            lines.push(if cell.kind == "markdown" { NotebookLine::Markdown(line) } else { NotebookLine::Code(line) });
+        }
+        lines.extend(cell.outputs.iter().map(|line| NotebookLine::Output(line)));
+        lines.push(NotebookLine::Blank);
+    }
+    (lines, cell_starts)
+}
+
+/// Largest JSON, YAML or TOML file the preview lays out, small enough to parse
+/// within a frame; bigger ones are shown as text.
+const DATA_PREVIEW_MAX_BYTES: u64 = 1024 * 1024;
+
+/// Structured previews longer than this start with their top-level entries folded.
+const DATA_FOLD_LINES: usize = 500;
+
+// A value of a JSON, YAML or TOML document, with maps in document order
+enum DataNode {
+    Null,
+    Bool(bool),
+    Number(String),
+    Text(String),
+    List(Vec<DataNode>),
+    Map(Vec<(String, DataNode)>),
+}
+
+// A line of a structured preview, as colored spans
+type DataLine = Vec<(Color, String)>;
+
+/// The structured format `path` is previewed as, by its extension.
+fn data_format(path: &Path) -> Option<&'static str> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    match extension.as_str() {
+        "json" | "geojson" => Some("JSON"),
+        "yaml" | "yml" => Some("YAML"),
+        "toml" => Some("TOML"),
+        _ => None,
+    }
+}
+
+/// Parse a JSON, YAML or TOML document. A failure comes with the line it was
+/// found on, when the parser says.
+fn parse_data(text: &str, format: &str) -> Result<DataNode, (String, Option<usize>)> {
+    match format {
+        "JSON" => serde_json::from_str(text).map(json_node).map_err(|e| (e.to_string(), Some(e.line()))),
+        "YAML" => {
+            let mut documents = yaml_rust2::YamlLoader::load_from_str(text).map_err(|e| (e.to_string(), Some(e.marker().line())))?;
+            // A stream of several documents is shown as a list of them
+            Ok(match documents.len() {
+                0 => DataNode::Null,
+                1 => yaml_node(documents.remove(0)),
+                _ => DataNode::List(documents.into_iter().map(yaml_node).collect()),
+            })
+        }
+        _ => {
+            let document = text.parse::<toml_edit::DocumentMut>().map_err(|e| {
+                let line = e.span().map(|span| text[..span.start.min(text.len())].matches('\n').count() + 1);
+                (e.message().to_string(), line)
+            })?;
+            Ok(toml_table_node(document.as_table()))
+        }
+    }
+}
+
+fn json_node(value: serde_json::Value) -> DataNode {
+    match value {
+        serde_json::Value::Null => DataNode::Null,
+        serde_json::Value::Bool(b) => DataNode::Bool(b),
+        serde_json::Value::Number(n) => DataNode::Number(n.to_string()),
+        serde_json::Value::String(s) => DataNode::Text(s),
+        serde_json::Value::Array(items) => DataNode::List(items.into_iter().map(json_node).collect()),
+        serde_json::Value::Object(map) => DataNode::Map(map.into_iter().map(|(k, v)| (k, json_node(v))).collect()),
+    }
+}
+
+fn yaml_node(value: yaml_rust2::Yaml) -> DataNode {
+    use yaml_rust2::Yaml;
+    let key_text = |key: Yaml| match key {
+        Yaml::String(s) | Yaml::Real(s) => s,
+        Yaml::Integer(i) => i.to_string(),
+        Yaml::Boolean(b) => b.to_string(),
+        Yaml::Null => "~".to_string(),
+        _ => "?".to_string(),
+    };
+    match value {
+        Yaml::Null | Yaml::BadValue | Yaml::Alias(_) => DataNode::Null,
+        Yaml::Boolean(b) => DataNode::Bool(b),
+        Yaml::Integer(i) => DataNode::Number(i.to_string()),
+        Yaml::Real(r) => DataNode::Number(r),
+        Yaml::String(s) => DataNode::Text(s),
+        Yaml::Array(items) => DataNode::List(items.into_iter().map(yaml_node).collect()),
+        Yaml::Hash(map) => DataNode::Map(map.into_iter().map(|(k, v)| (key_text(k), yaml_node(v))).collect()),
+    }
+}
+
+fn toml_table_node(table: &toml_edit::Table) -> DataNode {
+    DataNode::Map(table.iter().map(|(key, item)| (key.to_string(), toml_item_node(item))).collect())
+}
+
+fn toml_item_node(item: &toml_edit::Item) -> DataNode {
+    match item {
+        toml_edit::Item::None => DataNode::Null,
+        toml_edit::Item::Value(value) => toml_value_node(value),
+        toml_edit::Item::Table(table) => toml_table_node(table),
+        toml_edit::Item::ArrayOfTables(tables) => DataNode::List(tables.iter().map(toml_table_node).collect()),
+    }
+}
+
+fn toml_value_node(value: &toml_edit::Value) -> DataNode {
+    use toml_edit::Value;
+    match value {
+        Value::String(s) => DataNode::Text(s.value().clone()),
+        Value::Integer(i) => DataNode::Number(i.value().to_string()),
+        Value::Float(f) => DataNode::Number(f.value().to_string()),
+        Value::Boolean(b) => DataNode::Bool(*b.value()),
+        Value::Datetime(d) => DataNode::Number(d.value().to_string()),
+        Value::Array(items) => DataNode::List(items.iter().map(toml_value_node).collect()),
+        Value::InlineTable(table) => DataNode::Map(table.iter().map(|(key, value)| (key.to_string(), toml_value_node(value))).collect()),
+    }
+}
+
+/// Lay a document out as indented `key: value` lines and `- item` lists. With
+/// `folded`, top-level entries holding more than a value show only their size.
+fn data_lines(root: &DataNode, folded: bool) -> Vec<DataLine> {
+    let mut lines = Vec::new();
+    match root {
+        DataNode::Map(entries) if !entries.is_empty() => {
+            for (key, value) in entries {
+                push_data_lines(&mut lines, value, 0, Some(key), folded);
+            }
+        }
+        DataNode::List(items) if !items.is_empty() => {
+            for item in items {
+                push_data_lines(&mut lines, item, 0, None, folded);
+            }
+        }
+        scalar => push_data_lines(&mut lines, scalar, 0, None, false),
+    }
+    lines
+}
+
+/// Add the lines of `node` at `depth`, under its key or as a list item when it has no key.
+fn push_data_lines(lines: &mut Vec<DataLine>, node: &DataNode, depth: usize, key: Option<&str>, fold: bool) {
+    let mut line: DataLine = vec![(Color::Reset, "  ".repeat(depth))];
+    match key {
+        Some(key) => line.push((Color::Blue, key.to_string())),
+        None => line.push((Color::DarkGrey, "-".to_string())),
+    }
+    let (children, count, unit) = match node {
+        DataNode::Map(entries) => (!entries.is_empty(), entries.len(), ("key", "keys", "{}")),
+        DataNode::List(items) => (!items.is_empty(), items.len(), ("item", "items", "[]")),
+        _ => (false, 0, ("", "", "")),
+    };
+    line.push((Color::DarkGrey, if key.is_some() { ":".to_string() } else { String::new() }));
+    if children && fold {
+        line.push((Color::DarkGrey, format!(" {} {}", count, if count == 1 { unit.0 } else { unit.1 })));
+        lines.push(line);
+        return;
+    }
+    if children {
+        lines.push(line);
+        match node {
+            DataNode::Map(entries) => entries.iter().for_each(|(key, value)| push_data_lines(lines, value, depth + 1, Some(key), false)),
+            DataNode::List(items) => items.iter().for_each(|item| push_data_lines(lines, item, depth + 1, None, false)),
+            _ => {}
+        }
+        return;
+    }
+    let value = match node {
+        DataNode::Null => (Color::DarkGrey, "null".to_string()),
+        DataNode::Bool(b) => (Color::Magenta, b.to_string()),
+        DataNode::Number(n) => (Color::Yellow, n.clone()),
+        DataNode::Text(s) => (Color::Green, serde_json::to_string(s).unwrap_or_default()),
+        DataNode::Map(_) | DataNode::List(_) => (Color::DarkGrey, unit.2.to_string()),
+    };
+    line.push((Color::Reset, " ".to_string()));
+    line.push(value);
+    lines.push(line);
+}
+
+/// Rows of a CSV or TSV file the table preview reads, header included.
+const TABLE_PREVIEW_ROWS: usize = 1000;
+
+/// Most lines and bytes a quoted table field runs over before its opening quote
+/// is read as a plain character.
+const TABLE_FIELD_MAX_LINES: usize = 64;
+const TABLE_FIELD_MAX_BYTES: usize = 64 * 1024;
+
+/// Widest a table preview column gets before its cells are cut short.
+const TABLE_COLUMN_MAX_WIDTH: usize = 32;
+
+/// Whether `path` is previewed as a table: comma or tab separated values.
+fn is_table_file(path: &Path) -> bool {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    matches!(extension.as_str(), "csv" | "tsv" | "tab")
+}
+
+/// Read up to `max_rows` records of a CSV or TSV file. TSV splits on tabs; CSV
+/// on whichever of `,;|` the first line has most of, with "quoted" fields that
+/// may hold the separator, doubled quotes and line breaks.
+fn read_table(path: &Path, max_rows: usize) -> io::Result<Vec<Vec<String>>> {
+    use io::BufRead;
+    let mut lines = io::BufReader::new(fs::File::open(path)?).lines();
+    let Some(first) = lines.next().transpose()? else {
+        return Ok(Vec::new());
+    };
+    let first = first.trim_start_matches('\u{feff}').to_string();
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    let separator = if extension != "csv" {
+        '\t'
+    } else {
+        [',', ';', '|'].into_iter().max_by_key(|&c| (first.matches(c).count(), c == ',')).unwrap_or(',')
+    };
+
+    let mut rows = Vec::new();
+    let mut pending = VecDeque::from([first]);
+    while rows.len() < max_rows {
+        let Some(line) = pending.pop_front().map(Ok).or_else(|| lines.next()).transpose()? else {
+            break;
+        };
+        let mut taken = vec![line];
+        // Opening quotes, by line and byte, that ran too long and are read as plain characters
+        let mut literal = Vec::new();
+        let (row, used) = 'parse: loop {
+            let mut row = Vec::new();
+            let mut field = String::new();
+            let mut quoted = None; // Where the open quote is
+            let mut index = 0;
+            loop {
+                let mut chars = taken[index].char_indices().peekable();
+                while let Some((at, ch)) = chars.next() {
+                    match ch {
+                        '"' if quoted.is_some() && chars.peek().is_some_and(|&(_, c)| c == '"') => {
+                            field.push('"');
+                            chars.next();
+                        }
+                        '"' if quoted.is_some() => quoted = None,
+                        '"' if field.is_empty() && separator != '\t' && !literal.contains(&(index, at)) => {
+                            quoted = Some((index, at))
+                        }
+                        c if c == separator && quoted.is_none() => row.push(std::mem::take(&mut field)),
+                        c => field.push(c),
+                    }
+                }
+                let Some(open) = quoted else {
+                    row.push(field);
+                    break 'parse (row, index + 1);
+                };
+                // A quoted field goes on over the line break, but an unclosed quote
+                // mustn't swallow the rest of the file
+                if index - open.0 >= TABLE_FIELD_MAX_LINES || field.len() > TABLE_FIELD_MAX_BYTES {
+                    literal.push(open);
+                    continue 'parse;
+                }
+                index += 1;
+                if index == taken.len() {
+                    match pending.pop_front().map(Ok).or_else(|| lines.next()).transpose()? {
+                        Some(next) => taken.push(next),
+                        None => {
+                            row.push(field);
+                            break 'parse (row, index);
+                        }
+                    }
+                }
+                field.push(' ');
+            }
+        };
+        // Lines read past the record start the next ones
+        for line in taken.drain(used..).rev() {
+            pending.push_front(line);
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Largest Markdown file the preview renders; bigger ones are shown as text.
+const MARKDOWN_RENDER_MAX_BYTES: u64 = 1024 * 1024;
+
+// How a span of rendered Markdown is drawn
+#[derive(Clone, Copy, Default, PartialEq)]
+struct MarkdownStyle {
+    bold: bool,
+    italic: bool,
+    code: bool, // `inline code`
+    link: bool, // Link text, dimmed with the target left out
+    color: Option<Color>,
+}
+
+// A line of a rendered Markdown preview
+#[derive(Clone)]
+enum MarkdownLine {
+    Text(Vec<(MarkdownStyle, String)>),
+    Code { language: String, text: String }, // A line of a fenced block, highlighted as its language
+    Rule,
+}
+
+/// Render Markdown line by line: headings, emphasis, lists, quotes, rules and
+/// code blocks, with the markup itself left out. Paragraphs keep their line breaks.
+fn markdown_lines(text: &str) -> Vec<MarkdownLine> {
+    let mut lines = Vec::new();
+    let mut fence: Option<(String, String)> = None; // Marker and language of the open code block
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if let Some((marker, language)) = &fence {
+            if trimmed.starts_with(marker.as_str()) && trimmed.trim_start_matches(marker.chars().next().unwrap_or('`')).trim().is_empty() {
+                fence = None;
+            } else {
+                lines.push(MarkdownLine::Code { language: language.clone(), text: line.replace('\t', "    ") });
+            }
+            continue;
+        }
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            let marker_char = trimmed.chars().next().unwrap_or('`');
+            let marker: String = trimmed.chars().take_while(|&c| c == marker_char).collect();
+            let language = trimmed[marker.len()..].split_whitespace().next().unwrap_or("").to_string();
+            fence = Some((marker, language));
+            continue;
+        }
+
+        let indent = " ".repeat((line.len() - trimmed.len()).min(8));
+        let is_rule = trimmed.len() >= 3
+            && ["-", "*", "_"].iter().any(|c| trimmed.replace(' ', "").chars().all(|ch| ch.to_string() == *c));
+        if is_rule {
+            lines.push(MarkdownLine::Rule);
+            continue;
+        }
+
+        let hashes = trimmed.chars().take_while(|&c| c == '#').count();
+        if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+            let color = match hashes {
+                1 => Color::Magenta,
+                2 => Color::Cyan,
+                _ => Color::Blue,
+            };
+            let style = MarkdownStyle { bold: true, color: Some(color), ..Default::default() };
+            let heading = trimmed[hashes..].trim().trim_end_matches('#').trim_end();
+            lines.push(MarkdownLine::Text(markdown_inline(heading, style)));
+            continue;
+        }
+
+        let mut spans = Vec::new();
+        let mut rest = trimmed;
+        let mut style = MarkdownStyle::default();
+        while let Some(quoted) = rest.strip_prefix('>') {
+            spans.push((MarkdownStyle { color: Some(Color::DarkGrey), ..Default::default() }, "│ ".to_string()));
+            rest = quoted.strip_prefix(' ').unwrap_or(quoted);
+            style.italic = true;
+        }
+        let bullet = ["- ", "* ", "+ "].iter().find_map(|marker| rest.strip_prefix(marker));
+        let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+        let numbered = (digits > 0).then(|| rest[digits..].strip_prefix(". ").or(rest[digits..].strip_prefix(") "))).flatten();
+        let marker_style = MarkdownStyle { color: Some(Color::Yellow), ..Default::default() };
+        if let Some(item) = bullet {
+            spans.insert(0, (MarkdownStyle::default(), indent.clone()));
+            let (check, item) = match item.get(..4) {
+                Some("[ ] ") => ("[ ] ", &item[4..]),
+                Some("[x] " | "[X] ") => ("[x] ", &item[4..]),
+                _ => ("", item),
+            };
+            spans.push((marker_style, format!("• {}", check)));
+            rest = item;
+        } else if let Some(item) = numbered {
+            spans.insert(0, (MarkdownStyle::default(), indent.clone()));
+            spans.push((marker_style, format!("{}. ", &rest[..digits])));
+            rest = item;
+        } else {
+            spans.insert(0, (MarkdownStyle::default(), indent.clone()));
+        }
+        spans.extend(markdown_inline(rest, style));
+        spans.retain(|(_, text)| !text.is_empty());
+        lines.push(MarkdownLine::Text(spans));
+    }
+    lines
+}
+
+/// Split a line of Markdown into styled spans for its emphasis, `code` and links.
+fn markdown_inline(text: &str, base: MarkdownStyle) -> Vec<(MarkdownStyle, String)> {
+    let mut spans: Vec<(MarkdownStyle, String)> = Vec::new();
+    let push = |spans: &mut Vec<(MarkdownStyle, String)>, style: MarkdownStyle, text: &str| match spans.last_mut() {
+        Some((last_style, last)) if *last_style == style => last.push_str(text),
+        _ => spans.push((style, text.to_string())),
+    };
+    let mut style = base;
+    let mut prev = ' ';
+    let mut rest = text;
+    while let Some(ch) = rest.chars().next() {
+        let after = &rest[ch.len_utf8()..];
+        let next = after.chars().next();
+        if ch == '\\' {
+            if let Some(escaped) = next.filter(|c| c.is_ascii_punctuation()) {
+                push(&mut spans, style, &escaped.to_string());
+                rest = &after[1..];
+                prev = escaped;
+                continue;
+            }
+        }
+        if ch == '`' {
+            if let Some(end) = after.find('`') {
+                push(&mut spans, MarkdownStyle { code: true, ..style }, &after[..end]);
+                rest = &after[end + 1..];
+                prev = '`';
+                continue;
+            }
+        }
+        if rest.starts_with("**") || rest.starts_with("__") {
+            style.bold = !style.bold;
+            rest = &rest[2..];
+            continue;
+        }
+        // An underscore inside a word, like snake_case, or a lone * is just text
+        let opens = !style.italic && next.is_some_and(|c| !c.is_whitespace()) && (ch == '*' || !prev.is_alphanumeric());
+        let closes = style.italic && !prev.is_whitespace() && (ch == '*' || !next.is_some_and(|c| c.is_alphanumeric()));
+        if (ch == '*' || ch == '_') && (opens || closes) {
+            style.italic = !style.italic;
+            rest = after;
+            continue;
+        }
+        let image = rest.starts_with("![");
+        if ch == '[' || image {
+            let label_start = if image { 2 } else { 1 };
+            let link = rest.find("](").filter(|&mid| mid >= label_start).and_then(|mid| rest[mid..].find(')').map(|end| (mid, mid + end)));
+            if let Some((mid, end)) = link {
+                let label = &rest[label_start..mid];
+                if image {
+                    push(&mut spans, MarkdownStyle { link: true, ..style }, &format!("[image: {}]", label));
+                } else {
+                    for (span_style, span) in markdown_inline(label, MarkdownStyle { link: true, ..style }) {
+                        push(&mut spans, span_style, &span);
+                    }
+                }
+                rest = &rest[end + 1..];
+                prev = ')';
+                continue;
+            }
+        }
+        push(&mut spans, style, &ch.to_string());
+        prev = ch;
+        rest = after;
+    }
+    spans
+}
+
+// Feedback on a prompt's text as it is typed
+type PromptCheck<'a> = &'a dyn Fn(&FileBrowser, &str) -> Option<(Severity, String)>;
+
+struct FileBrowser {
+    current_dir: PathBuf,
+    entries: Vec<PathBuf>,
+    selected: usize,
+    scroll_offset: usize,
+    num_cols: usize,
+    collapsed_groups: Vec<usize>, // Groups of FILE_GROUPS collapsed to their header while sorted by extension
+    collapsed_entries: Vec<PathBuf>, // Entries of the current directory hidden in collapsed groups
+    entry_groups: HashMap<PathBuf, usize>, // Group of each entry, worked out while sorting by extension
+    start_row: u16, // The row where the content starts drawing
+    breadcrumbs: Vec<String>, // Track folders we've navigated into
+    ancestor_jump_pending: bool, // Waiting for a digit or shortcut name after the ancestor jump key
+    ancestor_jump_buffer: String, // Shortcut name typed so far after the jump key
+    path_jump_pending: bool, // Path bar segments carry hints; the next key picks one
+    find_char_pending: bool, // Waiting for the letter after the find-char key
+    bookmarks: Bookmarks, // Directories saved under a letter
+    tags: Tags, // Labels given to entries
+    bookmark_add_pending: bool, // Waiting for the letter to bookmark the current directory under
+    bookmarks_mode: bool, // Whether the bookmarks picker is open
+    bookmarks_selected: usize, // Highlighted row of the bookmarks picker
+    open_with: Option<(PathBuf, Vec<(String, String)>)>, // Entry the "open with" chooser is for, and its commands with where each came from
+    open_with_selected: usize, // Highlighted row of the "open with" chooser
+    key_sequence: String, // Keys typed so far of a multi-key binding
+    key_sequence_at: Instant, // When the last key of key_sequence was typed
+    replay_event: Option<Event>, // Key to handle again after a sequence broke off
+    shortcuts: BTreeMap<String, String>, // Directory aliases from config
+    openers: BTreeMap<String, String>, // File patterns to the commands Enter opens them with
+    show_dir_slash: bool, // Whether to show trailing slash for directories
+    preview_mode: bool, // Whether preview pane is active
+    readme_preview_opened: bool, // Whether the preview pane was opened for a README, not by the user
+    last_selected: HashMap<PathBuf, PathBuf>, // Entry selected when each directory was last left, for enter_selects = "last"
+    preview_scroll_map: HashMap<PathBuf, usize>, // Per-file scroll positions
+    saved_preview_scroll: HashMap<PathBuf, usize>, // Positions as loaded from the last session
+    preview_split_ratio: f32, // Ratio of screen for preview (0.0-1.0)
+    show_help: bool, // Whether to show help screen
+    help_screen_mode: bool, // Whether the full help screen is open
+    help_screen_query: String, // Search text for the help screen
+    help_screen_scroll: usize, // First visible line of the help screen
+    show_hidden: bool, // Whether to show hidden files
+    hidden_count: usize, // Dotfiles left out of the current listing
+    hide_gitignored: bool, // Whether to leave out entries git ignores
+    ignored_entries: HashSet<PathBuf>, // Entries left out of the current listing because git ignores them
+    ignored_pending: bool, // Whether the git job is yet to say which entries to leave out as ignored
+    fuzzy_mode: bool, // Whether fuzzy find mode is active
+    fuzzy_query: String, // Current fuzzy search query
+    fuzzy_prev_count: usize, // Previous match count for fuzzy finder
+    fuzzy_jump_mode: bool, // Whether fuzzy mode should auto-exit on selection
+    filter_mode: bool, // Whether the incremental filter is narrowing the list
+    filter_editor: LineEditor, // Current filter text
+    filter_all_entries: Vec<PathBuf>, // Full listing while the filter is active
+    search_matches: Vec<PathBuf>, // Matches of the last find or filter, for match_next and match_prev
+    list_mode: bool, // Whether to show in list mode (vs grid mode)
+    list_info_mode: u8, // 0 = none, 1 = modified date, 2 = permissions, 3 = size, 4 = media duration
+    show_line_numbers: bool, // Whether to show line numbers in preview
+    clipboard_selection: Vec<PathBuf>, // Multi-select clipboard for copy operations
+    marked: Vec<PathBuf>, // Entries marked for batch copy, trash and delete
+    copy_in_progress: bool, // Whether a paste operation is currently in progress
+    copy_progress_current: usize, // Current item being copied
+    copy_progress_total: usize, // Total items to copy
+    archive_progress: Option<String>, // Status bar text while an archive is being extracted or written
+    undo_stack: Vec<UndoAction>, // Undo history
+    redo_stack: Vec<UndoAction>, // Redo history
+    keybindings: Keybindings,
+    color_config: ColorConfig,
+    settings: Settings,
+    locale: Locale, // Resolved from settings.locale at startup
+    preview_cache: Arc<Mutex<LruCache<PreviewState>>>, // Cache preview content with loading state
+    item_count_cache: Arc<Mutex<HashMap<PathBuf, ItemCount>>>, // Directory entry counts, filled in the background
+    git_status_cache: Arc<Mutex<HashMap<PathBuf, GitStatus>>>, // Branch and dirty state of listed repositories
+    git_status_waiting: bool, // Whether a visible repository was drawn before its status was ready
+    git_root: Option<PathBuf>, // Work tree containing the current directory
+    git_changes: Arc<Mutex<Option<GitChanges>>>, // Git status of the entries of a directory in git_root
+    git_changes_waiting: bool, // Whether the listing was drawn before git_changes held the current directory
+    item_counts_waiting: bool, // Whether a visible directory was drawn before its count was ready
+    project_badges: Mutex<HashMap<PathBuf, Option<&'static str>>>, // Project marker found in each listed directory
+    media_info: Arc<Mutex<HashMap<PathBuf, Option<Option<MediaInfo>>>>>, // Headers of listed media files for the media column, None while being read
+    media_info_waiting: bool, // Whether a visible media file was drawn before its headers were read
+    gallery_mode: bool, // Whether the grid shows image thumbnails
+    disk_space: Option<(u64, u64)>, // Free and total bytes of the current filesystem, read on load_entries
+    listing_modified: Option<SystemTime>, // Current directory's mtime when it was last listed
+    preview_watch: Option<(PathBuf, Option<SystemTime>, u64)>, // Previewed file with its mtime and size when last checked
+    load_error: Option<io::Error>, // Why the current directory could not be listed, shown in place of its entries
+    slow_filesystem: bool, // Whether the current directory is on a network filesystem (no prefetching or background reads)
+    thumbnails_pending: Arc<Mutex<HashSet<PathBuf>>>, // Thumbnail files being generated in the background
+    thumbnails_failed: Arc<Mutex<HashSet<PathBuf>>>, // Thumbnail files whose image couldn't be decoded, not tried again
+    pdf_cancelled: Arc<Mutex<HashSet<PathBuf>>>, // PDFs whose text extraction should stop at the next page
+    thumbnails_waiting: bool, // Whether a visible image was drawn before its thumbnail was ready
+    preview_waiting: bool, // Whether the preview was drawn while its content was still loading
+    syntax_set: Option<SyntaxSet>,  // Lazy-loaded on first preview
+    preview_header: Option<(PathBuf, Option<SystemTime>, String)>, // Facts line for the last previewed file and its mtime
+    preview_binary: Option<(PathBuf, Option<SystemTime>, bool)>, // Whether the last previewed file looks binary, as of its mtime
+    line_counts: LineCounts, // Lines of previewed files by path and mtime, None while being counted
+    show_diff: bool, // Whether the preview shows the unstaged diff of modified files instead of their contents
+    preview_diff: PreviewDiff, // Diff of the last previewed file and its mtime, None while git runs
+    render_markdown: bool, // Whether Markdown files are previewed rendered rather than as text
+    preview_table: Option<(PathBuf, Option<SystemTime>, Vec<Vec<String>>)>, // Rows of the last previewed CSV or TSV file and its mtime
+    preview_data: Option<(PathBuf, Option<SystemTime>, bool, Vec<DataLine>)>, // Laid out lines of the last previewed JSON, YAML or TOML file, its mtime and whether it is folded
+    preview_data_fold: Option<(PathBuf, bool)>, // Whether the top-level entries of a structured preview were folded or unfolded by hand
+    image_protocol: Option<ImageProtocol>, // How images are drawn, worked out on the first one
+    decoded_images: LruCache<(Option<SystemTime>, image::DynamicImage)>, // Images and thumbnails decoded for display, by path, with their mtime
+    image_graphics: LruCache<(Option<SystemTime>, (u16, u16), String)>, // Escape codes built to draw an image, by path, with its mtime and the cells it fits in
+    preview_columns_scroll: Option<(PathBuf, usize)>, // First column shown of a table preview
+    preview_markdown: Option<(PathBuf, Option<SystemTime>, Vec<MarkdownLine>)>, // Rendered lines of the last previewed Markdown file and its mtime
+    theme_set: Option<ThemeSet>,    // Lazy-loaded on first preview
+    syntax_loader: Option<thread::JoinHandle<(SyntaxSet, ThemeSet)>>, // Loads the two above in the background
+    config_error: Option<String>,   // Config loading error message
+    dir_size_cache: LruCache<DiskUsage>, // Cache directory sizes
+    calculating_sizes: Option<Instant>, // When sizing every listed directory started, until the last size is in
+    dir_size_jobs: Arc<Mutex<HashMap<PathBuf, Option<DiskUsage>>>>, // Single directories being sized in the background, None until done
+    dir_size_waiting: bool, // Whether a background directory size hasn't arrived yet
+    sort_sizing: Option<Arc<Mutex<VecDeque<PathBuf>>>>, // Directories a size sort has yet to size, dropped on leaving them
+    dir_size_announce: HashSet<PathBuf>, // Background sizes to report in a message when done
+    show_created_date: bool, // Toggle between modified and created date
+    toasts: Vec<Toast>, // Status messages, oldest first, removed once expired
+    message_log: VecDeque<(Instant, Severity, String)>, // Every message shown, oldest dropped first
+    messages_mode: bool, // Whether the message history panel is open
+    messages_scroll: usize, // First visible line of the message history
+    info_panel: Option<(String, Vec<(String, String)>)>, // Title and label/value rows of an open details panel
+    focused: bool, // Whether the terminal window has focus, from focus change events
+    pending_desktop_notification: Option<String>, // Finished long job, sent once queued focus events are read
+    input_block_until: Option<std::time::Instant>, // Block input until this time
+    wrapper_warning: bool, // Whether to show wrapper not installed warning
+    dir_history: DirectoryHistory, // Directory visit history for frecency
+    history_mode: bool, // Whether we're in history navigation mode
+    history_query: String, // Query for history search
+    history_filtered: Vec<DirectoryEntry>, // Filtered history results
+    grep_mode: bool, // Whether the content search results replace the listing
+    grep_names: bool, // Whether that search matches file names in subdirectories instead of contents
+    grep_editor: LineEditor, // Content search query
+    grep_results: Arc<Mutex<GrepResults>>, // Matches of the running or last search
+    grep_cancel: Arc<AtomicBool>, // Set to stop the running search
+    grep_return_selection: usize, // Entry selected before the search opened
+    grep_waiting: bool, // Whether the results were drawn while the search was still running
+    path_edit_mode: bool, // Whether the path bar is being edited
+    path_editor: LineEditor, // Current contents of the editable path bar
+    prompt: Option<(String, &'static str)>, // Label and kind of the open bottom-row prompt
+    prompt_feedback: Option<(Severity, String)>, // What the prompt's check says about the text typed so far
+    prompt_editors: HashMap<&'static str, LineEditor>, // Input of each kind of prompt, with its own history
+    tabs: Vec<Tab>, // Every open tab; the active one is only up to date when switching away
+    active_tab: usize, // Index into tabs of the tab being shown
+    other_pane: Option<Tab>, // The inactive pane while dual pane mode is on
+    other_pane_entries: Vec<PathBuf>, // Listing of the inactive pane
+    pane_sync: Option<PathBuf>, // With synced panes, the active pane's directory the other pane last followed
+}
+
+impl PreviewState {
+    /// Rough memory use, for the preview cache limit.
+    fn weight(&self) -> usize {
+        match self {
+            PreviewState::Loaded(lines) => lines.iter().map(|line| line.len() + std::mem::size_of::<String>()).sum(),
+            PreviewState::Notebook { cells, .. } => cells
+                .iter()
+                .flat_map(|cell| cell.source.iter().chain(&cell.outputs))
+                .map(|line| line.len() + std::mem::size_of::<String>())
+                .sum(),
+            PreviewState::Error(message) => message.len(),
+            PreviewState::NotLoaded | PreviewState::Loading | PreviewState::Extracting { .. } => 0,
+        }
+    }
+}
+
+impl FileBrowser {
+    fn wrap_text(text: &str, max_width: usize) -> Vec<String> {
+        if max_width == 0 {
+            return vec![text.to_string()];
+        }
+
+        let mut lines = Vec::new();
+        let mut current_line = String::new();
+        let mut current_width = 0;
+
+        for ch in text.chars() {
+            let ch_width = 1; // Simplified: treat all chars as width 1
+
+            if current_width + ch_width > max_width {
+                // Start new line
+                if !current_line.is_empty() {
+                    lines.push(current_line.clone());
+                    current_line.clear();
+                    current_width = 0;
+                }
+            }
+
+            current_line.push(ch);
+            current_width += ch_width;
+        }
+
+        if !current_line.is_empty() {
+            lines.push(current_line);
+        }
+
+        if lines.is_empty() {
+            vec![String::new()]
+        } else {
+            lines
+        }
+    }
+
+    fn truncate_string_safe(s: &str, max_width: usize) -> String {
+        if max_width <= 3 {
+            return "...".to_string();
+        }
+
+        let char_count: usize = s.chars().count();
+        if char_count <= max_width {
+            return s.to_string();
+        }
+
+        // Take max_width - 3 characters and add "..."
+        let truncated: String = s.chars().take(max_width.saturating_sub(3)).collect();
+        format!("{}...", truncated)
+    }
+
+    fn format_path_display(&self) -> String {
+        self.tilde_path(&self.current_dir)
+    }
+
+    /// `path` with the home directory shown as ~ when show_tilde_for_home is on.
+    fn tilde_path(&self, path: &Path) -> String {
+        if self.settings.show_tilde_for_home {
+            if let Ok(home) = env::var("HOME") {
+                let home_path = PathBuf::from(home);
+                if let Ok(relative) = path.strip_prefix(&home_path) {
+                    if relative.as_os_str().is_empty() {
+                        return "~".to_string();
+                    } else {
+                        return format!("~/{}", relative.display());
+                    }
+                }
+            }
+        }
+        path.display().to_string()
+    }
+
+    /// Path bar segments from the root down, each ancestor paired with its hint in
+    /// `hints` (the parent takes the first), e.g. "2 ~ › 1 dev › ils" with digits.
+    fn path_segments(&self, hints: &str) -> Vec<(Option<char>, String)> {
+        let display_path = self.format_path_display();
+        let mut segments: Vec<&str> = display_path.split('/').collect();
+        if segments.first() == Some(&"") {
+            segments[0] = "/";
+        }
+        segments.retain(|s| !s.is_empty());
+
+        let last = segments.len().saturating_sub(1);
+        segments
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let hint = if i == last { None } else { hints.chars().nth(last - i - 1) };
+                (hint, name.to_string())
+            })
+            .collect()
+    }
+
+    /// Draws the path bar as separate segments while a jump is pending: hints
+    /// highlighted, ancestors dimmed and the current directory bold. Returns the
+    /// width drawn.
+    fn draw_path_segments(&self, stdout: &mut impl Write, hints: &str) -> io::Result<usize> {
+        use crossterm::style::{Attribute, SetAttribute};
+        let segments = self.path_segments(hints);
+        let separator = self.plain(" › ");
+        let last = segments.len().saturating_sub(1);
+        let mut width = 2;
+        queue!(stdout, Print(" "))?;
+        for (i, (hint, name)) in segments.iter().enumerate() {
+            if i > 0 {
+                queue!(stdout, SetForegroundColor(Color::DarkGrey), Print(&separator), ResetColor)?;
+                width += separator.chars().count();
+            }
+            if let Some(hint) = hint {
+                queue!(
+                    stdout,
+                    SetForegroundColor(Color::Yellow),
+                    SetAttribute(Attribute::Bold),
+                    Print(format!("{} ", hint)),
+                    SetAttribute(Attribute::Reset)
+                )?;
+                width += 2;
+            }
+            if i == last {
+                queue!(stdout, SetAttribute(Attribute::Bold), Print(name), SetAttribute(Attribute::Reset))?;
+            } else {
+                queue!(stdout, SetForegroundColor(Color::Grey), Print(name), ResetColor)?;
+            }
+            width += name.chars().count();
+        }
+        queue!(stdout, Print(" "))?;
+        Ok(width)
+    }
+
+    fn new(start_dir: PathBuf) -> io::Result<Self> {
+        let (_, row) = cursor::position()?;
+
+        // Load unified config or create default if not exists
+        let (config, config_error) = if let Some(config_path) = Config::path() {
+            if config_path.exists() {
+                Config::load()
+            } else {
+                let _ = Config::create_default();
+                (Config::default(), None)
+            }
+        } else {
+            (Config::default(), None)
+        };
+
+        let keybindings = config.keybindings;
+        let color_config = config.colors;
+        let settings = config.settings;
+
+        // Use show_help setting from config
+        let show_help = settings.show_help_on_start;
+
+        // Use preview split ratio from config
+        let preview_split_ratio = settings.preview_split_ratio;
+        let gallery_mode = settings.view_on_start == "gallery";
+
+        // Bounded caches, sized from config
+        let preview_cache = LruCache::new(
+            settings.preview_cache_entries,
+            settings.preview_cache_mb.saturating_mul(1024 * 1024),
+            PreviewState::weight,
+        );
+        let dir_size_cache = LruCache::new(settings.dir_size_cache_entries, usize::MAX, |_| 0);
+
+        // Resume previews where the last session left them
+        let saved_preview_scroll = PreviewPositions::load().lines();
+
+        // start drawing content on the row *after* the initial position
+        let mut browser = FileBrowser {
+            current_dir: start_dir.clone(),
+            entries: Vec::new(),
+            selected: 0,
+            scroll_offset: 0,
+            num_cols: 1,
+            collapsed_groups: Vec::new(),
+            collapsed_entries: Vec::new(),
+            entry_groups: HashMap::new(),
+            start_row: row,
+            breadcrumbs: Vec::new(),
+            ancestor_jump_pending: false,
+            ancestor_jump_buffer: String::new(),
+            path_jump_pending: false,
+            find_char_pending: false,
+            bookmarks: Bookmarks::load(),
+            tags: Tags::load(),
+            bookmark_add_pending: false,
+            bookmarks_mode: false,
+            bookmarks_selected: 0,
+            open_with: None,
+            open_with_selected: 0,
+            key_sequence: String::new(),
+            key_sequence_at: Instant::now(),
+            replay_event: None,
+            shortcuts: config.shortcuts,
+            openers: config.openers,
+            show_dir_slash: settings.show_dir_slash,
+            preview_mode: settings.preview_on_start,
+            readme_preview_opened: false,
+            last_selected: HashMap::new(),
+            preview_scroll_map: saved_preview_scroll.clone(),
+            saved_preview_scroll,
+            preview_split_ratio,
+            show_help,
+            help_screen_mode: false,
+            help_screen_query: String::new(),
+            help_screen_scroll: 0,
+            show_hidden: settings.show_hidden,
+            hidden_count: 0,
+            hide_gitignored: settings.hide_gitignored,
+            ignored_entries: HashSet::new(),
+            ignored_pending: false,
+            fuzzy_mode: false,
+            fuzzy_query: String::new(),
+            fuzzy_prev_count: 0,
+            fuzzy_jump_mode: false,
+            filter_mode: false,
+            filter_editor: LineEditor::default(),
+            filter_all_entries: Vec::new(),
+            search_matches: Vec::new(),
+            list_mode: settings.view_on_start == "list",
+            list_info_mode: match settings.list_column_on_start.as_str() {
+                "modified" => 1,
+                "permissions" => 2,
+                "size" => 3,
+                "duration" => 4,
+                _ => 0,
+            },
+            show_line_numbers: settings.line_numbers_on_start,
+            clipboard_selection: Vec::new(),
+            marked: Vec::new(),
+            copy_in_progress: false,
+            copy_progress_current: 0,
+            copy_progress_total: 0,
+            archive_progress: None,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            keybindings,
+            color_config,
+            locale: Locale::from_setting(&settings.locale),
+            settings,
+            preview_cache: Arc::new(Mutex::new(preview_cache)),
+            item_count_cache: Arc::new(Mutex::new(HashMap::new())),
+            git_status_cache: Arc::new(Mutex::new(HashMap::new())),
+            git_status_waiting: false,
+            git_root: None,
+            git_changes: Arc::new(Mutex::new(None)),
+            git_changes_waiting: false,
+            item_counts_waiting: false,
+            project_badges: Mutex::new(HashMap::new()),
+            media_info: Arc::new(Mutex::new(HashMap::new())),
+            media_info_waiting: false,
+            gallery_mode,
+            disk_space: None,
+            listing_modified: None,
+            preview_watch: None,
+            load_error: None,
+            slow_filesystem: false,
+            thumbnails_pending: Arc::new(Mutex::new(HashSet::new())),
+            thumbnails_failed: Arc::new(Mutex::new(HashSet::new())),
+            pdf_cancelled: Arc::new(Mutex::new(HashSet::new())),
+            thumbnails_waiting: false,
+            preview_waiting: false,
+            syntax_set: None,  // Lazy-loaded
+            preview_header: None,
+            preview_binary: None,
+            line_counts: Arc::new(Mutex::new(HashMap::new())),
+            show_diff: false,
+            preview_diff: Arc::new(Mutex::new(None)),
+            render_markdown: true,
+            preview_table: None,
+            preview_data: None,
+            preview_data_fold: None,
+            image_protocol: None,
+            decoded_images: LruCache::new(IMAGE_CACHE_ENTRIES, IMAGE_CACHE_MB * 1024 * 1024, |(_, image)| image.as_bytes().len()),
+            image_graphics: LruCache::new(IMAGE_CACHE_ENTRIES, IMAGE_CACHE_MB * 1024 * 1024, |(_, _, graphics)| graphics.len()),
+            preview_columns_scroll: None,
+            preview_markdown: None,
+            theme_set: None,   // Lazy-loaded
+            syntax_loader: None,
+            config_error,
+            dir_size_cache,
+            calculating_sizes: None,
+            dir_size_jobs: Arc::new(Mutex::new(HashMap::new())),
+            dir_size_waiting: false,
+            sort_sizing: None,
+            dir_size_announce: HashSet::new(),
+            show_created_date: false,
+            toasts: Vec::new(),
+            message_log: VecDeque::new(),
+            messages_mode: false,
+            messages_scroll: 0,
+            info_panel: None,
+            focused: true,
+            pending_desktop_notification: None,
+            input_block_until: None,
+            wrapper_warning: false,
+            dir_history: DirectoryHistory::load(),
+            history_mode: false,
+            history_query: String::new(),
+            history_filtered: Vec::new(),
+            grep_mode: false,
+            grep_names: false,
+            grep_editor: LineEditor::default(),
+            grep_results: Arc::new(Mutex::new(GrepResults::default())),
+            grep_cancel: Arc::new(AtomicBool::new(false)),
+            grep_return_selection: 0,
+            grep_waiting: false,
+            path_edit_mode: false,
+            path_editor: LineEditor::default(),
+            prompt: None,
+            prompt_feedback: None,
+            prompt_editors: HashMap::new(),
+            tabs: vec![Tab { dir: start_dir.clone(), selected: None, scroll_offset: 0, preview_mode: false }],
+            active_tab: 0,
+            other_pane: None,
+            other_pane_entries: Vec::new(),
+            pane_sync: None,
+        };
+        browser.load_entries()?;
+
+        // Record initial directory visit
+        browser.dir_history.record_visit(&start_dir, browser.settings.history_max_storage);
+        let _ = browser.dir_history.save();
+        browser.select_on_enter();
+        browser.preview_readme();
+        // Don't calculate layout here - will be done on first draw for faster startup
+
+        Ok(browser)
+    }
+
+    /// Notebook cells as headed blocks: highlighted source followed by its output.
+    fn draw_notebook_preview(&mut self, stdout: &mut io::Stdout, selected: &Path, split_line: u16, preview_lines: usize) -> io::Result<()> {
+        let cache_state = self.preview_cache.lock().ok().and_then(|mut cache| cache.get(selected).cloned());
+        let (language, cells) = match cache_state {
+            Some(PreviewState::Notebook { language, cells }) => (language, cells),
+            Some(PreviewState::Error(msg)) => {
+                queue!(stdout, cursor::MoveTo(0, split_line + 1), Print(format!("({})", msg)))?;
+                return Ok(());
+            }
+            Some(PreviewState::Loading) => {
+                queue!(stdout, cursor::MoveTo(0, split_line + 1), Print("Loading notebook..."))?;
+                self.preview_waiting = true;
+                return Ok(());
+            }
+            _ => {
+                self.start_preview_load(selected.to_path_buf());
+                queue!(stdout, cursor::MoveTo(0, split_line + 1), Print("Loading notebook..."))?;
+                self.preview_waiting = true;
+                return Ok(());
+            }
+        };
+
+        // Plain text until the syntaxes have loaded in the background
+        let syntaxes = if self.syntax_ready() {
+            let syntax_set = self.syntax_set.as_ref().unwrap();
+            let theme = &self.theme_set.as_ref().unwrap().themes["base16-ocean.dark"];
+            let code_syntax = syntax_set.find_syntax_by_token(&language).unwrap_or_else(|| syntax_set.find_syntax_plain_text());
+            let markdown_syntax = syntax_set.find_syntax_by_name("Markdown").unwrap_or_else(|| syntax_set.find_syntax_plain_text());
+            Some((syntax_set, theme, code_syntax, markdown_syntax))
+        } else {
+            self.preview_waiting = true;
+            None
+        };
+        let mut highlighters = syntaxes.map(|(_, theme, code_syntax, markdown_syntax)| (HighlightLines::new(code_syntax, theme), HighlightLines::new(markdown_syntax, theme)));
+
+        let (lines, _) = notebook_lines(&cells);
+        let scroll_pos = self.preview_scroll_map.get(selected).copied().unwrap_or(0);
+        for (i, line) in lines.iter().skip(scroll_pos).take(preview_lines).enumerate() {
+            queue!(stdout, cursor::MoveTo(0, split_line + 1 + i as u16))?;
+            match line {
+                NotebookLine::Header(title) => {
+                    // Each cell is highlighted from a clean state
+                    if let Some((_, theme, code_syntax, markdown_syntax)) = syntaxes {
+                        highlighters = Some((HighlightLines::new(code_syntax, theme), HighlightLines::new(markdown_syntax, theme)));
+                    }
+                    queue!(
+                        stdout,
+                        SetForegroundColor(Color::Cyan),
+                        Print(self.plain(&format!("── {} ──", title))),
+                        ResetColor
+                    )?;
+                }
+                NotebookLine::Code(text) | NotebookLine::Markdown(text) => match (&mut highlighters, syntaxes) {
+                    (Some((code_highlighter, markdown_highlighter)), Some((syntax_set, ..))) => {
+                        let highlighter = if matches!(line, NotebookLine::Code(_)) { code_highlighter } else { markdown_highlighter };
+                        let ranges = highlighter.highlight_line(text, syntax_set).unwrap_or_default();
+                        queue!(stdout, Print(as_24_bit_terminal_escaped(&ranges[..], false)), ResetColor)?;
+                    }
+                    _ => queue!(stdout, Print(text))?,
+                },
+                NotebookLine::Output(text) => {
+                    queue!(stdout, SetForegroundColor(Color::DarkGrey), Print(text), ResetColor)?;
+                }
+                NotebookLine::Blank => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Unstaged `git diff` of a previewed file from its first hunk on, when diffs are
+    /// shown and the file has changes. git runs on a background thread, once per
+    /// file until it or the index changes; None until it's done.
+    fn unstaged_diff(&mut self, path: &Path) -> Option<Vec<String>> {
+        if !self.show_diff || !matches!(self.git_change(path), Some((_, y)) if y != ' ' && y != '?') {
+            return None;
+        }
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut diff = self.preview_diff.lock().ok()?;
+        if let Some((cached_path, cached_modified, lines)) = &*diff {
+            if cached_path == path && *cached_modified == modified {
+                if lines.is_none() {
+                    self.preview_waiting = true;
+                }
+                return lines.clone().filter(|lines| !lines.is_empty());
+            }
+        }
+        *diff = Some((path.to_path_buf(), modified, None));
+        drop(diff);
+        self.preview_waiting = true;
+
+        let diff = Arc::clone(&self.preview_diff);
+        let path = path.to_path_buf();
+        thread::spawn(move || {
+            let lines = git_diff_lines(&path);
+            if let Ok(mut diff) = diff.lock() {
+                // Only if the preview hasn't moved on to another file meanwhile
+                if let Some((diffed, diffed_modified, slot @ None)) = &mut *diff {
+                    if *diffed == path && *diffed_modified == modified {
+                        *slot = Some(lines);
+                    }
+                }
+            }
+        });
+        None
+    }
+
+    /// Rendered lines of a Markdown file, while rendering is on and the file is
+    /// small enough. Kept until the file changes on disk.
+    fn rendered_markdown(&mut self, path: &Path) -> Option<Vec<MarkdownLine>> {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        if !self.render_markdown || !matches!(extension.as_str(), "md" | "markdown" | "mdown" | "mkd") {
+            return None;
+        }
+        let metadata = fs::metadata(path).ok().filter(|m| m.len() <= MARKDOWN_RENDER_MAX_BYTES)?;
+        let modified = metadata.modified().ok();
+        if let Some((cached_path, cached_modified, lines)) = &self.preview_markdown {
+            if cached_path == path && *cached_modified == modified {
+                return Some(lines.clone());
+            }
+        }
+        let text = fs::read_to_string(path).ok()?;
+        let lines = markdown_lines(&text);
+        self.preview_markdown = Some((path.to_path_buf(), modified, lines.clone()));
+        Some(lines)
+    }
+
+    /// Rendered Markdown, with code blocks highlighted as their fence's language.
+    fn draw_markdown_preview(&mut self, stdout: &mut io::Stdout, selected: &Path, lines: &[MarkdownLine], split_line: u16, preview_lines: usize, width: u16) -> io::Result<()> {
+        use crossterm::style::{Attribute, SetAttribute};
+        let scroll_pos = self.preview_scroll_map.get(selected).copied().unwrap_or(0);
+        // Plain code until the syntaxes have loaded in the background
+        let syntaxes = if self.syntax_ready() {
+            Some((self.syntax_set.as_ref().unwrap(), &self.theme_set.as_ref().unwrap().themes["base16-ocean.dark"]))
+        } else {
+            self.preview_waiting = true;
+            None
+        };
+
+        // Code blocks are highlighted from their first line, even when scrolled into
+        let mut highlighter: Option<HighlightLines> = None;
+        for (i, line) in lines.iter().enumerate().take(scroll_pos + preview_lines) {
+            let visible = i >= scroll_pos;
+            if visible {
+                queue!(stdout, cursor::MoveTo(0, split_line + 1 + (i - scroll_pos) as u16))?;
+            }
+            match line {
+                MarkdownLine::Code { language, text } => {
+                    let starts_block = i == 0 || !matches!(lines[i - 1], MarkdownLine::Code { .. });
+                    if let Some((syntax_set, theme)) = syntaxes {
+                        if starts_block || highlighter.is_none() {
+                            let syntax = syntax_set.find_syntax_by_token(language).unwrap_or_else(|| syntax_set.find_syntax_plain_text());
+                            highlighter = Some(HighlightLines::new(syntax, theme));
+                        }
+                    }
+                    // Clipped to the pane rather than wrapped, like text previews
+                    let shown = &text[..text.char_indices().nth((width as usize).saturating_sub(2)).map_or(text.len(), |(end, _)| end)];
+                    match highlighter.as_mut().zip(syntaxes) {
+                        Some((highlighter, (syntax_set, _))) => {
+                            let ranges = highlighter.highlight_line(text, syntax_set).unwrap_or_default();
+                            if visible {
+                                let (ranges, _) = syntect::util::split_at(&ranges, shown.len());
+                                queue!(stdout, Print("  "), Print(as_24_bit_terminal_escaped(&ranges[..], false)), ResetColor)?;
+                            }
+                        }
+                        None if visible => queue!(stdout, SetForegroundColor(Color::Green), Print(format!("  {}", shown)), ResetColor)?,
+                        None => {}
+                    }
+                }
+                _ if !visible => {}
+                MarkdownLine::Rule => {
+                    queue!(stdout, SetForegroundColor(Color::DarkGrey), Print(self.plain(&"─".repeat(width as usize))), ResetColor)?;
+                }
+                MarkdownLine::Text(spans) => {
+                    let mut room = width as usize;
+                    for (style, text) in spans {
+                        if room == 0 {
+                            break;
+                        }
+                        let text: String = self.plain(text).chars().take(room).collect();
+                        room -= text.chars().count();
+                        let color = if style.link { Some(Color::DarkGrey) } else if style.code { Some(Color::Yellow) } else { style.color };
+                        if let Some(color) = color {
+                            queue!(stdout, SetForegroundColor(color))?;
+                        }
+                        if style.bold {
+                            queue!(stdout, SetAttribute(Attribute::Bold))?;
+                        }
+                        if style.italic {
+                            queue!(stdout, SetAttribute(Attribute::Italic))?;
+                        }
+                        if style.link {
+                            queue!(stdout, SetAttribute(Attribute::Underlined))?;
+                        }
+                        queue!(stdout, Print(text), SetAttribute(Attribute::Reset), ResetColor)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Lay out a JSON, YAML or TOML file for the preview into `preview_data`,
+    /// or an invalid one as its parse error over the text. Kept until the file
+    /// changes on disk. Returns whether `path` is previewed this way.
+    fn load_data_preview(&mut self, path: &Path) -> bool {
+        let Some(format) = data_format(path) else {
+            return false;
+        };
+        let Some(metadata) = fs::metadata(path).ok().filter(|m| m.len() <= DATA_PREVIEW_MAX_BYTES) else {
+            return false;
+        };
+        let modified = metadata.modified().ok();
+        let fold = match &self.preview_data_fold {
+            Some((folded_path, fold)) if folded_path == path => Some(*fold),
+            _ => None,
+        };
+        if let Some((cached_path, cached_modified, _, _)) = &self.preview_data {
+            if cached_path == path && *cached_modified == modified {
+                return true;
+            }
+        }
+        let Ok(text) = fs::read_to_string(path) else {
+            return false;
+        };
+
+        let (folded, lines) = match parse_data(&text, format) {
+            Ok(root) => {
+                let lines = data_lines(&root, false);
+                // Big documents open folded, to see what they hold at a glance
+                match fold.unwrap_or(lines.len() > DATA_FOLD_LINES) {
+                    true => {
+                        let toggle = Keybindings::format_keys(&self.keybindings.fold_preview);
+                        let mut folded = vec![vec![(Color::DarkGrey, format!("Top-level entries folded, {} unfolds", toggle))]];
+                        folded.extend(data_lines(&root, true));
+                        (true, folded)
+                    }
+                    false => (false, lines),
+                }
+            }
+            Err((message, error_line)) => {
+                let mut lines = vec![vec![(Color::Red, format!("Invalid {}: {}", format, message.lines().next().unwrap_or("")))], Vec::new()];
+                lines.extend(text.lines().enumerate().map(|(i, line)| {
+                    let color = if error_line == Some(i + 1) { Color::Red } else { Color::DarkGrey };
+                    vec![(color, self.plain(&format!("{:4} │ ", i + 1))), (Color::Reset, line.replace('\t', "    "))]
+                }));
+                (false, lines)
+            }
+        };
+        self.preview_data = Some((path.to_path_buf(), modified, folded, lines));
+        true
+    }
+
+    /// Fold the top-level entries of the previewed JSON, YAML or TOML file, or unfold them.
+    fn toggle_data_fold(&mut self) {
+        let Some(selected) = self.get_selected_path() else {
+            return;
+        };
+        if !self.preview_mode || !self.load_data_preview(&selected) {
+            return;
+        }
+        let folded = self.preview_data.as_ref().is_some_and(|(_, _, folded, _)| *folded);
+        self.preview_data_fold = Some((selected.clone(), !folded));
+        self.preview_data = None;
+        self.preview_scroll_map.remove(&selected);
+    }
+
+    /// The laid out lines of a structured preview, see `load_data_preview`.
+    fn draw_data_preview(&self, stdout: &mut io::Stdout, selected: &Path, split_line: u16, preview_lines: usize, width: u16) -> io::Result<()> {
+        let Some((_, _, _, lines)) = &self.preview_data else {
+            return Ok(());
+        };
+        let scroll_pos = self.preview_scroll_map.get(selected).copied().unwrap_or(0);
+        for (i, line) in lines.iter().skip(scroll_pos).take(preview_lines).enumerate() {
+            queue!(stdout, cursor::MoveTo(0, split_line + 1 + i as u16))?;
+            let mut room = width as usize;
+            for (color, text) in line {
+                let text: String = text.chars().take(room).collect();
+                room -= text.chars().count();
+                queue!(stdout, SetForegroundColor(*color), Print(text), ResetColor)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The first rows of a CSV or TSV file, header first. Kept until the file changes on disk.
+    fn table_rows(&mut self, path: &Path) -> Option<Vec<Vec<String>>> {
+        if !is_table_file(path) {
+            return None;
+        }
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        if let Some((cached_path, cached_modified, rows)) = &self.preview_table {
+            if cached_path == path && *cached_modified == modified {
+                return Some(rows.clone());
+            }
+        }
+        let rows = read_table(path, TABLE_PREVIEW_ROWS).ok()?;
+        self.preview_table = Some((path.to_path_buf(), modified, rows.clone()));
+        Some(rows)
+    }
+
+    /// A table preview's first column shown.
+    fn table_columns_scroll(&self, path: &Path) -> usize {
+        match &self.preview_columns_scroll {
+            Some((scrolled, column)) if scrolled == path => *column,
+            _ => 0,
+        }
+    }
+
+    /// Scroll a table preview sideways by a column.
+    fn scroll_table(&mut self, right: bool) {
+        let Some(selected) = self.get_selected_path() else {
+            return;
+        };
+        let Some(rows) = self.table_rows(&selected) else {
+            return;
+        };
+        let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        let current = self.table_columns_scroll(&selected);
+        let column = if right { (current + 1).min(columns.saturating_sub(1)) } else { current.saturating_sub(1) };
+        self.preview_columns_scroll = Some((selected, column));
+    }
+
+    /// Rows of a CSV or TSV file lined up in columns, the header kept on top.
+    fn draw_table_preview(&self, stdout: &mut io::Stdout, selected: &Path, rows: &[Vec<String>], split_line: u16, preview_lines: usize, width: u16) -> io::Result<()> {
+        use crossterm::style::{Attribute, SetAttribute};
+        let Some((header, body)) = rows.split_first() else {
+            queue!(stdout, cursor::MoveTo(0, split_line + 1), Print("(empty table)"))?;
+            return Ok(());
+        };
+        let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        let mut widths = vec![0; columns];
+        for row in rows {
+            for (column, cell) in row.iter().enumerate() {
+                widths[column] = widths[column].max(cell.chars().count().min(TABLE_COLUMN_MAX_WIDTH));
+            }
+        }
+        let first_column = self.table_columns_scroll(selected).min(columns.saturating_sub(1));
+        let separator = self.plain(" │ ");
+
+        // Cells from the first scrolled-to column, as many as fit
+        let fit = |row: &[String]| -> String {
+            let mut line = String::new();
+            for (column, &column_width) in widths.iter().enumerate().skip(first_column) {
+                if column > first_column {
+                    line.push_str(&separator);
+                }
+                let cell = row.get(column).map(String::as_str).unwrap_or("");
+                let cell = if cell.chars().count() > column_width { Self::truncate_string_safe(cell, column_width) } else { cell.to_string() };
+                line.push_str(&format!("{:<w$}", cell, w = column_width));
+                if line.chars().count() >= width as usize {
+                    break;
+                }
+            }
+            line.chars().take(width as usize).collect()
+        };
+
+        queue!(
+            stdout,
+            cursor::MoveTo(0, split_line + 1),
+            SetForegroundColor(Color::Cyan),
+            SetAttribute(Attribute::Bold),
+            Print(fit(header)),
+            SetAttribute(Attribute::Reset),
+            ResetColor
+        )?;
+        let scroll_pos = self.preview_scroll_map.get(selected).copied().unwrap_or(0);
+        for (i, row) in body.iter().skip(scroll_pos).take(preview_lines.saturating_sub(1)).enumerate() {
+            queue!(stdout, cursor::MoveTo(0, split_line + 2 + i as u16), Print(fit(row)))?;
+        }
+        if first_column > 0 {
+            // Columns are off to the left
+            queue!(
+                stdout,
+                cursor::MoveTo(0, split_line + 1),
+                SetForegroundColor(Color::DarkGrey),
+                Print(self.plain("…")),
+                ResetColor
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Switch Markdown previews between rendered and raw text.
+    fn toggle_markdown_preview(&mut self) {
+        self.render_markdown = !self.render_markdown;
+        // The two views have their own lengths, so start from the top
+        if let Some(selected) = self.get_selected_path() {
+            self.preview_scroll_map.remove(&selected);
+        }
+        let message = if self.render_markdown { "Previewing Markdown rendered" } else { "Previewing Markdown as text" };
+        self.notify(Severity::Info, message);
+    }
+
+    /// One line of facts about a previewed file, like "Rust • UTF-8 • 14 KB • 412 lines".
+    /// Kept for the selected file until it changes on disk.
+    fn preview_header(&mut self, path: &Path) -> String {
+        let metadata = fs::metadata(path).ok();
+        let modified = metadata.as_ref().and_then(|m| m.modified().ok());
+        if let Some((cached_path, cached_modified, header)) = &self.preview_header {
+            if cached_path == path && *cached_modified == modified {
+                return header.clone();
+            }
+        }
+
+        let size = metadata.as_ref().map_or(0, |m| m.len());
+        let mut head = Vec::new();
+        let readable = match fs::File::open(path) {
+            Ok(file) => io::Read::read_to_end(&mut io::Read::take(file, 8192), &mut head).is_ok(),
+            Err(_) => false,
+        };
+        let encoding = if head.starts_with(&[0xEF, 0xBB, 0xBF]) {
+            "UTF-8 with BOM"
+        } else if head.starts_with(&[0xFF, 0xFE]) {
+            "UTF-16 LE"
+        } else if head.starts_with(&[0xFE, 0xFF]) {
+            "UTF-16 BE"
+        } else if head.contains(&0) {
+            "binary"
+        } else if head.is_ascii() {
+            "ASCII"
+        } else {
+            match std::str::from_utf8(&head) {
+                Ok(_) => "UTF-8",
+                // A multi-byte character may be cut off at the end of the sample
+                Err(e) if e.error_len().is_none() => "UTF-8",
+                Err(_) => "not UTF-8",
+            }
+        };
+
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        let mut named = true;
+        let kind = match (extension.as_str(), media_format(path), archive_kind(path)) {
+            ("png" | "jpg" | "jpeg" | "gif" | "bmp" | "webp", _, _) => format!("{} image", extension.to_uppercase()),
+            ("pdf", _, _) => "PDF".to_string(),
+            ("ipynb", _, _) => "Jupyter notebook".to_string(),
+            ("plist", _, _) => "Property list".to_string(),
+            (_, Some((format, audio_only)), _) => format!("{} {}", format, if audio_only { "audio" } else { "video" }),
+            (_, _, Some(archive)) => format!("{} archive", archive),
+            _ if encoding == "binary" => "Binary".to_string(),
+            // Named once the syntaxes are in; until then the header isn't kept
+            _ if !self.syntax_ready() => {
+                named = false;
+                self.preview_waiting = true;
+                "Text".to_string()
+            }
+            _ => {
+                let syntaxes = self.syntax_set.as_ref().unwrap();
+                syntaxes
+                    .find_syntax_for_file(path)
+                    .ok()
+                    .flatten()
+                    .map_or_else(|| "Text".to_string(), |syntax| syntax.name.clone())
+            }
+        };
+
+        let mut facts = vec![kind];
+        if encoding != "binary" {
+            facts.push(encoding.to_string());
+        }
+        facts.push(self.locale.format_size(size, &["B", "KB", "MB", "GB"]));
+        // Counting reads the whole file, so it happens in the background and very large ones are skipped
+        if encoding != "binary" && size <= PREVIEW_LINE_COUNT_MAX_BYTES && readable {
+            match self.line_count(path, modified) {
+                Some(lines) => facts.push(format!("{} {}", lines, if lines == 1 { "line" } else { "lines" })),
+                None => {
+                    named = false;
+                    self.preview_waiting = true;
+                }
+            }
+        }
+
+        let header = facts.join(" • ");
+        if named {
+            self.preview_header = Some((path.to_path_buf(), modified, header.clone()));
+        }
+        header
+    }
+
+    /// is_binary_file for the previewed file, read again only when it changes on disk.
+    fn preview_is_binary(&mut self, path: &Path) -> bool {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        if let Some((cached_path, cached_modified, binary)) = &self.preview_binary {
+            if cached_path == path && *cached_modified == modified {
+                return *binary;
+            }
+        }
+        let binary = is_binary_file(path);
+        self.preview_binary = Some((path.to_path_buf(), modified, binary));
+        binary
+    }
+
+    /// Lines in `path` as of `modified`, or None while they're counted on a background thread.
+    fn line_count(&self, path: &Path, modified: Option<SystemTime>) -> Option<usize> {
+        let mut counts = self.line_counts.lock().ok()?;
+        match counts.get(path) {
+            Some((counted_modified, lines)) if *counted_modified == modified => return *lines,
+            _ => {}
+        }
+        counts.insert(path.to_path_buf(), (modified, None));
+        drop(counts);
+
+        let counts = Arc::clone(&self.line_counts);
+        let path = path.to_path_buf();
+        thread::spawn(move || {
+            let lines = count_lines(&path).unwrap_or(0);
+            if let Ok(mut counts) = counts.lock() {
+                counts.insert(path, (modified, Some(lines)));
+            }
+        });
+        None
+    }
+
+    fn ensure_syntax_loaded(&mut self) {
+        if self.syntax_set.is_none() {
+            let (syntax_set, theme_set) = match self.syntax_loader.take().map(thread::JoinHandle::join) {
+                Some(Ok(loaded)) => loaded,
+                _ => (SyntaxSet::load_defaults_newlines(), ThemeSet::load_defaults()),
+            };
+            self.syntax_set = Some(syntax_set);
+            self.theme_set = Some(theme_set);
+        }
+    }
+
+    /// Whether highlighting can be used without waiting. The first call starts
+    /// loading the syntaxes and themes in the background, so a preview shown
+    /// straight away (like with `preview_on_start`) doesn't hold up the first frame.
+    fn syntax_ready(&mut self) -> bool {
+        if self.syntax_set.is_some() {
+            return true;
+        }
+        match &self.syntax_loader {
+            Some(loader) if loader.is_finished() => {
+                self.ensure_syntax_loaded();
+                true
+            }
+            Some(_) => false,
+            None => {
+                self.syntax_loader = Some(thread::spawn(|| (SyntaxSet::load_defaults_newlines(), ThemeSet::load_defaults())));
+                false
+            }
+        }
+    }
+
+    fn calculate_dir_size(dir: &PathBuf) -> DiskUsage {
+        let mut total = DiskUsage::default();
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.filter_map(|e| e.ok()) {
+                if let Ok(metadata) = entry.metadata() {
+                    if metadata.is_file() {
+                        total.add(DiskUsage::of(&metadata));
+                    } else if metadata.is_dir() {
+                        total.add(Self::calculate_dir_size(&entry.path()));
+                    }
+                }
+            }
+        }
+        total
+    }
+
+    /// Size every listed directory in the background; sizes fill in as they
+    /// arrive and "Sizes calculated" is reported once the last one is in.
+    fn calculate_all_dir_sizes(&mut self) {
+        let dirs: Vec<PathBuf> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.is_dir() && !self.dir_size_cache.contains_key(entry))
+            .cloned()
+            .collect();
+        if dirs.is_empty() {
+            self.notify(Severity::Success, "Sizes calculated");
+            return;
+        }
+        self.calculating_sizes.get_or_insert_with(Instant::now);
+        self.start_dir_sizes(dirs);
+    }
+
+    /// Size the selected directory on a background thread, for when only one
+    /// folder matters and `e` would walk every directory in the listing.
+    fn calculate_selected_dir_size(&mut self) {
+        let Some(dir) = self.get_selected_path().filter(|path| path.is_dir()) else {
+            self.notify(Severity::Warning, "Select a directory to size");
+            return;
+        };
+        self.dir_size_announce.insert(dir.clone());
+        self.start_dir_sizes(vec![dir]);
+    }
+
+    /// Size `dirs` on a few background threads, skipping any already being sized.
+    fn start_dir_sizes(&mut self, dirs: Vec<PathBuf>) -> Option<Arc<Mutex<VecDeque<PathBuf>>>> {
+        let mut jobs = self.dir_size_jobs.lock().ok()?;
+        let dirs: Vec<PathBuf> = dirs.into_iter().filter(|dir| !jobs.contains_key(dir)).collect();
+        if dirs.is_empty() {
+            return None;
+        }
+        for dir in &dirs {
+            jobs.insert(dir.clone(), None);
+        }
+        drop(jobs);
+        self.dir_size_waiting = true;
+
+        // Each worker takes the next directory as it finishes one, so a huge tree holds up only one of them
+        let workers = thread::available_parallelism().map_or(1, |n| n.get()).min(DIR_SIZE_WORKERS).min(dirs.len());
+        let queue = Arc::new(Mutex::new(VecDeque::from(dirs)));
+        for _ in 0..workers {
+            let queue = Arc::clone(&queue);
+            let jobs = Arc::clone(&self.dir_size_jobs);
+            thread::spawn(move || {
+                while let Some(dir) = queue.lock().ok().and_then(|mut queue| queue.pop_front()) {
+                    let usage = Self::calculate_dir_size(&dir);
+                    match jobs.lock() {
+                        Ok(mut jobs) => jobs.insert(dir, Some(usage)),
+                        Err(_) => return,
+                    };
+                }
+            });
+        }
+        Some(queue)
+    }
+
+    /// With a size sort, size the listed directories in the background so the
+    /// heaviest float to the top as their sizes arrive. Only the first
+    /// SORT_SIZE_MAX_DIRS are sized, and those not reached yet are dropped when
+    /// the listing changes.
+    fn size_dirs_for_sort(&mut self) {
+        self.cancel_sort_sizing();
+        if !matches!(self.settings.sort_by.as_str(), "size" | "allocated") || self.slow_filesystem {
+            return;
+        }
+        let dirs: Vec<PathBuf> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.is_dir() && !self.dir_size_cache.contains_key(entry))
+            .take(SORT_SIZE_MAX_DIRS)
+            .cloned()
+            .collect();
+        self.sort_sizing = self.start_dir_sizes(dirs);
+    }
+
+    /// Take the directories a size sort hasn't got to yet off the queue. Those
+    /// being sized already finish and are cached.
+    fn cancel_sort_sizing(&mut self) {
+        let Some(queue) = self.sort_sizing.take() else {
+            return;
+        };
+        let dropped: Vec<PathBuf> = queue.lock().map(|mut queue| queue.drain(..).collect()).unwrap_or_default();
+        if let Ok(mut jobs) = self.dir_size_jobs.lock() {
+            for dir in &dropped {
+                jobs.remove(dir);
+            }
+        }
+    }
+
+    /// Move finished background directory sizes into the cache and report them.
+    fn collect_dir_sizes(&mut self) {
+        let finished: Vec<(PathBuf, DiskUsage)> = match self.dir_size_jobs.lock() {
+            Ok(mut jobs) => {
+                let done: Vec<PathBuf> = jobs.iter().filter(|(_, usage)| usage.is_some()).map(|(dir, _)| dir.clone()).collect();
+                self.dir_size_waiting = jobs.len() > done.len();
+                done.into_iter().filter_map(|dir| jobs.remove(&dir).flatten().map(|usage| (dir, usage))).collect()
+            }
+            Err(_) => return,
+        };
+        if finished.is_empty() {
+            return;
+        }
+        if !self.dir_size_waiting {
+            if let Some(started) = self.calculating_sizes.take() {
+                self.notify(Severity::Success, "Sizes calculated");
+                self.job_finished(started, "Sizes calculated");
+            }
+        }
+        let units = ["B", "KB", "MB", "GB", "TB"];
+        for (dir, usage) in finished {
+            // Only directories sized on request are reported
+            if !self.dir_size_announce.remove(&dir) {
+                self.dir_size_cache.insert(dir, usage);
+                continue;
+            }
+            let name = dir.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            self.notify(
+                Severity::Success,
+                format!(
+                    "{}: {} ({} on disk)",
+                    name,
+                    self.locale.format_size(usage.apparent, &units),
+                    self.locale.format_size(usage.allocated, &units)
+                ),
+            );
+            self.dir_size_cache.insert(dir, usage);
+        }
+        if matches!(self.settings.sort_by.as_str(), "size" | "allocated") {
+            self.resort();
+        }
+    }
+
+    /// Queue a desktop notification for a job that ran long enough for the user
+    /// to have switched to another window.
+    fn job_finished(&mut self, started: Instant, message: &str) {
+        if self.settings.desktop_notifications
+            && started.elapsed() >= Duration::from_secs(self.settings.desktop_notify_after_secs)
+        {
+            self.pending_desktop_notification = Some(format!("{} in {}", message, self.current_dir.display()));
+        }
+    }
+
+    /// Send the queued desktop notification if the terminal is still unfocused.
+    /// Called once no more input is waiting, so a focus change made while the
+    /// job ran has been seen.
+    fn flush_desktop_notification(&mut self) {
+        if let Some(message) = self.pending_desktop_notification.take() {
+            if !self.focused {
+                send_desktop_notification("ils", &message);
+            }
+        }
+    }
+
+    /// Location under ~/.cache/ils/`dir` of data derived from a file, keyed by its
+    /// path and mtime so an edited file gets a fresh entry.
+    fn derived_cache_path(path: &Path, dir: &str, suffix: &str) -> Option<PathBuf> {
+        use std::hash::{Hash, Hasher};
+
+        let home = env::var("HOME").ok()?;
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()).hash(&mut hasher);
+        modified.hash(&mut hasher);
+        let name = format!("{:016x}{}", hasher.finish(), suffix);
+        Some(PathBuf::from(home).join(".cache/ils").join(dir).join(name))
+    }
+
+    /// Location of the cached `size`-pixel thumbnail for an image.
+    fn thumbnail_path(path: &Path, size: u32) -> Option<PathBuf> {
+        Self::derived_cache_path(path, "thumbs", &format!("-{}.png", size))
+    }
+
+    /// Text of a PDF, from the disk cache or extracted page by page. Progress is
+    /// published to the preview cache; extraction stops early if the path shows up
+    /// in `cancelled`.
+    fn extract_pdf_text(
+        path: &Path,
+        cache: &Mutex<LruCache<PreviewState>>,
+        cancelled: &Mutex<HashSet<PathBuf>>,
+    ) -> PreviewState {
+        let text_path = Self::derived_cache_path(path, "pdf-text", ".txt");
+        if let Some(text) = text_path.as_ref().and_then(|text_path| fs::read_to_string(text_path).ok()) {
+            return PreviewState::Loaded(text.lines().map(|s| s.to_string()).collect());
+        }
+
+        let Ok(mut doc) = pdf_extract::Document::load(path) else {
+            return PreviewState::Error("Cannot extract PDF text".to_string());
+        };
+        if doc.is_encrypted() && doc.decrypt("").is_err() {
+            return PreviewState::Error("PDF is password protected".to_string());
+        }
+
+        let pages = doc.get_pages();
+        let mut text = String::new();
+        for (i, &page_num) in pages.keys().enumerate() {
+            if cancelled.lock().map(|mut cancelled| cancelled.remove(path)).unwrap_or(false) {
+                return PreviewState::Error("PDF text extraction cancelled".to_string());
+            }
+            if let Ok(mut cache_lock) = cache.lock() {
+                cache_lock.insert(path.to_path_buf(), PreviewState::Extracting { page: i + 1, pages: pages.len() });
+            }
+            // A page that fails to parse is skipped rather than losing the rest
+            let _ = pdf_extract::output_doc_page(&doc, &mut pdf_extract::PlainTextOutput::new(&mut text), page_num);
+        }
+        // A cancel that came in after the last page no longer applies
+        if let Ok(mut cancelled) = cancelled.lock() {
+            cancelled.remove(path);
+        }
+
+        // Write to a temp file first so a half-written cache entry is never picked up
+        if let Some(text_path) = text_path {
+            if let Some(dir) = text_path.parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+            let tmp_path = text_path.with_extension("tmp");
+            if fs::write(&tmp_path, &text).is_ok() {
+                let _ = fs::rename(&tmp_path, &text_path);
+            }
+        }
+        PreviewState::Loaded(text.lines().map(|s| s.to_string()).collect())
+    }
+
+    /// Stop extracting the selected PDF's text, if that's what the preview is
+    /// waiting on. Returns whether there was anything to cancel.
+    fn cancel_pdf_extraction(&mut self) -> bool {
+        let Some(selected) = self.get_selected_path().filter(|_| self.preview_mode) else {
+            return false;
+        };
+        let extracting = self.preview_cache.lock().ok().is_some_and(|mut cache| {
+            matches!(cache.get(&selected), Some(PreviewState::Loading | PreviewState::Extracting { .. }))
+        });
+        if extracting && selected.extension().is_some_and(|e| e.eq_ignore_ascii_case("pdf")) {
+            if let Ok(mut cancelled) = self.pdf_cancelled.lock() {
+                cancelled.insert(selected);
+            }
+            return true;
+        }
+        false
+    }
+
+    /// The protocol images are drawn with: the image_protocol setting, or the
+    /// one detected for "auto" on the first image.
+    fn image_protocol(&mut self) -> ImageProtocol {
+        if let Some(protocol) = self.image_protocol {
+            return protocol;
+        }
+        let protocol = match self.settings.image_protocol.as_str() {
+            "kitty" => ImageProtocol::Kitty,
+            "iterm" => ImageProtocol::Iterm,
+            "sixel" => ImageProtocol::Sixel,
+            "blocks" => ImageProtocol::Blocks,
+            _ => ImageProtocol::detect(),
+        };
+        self.image_protocol = Some(protocol);
+        protocol
+    }
+
+    /// Draw the image in `file` from `x`, `y`, scaled to fit `cols` by `rows`
+    /// cells. For the graphics protocols, decoded images are kept by path and
+    /// mtime, and so are the escape codes built for a size. Returns false if the
+    /// image can't be decoded.
+    fn print_image(&mut self, stdout: &mut io::Stdout, file: &Path, (x, y): (u16, u16), (cols, rows): (u16, u16)) -> io::Result<bool> {
+        let protocol = self.image_protocol();
+        if protocol == ImageProtocol::Blocks {
+            // viuer writes straight to the terminal, so flush what's queued first
+            stdout.flush()?;
+            let conf = ViuerConfig {
+                transparent: true,
+                absolute_offset: true,
+                x,
+                y: y as i16,
+                width: Some(cols as u32),
+                height: Some(rows as u32),
+                use_kitty: false,
+                use_iterm: false,
+                ..Default::default()
+            };
+            return Ok(print_from_file(file, &conf).is_ok());
+        }
+        let modified = fs::metadata(file).and_then(|m| m.modified()).ok();
+        if let Some((_, _, graphics)) = self.image_graphics.get(file).filter(|(m, size, _)| *m == modified && *size == (cols, rows)) {
+            queue!(stdout, cursor::MoveTo(x, y), Print(graphics))?;
+            return Ok(true);
+        }
+        if self.decoded_images.get(file).is_none_or(|(m, _)| *m != modified) {
+            let Ok(image) = image::open(file) else {
+                return Ok(false);
+            };
+            self.decoded_images.insert(file.to_path_buf(), (modified, image));
+        }
+        let Some((_, image)) = self.decoded_images.peek(file) else {
+            return Ok(false);
+        };
+
+        let ((cell_cols, cell_rows), (pixel_width, pixel_height)) = fit_image(image.width(), image.height(), cols, rows);
+        let png = || {
+            let mut png = Vec::new();
+            image.write_to(&mut io::Cursor::new(&mut png), image::ImageFormat::Png).ok().map(|_| png)
+        };
+        let graphics = match protocol {
+            ImageProtocol::Kitty => png().map(|png| kitty_graphics(&png, cell_cols, cell_rows)),
+            ImageProtocol::Iterm => png().map(|png| iterm_graphics(&png, cell_cols, cell_rows)),
+            ImageProtocol::Sixel => {
+                let scaled = image.resize_exact(pixel_width, pixel_height, image::imageops::FilterType::Triangle);
+                Some(sixel_graphics(&scaled.to_rgba8()))
+            }
+            ImageProtocol::Blocks => None,
+        };
+        let Some(graphics) = graphics else {
+            return Ok(false);
+        };
+        queue!(stdout, cursor::MoveTo(x, y), Print(&graphics))?;
+        self.image_graphics.insert(file.to_path_buf(), (modified, (cols, rows), graphics));
+        Ok(true)
+    }
+
+    /// Decode an image and store a downscaled copy in the thumbnail cache.
+    /// Returns false if the image can't be decoded or the cache can't be written.
+    fn create_thumbnail(path: &Path, thumb_path: &Path, size: u32) -> bool {
+        let Ok(image) = image::open(path) else {
+            return false;
+        };
+        let thumb = image.thumbnail(size, size);
+        if let Some(dir) = thumb_path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        // Write to a temp file first so a half-written thumbnail is never picked up
+        let tmp_path = thumb_path.with_extension("tmp");
+        if thumb.save_with_format(&tmp_path, image::ImageFormat::Png).is_ok() {
+            fs::rename(&tmp_path, thumb_path).is_ok()
+        } else {
+            let _ = fs::remove_file(&tmp_path);
+            false
+        }
+    }
+
+    /// Generate thumbnails for (image, thumbnail path) pairs in one background
+    /// thread, skipping any that are already being generated or have failed.
+    fn start_thumbnails(&self, jobs: Vec<(PathBuf, PathBuf)>, size: u32) {
+        let pending = Arc::clone(&self.thumbnails_pending);
+        let failed = Arc::clone(&self.thumbnails_failed);
+        let jobs: Vec<(PathBuf, PathBuf)> = jobs.into_iter().filter(|(_, thumb_path)| !self.thumbnail_failed(thumb_path)).collect();
+        let jobs: Vec<(PathBuf, PathBuf)> = match pending.lock() {
+            Ok(mut pending_lock) => jobs.into_iter().filter(|(_, thumb_path)| pending_lock.insert(thumb_path.clone())).collect(),
+            Err(_) => return,
+        };
+        if jobs.is_empty() {
+            return;
+        }
+        thread::spawn(move || {
+            for (path, thumb_path) in jobs {
+                if !Self::create_thumbnail(&path, &thumb_path, size) {
+                    if let Ok(mut failed_lock) = failed.lock() {
+                        failed_lock.insert(thumb_path.clone());
+                    }
+                }
+                if let Ok(mut pending_lock) = pending.lock() {
+                    pending_lock.remove(&thumb_path);
+                }
+            }
+        });
+    }
+
+    /// Whether generating this thumbnail failed before. The thumbnail path
+    /// changes with the image's mtime, so an edited image is tried again.
+    fn thumbnail_failed(&self, thumb_path: &Path) -> bool {
+        self.thumbnails_failed.lock().is_ok_and(|failed| failed.contains(thumb_path))
+    }
+
+    /// Preload previews for the entries around the selection so moving onto
+    /// them is instant. Only PDFs and images are slow enough to be worth it.
+    fn prefetch_adjacent_previews(&mut self) {
+        let depth = self.settings.preview_prefetch;
+        if !self.preview_mode || depth == 0 || self.slow_filesystem {
+            return;
+        }
+        let mut thumbnails = Vec::new();
+        for offset in 1..=depth {
+            for idx in [self.selected.checked_sub(offset), self.selected.checked_add(offset)].into_iter().flatten() {
+                let Some(path) = self.entries.get(idx).cloned() else {
+                    continue;
+                };
+                let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+                if extension == "pdf" {
+                    let cached = self.preview_cache.lock().map(|cache| cache.contains_key(&path)).unwrap_or(true);
+                    if !cached {
+                        self.start_preview_load(path);
+                    }
+                } else if is_image(&path) {
+                    if let Some(thumb_path) = Self::thumbnail_path(&path, THUMBNAIL_SIZE).filter(|p| !p.exists()) {
+                        thumbnails.push((path, thumb_path));
+                    }
+                }
+            }
+        }
+        self.start_thumbnails(thumbnails, THUMBNAIL_SIZE);
+    }
+
+    fn start_preview_load(&self, path: PathBuf) {
+        let cache = Arc::clone(&self.preview_cache);
+        let pdf_cancelled = Arc::clone(&self.pdf_cancelled);
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        let strings_min_length = self.settings.strings_min_length;
+        let locale = self.locale;
+
+        // Mark as loading
+        if let Ok(mut cache_lock) = cache.lock() {
+            cache_lock.insert(path.clone(), PreviewState::Loading);
+        }
+
+        thread::spawn(move || {
+            let result = if matches!(extension.as_str(), "png" | "jpg" | "jpeg" | "gif" | "bmp" | "webp") {
+                // For images, we can't really cache rendered output easily
+                // Just mark as loaded with placeholder
+                PreviewState::Loaded(vec!["[Image Preview]".to_string()])
+            } else if extension == "pdf" {
+                Self::extract_pdf_text(&path, &cache, &pdf_cancelled)
+            } else if is_apple_encoded(&path, &extension) {
+                match decode_apple_file(&path) {
+                    Ok(lines) => PreviewState::Loaded(lines),
+                    Err(e) => PreviewState::Error(format!("Cannot decode file: {}", e)),
+                }
+            } else if media_format(&path).is_some() {
+                match read_media_info(&path) {
+                    Ok(info) => PreviewState::Loaded(media_lines(&info)),
+                    Err(e) => PreviewState::Error(format!("Cannot read media file: {}", e)),
+                }
+            } else if archive_kind(&path).is_some() {
+                match list_archive(&path) {
+                    Ok(entries) => PreviewState::Loaded(archive_lines(&entries, locale)),
+                    Err(e) => PreviewState::Error(format!("Cannot read archive: {}", e)),
+                }
+            } else if extension != "ipynb" && is_binary_file(&path) {
+                match extract_strings(&path, strings_min_length) {
+                    Ok(lines) => PreviewState::Loaded(lines),
+                    Err(e) => PreviewState::Error(format!("Cannot read file: {}", e)),
+                }
+            } else if extension == "ipynb" {
+                match fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|text| parse_notebook(&text)) {
+                    Ok((language, cells)) => PreviewState::Notebook { language, cells },
+                    Err(e) => PreviewState::Error(format!("Cannot read notebook: {}", e)),
+                }
+            } else {
+                PreviewState::NotLoaded
+            };
+
+            if let Ok(mut cache_lock) = cache.lock() {
+                cache_lock.insert(path, result);
+            }
+        });
+    }
+
+    /// Badge text for a directory in list mode (empty if it can't be read), or None
+    /// while it is still being counted. Directories that need (re)counting are
+    /// pushed onto `to_count`.
+    fn item_count_badge(&self, dir: &Path, to_count: &mut Vec<PathBuf>) -> Option<String> {
+        let modified = fs::metadata(dir).and_then(|m| m.modified()).ok();
+        let cache = self.item_count_cache.lock().ok()?;
+        match cache.get(dir) {
+            Some(ItemCount::Counted { modified: counted_at, all, visible }) if *counted_at == modified => {
+                let count = if self.show_hidden { *all } else { *visible };
+                Some(format!(" {}", count))
+            }
+            Some(ItemCount::Unreadable) => Some(String::new()),
+            Some(ItemCount::Counting) => None,
+            _ => {
+                if !to_count.iter().any(|queued| queued == dir) {
+                    to_count.push(dir.to_path_buf());
+                }
+                None
+            }
+        }
+    }
+
+    /// Whether a listed directory is empty (counting hidden entries only when they
+    /// are shown) or unreadable, once it has been counted. Directories that need
+    /// (re)counting are pushed onto `to_count`, and `waiting` is set until then.
+    fn dir_state(&self, dir: &Path, to_count: &mut Vec<PathBuf>, waiting: &mut bool) -> Option<DirState> {
+        if !self.settings.mark_empty_and_unreadable || self.slow_filesystem {
+            return None;
+        }
+        let modified = fs::metadata(dir).and_then(|m| m.modified()).ok();
+        let cache = self.item_count_cache.lock().ok()?;
+        match cache.get(dir) {
+            Some(ItemCount::Counted { modified: counted_at, all, visible }) if *counted_at == modified => {
+                let count = if self.show_hidden { *all } else { *visible };
+                (count == 0).then_some(DirState::Empty)
+            }
+            Some(ItemCount::Unreadable) => Some(DirState::Unreadable),
+            Some(ItemCount::Counting) => {
+                *waiting = true;
+                None
+            }
+            _ => {
+                if !to_count.iter().any(|queued| queued == dir) {
+                    to_count.push(dir.to_path_buf());
+                }
+                *waiting = true;
+                None
+            }
+        }
+    }
+
+    /// Language badge of a project directory, looked up once per listing.
+    fn project_badge(&self, dir: &Path) -> Option<&'static str> {
+        if !self.settings.show_project_badges || self.slow_filesystem {
+            return None;
+        }
+        let mut badges = self.project_badges.lock().ok()?;
+        *badges.entry(dir.to_path_buf()).or_insert_with(|| {
+            PROJECT_MARKERS.iter().find(|(marker, _)| dir.join(marker).is_file()).map(|(_, badge)| *badge)
+        })
+    }
+
+    /// Duration and resolution of a media file for the list's media column, read once per
+    /// listing. Files not read yet are pushed onto `to_read` and set `waiting`.
+    fn media_summary(&self, path: &Path, to_read: &mut Vec<PathBuf>, waiting: &mut bool) -> Option<String> {
+        media_format(path)?;
+        if self.slow_filesystem {
+            return None;
+        }
+        let media = self.media_info.lock().ok()?;
+        let info = match media.get(path) {
+            Some(Some(info)) => info.as_ref()?,
+            Some(None) => {
+                *waiting = true;
+                return None;
+            }
+            None => {
+                to_read.push(path.to_path_buf());
+                *waiting = true;
+                return None;
+            }
+        };
+        let duration = info.duration.map_or_else(String::new, format_duration);
+        Some(match info.resolution() {
+            Some((width, height)) => format!("{:>8}  {}x{}", duration, width, height),
+            None => format!("{:>8}", duration),
+        })
+    }
+
+    /// Read the headers of `files` on a background thread for the media column.
+    fn start_media_info(&self, files: Vec<PathBuf>) {
+        if files.is_empty() {
+            return;
+        }
+        let media = Arc::clone(&self.media_info);
+        if let Ok(mut media_lock) = media.lock() {
+            for file in &files {
+                media_lock.insert(file.clone(), None);
+            }
+        }
+        thread::spawn(move || {
+            for file in files {
+                let info = read_media_info(&file).ok();
+                match media.lock() {
+                    Ok(mut media_lock) => media_lock.insert(file, Some(info)),
+                    Err(_) => return,
+                };
+            }
+        });
+    }
+
+    fn start_item_count(&self, dirs: Vec<PathBuf>) {
+        if dirs.is_empty() {
+            return;
+        }
+        let cache = Arc::clone(&self.item_count_cache);
+
+        // Mark as counting so the next draw doesn't queue them again
+        if let Ok(mut cache_lock) = cache.lock() {
+            for dir in &dirs {
+                cache_lock.insert(dir.clone(), ItemCount::Counting);
+            }
+        }
+
+        thread::spawn(move || {
+            for dir in dirs {
+                let modified = fs::metadata(&dir).and_then(|m| m.modified()).ok();
+                let (all, visible) = match fs::read_dir(&dir) {
+                    Ok(read_dir) => read_dir.filter_map(|e| e.ok()).fold((0, 0), |(all, visible), e| {
+                        let hidden = e.file_name().to_string_lossy().starts_with('.');
+                        (all + 1, if hidden { visible } else { visible + 1 })
+                    }),
+                    Err(_) => {
+                        // Unreadable directories get no badge
+                        if let Ok(mut cache_lock) = cache.lock() {
+                            cache_lock.insert(dir, ItemCount::Unreadable);
+                        }
+                        continue;
+                    }
+                };
+                if let Ok(mut cache_lock) = cache.lock() {
+                    cache_lock.insert(dir, ItemCount::Counted { modified, all, visible });
+                }
+            }
+        });
+    }
+
+    /// Git state of a listed directory, or None if it isn't a repository. Repositories
+    /// not checked since the last load are pushed onto `to_scan`.
+    fn git_status(&self, dir: &Path, to_scan: &mut Vec<PathBuf>) -> Option<GitStatus> {
+        if !dir.join(".git").exists() {
+            return None;
+        }
+        let cache = self.git_status_cache.lock().ok()?;
+        match cache.get(dir) {
+            Some(status) => Some(status.clone()),
+            None => {
+                to_scan.push(dir.to_path_buf());
+                Some(GitStatus::Scanning)
+            }
+        }
+    }
+
+    /// Status codes of entries inside the repository, once scanned for the
+    /// current directory.
+    fn git_change(&self, entry: &Path) -> Option<(char, char)> {
+        let changes = self.git_changes.lock().ok()?;
+        match changes.as_ref() {
+            Some((dir, codes, _)) if *dir == self.current_dir => codes.get(entry).copied(),
+            _ => None,
+        }
+    }
+
+    fn git_changes_loaded(&self) -> bool {
+        self.git_changes.lock().is_ok_and(|changes| changes.as_ref().is_some_and(|(dir, _, _)| *dir == self.current_dir))
+    }
+
+    /// Leave out the entries the git job found ignored once it's done, keeping the
+    /// same entry selected. Waits while a mode like the filter works on the listing.
+    fn hide_ignored_entries(&mut self) {
+        if !self.ignored_pending || self.has_active_mode() {
+            return;
+        }
+        let ignored = match self.git_changes.lock() {
+            Ok(changes) => match changes.as_ref() {
+                Some((dir, _, Some(ignored))) if *dir == self.current_dir => ignored.clone(),
+                _ => return,
+            },
+            Err(_) => HashSet::new(),
+        };
+        self.ignored_pending = false;
+        if ignored.is_empty() {
+            return;
+        }
+        let selected = self.get_selected_path();
+        self.entries.retain(|path| !ignored.contains(path));
+        self.collapsed_entries.retain(|path| !ignored.contains(path));
+        self.ignored_entries.extend(ignored);
+        match selected.and_then(|selected| self.entries.iter().position(|path| *path == selected)) {
+            Some(idx) => self.selected = idx,
+            None => self.keep_selection_near(self.selected),
+        }
+    }
+
+    /// Scan `git status` for the current directory in the background. Paths
+    /// below a listed directory count towards that directory. Which of `check`
+    /// git ignores is found out in the same job.
+    fn start_git_changes(&self, check: Option<Vec<PathBuf>>) {
+        let Some(root) = self.git_root.clone().filter(|_| self.settings.show_git_status || check.is_some()) else {
+            return;
+        };
+        let show_status = self.settings.show_git_status;
+        let dir = self.current_dir.clone();
+        let changes = Arc::clone(&self.git_changes);
+        // Forget the last scan so the listing waits for, and redraws with, this one
+        if let Ok(mut changes) = changes.lock() {
+            *changes = None;
+        }
+        thread::spawn(move || {
+            let ignored = check.map(|paths| gitignored(&dir, &paths));
+            let output = show_status.then(|| {
+                std::process::Command::new("git")
+                    .arg("-C")
+                    .arg(&dir)
+                    .args(["status", "--porcelain", "-z", "--", "."])
+                    .stdin(std::process::Stdio::null())
+                    .stderr(std::process::Stdio::null())
+                    .output()
+            });
+            let mut codes: HashMap<PathBuf, (char, char)> = HashMap::new();
+            if let Some(output) = output.and_then(Result::ok).filter(|output| output.status.success()) {
+                let text = String::from_utf8_lossy(&output.stdout);
+                let mut records = text.split('\0');
+                while let Some(record) = records.next() {
+                    let mut chars = record.chars();
+                    let (Some(x), Some(y), Some(' ')) = (chars.next(), chars.next(), chars.next()) else {
+                        continue;
+                    };
+                    if x == 'R' || x == 'C' {
+                        // Renames and copies are followed by the old path
+                        records.next();
+                    }
+                    let path = root.join(chars.as_str().trim_end_matches('/'));
+                    let Ok(relative) = path.strip_prefix(&dir) else {
+                        continue;
+                    };
+                    let Some(first) = relative.components().next() else {
+                        continue;
+                    };
+                    let entry = codes.entry(dir.join(first)).or_insert((x, y));
+                    // A directory shows unstaged work if any of its files has some
+                    if entry.1 == ' ' && y != ' ' {
+                        *entry = (x, y);
+                    }
+                }
+            }
+            if let Ok(mut changes) = changes.lock() {
+                *changes = Some((dir, codes, ignored));
+            }
+        });
+    }
+
+    /// Switch the preview of modified files between their contents and their diff.
+    fn toggle_diff_preview(&mut self) {
+        if self.git_root.is_none() {
+            self.notify(Severity::Warning, "Not inside a git repository");
+            return;
+        }
+        self.show_diff = !self.show_diff;
+        if self.show_diff {
+            self.preview_mode = true;
+        }
+        // Diff and contents have their own lengths, so start both from the top
+        if let Some(selected) = self.get_selected_path() {
+            self.preview_scroll_map.remove(&selected);
+        }
+        let message = if self.show_diff { "Previewing git diffs of modified files" } else { "Previewing file contents" };
+        self.notify(Severity::Info, message);
+    }
+
+    /// Stage or unstage the marked entries, or the selected one.
+    fn git_stage(&mut self, stage: bool) -> io::Result<()> {
+        if self.git_root.is_none() {
+            self.notify(Severity::Warning, "Not inside a git repository");
+            return Ok(());
+        }
+        let targets = self.action_targets();
+        if targets.is_empty() {
+            return Ok(());
+        }
+        let mut command = std::process::Command::new("git");
+        command.arg("-C").arg(&self.current_dir);
+        if stage {
+            command.arg("add");
+        } else {
+            command.args(["restore", "--staged"]);
+        }
+        let output = command
+            .arg("--")
+            .args(&targets)
+            .stdin(std::process::Stdio::null())
+            .output();
+
+        match output {
+            Ok(output) if output.status.success() => {
+                let verb = if stage { "Staged" } else { "Unstaged" };
+                let message = match targets.as_slice() {
+                    [path] => format!("{} '{}'", verb, path.file_name().unwrap_or_default().to_string_lossy()),
+                    _ => format!("{} {} items", verb, targets.len()),
+                };
+                self.notify(Severity::Success, message);
+                self.marked.clear();
+            }
+            Ok(output) => {
+                let error = String::from_utf8_lossy(&output.stderr);
+                self.notify(Severity::Error, format!("git: {}", error.lines().next().unwrap_or("failed")));
+            }
+            Err(e) => self.notify(Severity::Error, format!("Couldn't run git: {}", e)),
+        }
+        self.start_git_changes(None);
+        if let Ok(mut cache) = self.git_status_cache.lock() {
+            cache.clear();
+        }
+        if let Ok(mut diff) = self.preview_diff.lock() {
+            *diff = None;
+        }
+        Ok(())
+    }
+
+    fn start_git_status(&self, dirs: Vec<PathBuf>) {
+        if dirs.is_empty() {
+            return;
+        }
+        let cache = Arc::clone(&self.git_status_cache);
+
+        // Mark as scanning so the next draw doesn't queue them again
+        if let Ok(mut cache_lock) = cache.lock() {
+            for dir in &dirs {
+                cache_lock.insert(dir.clone(), GitStatus::Scanning);
+            }
+        }
+
+        thread::spawn(move || {
+            for dir in dirs {
+                let output = std::process::Command::new("git")
+                    .arg("-C")
+                    .arg(&dir)
+                    .args(["status", "--porcelain", "--branch"])
+                    .stdin(std::process::Stdio::null())
+                    .stderr(std::process::Stdio::null())
+                    .output();
+                let status = match output {
+                    Ok(output) if output.status.success() => {
+                        let text = String::from_utf8_lossy(&output.stdout);
+                        let mut lines = text.lines();
+                        // "## main...origin/main [ahead 1]", "## No commits yet on main" or "## HEAD (no branch)"
+                        let header = lines.next().unwrap_or("").trim_start_matches("## ");
+                        let branch = if let Some(branch) = header.strip_prefix("No commits yet on ") {
+                            branch.to_string()
+                        } else if header.starts_with("HEAD (no branch)") {
+                            String::from("detached")
+                        } else {
+                            header.split("...").next().unwrap_or(header).to_string()
+                        };
+                        GitStatus::Scanned { branch, dirty: lines.next().is_some() }
+                    }
+                    _ => GitStatus::Failed,
+                };
+                if let Ok(mut cache_lock) = cache.lock() {
+                    cache_lock.insert(dir, status);
+                }
+            }
+        });
+    }
+
+    #[allow(dead_code)]
+    fn config_exists() -> bool {
+        if let Ok(home) = env::var("HOME") {
+            let config_path = PathBuf::from(home).join(".config/ils/preview_ratio");
+            config_path.exists()
+        } else {
+            false
+        }
+    }
+
+    #[allow(dead_code)]
+    fn color_config_exists() -> bool {
+        if let Ok(home) = env::var("HOME") {
+            let config_path = PathBuf::from(home).join(".config/ils/colors.toml");
+            config_path.exists()
+        } else {
+            false
+        }
+    }
+
+    fn save_preview_ratio(&self) -> io::Result<()> {
+        // Update preview_split_ratio in the saved config
+        let value = self.preview_split_ratio;
+        Config::update_settings(|settings| settings.preview_split_ratio = value)
+    }
+
+    fn save_show_help_state(&self) -> io::Result<()> {
+        // Update show_help_on_start in the saved config
+        let value = self.show_help;
+        Config::update_settings(|settings| settings.show_help_on_start = value)
+    }
+
+    /// Sort the listing by `sort_by`, directories first. Grouped by type, the
+    /// entries of collapsed groups are set aside in `collapsed_entries`.
+    fn sort_entries(&mut self) {
+        let mut entries = std::mem::take(&mut self.entries);
+        entries.append(&mut self.collapsed_entries);
+        let sorted = self.sorted_kinds(entries);
+        self.entry_groups = if self.settings.group_by_type && self.settings.sort_by == "extension" {
+            sorted.iter().map(|(path, is_dir)| (path.clone(), file_group(path, *is_dir))).collect()
+        } else {
+            HashMap::new()
+        };
+        let sorted = sorted.into_iter().map(|(path, _)| path);
+        if self.grouped() && !self.collapsed_groups.is_empty() {
+            (self.collapsed_entries, self.entries) = sorted.partition(|path| self.collapsed_groups.contains(&self.group_of(path)));
+        } else {
+            self.entries = sorted.collect();
+        }
+    }
+
+    /// The group an entry of the listing is under, as worked out by the last sort.
+    fn group_of(&self, path: &Path) -> usize {
+        self.entry_groups.get(path).copied().unwrap_or_else(|| file_group(path, path.is_dir()))
+    }
+
+    /// Set the entries of collapsed groups aside, or put them back, to match
+    /// whether the listing is grouped right now: a filter, fuzzy find or the
+    /// gallery go through everything. Coming back, the selected entry's group opens.
+    fn sync_collapsed(&mut self) {
+        if self.collapsed_groups.is_empty() {
+            return;
+        }
+        let grouped = self.grouped();
+        if grouped {
+            if !self.entries.iter().any(|path| self.collapsed_groups.contains(&self.group_of(path))) {
+                return;
+            }
+            if let Some(selected) = self.get_selected_path() {
+                let group = self.group_of(&selected);
+                self.collapsed_groups.retain(|collapsed| *collapsed != group);
+            }
+        } else if self.collapsed_entries.is_empty() {
+            return;
+        }
+        self.resort();
+    }
+
+    /// Whether the listing shows under group headers: sorted by extension with
+    /// group_by_type on, and not narrowed by a filter or shown as a gallery.
+    fn grouped(&self) -> bool {
+        self.settings.group_by_type && self.settings.sort_by == "extension" && !self.filter_mode && !self.fuzzy_mode && !self.gallery_active()
+    }
+
+    /// The rows of the listing, `num_cols` entries each. Grouped, every group
+    /// starts on a row of its own under its header, and a collapsed one is just the header.
+    fn listing_rows(&self) -> Vec<ListingRow> {
+        let cols = self.num_cols.max(1);
+        let mut rows = Vec::new();
+        let chunk = |rows: &mut Vec<ListingRow>, start: usize, end: usize| {
+            rows.extend((start..end).step_by(cols).map(|first| ListingRow::Entries(first, (first + cols).min(end))));
+        };
+        if !self.grouped() {
+            chunk(&mut rows, 0, self.entries.len());
+            return rows;
+        }
+
+        // Directories stay first when the order is reversed
+        let order: Vec<usize> = match self.settings.sort_reverse {
+            false => (0..FILE_GROUPS.len()).collect(),
+            true => std::iter::once(0).chain((1..FILE_GROUPS.len()).rev()).collect(),
+        };
+        let mut start = 0;
+        for group in order {
+            let shown = self.entries[start..].iter().take_while(|path| self.group_of(path) == group).count();
+            let collapsed = self.collapsed_entries.iter().filter(|path| self.group_of(path) == group).count();
+            if shown + collapsed == 0 {
+                continue;
+            }
+            rows.push(ListingRow::Group(group, shown + collapsed));
+            chunk(&mut rows, start, start + shown);
+            start += shown;
+        }
+        // Anything out of order since the last sort goes at the end
+        chunk(&mut rows, start, self.entries.len());
+        rows
+    }
+
+    /// The row of `rows` holding the selected entry, and its column in it.
+    fn selected_cell(&self, rows: &[ListingRow]) -> Option<(usize, usize)> {
+        rows.iter().enumerate().find_map(|(row, cells)| match *cells {
+            ListingRow::Entries(first, last) if (first..last).contains(&self.selected) => Some((row, self.selected - first)),
+            _ => None,
+        })
+    }
+
+    /// Collapse the group of the selected entry to its header, selecting what follows it.
+    fn collapse_group(&mut self) {
+        if !self.grouped() {
+            self.notify(Severity::Info, "Groups show when sorting by extension");
+            return;
+        }
+        let Some(selected) = self.get_selected_path() else {
+            return;
+        };
+        let group = self.group_of(&selected);
+        let first = self.entries.iter().position(|path| self.group_of(path) == group).unwrap_or(0);
+        self.collapsed_groups.push(group);
+        self.sort_entries();
+        self.selected = first.min(self.entries.len().saturating_sub(1));
+        self.scroll_to_selected();
+    }
+
+    fn expand_groups(&mut self) {
+        if self.collapsed_groups.is_empty() {
+            return;
+        }
+        self.collapsed_groups.clear();
+        self.resort();
+    }
+
+    fn sorted(&self, paths: Vec<PathBuf>) -> Vec<PathBuf> {
+        self.sorted_kinds(paths).into_iter().map(|(path, _)| path).collect()
+    }
+
+    /// `paths` in listing order, each with whether it is a directory.
+    fn sorted_kinds(&self, paths: Vec<PathBuf>) -> Vec<(PathBuf, bool)> {
+        use std::cmp::Ordering;
+        let mode = self.settings.sort_by.as_str();
+        let mut keyed: Vec<(PathBuf, bool, Option<fs::Metadata>)> = paths
+            .into_iter()
+            .map(|path| {
+                let metadata = if matches!(mode, "modified" | "size" | "allocated") { fs::metadata(&path).ok() } else { None };
+                let is_dir = metadata.as_ref().map_or_else(|| path.is_dir(), |m| m.is_dir());
+                (path, is_dir, metadata)
+            })
+            .collect();
+
+        let name = |path: &Path| path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        keyed.sort_by(|(a, a_is_dir, a_meta), (b, b_is_dir, b_meta)| {
+            if a_is_dir != b_is_dir {
+                return b_is_dir.cmp(a_is_dir);
+            }
+            let by_name = || a.file_name().cmp(&b.file_name());
+            let ordering = match mode {
+                // Newest and largest first, like ls -t and ls -S
+                "modified" => {
+                    let modified = |meta: &Option<fs::Metadata>| meta.as_ref().and_then(|m| m.modified().ok());
+                    modified(b_meta).cmp(&modified(a_meta))
+                }
+                // Directories go by their calculated size, those not yet
+                // calculated after the rest by name
+                "size" | "allocated" => {
+                    let size = |path: &Path, meta: &Option<fs::Metadata>, is_dir: bool| {
+                        let usage = if is_dir { self.dir_size_cache.peek(path).copied() } else { meta.as_ref().map(DiskUsage::of) };
+                        usage.map(|usage| if mode == "size" { usage.apparent } else { usage.allocated })
+                    };
+                    size(b, b_meta, *b_is_dir).cmp(&size(a, a_meta, *a_is_dir))
+                }
+                "extension" if self.settings.group_by_type => file_group(a, *a_is_dir)
+                    .cmp(&file_group(b, *b_is_dir))
+                    .then_with(|| a.extension().cmp(&b.extension())),
+                "extension" => a.extension().cmp(&b.extension()),
+                "natural" => natural_cmp(&name(a), &name(b)),
+                _ => Ordering::Equal,
+            }
+            .then_with(by_name);
+            if self.settings.sort_reverse { ordering.reverse() } else { ordering }
+        });
+        keyed.into_iter().map(|(path, is_dir, _)| (path, is_dir)).collect()
+    }
+
+    /// Sort the listing again, keeping the selection on the same entry.
+    fn resort(&mut self) {
+        let selected = self.get_selected_path();
+        self.sort_entries();
+        self.selected = selected.and_then(|path| self.entries.iter().position(|p| *p == path)).unwrap_or(0);
+        self.scroll_to_selected();
+    }
+
+    /// How the listing is sorted, for the path bar and column headers.
+    fn sort_label(&self) -> &'static str {
+        match (self.settings.sort_by.as_str(), self.settings.sort_reverse) {
+            ("modified", false) => "newest first",
+            ("modified", true) => "oldest first",
+            ("size", false) => "largest first",
+            ("size", true) => "smallest first",
+            ("allocated", false) => "largest on disk first",
+            ("allocated", true) => "smallest on disk first",
+            ("extension", false) => "by extension",
+            ("extension", true) => "by extension, reversed",
+            ("natural", false) => "natural order",
+            ("natural", true) => "natural order, reversed",
+            (_, false) => "name A-Z",
+            (_, true) => "name Z-A",
+        }
+    }
+
+    /// Switch to the next sort mode (or flip the order), keeping the selection
+    /// on the same entry, and save the choice.
+    fn change_sort(&mut self, reverse: bool) {
+        if reverse {
+            self.settings.sort_reverse = !self.settings.sort_reverse;
+        } else {
+            let current = SORT_MODES.iter().position(|mode| *mode == self.settings.sort_by).unwrap_or(0);
+            self.settings.sort_by = SORT_MODES[(current + 1) % SORT_MODES.len()].to_string();
+        }
+        self.resort();
+        self.size_dirs_for_sort();
+        self.notify(Severity::Info, format!("Sorted {}", self.sort_label()));
+
+        let (sort_by, sort_reverse) = (self.settings.sort_by.clone(), self.settings.sort_reverse);
+        let _ = Config::update_settings(|settings| {
+            settings.sort_by = sort_by;
+            settings.sort_reverse = sort_reverse;
+        });
+    }
+
+    /// Keep this session's preview scroll positions for the next one.
+    fn save_preview_positions(&self) -> io::Result<()> {
+        PreviewPositions::save_changes(&self.saved_preview_scroll, &self.preview_scroll_map)
+    }
+
+    fn save_show_hidden(&self) -> io::Result<()> {
+        // Update show_hidden in the saved config
+        let value = self.show_hidden;
+        Config::update_settings(|settings| settings.show_hidden = value)
+    }
+
+    fn save_hide_gitignored(&self) -> io::Result<()> {
+        let value = self.hide_gitignored;
+        Config::update_settings(|settings| settings.hide_gitignored = value)
+    }
+
+    fn load_entries(&mut self) -> io::Result<()> {
+        if let Some(selected) = self.entries.get(self.selected) {
+            if let Some(dir) = selected.parent() {
+                self.last_selected.insert(dir.to_path_buf(), selected.clone());
+            }
+        }
+        self.entries.clear();
+        self.selected = 0;
+        self.scroll_offset = 0;
+
+        self.listing_modified = fs::metadata(&self.current_dir).and_then(|m| m.modified()).ok();
+        // An unreadable directory stays current with no entries, so the error pane can offer a way out
+        self.load_error = None;
+        let mut entries: Vec<PathBuf> = match fs::read_dir(&self.current_dir) {
+            Ok(read_dir) => read_dir.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+            Err(e) => {
+                self.load_error = Some(e);
+                Vec::new()
+            }
+        };
+
+        // Filter out hidden files (starting with '.') if show_hidden is false
+        let total = entries.len();
+        if !self.show_hidden {
+            entries.retain(|path| {
+                path.file_name()
+                    .and_then(|n| n.to_str())
+                    .map(|n| !n.starts_with('.'))
+                    .unwrap_or(true)
+            });
+        }
+        self.hidden_count = total - entries.len();
+
+        self.git_root = git_root(&self.current_dir);
+        self.ignored_entries.clear();
+        self.ignored_pending = self.hide_gitignored && self.git_root.is_some();
+
+        // Working trees change without touching the directory, so check repositories afresh
+        if let Ok(mut cache) = self.git_status_cache.lock() {
+            cache.clear();
+        }
+        if let Ok(mut badges) = self.project_badges.lock() {
+            badges.clear();
+        }
+        if let Ok(mut media) = self.media_info.lock() {
+            media.clear();
+        }
+        // Ignore rules can sit in any directory up to the root, so git matches them for this one
+        self.start_git_changes(self.ignored_pending.then(|| entries.clone()));
+
+        self.entries = entries;
+        self.collapsed_entries.clear();
+        self.sort_entries();
+        if self.settings.show_disk_space {
+            self.disk_space = disk_space(&self.current_dir);
+        }
+        self.slow_filesystem = mount_info(&self.current_dir).is_some_and(|mount| mount.is_network());
+        self.size_dirs_for_sort();
+        if self.in_trash() {
+            // Sized afresh on every visit, since trashing elsewhere changes it
+            self.dir_size_cache.remove(&self.current_dir);
+            self.start_dir_sizes(vec![self.current_dir.clone()]);
+        }
+        self.refresh_other_pane();
+        self.update_layout()?; // Recalculate layout after loading new directory entries
+        Ok(())
+    }
+
+    /// Recalculates the number of columns and adjusts selected/scroll indices based on current terminal size.
+    fn update_layout(&mut self) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let term_width = self.pane_width(width) as usize;
+        let term_height = height as usize;
+
+        // Fixed cell width: 20 characters for name + 2 for prefix
+        const CELL_WIDTH: usize = 22;
+
+        // Calculate available rows for content (subtract header and footer)
+        let available_rows = term_height.saturating_sub(self.start_row as usize).saturating_sub(2);
+
+        // Layout method: Square
+        // Distribute into a square-like layout (cols ≈ rows)
+        let num_entries = self.entries.len().max(1);
+
+        // In list mode (and while filtering), always use 1 column
+        if self.list_mode || self.filter_mode {
+            self.num_cols = 1;
+        } else if self.gallery_active() {
+            // Gallery cells are tall, so fill the width instead of aiming for a square
+            self.num_cols = (term_width / CELL_WIDTH).max(1);
+        } else {
+            // Calculate max columns that can fit
+            let max_cols = (term_width / CELL_WIDTH).max(1);
+
+            // Start with square root as ideal column count
+            let ideal_cols = (num_entries as f64).sqrt().ceil() as usize;
+            let ideal_cols = ideal_cols.clamp(1, max_cols);
+
+            let mut best_cols = ideal_cols;
+            let mut best_score = usize::MAX;
+
+            // Try column counts around the ideal, find the one closest to square that fits
+            for cols in 1..=max_cols {
+                let rows_needed = num_entries.div_ceil(cols);
+                if rows_needed <= available_rows {
+                    // Score based on how close to square (minimize abs difference between cols and rows)
+                    let score = cols.abs_diff(rows_needed);
+
+                    if score < best_score {
+                        best_score = score;
+                        best_cols = cols;
+                    }
+                }
+            }
+
+            self.num_cols = best_cols;
+        }
+
+        // Ensure selected index is valid after layout change
+        if !self.entries.is_empty() {
+            self.selected = self.selected.min(self.entries.len().saturating_sub(1));
+        }
+
+        Ok(())
+    }
+
+    fn draw(&mut self) -> io::Result<()> {
+        let mut stdout = io::stdout();
+
+        let (width, height) = terminal::size()?;
+
+        self.hide_ignored_entries();
+        // A filter or the gallery shows collapsed groups' entries too
+        self.sync_collapsed();
+        // Ensure layout is calculated (deferred from new() for faster startup)
+        self.update_layout()?;
+        self.reserve_rows(&mut stdout, height)?;
+        self.preview_waiting = false;
+        self.collect_dir_sizes();
+
+        // Calculate split if in preview mode
+        let split_line = if self.preview_mode {
+            self.start_row + ((height - self.start_row) as f32 * (1.0 - self.preview_split_ratio)) as u16
+        } else {
+            height
+        };
+        let display_height = split_line;
+
+        // 1. Clear from start row downward (execute for immediate effect to reduce flicker)
+        execute!(stdout, cursor::MoveTo(0, self.start_row))?;
+        execute!(stdout, terminal::Clear(ClearType::FromCursorDown))?;
+        // Kitty keeps images around after the text is cleared, so remove the previous ones
+        if self.image_protocol == Some(ImageProtocol::Kitty) {
+            queue!(stdout, Print("\x1b_Ga=d,q=2\x1b\\"))?;
+        }
+        self.draw_tab_bar(&mut stdout, width)?;
+
+        // Display directory path with color config
+        queue!(stdout, cursor::MoveTo(0, self.start_row))?;
+
+        let fg_color = self.color_config.parse_fg_color();
+        let bg_color = self.color_config.parse_bg_color();
+
+        let display_path = if self.path_edit_mode {
+            self.path_editor.text.clone()
+        } else if self.find_char_pending {
+            self.plain(" Find Char │ Type a letter to jump to the next entry starting with it")
+        } else if self.bookmark_add_pending {
+            self.plain(&format!(" Bookmark │ Type a letter to bookmark {}", self.format_path_display()))
+        } else {
+            self.format_path_display()
+        };
+
+        let jump_hints = if self.path_jump_pending {
+            Some(PATH_JUMP_HINTS)
+        } else if self.ancestor_jump_pending {
+            Some(&PATH_JUMP_HINTS[..9])
+        } else {
+            None
+        };
+        let mut path_width = display_path.chars().count() + 2;
+        if let Some(hints) = jump_hints {
+            path_width = self.draw_path_segments(&mut stdout, hints)?;
+        } else if fg_color.is_none() && bg_color.is_none() {
+            // Use reverse attribute (default)
+            queue!(
+                stdout,
+                crossterm::style::SetAttribute(crossterm::style::Attribute::Reverse),
+                Print(format!(" {} ", display_path)),
+                crossterm::style::SetAttribute(crossterm::style::Attribute::Reset)
+            )?;
+        } else {
+            // Use specified colors
+            if let Some(fg) = fg_color {
+                queue!(stdout, SetForegroundColor(fg))?;
+            }
+            if let Some(bg) = bg_color {
+                queue!(stdout, crossterm::style::SetBackgroundColor(bg))?;
+            }
+            queue!(stdout, Print(format!(" {} ", display_path)))?;
+            queue!(stdout, ResetColor)?;
+        }
+
+        // Say what isn't shown, so a listing emptied by hidden files or the filter
+        // doesn't look like an empty directory, any order other than by name, and
+        // how many matches of the last find or filter n and N step through
+        if !self.path_edit_mode {
+            let mut suppressed = Vec::new();
+            if self.hidden_count > 0 {
+                suppressed.push(format!("+{} hidden", self.hidden_count));
+            }
+            if !self.ignored_entries.is_empty() {
+                suppressed.push(format!("+{} ignored", self.ignored_entries.len()));
+            }
+            if self.in_trash() {
+                let units = ["B", "KB", "MB", "GB", "TB"];
+                suppressed.push(match self.dir_size_cache.peek(&self.current_dir) {
+                    Some(usage) => format!("{} in trash", self.locale.format_size(usage.apparent, &units)),
+                    None => "sizing trash".to_string(),
+                });
+            }
+            if self.filter_mode && self.filter_all_entries.len() > self.entries.len() {
+                suppressed.push(format!("{} filtered", self.filter_all_entries.len() - self.entries.len()));
+            }
+            if self.settings.sort_by != "name" || self.settings.sort_reverse {
+                suppressed.push(self.sort_label().to_string());
+            }
+            let matches = if self.filter_mode || self.fuzzy_mode { 0 } else { self.match_positions().len() };
+            if matches > 0 {
+                suppressed.push(format!("{} {}", matches, if matches == 1 { "match" } else { "matches" }));
+            }
+            if !suppressed.is_empty() {
+                let text = format!(" ({})", suppressed.join(", "));
+                path_width += text.len();
+                queue!(stdout, SetForegroundColor(Color::DarkGrey), Print(text), ResetColor)?;
+            }
+        }
+
+        // Free space at the right end of the path bar, if it fits
+        if let Some((free, total)) = self.disk_space.filter(|_| self.settings.show_disk_space && !self.path_edit_mode) {
+            let units = ["B", "KB", "MB", "GB", "TB"];
+            let text = format!(
+                " {} free of {} ",
+                self.locale.format_size(free, &units),
+                self.locale.format_size(total, &units)
+            );
+            if path_width + text.len() < width as usize {
+                let color = if total > 0 && free * 100 / total < 5 {
+                    Color::Red
+                } else if total > 0 && free * 100 / total < 10 {
+                    Color::Yellow
+                } else {
+                    Color::DarkGrey
+                };
+                queue!(
+                    stdout,
+                    cursor::MoveTo(width - text.len() as u16, self.start_row),
+                    SetForegroundColor(color),
+                    Print(text),
+                    ResetColor
+                )?;
+            }
+        }
+
+        if self.help_screen_mode {
+            self.draw_help_screen(&mut stdout, width, height)?;
+            stdout.flush()?;
+            return Ok(());
+        }
+        if self.messages_mode {
+            self.draw_messages(&mut stdout, width, height)?;
+            stdout.flush()?;
+            return Ok(());
+        }
+        if self.info_panel.is_some() {
+            self.draw_info_panel(&mut stdout, width, height)?;
+            // A panel can be what a question is about
+            self.draw_prompt(&mut stdout, width, height)?;
+            stdout.flush()?;
+            return Ok(());
+        }
+        if self.bookmarks_mode {
+            self.draw_bookmarks(&mut stdout, width)?;
+            stdout.flush()?;
+            return Ok(());
+        }
+        if self.open_with.is_some() {
+            self.draw_open_with(&mut stdout, width, height)?;
+            stdout.flush()?;
+            return Ok(());
+        }
+
+        // Explicitly move to next line
+        queue!(stdout, cursor::MoveTo(0, self.start_row + 1))?;
+
+        // Display breadcrumbs (currently hidden)
+        // for folder in &self.breadcrumbs {
+        //     queue!(
+        //         stdout,
+        //         SetForegroundColor(Color::DarkGrey),
+        //         Print(format!("← {}/", folder)),
+        //         ResetColor,
+        //         Print("\n")
+        //     )?;
+        // }
+
+        let start_content_row = self.start_row + 1; // + self.breadcrumbs.len() as u16;
+        // Move cursor to where file list starts.
+        queue!(stdout, cursor::MoveTo(0, start_content_row))?;
+
+        // Screen position of the selected entry, for parking the cursor in ASCII mode
+        let mut selected_pos: Option<(u16, u16)> = None;
+
+        // Display entries - either history mode or normal file browsing
+        if self.grep_mode {
+            self.draw_grep_results(&mut stdout, width, display_height)?;
+        } else if self.history_mode {
+            // History mode - zoxide-style directory jump
+            // Show query prompt
+            let query_color = self.color_config.parse_history_query_fg().unwrap_or(Color::Cyan);
+            queue!(
+                stdout,
+                SetForegroundColor(query_color),
+                Print(format!("Jump to: {}", self.history_query)),
+                Print(self.plain("█")), // Cursor
+                ResetColor,
+                Print("\r\n\r\n")
+            )?;
+
+            // Show filtered results
+            if !self.history_filtered.is_empty() {
+                let max_display_rows = (display_height as usize).saturating_sub(self.start_row as usize).saturating_sub(4);
+                let start_idx = self.scroll_offset;
+                let end_idx = (start_idx + max_display_rows).min(self.history_filtered.len());
+
+                for idx in start_idx..end_idx {
+                    let entry = &self.history_filtered[idx];
+                    let is_selected = idx == self.selected;
+
+                    let prefix = if !is_selected { "  " } else if self.settings.ascii_mode { "* " } else { "> " };
+
+                    // Print prefix with cursor color
+                    if is_selected {
+                        if let Some(cursor_color) = self.color_config.parse_cursor_fg() {
+                            queue!(stdout, SetForegroundColor(cursor_color))?;
+                        } else {
+                            queue!(stdout, SetForegroundColor(Color::Green))?;
+                        }
+                        if let Some(cursor_bg) = self.color_config.parse_cursor_bg() {
+                            queue!(stdout, crossterm::style::SetBackgroundColor(cursor_bg))?;
+                        }
+                    }
+                    queue!(stdout, Print(prefix))?;
+
+                    // Display path with highlighting
+                    let path_color = if is_selected {
+                        self.color_config.parse_selected_fg().unwrap_or(Color::Green)
+                    } else {
+                        self.color_config.parse_history_path_fg().unwrap_or(Color::Cyan)
+                    };
+
+                    // Find and highlight the query match
+                    let query_lower = self.history_query.to_lowercase();
+                    let path_lower = entry.path.to_lowercase();
+
+                    if let Some(match_pos) = path_lower.find(&query_lower) {
+                        // Print before match
+                        queue!(stdout, SetForegroundColor(path_color), Print(&entry.path[..match_pos]))?;
+
+                        // Print matched part with highlight
+                        let match_end = match_pos + self.history_query.len();
+                        queue!(
+                            stdout,
+                            crossterm::style::SetAttribute(crossterm::style::Attribute::Bold),
+                            SetForegroundColor(self.color_config.parse_fuzzy_highlight_fg().unwrap_or(Color::Yellow)),
+                            Print(&entry.path[match_pos..match_end]),
+                            crossterm::style::SetAttribute(crossterm::style::Attribute::Reset)
+                        )?;
+
+                        // Print after match
+                        queue!(stdout, SetForegroundColor(path_color), Print(&entry.path[match_end..]))?;
+                    } else {
+                        queue!(stdout, SetForegroundColor(path_color), Print(&entry.path))?;
+                    }
+
+                    // Show visit count if setting is enabled
+                    if self.settings.show_history_visit_count {
+                        let count_color = self.color_config.parse_history_count_fg().unwrap_or(Color::DarkGrey);
+                        queue!(
+                            stdout,
+                            ResetColor,
+                            SetForegroundColor(count_color),
+                            Print(format!(" ({})", entry.visit_count)),
+                            ResetColor
+                        )?;
+                    }
+
+                    queue!(stdout, ResetColor, Print("\r\n"))?;
+                }
+            }
+        } else if let Some(reason) = self.load_error_reason() {
+            let fmt_keys = |keys: &Vec<String>| Keybindings::format_keys(keys);
+            let back_to = match self.readable_ancestor() {
+                Some(dir) => self.tilde_path(dir),
+                None => "~".to_string(),
+            };
+            let lines = [
+                (Color::Red, self.plain(&format!("  ⚠ Can't open {}", self.format_path_display()))),
+                (Color::Reset, format!("  {}", reason)),
+                (Color::Reset, String::new()),
+                (Color::DarkGrey, format!("  {} Retry", fmt_keys(&self.keybindings.retry_load))),
+                (Color::DarkGrey, format!("  {}/Backspace Back to {}", fmt_keys(&self.keybindings.back), back_to)),
+                (Color::DarkGrey, format!("  {} Home", fmt_keys(&self.keybindings.home))),
+            ];
+            for (row, (color, line)) in lines.iter().enumerate() {
+                queue!(
+                    stdout,
+                    cursor::MoveTo(0, start_content_row + row as u16),
+                    SetForegroundColor(*color),
+                    Print(line),
+                    ResetColor
+                )?;
+            }
+        } else if self.entries.is_empty() {
+            let message = if self.filter_mode { "  (no matches)\n" } else { "  (empty directory)\n" };
+            queue!(
+                stdout,
+                SetForegroundColor(Color::Yellow),
+                Print(message),
+                ResetColor
+            )?;
+        } else if self.gallery_active() {
+            let num_rows = self.draw_gallery(&mut stdout, display_height, start_content_row, &mut selected_pos)?;
+
+            // Display config error if present (below the entries)
+            if let Some(error) = &self.config_error {
+                queue!(stdout, cursor::MoveTo(0, start_content_row + (num_rows * GALLERY_ROW_HEIGHT) as u16))?;
+                queue!(
+                    stdout,
+                    SetForegroundColor(Color::Red),
+                    Print(self.plain(&format!("⚠ {}", error))),
+                    ResetColor
+                )?;
+            }
+        } else {
+            // Fixed dimensions for grid
+            const CELL_WIDTH: usize = 22;
+            const NAME_WIDTH: usize = 20;
+
+            // Column headers stay pinned above the scrolling entries
+            let header_rows = self.header_rows();
+            if header_rows > 0 {
+                let label = match self.list_info_mode {
+                    1 if self.show_created_date => "Created",
+                    1 => "Modified",
+                    2 => "Permissions",
+                    4 => "Duration",
+                    _ => "     Size    On disk",
+                };
+                queue!(
+                    stdout,
+                    SetForegroundColor(Color::DarkGrey),
+                    Print(format!("  {:<width$}  {:<12}    Sort: {}", "Name", label, self.sort_label(), width = NAME_WIDTH)),
+                    ResetColor,
+                    Print("\r\n")
+                )?;
+            }
+            let start_content_row = start_content_row + header_rows as u16;
+
+            let max_display_rows = (display_height as usize).saturating_sub(self.start_row as usize).saturating_sub(2 + header_rows); // + self.breadcrumbs.len());
+            let rows = self.listing_rows();
+            let total_rows = rows.len();
+
+            // Use scroll_offset to show the right portion (works for both list and grid mode)
+            let start_row = self.scroll_offset;
+            let end_row = (start_row + max_display_rows).min(total_rows);
+            let num_rows = end_row - start_row;
+            let mut dirs_to_count = Vec::new();
+            let mut item_counts_waiting = false;
+            let mut repos_to_scan = Vec::new();
+            let mut git_status_waiting = false;
+            let mut git_changes_waiting = false;
+            let mut media_to_read = Vec::new();
+            let mut media_info_waiting = false;
+            let marked: HashSet<&PathBuf> = self.marked.iter().collect();
+
+            for (row, cells) in rows.iter().enumerate().take(end_row).skip(start_row) {
+                let (first, last) = match *cells {
+                    ListingRow::Entries(first, last) => (first, last),
+                    ListingRow::Group(group, count) => {
+                        let collapsed = self.collapsed_groups.contains(&group);
+                        let marker = match (collapsed, self.settings.ascii_mode) {
+                            (true, false) => "▸",
+                            (false, false) => "▾",
+                            (true, true) => "+",
+                            (false, true) => "-",
+                        };
+                        queue!(
+                            stdout,
+                            SetForegroundColor(Color::DarkGrey),
+                            crossterm::style::SetAttribute(crossterm::style::Attribute::Bold),
+                            Print(format!(" {} {} ({})", marker, FILE_GROUPS[group], count)),
+                            crossterm::style::SetAttribute(crossterm::style::Attribute::Reset),
+                            ResetColor,
+                            Print("\r\n")
+                        )?;
+                        continue;
+                    }
+                };
+                for idx in first..last {
+                    let col = idx - first;
+                    let entry = &self.entries[idx];
+                    let is_selected = idx == self.selected;
+                    let is_dir = entry.is_dir();
+
+                    let name = entry.file_name()
+                        .and_then(|n| n.to_str())
+                        .unwrap_or("?");
+
+                    // Truncate name if needed; ASCII mode always marks directories in text
+                    let mut display_name = if is_dir && (self.show_dir_slash || self.settings.ascii_mode) {
+                        format!("{}/", name)
+                    } else {
+                        name.to_string()
+                    };
+
+                    // Entry count after directory names in list mode
+                    let badge = if self.list_mode && is_dir && self.settings.show_item_counts {
+                        self.item_count_badge(entry, &mut dirs_to_count).unwrap_or_else(|| {
+                            item_counts_waiting = true;
+                            String::new()
+                        })
+                    } else {
+                        String::new()
+                    };
+                    // Language of project directories
+                    let project_badge = match is_dir.then(|| self.project_badge(entry)).flatten() {
+                        Some(badge) => format!(" {}", badge),
+                        None => String::new(),
+                    };
+                    // Empty directories are dimmed and unreadable ones locked; ASCII mode says so in words
+                    let dir_state = if is_dir { self.dir_state(entry, &mut dirs_to_count, &mut item_counts_waiting) } else { None };
+                    let (state_badge, state_width) = match (dir_state, self.settings.ascii_mode) {
+                        (Some(DirState::Unreadable), false) => (" 🔒", 3),
+                        (Some(DirState::Unreadable), true) => (" (locked)", 9),
+                        (Some(DirState::Empty), true) => (" (empty)", 8),
+                        _ => ("", 0),
+                    };
+                    // Tags show as colored dots in the grid and by name at the end of list rows
+                    let tags = self.tags.of(entry).to_vec();
+                    let tag_dots = if self.list_mode { 0 } else { tags.len().min(3) };
+                    let tags_width = if tag_dots > 0 { tag_dots + 1 } else { 0 };
+                    let name_width = NAME_WIDTH.saturating_sub(badge.len() + project_badge.len() + state_width + tags_width);
+
+                    // In grid mode or list mode with info, truncate to NAME_WIDTH
+                    // In list mode without info, don't truncate
+                    if (!self.list_mode || self.list_info_mode > 0) && display_name.len() > name_width {
+                        // Find a valid UTF-8 boundary for truncation
+                        let mut truncate_at = name_width - 1;
+                        while truncate_at > 0 && !display_name.is_char_boundary(truncate_at) {
+                            truncate_at -= 1;
+                        }
+                        display_name.truncate(truncate_at);
+                        display_name.push('~');
+                    }
+
+                    // Second column of the prefix carries the mark
+                    let prefix = if !is_selected { " " } else if self.settings.ascii_mode { "*" } else { ">" };
+                    let mark = if !marked.contains(entry) { " " } else if self.settings.ascii_mode { "+" } else { "✓" };
+                    // Unmarked entries show their git status there instead
+                    let git_change = if mark == " " && self.git_root.is_some() && self.settings.show_git_status {
+                        let change = self.git_change(entry);
+                        if change.is_none() && !self.git_changes_loaded() {
+                            git_changes_waiting = true;
+                        }
+                        change
+                    } else {
+                        None
+                    };
+                    if is_selected {
+                        selected_pos = Some(((col * CELL_WIDTH) as u16, start_content_row + (row - start_row) as u16));
+                    }
+
+                    // Characters of the name matched by the fuzzy query
+                    let fuzzy_positions = if self.fuzzy_mode && !self.fuzzy_query.is_empty() {
+                        fuzzy_score(&self.fuzzy_query, name, self.settings.case_sensitive_search)
+                            .map(|(_, positions)| positions)
+                            .unwrap_or_default()
+                    } else {
+                        Vec::new()
+                    };
+
+                    // Print prefix with cursor color
+                    if is_selected {
+                        // Apply cursor colors for the ">" prefix
+                        if let Some(cursor_color) = self.color_config.parse_cursor_fg() {
+                            queue!(stdout, SetForegroundColor(cursor_color))?;
+                        } else {
+                            queue!(stdout, SetForegroundColor(Color::Green))?;
+                        }
+                        if let Some(cursor_bg) = self.color_config.parse_cursor_bg() {
+                            queue!(stdout, crossterm::style::SetBackgroundColor(cursor_bg))?;
+                        }
+                    }
+                    queue!(stdout, Print(prefix))?;
+                    match git_change {
+                        Some(('?', _)) => queue!(stdout, SetForegroundColor(Color::DarkGrey), Print("?"))?,
+                        Some((_, unstaged)) if unstaged != ' ' => queue!(stdout, SetForegroundColor(Color::Red), Print(unstaged))?,
+                        Some((staged, _)) => queue!(stdout, SetForegroundColor(Color::Green), Print(staged))?,
+                        None => {
+                            if mark != " " {
+                                queue!(stdout, SetForegroundColor(Color::Yellow))?;
+                            }
+                            queue!(stdout, Print(mark))?;
+                        }
+                    }
+
+                    // Now set the colors for the filename
+                    if is_selected {
+                        // Apply selected colors
+                        if let Some(fg) = self.color_config.parse_selected_fg() {
+                            queue!(stdout, SetForegroundColor(fg))?;
+                        } else {
+                            queue!(stdout, SetForegroundColor(Color::Green))?;
+                        }
+                        if let Some(bg) = self.color_config.parse_selected_bg() {
+                            queue!(stdout, crossterm::style::SetBackgroundColor(bg))?;
+                        }
+                    } else if is_dir && dir_state.is_some() {
+                        let color = if dir_state == Some(DirState::Unreadable) { Color::DarkRed } else { Color::DarkGrey };
+                        queue!(stdout, SetForegroundColor(color))?;
+                    } else if is_dir {
+                        // Apply directory colors
+                        if let Some(fg) = self.color_config.parse_directory_fg() {
+                            queue!(stdout, SetForegroundColor(fg))?;
+                        } else {
+                            queue!(stdout, SetForegroundColor(Color::Blue))?;
+                        }
+                        if let Some(bg) = self.color_config.parse_directory_bg() {
+                            queue!(stdout, crossterm::style::SetBackgroundColor(bg))?;
+                        }
+                    } else {
+                        // Apply file colors
+                        if let Some(fg) = self.color_config.parse_file_fg() {
+                            queue!(stdout, SetForegroundColor(fg))?;
+                        } else {
+                            queue!(stdout, ResetColor)?;
+                        }
+                        if let Some(bg) = self.color_config.parse_file_bg() {
+                            queue!(stdout, crossterm::style::SetBackgroundColor(bg))?;
+                        }
+                    }
+
+                    // Print name with the fuzzy-matched characters highlighted
+                    if !fuzzy_positions.is_empty() {
+                        let chars: Vec<char> = display_name.chars().collect();
+                        let mut run_start = 0;
+                        while run_start < chars.len() {
+                            let highlighted = fuzzy_positions.contains(&run_start);
+                            let run_end = (run_start..chars.len())
+                                .find(|i| fuzzy_positions.contains(i) != highlighted)
+                                .unwrap_or(chars.len());
+                            let run: String = chars[run_start..run_end].iter().collect();
+                            run_start = run_end;
+
+                            if !highlighted {
+                                queue!(stdout, Print(run))?;
+                                continue;
+                            }
+
+                            // Print matching part with fuzzy highlight colors
+                            queue!(stdout, crossterm::style::SetAttribute(crossterm::style::Attribute::Bold))?;
+                            if let Some(fg) = self.color_config.parse_fuzzy_highlight_fg() {
+                                queue!(stdout, SetForegroundColor(fg))?;
+                            } else {
+                                queue!(stdout, SetForegroundColor(Color::Rgb { r: 255, g: 255, b: 0 }))?;
+                            }
+                            if let Some(bg) = self.color_config.parse_fuzzy_highlight_bg() {
+                                queue!(stdout, crossterm::style::SetBackgroundColor(bg))?;
+                            } else {
+                                queue!(stdout, crossterm::style::SetBackgroundColor(Color::Rgb { r: 50, g: 50, b: 50 }))?;
+                            }
+                            queue!(stdout, Print(run))?;
+                            queue!(stdout, crossterm::style::SetAttribute(crossterm::style::Attribute::Reset))?;
+
+                            // Reset to original color for rest
+                            if is_selected {
+                                if let Some(fg) = self.color_config.parse_selected_fg() {
+                                    queue!(stdout, SetForegroundColor(fg))?;
+                                } else {
+                                    queue!(stdout, SetForegroundColor(Color::Green))?;
+                                }
+                                if let Some(bg) = self.color_config.parse_selected_bg() {
+                                    queue!(stdout, crossterm::style::SetBackgroundColor(bg))?;
+                                }
+                            } else if is_dir && dir_state.is_some() {
+                                let color = if dir_state == Some(DirState::Unreadable) { Color::DarkRed } else { Color::DarkGrey };
+                                queue!(stdout, SetForegroundColor(color))?;
+                            } else if is_dir {
+                                if let Some(fg) = self.color_config.parse_directory_fg() {
+                                    queue!(stdout, SetForegroundColor(fg))?;
+                                } else {
+                                    queue!(stdout, SetForegroundColor(Color::Blue))?;
+                                }
+                            } else {
+                                queue!(stdout, ResetColor)?;
+                            }
+                        }
+                    } else {
+                        // No match, print normally
+                        queue!(stdout, Print(&display_name))?;
+                    }
+
+                    if !badge.is_empty() {
+                        queue!(stdout, ResetColor, SetForegroundColor(Color::DarkGrey), Print(&badge))?;
+                    }
+                    if !project_badge.is_empty() {
+                        queue!(stdout, ResetColor, SetForegroundColor(Color::Magenta), Print(&project_badge))?;
+                    }
+                    if !state_badge.is_empty() {
+                        queue!(stdout, ResetColor, SetForegroundColor(Color::DarkRed), Print(state_badge))?;
+                    }
+                    if tag_dots > 0 {
+                        queue!(stdout, ResetColor, Print(" "))?;
+                        let dot = if self.settings.ascii_mode { "#" } else { "●" };
+                        for tag in &tags[..tag_dots] {
+                            queue!(stdout, SetForegroundColor(tag_color(tag)), Print(dot))?;
+                        }
+                    }
+                    let padding = name_width.saturating_sub(display_name.len());
+                    queue!(stdout, Print(" ".repeat(padding)), ResetColor)?;
+
+                    // In list mode, show info after the name
+                    if self.list_mode && self.list_info_mode > 0 {
+                        if self.list_info_mode == 1 {
+                            // Show date (modified or created based on toggle)
+                            if let Ok(metadata) = entry.metadata() {
+                                let time_result = if self.show_created_date {
+                                    metadata.created()
+                                } else {
+                                    metadata.modified()
+                                };
+
+                                if let Ok(time) = time_result {
+                                    use std::time::SystemTime;
+                                    if let Ok(elapsed) = SystemTime::now().duration_since(time) {
+                                        let secs = elapsed.as_secs();
+                                        let three_months = 3 * 30 * 24 * 60 * 60; // ~3 months in seconds
+
+                                        let date_str = if self.settings.verbose_dates || secs < three_months {
+                                            // Use relative format
+                                            if secs < 60 {
+                                                format!("{:>9}s", secs)
+                                            } else if secs < 3600 {
+                                                format!("{:>8}min", secs / 60)
+                                            } else if secs < 86400 {
+                                                format!("{:>8}hrs", secs / 3600)
+                                            } else if secs < 2592000 { // 30 days
+                                                format!("{:>7}days", secs / 86400)
+                                            } else if secs < 31536000 { // 365 days
+                                                format!("{:>6}month", secs / 2592000)
+                                            } else {
+                                                format!("{:>7}year", secs / 31536000)
+                                            }
+                                        } else {
+                                            // Use "Aug '25" format for dates > 3 months
+                                            use std::time::UNIX_EPOCH;
+                                            if let Ok(duration) = time.duration_since(UNIX_EPOCH) {
+                                                let timestamp = duration.as_secs();
+
+                                                // Special case: Unix epoch (likely means unknown/unset date)
+                                                if timestamp == 0 {
+                                                    format!("{:>9}", "unknown")
+                                                } else {
+                                                    // Proper date calculation accounting for leap years
+                                                let mut year = 1970;
+                                                let mut remaining_secs = timestamp;
+
+                                                // Skip through years
+                                                loop {
+                                                    let year_secs = if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 {
+                                                        366 * 86400 // leap year
+                                                    } else {
+                                                        365 * 86400 // normal year
+                                                    };
+
+                                                    if remaining_secs < year_secs {
+                                                        break;
+                                                    }
+                                                    remaining_secs -= year_secs;
+                                                    year += 1;
+                                                }
+
+                                                // Calculate month
+                                                let is_leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+                                                let days_in_months = if is_leap {
+                                                    [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31]
+                                                } else {
+                                                    [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31]
+                                                };
+
+                                                let mut remaining_days = (remaining_secs / 86400) as usize;
+                                                let mut month_idx = 0;
+
+                                                for (i, &days) in days_in_months.iter().enumerate() {
+                                                    if remaining_days < days {
+                                                        month_idx = i;
+                                                        break;
+                                                    }
+                                                    remaining_days -= days;
+                                                }
+
+                                                format!("{:>9}", self.locale.month_year(month_idx, year))
+                                                }
+                                            } else {
+                                                format!("{:>9}s", secs)
+                                            }
+                                        };
+
+                                        let date_color = if self.show_created_date {
+                                            Color::DarkCyan // Created date
+                                        } else {
+                                            Color::DarkGrey // Modified date
+                                        };
+                                        queue!(
+                                            stdout,
+                                            SetForegroundColor(date_color),
+                                            Print(format!("  {}", date_str)),
+                                            ResetColor
+                                        )?;
+                                    }
+                                }
+                            }
+                        } else if self.list_info_mode == 2 {
+                            // Show permissions
+                            #[cfg(unix)]
+                            {
+                                use std::os::unix::fs::PermissionsExt;
+                                if let Ok(metadata) = entry.metadata() {
+                                    let mode = metadata.permissions().mode();
+                                    let perms = format!(
+                                        "{}{}{}{}{}{}{}{}{}",
+                                        if mode & 0o400 != 0 { 'r' } else { '-' },
+                                        if mode & 0o200 != 0 { 'w' } else { '-' },
+                                        if mode & 0o100 != 0 { 'x' } else { '-' },
+                                        if mode & 0o040 != 0 { 'r' } else { '-' },
+                                        if mode & 0o020 != 0 { 'w' } else { '-' },
+                                        if mode & 0o010 != 0 { 'x' } else { '-' },
+                                        if mode & 0o004 != 0 { 'r' } else { '-' },
+                                        if mode & 0o002 != 0 { 'w' } else { '-' },
+                                        if mode & 0o001 != 0 { 'x' } else { '-' },
+                                    );
+                                    queue!(
+                                        stdout,
+                                        SetForegroundColor(Color::DarkGrey),
+                                        Print(format!("  {}", perms)),
+                                        ResetColor
+                                    )?;
+                                }
+                            }
+                        } else if self.list_info_mode == 4 {
+                            // Show duration and resolution of media files
+                            if let Some(summary) = self.media_summary(entry, &mut media_to_read, &mut media_info_waiting) {
+                                queue!(
+                                    stdout,
+                                    SetForegroundColor(Color::DarkGrey),
+                                    Print(format!("  {}", summary)),
+                                    ResetColor
+                                )?;
+                            }
+                        } else if self.list_info_mode == 3 {
+                            // Show apparent and on-disk size (with cached dir size)
+                            if let Ok(metadata) = entry.metadata() {
+                                let units = ["B", "K", "M", "G"];
+                                let usage = if is_dir { self.dir_size_cache.get(entry).copied() } else { Some(DiskUsage::of(&metadata)) };
+                                let size = if let Some(usage) = usage {
+                                    format!(
+                                        "{:>9}  {:>9}",
+                                        self.locale.format_size(usage.apparent, &units),
+                                        self.locale.format_size(usage.allocated, &units)
+                                    )
+                                } else if self.dir_size_jobs.lock().is_ok_and(|jobs| jobs.contains_key(entry)) {
+                                    String::from("  calc...")
+                                } else {
+                                    String::from("    <DIR>")
+                                };
+                                queue!(
+                                    stdout,
+                                    SetForegroundColor(Color::DarkGrey),
+                                    Print(format!("  {}", size)),
+                                    ResetColor
+                                )?;
+                            }
+                        }
+
+                        // Branch of a git repository, marked when it has uncommitted work
+                        if is_dir && self.settings.show_git_status {
+                            match self.git_status(entry, &mut repos_to_scan) {
+                                Some(GitStatus::Scanned { branch, dirty }) => {
+                                    let (color, marker) = if dirty { (Color::Yellow, " *") } else { (Color::Green, "") };
+                                    queue!(
+                                        stdout,
+                                        SetForegroundColor(color),
+                                        Print(format!("  {}{}", branch, marker)),
+                                        ResetColor
+                                    )?;
+                                }
+                                Some(GitStatus::Scanning) => git_status_waiting = true,
+                                Some(GitStatus::Failed) | None => {}
+                            }
+                        }
+                    }
+                    if self.list_mode && !tags.is_empty() {
+                        queue!(stdout, Print(" "))?;
+                        for tag in &tags {
+                            queue!(stdout, SetForegroundColor(tag_color(tag)), Print(format!(" #{}", tag)), ResetColor)?;
+                        }
+                    }
+                }
+                queue!(stdout, Print("\r\n"))?;
+            }
+            self.start_item_count(dirs_to_count);
+            self.item_counts_waiting = item_counts_waiting;
+            self.start_git_status(repos_to_scan);
+            self.git_status_waiting = git_status_waiting;
+            self.git_changes_waiting = git_changes_waiting;
+            self.start_media_info(media_to_read);
+            self.media_info_waiting = media_info_waiting;
+
+            // Display config error if present (below the entries)
+            if let Some(error) = &self.config_error {
+                queue!(stdout, cursor::MoveTo(0, start_content_row + num_rows as u16 + 1))?;
+                queue!(
+                    stdout,
+                    SetForegroundColor(Color::Red),
+                    Print(self.plain(&format!("⚠ {}", error))),
+                    ResetColor
+                )?;
+            }
+        }
+
+        // 3. Draw separator and preview if in preview mode
+        if self.preview_mode {
+            // In dual pane mode the preview stays in the active pane's columns
+            let width = self.pane_width(width);
+            queue!(stdout, cursor::MoveTo(0, split_line))?;
+
+            if let Some(border_color) = self.color_config.parse_preview_border_fg() {
+                queue!(stdout, SetForegroundColor(border_color))?;
+            } else {
+                queue!(stdout, SetForegroundColor(Color::DarkGrey))?;
+            }
+            // Facts about a previewed file fill the start of the separator
+            let header = match self.get_selected_path() {
+                Some(selected) if selected.is_file() => match self.unstaged_diff(&selected) {
+                    Some(_) => format!("─ {} • unstaged diff ", self.preview_header(&selected)),
+                    None => format!("─ {} ", self.preview_header(&selected)),
+                },
+                _ => String::new(),
+            };
+            let header = Self::truncate_string_safe(&self.plain(&header), width as usize);
+            let rule = self.plain("─").repeat((width as usize).saturating_sub(header.chars().count()));
+            queue!(stdout, Print(header), Print(rule), ResetColor)?;
+
+            // Draw preview
+            if let Some(selected) = self.get_selected_path() {
+                if selected.is_dir() {
+                    // Directory preview - show contents and stats
+                    let preview_lines = (height - split_line - 3) as usize;
+
+                    if let Ok(entries) = fs::read_dir(&selected) {
+                        let mut dirs = 0;
+                        let mut files = 0;
+                        let mut total_size: u64 = 0;
+                        let mut has_uncached_dirs = false;
+                        let mut items: Vec<(String, bool)> = Vec::new();
+
+                        for entry in entries.filter_map(|e| e.ok()) {
+                            let path = entry.path();
+                            let is_dir = path.is_dir();
+                            let name = path.file_name()
+                                .and_then(|n| n.to_str())
+                                .unwrap_or("?")
+                                .to_string();
+
+                            if is_dir {
+                                dirs += 1;
+                                // Check if size is cached, otherwise calculate and cache it
+                                if let Some(cached_size) = self.dir_size_cache.get(&path) {
+                                    total_size += cached_size.apparent;
+                                } else {
+                                    // Calculate in preview without blocking
+                                    has_uncached_dirs = true;
+                                }
+                                items.push((name, true));
+                            } else {
+                                files += 1;
+                                if let Ok(metadata) = entry.metadata() {
+                                    total_size += metadata.len();
+                                }
+                                items.push((name, false));
+                            }
+                        }
+
+                        // Sort: directories first, then files
+                        items.sort_by(|a, b| {
+                            if a.1 == b.1 {
+                                a.0.cmp(&b.0)
+                            } else {
+                                b.1.cmp(&a.1)
+                            }
+                        });
+
+                        // Display stats
+                        queue!(stdout, cursor::MoveTo(0, split_line + 1))?;
+                        queue!(
+                            stdout,
+                            SetForegroundColor(Color::Cyan),
+                            Print(format!("[DIR] {} items ({} dirs, {} files)", dirs + files, dirs, files)),
+                            ResetColor
+                        )?;
+
+                        // Display size (only files, subdirectories would require recursive calculation)
+                        let size_str = self.locale.format_size(total_size, &["B", "KB", "MB", "GB"]);
+
+                        let size_display = if has_uncached_dirs {
+                            format!("{} (files only, dirs not calculated)", size_str)
+                        } else {
+                            size_str
+                        };
+
+                        queue!(stdout, cursor::MoveTo(0, split_line + 2))?;
+                        queue!(
+                            stdout,
+                            SetForegroundColor(Color::DarkGrey),
+                            Print(format!("Size: {}", size_display)),
+                            ResetColor
+                        )?;
+
+                        // Display first few items
+                        for (i, (name, is_dir)) in items.iter().take(preview_lines.saturating_sub(3)).enumerate() {
+                            queue!(stdout, cursor::MoveTo(0, split_line + 4 + i as u16))?;
+                            if *is_dir {
+                                queue!(
+                                    stdout,
+                                    SetForegroundColor(Color::Blue),
+                                    Print(format!("  {}/", name)),
+                                    ResetColor
+                                )?;
+                            } else {
+                                queue!(stdout, Print(format!("  {}", name)))?;
+                            }
+                        }
+                    }
+                } else if selected.is_file() {
+                    let preview_lines = (height - split_line - 3) as usize;
+                    let preview_width = width as u32;
+                    let preview_height = preview_lines as u32;
+
+                    // Check file extension for special handling
+                    let extension = selected.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+
+                    if let Some(diff) = self.unstaged_diff(&selected) {
+                        // Added lines green, removed red, hunk headers cyan
+                        let scroll_pos = self.preview_scroll_map.get(&selected).copied().unwrap_or(0);
+                        for (i, line) in diff.iter().skip(scroll_pos).take(preview_lines).enumerate() {
+                            let color = match line.chars().next() {
+                                Some('+') => Color::Green,
+                                Some('-') => Color::Red,
+                                Some('@') => Color::Cyan,
+                                Some(' ') => Color::Reset,
+                                _ => Color::DarkGrey,
+                            };
+                            queue!(
+                                stdout,
+                                cursor::MoveTo(0, split_line + 1 + i as u16),
+                                SetForegroundColor(color),
+                                Print(Self::truncate_string_safe(line, width as usize)),
+                                ResetColor
+                            )?;
+                        }
+                    } else if matches!(extension.as_str(), "png" | "jpg" | "jpeg" | "gif" | "bmp" | "webp") {
+                        // Image preview - render from the thumbnail cache, creating the
+                        // thumbnail on first view so the original is only decoded once
+                        let image_path = match Self::thumbnail_path(&selected, THUMBNAIL_SIZE) {
+                            Some(thumb_path) if thumb_path.exists() => Some(thumb_path),
+                            Some(thumb_path) if self.thumbnail_failed(&thumb_path) => None,
+                            Some(thumb_path) if Self::create_thumbnail(&selected, &thumb_path, THUMBNAIL_SIZE) => Some(thumb_path),
+                            Some(thumb_path) => {
+                                if let Ok(mut failed) = self.thumbnails_failed.lock() {
+                                    failed.insert(thumb_path);
+                                }
+                                None
+                            }
+                            None => Some(selected.clone()),
+                        };
+                        let drawn = match image_path {
+                            Some(image_path) => self.print_image(&mut stdout, &image_path, (0, split_line + 1), (preview_width as u16, preview_height as u16))?,
+                            None => false,
+                        };
+                        if !drawn {
+                            queue!(stdout, cursor::MoveTo(0, split_line + 1))?;
+                            queue!(stdout, Print("(cannot preview image)"))?;
+                        }
+                    } else if extension == "pdf"
+                        || is_apple_encoded(&selected, &extension)
+                        || archive_kind(&selected).is_some()
+                        || media_format(&selected).is_some()
+                        || (extension != "ipynb" && self.preview_is_binary(&selected))
+                    {
+                        // PDF, binary plist, archive listing, media headers and strings of other binaries - use cache with background loading
+                        let loading_message = match extension.as_str() {
+                            "pdf" => "Loading PDF...",
+                            _ if archive_kind(&selected).is_some() => "Reading archive...",
+                            _ if media_format(&selected).is_some() => "Reading media headers...",
+                            "plist" | "strings" | "mobileprovision" => "Decoding...",
+                            _ => "Extracting strings...",
+                        };
+                        let cache_state = if let Ok(mut cache_lock) = self.preview_cache.lock() {
+                            cache_lock.get(&selected).cloned()
+                        } else {
+                            None
+                        };
+
+                        match cache_state {
+                            Some(PreviewState::Loaded(lines)) => {
+                                let scroll_pos = self.preview_scroll_map.get(&selected).copied().unwrap_or(0);
+                                let display_lines: Vec<&String> = lines.iter()
+                                    .skip(scroll_pos)
+                                    .take(preview_lines)
+                                    .collect();
+
+                                for (i, line) in display_lines.iter().enumerate() {
+                                    queue!(stdout, cursor::MoveTo(0, split_line + 1 + i as u16))?;
+
+                                    if self.show_line_numbers {
+                                        let line_num = scroll_pos + i + 1;
+                                        let line_color = self.color_config.parse_line_number_fg()
+                                            .unwrap_or(Color::DarkGrey);
+                                        queue!(
+                                            stdout,
+                                            SetForegroundColor(line_color),
+                                            Print(self.plain(&format!("{:4} │ ", line_num))),
+                                            ResetColor
+                                        )?;
+                                    }
+
+                                    queue!(stdout, Print(line.as_str()))?;
+                                }
+                            }
+                            Some(PreviewState::Loading) => {
+                                queue!(stdout, cursor::MoveTo(0, split_line + 1))?;
+                                queue!(stdout, Print(loading_message))?;
+                                self.preview_waiting = true;
+                            }
+                            Some(PreviewState::Extracting { page, pages }) => {
+                                queue!(
+                                    stdout,
+                                    cursor::MoveTo(0, split_line + 1),
+                                    Print(format!("Extracting PDF text... page {} of {}", page, pages)),
+                                    SetForegroundColor(Color::DarkGrey),
+                                    Print("  (Esc to cancel)"),
+                                    ResetColor
+                                )?;
+                                self.preview_waiting = true;
+                            }
+                            Some(PreviewState::Error(msg)) => {
+                                queue!(stdout, cursor::MoveTo(0, split_line + 1))?;
+                                queue!(stdout, Print(format!("({})", msg)))?;
+                            }
+                            None | Some(PreviewState::NotLoaded) | Some(PreviewState::Notebook { .. }) => {
+                                // Start loading in background
+                                self.start_preview_load(selected.clone());
+                                queue!(stdout, cursor::MoveTo(0, split_line + 1))?;
+                                queue!(stdout, Print(loading_message))?;
+                                self.preview_waiting = true;
+                            }
+                        }
+                    } else if extension == "ipynb" {
+                        self.draw_notebook_preview(&mut stdout, &selected, split_line, preview_lines)?;
+                    } else if self.load_data_preview(&selected) {
+                        self.draw_data_preview(&mut stdout, &selected, split_line, preview_lines, width)?;
+                    } else if let Some(rows) = self.table_rows(&selected) {
+                        self.draw_table_preview(&mut stdout, &selected, &rows, split_line, preview_lines, width)?;
+                    } else if let Some(lines) = self.rendered_markdown(&selected) {
+                        self.draw_markdown_preview(&mut stdout, &selected, &lines, split_line, preview_lines, width)?;
+                    } else {
+                        // Text file preview with syntax highlighting
+                        if let Ok(file) = fs::File::open(&selected) {
+                            use io::BufRead;
+                            let reader = io::BufReader::new(file);
+                            let scroll_pos = self.preview_scroll_map.get(&selected).copied().unwrap_or(0);
+
+                            // Plain until the syntaxes have loaded in the background
+                            let mut highlighter = if self.syntax_ready() {
+                                let syntax = self.syntax_set.as_ref().unwrap()
+                                    .find_syntax_for_file(&selected)
+                                    .ok()
+                                    .flatten()
+                                    .unwrap_or_else(|| self.syntax_set.as_ref().unwrap().find_syntax_plain_text());
+                                let theme = &self.theme_set.as_ref().unwrap().themes["base16-ocean.dark"];
+                                Some(HighlightLines::new(syntax, theme))
+                            } else {
+                                self.preview_waiting = true;
+                                None
+                            };
+
+                            // Only read the lines we need
+                            let lines_to_display: Vec<String> = reader
+                                .lines()
+                                .skip(scroll_pos)
+                                .take(preview_lines)
+                                .filter_map(|l| l.ok())
+                                .collect();
+
+                            let gutter = if self.show_line_numbers { 7 } else { 0 };
+                            let room = (width as usize).saturating_sub(gutter);
+                            for (i, line) in lines_to_display.iter().enumerate() {
+                                queue!(stdout, cursor::MoveTo(0, split_line + 1 + i as u16))?;
+
+                                // Print line number if enabled
+                                if self.show_line_numbers {
+                                    let line_num = scroll_pos + i + 1;
+                                    let line_color = self.color_config.parse_line_number_fg()
+                                        .unwrap_or(Color::DarkGrey);
+                                    queue!(
+                                        stdout,
+                                        SetForegroundColor(line_color),
+                                        Print(self.plain(&format!("{:4} │ ", line_num))),
+                                        ResetColor
+                                    )?;
+                                }
+
+                                // Highlight the line
+                                match highlighter.as_mut() {
+                                    Some(highlighter) => {
+                                        let ranges = highlighter.highlight_line(line, self.syntax_set.as_ref().unwrap()).unwrap_or_default();
+                                        // Cut at the edge of the pane, highlighted or not
+                                        let mut left = room;
+                                        let ranges: Vec<_> = ranges
+                                            .into_iter()
+                                            .map_while(|(style, text)| {
+                                                if left == 0 {
+                                                    return None;
+                                                }
+                                                let cut = text.char_indices().nth(left).map_or(text.len(), |(at, _)| at);
+                                                left -= text[..cut].chars().count();
+                                                Some((style, &text[..cut]))
+                                            })
+                                            .collect();
+                                        queue!(stdout, Print(as_24_bit_terminal_escaped(&ranges[..], false)), ResetColor)?;
+                                    }
+                                    None => queue!(stdout, Print(Self::truncate_string_safe(line, room)))?,
+                                }
+                            }
+                        } else {
+                            queue!(stdout, cursor::MoveTo(0, split_line + 1))?;
+                            queue!(stdout, Print("(binary file or cannot read)"))?;
+                        }
+                    }
+                }
+            }
+        }
+
+        if self.other_pane.is_some() && !self.history_mode && !self.grep_mode {
+            // The other pane takes the right-hand columns all the way down, beside the preview too
+            self.draw_other_pane(&mut stdout, width, height)?;
+        }
+
+        // 4. Draw footer with filename if in preview mode
+        if self.preview_mode {
+            if let Some(selected) = self.get_selected_path() {
+                if selected.is_file() {
+                    queue!(stdout, cursor::MoveTo(0, height.saturating_sub(1)))?;
+                    queue!(
+                        stdout,
+                        ResetColor,
+                        SetForegroundColor(Color::DarkGrey),
+                        Print(selected.file_name().and_then(|n| n.to_str()).unwrap_or("").to_string()),
+                        ResetColor
+                    )?;
+                }
+            }
+        }
+
+        // 5. Draw fuzzy search / filter bar
+        if self.filter_mode {
+            queue!(stdout, cursor::MoveTo(0, height.saturating_sub(1)))?;
+            queue!(
+                stdout,
+                ResetColor,
+                SetForegroundColor(Color::Yellow),
+                Print(format!("Filter: {}", self.filter_editor.text)),
+                SetForegroundColor(Color::DarkGrey),
+                Print(format!("  ({}/{})", self.entries.len(), self.filter_all_entries.len())),
+                ResetColor
+            )?;
+        }
+        if self.fuzzy_mode {
+            queue!(stdout, cursor::MoveTo(0, height.saturating_sub(1)))?;
+            queue!(
+                stdout,
+                ResetColor,
+                SetForegroundColor(Color::Yellow),
+                Print(format!("Find: {}_", self.fuzzy_query)),
+                ResetColor
+            )?;
+        }
+
+        // Display copy/paste status bar if there are items selected or operation in progress
+        if self.copy_in_progress {
+            // Show paste progress with background
+            queue!(
+                stdout,
+                cursor::MoveTo(0, height - 1),
+                SetForegroundColor(Color::Black),
+                crossterm::style::SetBackgroundColor(Color::Cyan),
+                Print(format!(" Pasting {}/{} items... ", self.copy_progress_current, self.copy_progress_total)),
+                ResetColor
+            )?;
+        } else if let Some(progress) = &self.archive_progress {
+            queue!(
+                stdout,
+                cursor::MoveTo(0, height - 1),
+                SetForegroundColor(Color::Black),
+                crossterm::style::SetBackgroundColor(Color::Cyan),
+                Print(format!(" {} ", progress)),
+                ResetColor
+            )?;
+        } else if self.calculating_sizes.is_some() {
+            // Show directory size calculation in progress
+            let left = self.dir_size_jobs.lock().map_or(0, |jobs| jobs.len());
+            queue!(
+                stdout,
+                cursor::MoveTo(0, height - 1),
+                SetForegroundColor(Color::Black),
+                crossterm::style::SetBackgroundColor(Color::Cyan),
+                Print(format!(" Calculating directory sizes... {} left ", left)),
+                ResetColor
+            )?;
+        } else if !self.marked.is_empty() {
+            let count = self.marked.len();
+            let item_word = if count == 1 { "item" } else { "items" };
+            let fmt_keys = |keys: &Vec<String>| Keybindings::format_keys(keys);
+            let hint = format!(
+                "{}: mark/unmark, {}: copy, {}: trash, {}: delete, {}: clear",
+                fmt_keys(&self.keybindings.mark),
+                fmt_keys(&self.keybindings.copy),
+                fmt_keys(&self.keybindings.trash),
+                fmt_keys(&self.keybindings.delete),
+                fmt_keys(&self.keybindings.clear_copy)
+            );
+            queue!(
+                stdout,
+                cursor::MoveTo(0, height - 1),
+                SetForegroundColor(Color::Black),
+                crossterm::style::SetBackgroundColor(Color::Yellow),
+                Print(format!(" {} {} marked ({}) ", count, item_word, hint)),
+                ResetColor
+            )?;
+        } else if !self.clipboard_selection.is_empty() {
+            // What a paste would bring here, ahead of the info mode hints so it stays in view
+            let count = self.clipboard_selection.len();
+            let contents = match &self.clipboard_selection[..] {
+                [path] => path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_else(|| path.display().to_string()),
+                _ => format!("{} items", count),
+            };
+            let fmt_keys = |keys: &Vec<String>| Keybindings::format_keys(keys);
+            let text = format!(
+                " Copy: {} ({}: add/remove, {}: clear, {}: paste here) ",
+                contents,
+                fmt_keys(&self.keybindings.copy),
+                fmt_keys(&self.keybindings.clear_copy),
+                fmt_keys(&self.keybindings.paste)
+            );
+            queue!(
+                stdout,
+                cursor::MoveTo(0, height - 1),
+                SetForegroundColor(Color::Black),
+                crossterm::style::SetBackgroundColor(Color::Yellow),
+                Print(Self::truncate_string_safe(&text, width as usize)),
+                ResetColor
+            )?;
+        } else if self.list_mode && self.list_info_mode == 3 {
+            // In size mode but not calculating - show hint
+            queue!(
+                stdout,
+                cursor::MoveTo(0, height - 1),
+                SetForegroundColor(Color::Black),
+                crossterm::style::SetBackgroundColor(Color::Blue),
+                Print(" Press 'e' to calculate directory sizes "),
+                ResetColor
+            )?;
+        } else if self.list_mode && self.list_info_mode == 2 {
+            // In permissions mode - show hint
+            queue!(
+                stdout,
+                cursor::MoveTo(0, height - 1),
+                SetForegroundColor(Color::Black),
+                crossterm::style::SetBackgroundColor(Color::Blue),
+                Print(" Press 'e' to edit permissions "),
+                ResetColor
+            )?;
+        } else if self.list_mode && self.list_info_mode == 1 {
+            // In date mode - show hint about toggling
+            let date_type = if self.show_created_date { "created" } else { "modified" };
+            queue!(
+                stdout,
+                cursor::MoveTo(0, height - 1),
+                SetForegroundColor(Color::Black),
+                crossterm::style::SetBackgroundColor(Color::Blue),
+                Print(format!(" Showing {} time (press 'e' to toggle) ", date_type)),
+                ResetColor
+            )?;
+        } else if self.show_help {
+            // Show contextual help in footer
+            self.draw_footer_help(&mut stdout, width, height)?;
+        }
+
+        // Toasts stack in the top-right corner, newest at the bottom
+        for (i, toast) in self.toasts.iter().enumerate() {
+            let message = if self.settings.ascii_mode {
+                format!("[{}] {}", toast.severity.label(), self.plain(&toast.message))
+            } else {
+                toast.message.clone()
+            };
+            let text = Self::truncate_string_safe(&format!(" {} ", message), width as usize);
+            let text_width = text.chars().count() as u16;
+            queue!(
+                stdout,
+                cursor::MoveTo(width.saturating_sub(text_width), self.start_row + 1 + i as u16),
+                SetForegroundColor(Color::Black),
+                crossterm::style::SetBackgroundColor(toast.severity.color()),
+                Print(text),
+                ResetColor
+            )?;
+        }
+
+        // Show the terminal cursor where text is being edited
+        if self.prompt.is_some() {
+            self.draw_prompt(&mut stdout, width, height)?;
+        } else if self.path_edit_mode {
+            queue!(stdout, cursor::MoveTo(1 + self.path_editor.cursor as u16, self.start_row), cursor::Show)?;
+        } else if self.filter_mode {
+            queue!(stdout, cursor::MoveTo(8 + self.filter_editor.cursor as u16, height.saturating_sub(1)), cursor::Show)?;
+        } else if self.grep_mode {
+            queue!(stdout, cursor::MoveTo(6 + self.grep_editor.cursor as u16, self.start_row + 1), cursor::Show)?;
+        } else if let Some((x, y)) = selected_pos.filter(|_| self.settings.ascii_mode) {
+            // Screen readers follow the terminal cursor, so keep it on the selection
+            queue!(stdout, cursor::MoveTo(x, y), cursor::Show)?;
+        } else {
+            queue!(stdout, cursor::Hide)?;
+        }
+
+        // Flush all queued commands simultaneously to minimize flicker
+        stdout.flush()?;
+        Ok(())
+    }
+
+    /// The prompt, covering the bottom row above whatever footer is there, with the
+    /// terminal cursor in its input. Input wider than the terminal scrolls left so
+    /// the cursor stays in view.
+    fn draw_prompt(&self, stdout: &mut io::Stdout, width: u16, height: u16) -> io::Result<()> {
+        let Some((label, kind)) = &self.prompt else {
+            return Ok(());
+        };
+        let cursor = self.prompt_editors.get(kind).map_or(0, |editor| editor.cursor);
+        let prompt_scroll = (label.chars().count() + cursor + 1).saturating_sub(width as usize);
+        let text = self.prompt_editors.get(kind).map(|editor| editor.text.as_str()).unwrap_or("");
+        let label_len = label.chars().count();
+        let shown_label: String = label.chars().skip(prompt_scroll).collect();
+        let room = (width as usize).saturating_sub(shown_label.chars().count() + 1);
+        let shown_text: String = text.chars().skip(prompt_scroll.saturating_sub(label_len)).take(room).collect();
+        queue!(
+            stdout,
+            cursor::MoveTo(0, height.saturating_sub(1)),
+            terminal::Clear(ClearType::CurrentLine),
+            SetForegroundColor(Color::Yellow),
+            Print(shown_label),
+            ResetColor,
+            Print(shown_text)
+        )?;
+        let room = (width as usize).saturating_sub(label_len + text.chars().count() - prompt_scroll + 3);
+        if let Some((severity, message)) = self.prompt_feedback.as_ref().filter(|_| room > 3) {
+            queue!(
+                stdout,
+                SetForegroundColor(severity.color()),
+                Print(format!("  {}", Self::truncate_string_safe(&self.plain(message), room))),
+                ResetColor
+            )?;
+        }
+        let column = label_len + cursor - prompt_scroll;
+        queue!(stdout, cursor::MoveTo(column as u16, height.saturating_sub(1)), cursor::Show)?;
+        Ok(())
+    }
+
+    /// Help screen contents as (is_header, text), generated from the active
+    /// keybindings and filtered by the help search query.
+    fn help_screen_lines(&self) -> Vec<(bool, String)> {
+        let mut entries: Vec<(&str, String, &str)> = self.keybindings.actions()
+            .into_iter()
+            .map(|(_, category, description, keys)| (category, Keybindings::format_keys(keys), description))
+            .collect();
+
+        entries.extend(self.keybindings.commands.iter().map(|(key, command)| {
+            ("Commands", Keybindings::format_keys(std::slice::from_ref(key)), command.as_str())
+        }));
+
+        // Keys handled directly by the event loop
+        entries.extend([
+            ("Other Keys", "Tab".to_string(), "Cycle list info column / toggle preview line numbers"),
+            ("Other Keys", "Alt+1-9".to_string(), "Jump up N levels"),
+            ("Other Keys", "Arrows".to_string(), "Move selection"),
+            ("Other Keys", "Backspace".to_string(), "Go to parent directory"),
+            ("Other Keys", "Esc".to_string(), "Quit without cd"),
+        ]);
+
+        let query = self.help_screen_query.to_lowercase();
+        let key_width = entries.iter().map(|(_, keys, _)| keys.chars().count()).max().unwrap_or(0);
+
+        let mut lines = Vec::new();
+        let mut current_category = "";
+        for (category, keys, description) in &entries {
+            if !query.is_empty()
+                && !description.to_lowercase().contains(&query)
+                && !keys.to_lowercase().contains(&query)
+                && !category.to_lowercase().contains(&query)
+            {
+                continue;
+            }
+            if *category != current_category {
+                if !lines.is_empty() {
+                    lines.push((false, String::new()));
+                }
+                lines.push((true, category.to_string()));
+                current_category = category;
+            }
+            lines.push((false, format!("  {:<width$}  {}", keys, description, width = key_width)));
+        }
+        lines
+    }
+
+    /// Draw the grid with an image thumbnail above each name. Thumbnails that aren't
+    /// cached yet are generated in the background and show a placeholder meanwhile.
+    /// Returns the number of gallery rows drawn.
+    fn draw_gallery(&mut self, stdout: &mut io::Stdout, display_height: u16, start_content_row: u16, selected_pos: &mut Option<(u16, u16)>) -> io::Result<usize> {
+        const CELL_WIDTH: usize = 22;
+        const NAME_WIDTH: usize = 20;
+        let image_height = GALLERY_ROW_HEIGHT - 2;
+
+        let max_display_rows = ((display_height as usize).saturating_sub(start_content_row as usize) / GALLERY_ROW_HEIGHT).max(1);
+        let total_rows = self.entries.len().div_ceil(self.num_cols);
+        let start_row = self.scroll_offset;
+        let end_row = (start_row + max_display_rows).min(total_rows);
+        let mut to_generate = Vec::new();
+        let mut thumbnails_waiting = false;
+
+        for row in start_row..end_row {
+            let y = start_content_row + ((row - start_row) * GALLERY_ROW_HEIGHT) as u16;
+            for col in 0..self.num_cols {
+                let idx = row * self.num_cols + col;
+                if idx >= self.entries.len() {
+                    break;
+                }
+                let entry = self.entries[idx].clone();
+                let x = (col * CELL_WIDTH) as u16;
+                let is_selected = idx == self.selected;
+                let is_dir = entry.is_dir();
+
+                if is_image(&entry) {
+                    let thumb_path = Self::thumbnail_path(&entry, GALLERY_THUMBNAIL_SIZE);
+                    match thumb_path {
+                        Some(thumb_path) if thumb_path.exists() => {
+                            let drawn = self.print_image(stdout, &thumb_path, (x + 2, y), (NAME_WIDTH as u16, image_height as u16))?;
+                            if !drawn {
+                                queue!(stdout, cursor::MoveTo(x + 2, y), SetForegroundColor(Color::DarkGrey), Print("(cannot preview)"), ResetColor)?;
+                            }
+                        }
+                        Some(thumb_path) if self.thumbnail_failed(&thumb_path) => {
+                            queue!(stdout, cursor::MoveTo(x + 2, y), SetForegroundColor(Color::DarkGrey), Print("(cannot preview)"), ResetColor)?;
+                        }
+                        Some(thumb_path) => {
+                            to_generate.push((entry.clone(), thumb_path));
+                            thumbnails_waiting = true;
+                            queue!(stdout, cursor::MoveTo(x + 2, y), SetForegroundColor(Color::DarkGrey), Print(self.plain("…")), ResetColor)?;
+                        }
+                        None => {}
+                    }
+                } else {
+                    let label = if is_dir { "[dir]" } else { "[file]" };
+                    queue!(stdout, cursor::MoveTo(x + 2, y + (image_height / 2) as u16), SetForegroundColor(Color::DarkGrey), Print(label), ResetColor)?;
+                }
+
+                // Name under the thumbnail
+                let name = entry.file_name().and_then(|n| n.to_str()).unwrap_or("?");
+                let mut display_name = if is_dir && (self.show_dir_slash || self.settings.ascii_mode) {
+                    format!("{}/", name)
+                } else {
+                    name.to_string()
+                };
+                if display_name.chars().count() > NAME_WIDTH {
+                    display_name = display_name.chars().take(NAME_WIDTH - 1).collect();
+                    display_name.push('~');
+                }
+                let name_y = y + image_height as u16;
+                let prefix = if !is_selected { "  " } else if self.settings.ascii_mode { "* " } else { "> " };
+                if is_selected {
+                    *selected_pos = Some((x, name_y));
+                }
+                let fg = if is_selected {
+                    self.color_config.parse_selected_fg().unwrap_or(Color::Green)
+                } else if is_dir {
+                    self.color_config.parse_directory_fg().unwrap_or(Color::Blue)
+                } else {
+                    self.color_config.parse_file_fg().unwrap_or(Color::Reset)
+                };
+                queue!(stdout, cursor::MoveTo(x, name_y), SetForegroundColor(fg), Print(prefix), Print(display_name), ResetColor)?;
+            }
+        }
+
+        self.start_thumbnails(to_generate, GALLERY_THUMBNAIL_SIZE);
+        self.thumbnails_waiting = thumbnails_waiting;
+
+        Ok(end_row - start_row)
+    }
+
+    /// The content search query and its matches, in place of the listing.
+    fn draw_grep_results(&mut self, stdout: &mut io::Stdout, width: u16, display_height: u16) -> io::Result<()> {
+        let results = Arc::clone(&self.grep_results);
+        let Ok(results) = results.lock() else {
+            return Ok(());
+        };
+        self.grep_waiting = !results.done;
+
+        let label = if self.grep_names { "Find: " } else { "Grep: " };
+        let status = if self.grep_editor.text.is_empty() {
+            String::new()
+        } else if self.grep_names {
+            let more = if results.done { "" } else { ", searching…" };
+            format!("  {} matches in {} directories{}", results.matches.len(), results.files, more)
+        } else {
+            let files = results.matches.iter().map(|found| &found.path).collect::<HashSet<_>>().len();
+            let more = if results.done { "" } else { ", searching…" };
+            format!("  {} matches in {} of {} files{}", results.matches.len(), files, results.files, more)
+        };
+        queue!(
+            stdout,
+            SetForegroundColor(self.color_config.parse_history_query_fg().unwrap_or(Color::Cyan)),
+            Print(format!("{}{}", label, self.grep_editor.text)),
+            SetForegroundColor(Color::DarkGrey),
+            Print(self.plain(&status)),
+            ResetColor,
+            Print("\r\n\r\n")
+        )?;
+
+        let query = if self.settings.case_sensitive_search { self.grep_editor.text.clone() } else { self.grep_editor.text.to_lowercase() };
+        let max_display_rows = (display_height as usize).saturating_sub(self.start_row as usize).saturating_sub(4);
+        let end_idx = (self.scroll_offset + max_display_rows).min(results.matches.len());
+        for idx in self.scroll_offset..end_idx {
+            let found = &results.matches[idx];
+            let is_selected = idx == self.selected;
+            let prefix = if !is_selected { "  " } else if self.settings.ascii_mode { "* " } else { "> " };
+            let relative = found.path.strip_prefix(&self.current_dir).unwrap_or(&found.path).display().to_string();
+            // Name matches show the path itself as the matched text
+            let (location, text) = if found.line == 0 {
+                let slash = if found.path.is_dir() { "/" } else { "" };
+                (String::new(), format!("{}{}", relative, slash))
+            } else {
+                (format!("{}:{}: ", relative, found.line), found.text.clone())
+            };
+            let room = (width as usize).saturating_sub(prefix.len() + location.chars().count());
+            let text: String = text.chars().take(room).collect();
+
+            let path_color = if is_selected {
+                self.color_config.parse_selected_fg().unwrap_or(Color::Green)
+            } else {
+                self.color_config.parse_history_path_fg().unwrap_or(Color::Cyan)
+            };
+            queue!(stdout, SetForegroundColor(path_color), Print(prefix), Print(&location), ResetColor)?;
+
+            // Highlight the first occurrence of the query, or for names the last
+            let haystack = if self.settings.case_sensitive_search { text.clone() } else { text.to_lowercase() };
+            let start = if found.line == 0 { haystack.rfind(&query) } else { haystack.find(&query) };
+            match start.filter(|_| !query.is_empty() && haystack.len() == text.len()) {
+                Some(start) => {
+                    let end = start + query.len();
+                    queue!(
+                        stdout,
+                        Print(&text[..start]),
+                        crossterm::style::SetAttribute(crossterm::style::Attribute::Bold),
+                        SetForegroundColor(self.color_config.parse_fuzzy_highlight_fg().unwrap_or(Color::Yellow)),
+                        Print(&text[start..end]),
+                        crossterm::style::SetAttribute(crossterm::style::Attribute::Reset),
+                        Print(&text[end..])
+                    )?;
+                }
+                None => queue!(stdout, Print(&text))?,
+            }
+            queue!(stdout, ResetColor, Print("\r\n"))?;
+        }
+        Ok(())
+    }
+
+    fn draw_help_screen(&mut self, stdout: &mut io::Stdout, width: u16, height: u16) -> io::Result<()> {
+        let lines = self.help_screen_lines();
+        let top = self.start_row + 2;
+        let visible = (height as usize).saturating_sub(top as usize + 1);
+        self.help_screen_scroll = self.help_screen_scroll.min(lines.len().saturating_sub(visible));
+
+        queue!(
+            stdout,
+            cursor::MoveTo(0, self.start_row + 1),
+            SetForegroundColor(Color::Yellow),
+            Print(format!("Search: {}_", self.help_screen_query)),
+            SetForegroundColor(Color::DarkGrey),
+            Print(self.plain("  (type to search, ↑/↓ scroll, Esc close)")),
+            ResetColor
+        )?;
+
+        let header_color = self.color_config.parse_directory_fg().unwrap_or(Color::Cyan);
+        for (i, (is_header, text)) in lines.iter().skip(self.help_screen_scroll).take(visible).enumerate() {
+            let text = Self::truncate_string_safe(text, width as usize);
+            queue!(stdout, cursor::MoveTo(0, top + i as u16))?;
+            if *is_header {
+                queue!(
+                    stdout,
+                    SetForegroundColor(header_color),
+                    crossterm::style::SetAttribute(crossterm::style::Attribute::Bold),
+                    Print(text),
+                    crossterm::style::SetAttribute(crossterm::style::Attribute::Reset),
+                    ResetColor
+                )?;
+            } else {
+                queue!(stdout, Print(text))?;
+            }
+        }
+
+        if lines.is_empty() {
+            queue!(stdout, cursor::MoveTo(0, top), SetForegroundColor(Color::DarkGrey), Print("  (no matching keys)"), ResetColor)?;
+        } else if lines.len() > visible {
+            let end = (self.help_screen_scroll + visible).min(lines.len());
+            queue!(
+                stdout,
+                cursor::MoveTo(0, height.saturating_sub(1)),
+                SetForegroundColor(Color::DarkGrey),
+                Print(format!("lines {}-{} of {}", self.help_screen_scroll + 1, end, lines.len())),
+                ResetColor
+            )?;
+        }
+        Ok(())
+    }
+
+    fn draw_messages(&mut self, stdout: &mut io::Stdout, width: u16, height: u16) -> io::Result<()> {
+        let top = self.start_row + 2;
+        let visible = (height as usize).saturating_sub(top as usize + 1);
+        let total = self.message_log.len();
+        self.messages_scroll = self.messages_scroll.min(total.saturating_sub(visible));
+
+        queue!(
+            stdout,
+            cursor::MoveTo(0, self.start_row + 1),
+            SetForegroundColor(Color::Yellow),
+            Print(format!("Messages ({})", total)),
+            SetForegroundColor(Color::DarkGrey),
+            Print(self.plain("  (↑/↓ scroll, Esc close)")),
+            ResetColor
+        )?;
+
+        if total == 0 {
+            queue!(stdout, cursor::MoveTo(0, top), SetForegroundColor(Color::DarkGrey), Print("  (no messages yet)"), ResetColor)?;
+            return Ok(());
+        }
+
+        for (i, (time, severity, message)) in self.message_log.iter().skip(self.messages_scroll).take(visible).enumerate() {
+            let label = severity.label();
+            let age = time.elapsed().as_secs();
+            let age = if age < 60 {
+                format!("{}s ago", age)
+            } else if age < 3600 {
+                format!("{}m ago", age / 60)
+            } else {
+                format!("{}h ago", age / 3600)
+            };
+            let text = Self::truncate_string_safe(&format!(" {:>7}  {:<5}  {}", age, label, message), width as usize);
+            queue!(
+                stdout,
+                cursor::MoveTo(0, top + i as u16),
+                SetForegroundColor(severity.color()),
+                Print(text),
+                ResetColor
+            )?;
+        }
+        Ok(())
+    }
+
+    fn draw_info_panel(&self, stdout: &mut io::Stdout, width: u16, height: u16) -> io::Result<()> {
+        let Some((title, rows)) = &self.info_panel else {
+            return Ok(());
+        };
+        queue!(
+            stdout,
+            cursor::MoveTo(0, self.start_row + 1),
+            SetForegroundColor(Color::Yellow),
+            Print(title),
+            SetForegroundColor(Color::DarkGrey),
+            Print("  (Esc close)"),
+            ResetColor
+        )?;
+        let label_width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+        // Rows that don't fit above the bottom line are counted on the last one that does
+        let room = (height.saturating_sub(self.start_row + 4) as usize).max(1);
+        let shown = if rows.len() > room { room - 1 } else { rows.len() };
+        if shown < rows.len() {
+            queue!(
+                stdout,
+                cursor::MoveTo(0, self.start_row + 3 + shown as u16),
+                SetForegroundColor(Color::DarkGrey),
+                Print(format!("  … and {} more", rows.len() - shown)),
+                ResetColor
+            )?;
+        }
+        for (i, (label, value)) in rows.iter().enumerate().take(shown) {
+            queue!(
+                stdout,
+                cursor::MoveTo(0, self.start_row + 3 + i as u16),
+                SetForegroundColor(Color::DarkGrey),
+                Print(format!("  {:<width$}  ", label, width = label_width)),
+                ResetColor,
+                Print(Self::truncate_string_safe(value, (width as usize).saturating_sub(label_width + 4)))
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The bookmarks picker: one row per bookmark, missing directories dimmed.
+    fn draw_bookmarks(&self, stdout: &mut io::Stdout, width: u16) -> io::Result<()> {
+        queue!(
+            stdout,
+            cursor::MoveTo(0, self.start_row + 1),
+            SetForegroundColor(Color::Yellow),
+            Print("Bookmarks"),
+            SetForegroundColor(Color::DarkGrey),
+            Print("  (letter or Enter jump, Del remove, Esc close)"),
+            ResetColor
+        )?;
+        if self.bookmarks.bookmarks.is_empty() {
+            let add = Keybindings::format_keys(&self.keybindings.bookmark_add);
+            queue!(
+                stdout,
+                cursor::MoveTo(0, self.start_row + 3),
+                SetForegroundColor(Color::DarkGrey),
+                Print(format!("  No bookmarks yet: press {} and a letter to bookmark the current directory", add)),
+                ResetColor
+            )?;
+            return Ok(());
+        }
+        for (i, (letter, path)) in self.bookmarks.bookmarks.iter().enumerate() {
+            let path = PathBuf::from(path);
+            let selected = i == self.bookmarks_selected;
+            let color = if !path.is_dir() { Color::DarkGrey } else if selected { Color::Green } else { Color::Reset };
+            let mut text = format!("{} {}  {}", if selected { ">" } else { " " }, letter, self.tilde_path(&path));
+            if !path.is_dir() {
+                text.push_str("  (missing)");
+            }
+            queue!(
+                stdout,
+                cursor::MoveTo(0, self.start_row + 3 + i as u16),
+                SetForegroundColor(color),
+                Print(Self::truncate_string_safe(&text, width as usize)),
+                ResetColor
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The "open with" chooser: numbered commands, then a row to type another.
+    fn draw_open_with(&self, stdout: &mut io::Stdout, width: u16, height: u16) -> io::Result<()> {
+        let Some((path, choices)) = &self.open_with else {
+            return Ok(());
+        };
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        queue!(
+            stdout,
+            cursor::MoveTo(0, self.start_row + 1),
+            SetForegroundColor(Color::Yellow),
+            Print(format!("Open {} with", name)),
+            SetForegroundColor(Color::DarkGrey),
+            Print("  (number or Enter run, Esc close)"),
+            ResetColor
+        )?;
+        let command_width = choices.iter().map(|(command, _)| command.chars().count()).max().unwrap_or(0);
+        // Scroll to keep the highlighted row above the bottom line
+        let rows = choices.len() + 1;
+        let room = (height.saturating_sub(self.start_row + 4) as usize).max(1);
+        let offset = (self.open_with_selected + 1).saturating_sub(room).min(rows.saturating_sub(room));
+        for i in offset..rows.min(offset + room) {
+            let selected = i == self.open_with_selected;
+            queue!(stdout, cursor::MoveTo(0, self.start_row + 3 + (i - offset) as u16))?;
+            let Some((command, source)) = choices.get(i) else {
+                queue!(
+                    stdout,
+                    SetForegroundColor(if selected { Color::Green } else { Color::DarkGrey }),
+                    Print(format!("{} o  Other command…", if selected { ">" } else { " " })),
+                    ResetColor
+                )?;
+                continue;
+            };
+            let number = if i < 9 { (i + 1).to_string() } else { " ".to_string() };
+            let text = format!("{} {}  {:<w$}", if selected { ">" } else { " " }, number, command, w = command_width);
+            queue!(
+                stdout,
+                SetForegroundColor(if selected { Color::Green } else { Color::Reset }),
+                Print(Self::truncate_string_safe(&text, width as usize)),
+                SetForegroundColor(Color::DarkGrey),
+                Print(Self::truncate_string_safe(&format!("  {}", source), (width as usize).saturating_sub(text.chars().count()))),
+                ResetColor
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Bookmark the current directory under `letter`, replacing what was there.
+    fn add_bookmark(&mut self, letter: char) {
+        let dir = self.current_dir.display().to_string();
+        let replaced = self.bookmarks.bookmarks.insert(letter.to_string(), dir).filter(|old| *old != self.current_dir.display().to_string());
+        if let Err(e) = self.bookmarks.save() {
+            self.notify(Severity::Error, format!("Couldn't save bookmarks: {}", e));
+            return;
+        }
+        let message = match replaced {
+            Some(old) => format!("Bookmarked {} as '{}' (was {})", self.format_path_display(), letter, self.tilde_path(Path::new(&old))),
+            None => format!("Bookmarked {} as '{}'", self.format_path_display(), letter),
+        };
+        self.notify(Severity::Success, message);
+    }
+
+    /// Go to the bookmark under `letter`, closing the picker.
+    fn jump_to_bookmark(&mut self, letter: &str) -> io::Result<()> {
+        let Some(path) = self.bookmarks.bookmarks.get(letter).cloned() else {
+            return Ok(());
+        };
+        self.bookmarks_mode = false;
+        self.go_to_path(PathBuf::from(path))
+    }
+
+    /// Open the details panel with cache usage.
+    fn show_diagnostics(&mut self) {
+        let preview = self.preview_cache.lock().map(|cache| cache.stats(&self.locale)).unwrap_or_default();
+        let item_counts = self.item_count_cache.lock().map(|cache| cache.len()).unwrap_or(0);
+        let rows = vec![
+            ("Preview cache".to_string(), preview),
+            ("Directory sizes".to_string(), self.dir_size_cache.stats(&self.locale)),
+            ("Item counts".to_string(), format!("{} directories", item_counts)),
+            ("Decoded images".to_string(), self.decoded_images.stats(&self.locale)),
+            ("Image drawing".to_string(), self.image_protocol.map_or("not worked out yet", ImageProtocol::name).to_string()),
+            ("Message log".to_string(), format!("{} of {} messages", self.message_log.len(), MESSAGE_LOG_SIZE)),
+        ];
+        self.info_panel = Some(("Diagnostics".to_string(), rows));
+    }
+
+    /// Open the details panel for the filesystem holding the current directory.
+    fn show_filesystem_info(&mut self) {
+        let mut rows = vec![("Path".to_string(), self.current_dir.display().to_string())];
+        match mount_info(&self.current_dir) {
+            Some(mount) => {
+                rows.push(("Mount point".to_string(), mount.mount_point));
+                rows.push(("Filesystem".to_string(), mount.fs_type));
+                rows.push(("Device".to_string(), mount.device));
+                rows.push(("Options".to_string(), mount.options));
+            }
+            None => rows.push(("Mount point".to_string(), "unknown".to_string())),
+        }
+        if let Some((free, total)) = disk_space(&self.current_dir) {
+            let units = ["B", "KB", "MB", "GB", "TB"];
+            rows.push((
+                "Space".to_string(),
+                format!("{} free of {}", self.locale.format_size(free, &units), self.locale.format_size(total, &units)),
+            ));
+        }
+        self.info_panel = Some(("Filesystem".to_string(), rows));
+    }
+
+    /// Open the details panel for the selected entry, with its apparent size next
+    /// to the space it takes on disk.
+    fn show_file_info(&mut self) {
+        let Some(path) = self.get_selected_path() else { return };
+        let Ok(metadata) = fs::symlink_metadata(&path) else { return };
+        let mut rows = vec![("Path".to_string(), path.display().to_string())];
+        let kind = if metadata.is_symlink() {
+            format!("Symlink to {}", fs::read_link(&path).map(|target| target.display().to_string()).unwrap_or_default())
+        } else if metadata.is_dir() {
+            "Directory".to_string()
+        } else {
+            "File".to_string()
+        };
+        rows.push(("Type".to_string(), kind));
+
+        let units = ["B", "KB", "MB", "GB", "TB"];
+        let usage = if metadata.is_dir() { self.dir_size_cache.get(&path).copied() } else { Some(DiskUsage::of(&metadata)) };
+        match usage {
+            Some(usage) => {
+                let note = if usage.allocated < usage.apparent { " (sparse or compressed)" } else { "" };
+                rows.push(("Size".to_string(), format!("{} ({} bytes)", self.locale.format_size(usage.apparent, &units), usage.apparent)));
+                rows.push(("On disk".to_string(), format!("{}{}", self.locale.format_size(usage.allocated, &units), note)));
+            }
+            None => rows.push((
+                "Size".to_string(),
+                format!("not calculated (press {} to size it)", Keybindings::format_keys(&self.keybindings.dir_size)),
+            )),
+        }
+        self.info_panel = Some(("Details".to_string(), rows));
+    }
+
+    fn draw_footer_help(&mut self, stdout: &mut io::Stdout, width: u16, height: u16) -> io::Result<()> {
+        // Helper to format keybindings
+        let fmt_keys = |keys: &Vec<String>| -> String { Keybindings::format_keys(keys) };
+
+        let nav_keys = {
+            let kb = &self.keybindings;
+            if kb.up.is_empty() && kb.down.is_empty() && kb.left.is_empty() && kb.right.is_empty() {
+                "↑/↓/←/→".to_string()
+            } else {
+                format!("{}/{}/{}/{}", fmt_keys(&kb.up), fmt_keys(&kb.down), fmt_keys(&kb.left), fmt_keys(&kb.right))
+            }
+        };
+
+        let shortcut_hints = self.shortcuts.iter()
+            .map(|(name, path)| format!("{} {}", name, path))
+            .collect::<Vec<_>>()
+            .join(" │ ");
+
+        let help_text = if self.filter_mode {
+            " Filter │ Type to narrow │ ↑/↓ Move │ Enter Select │ Esc Cancel".to_string()
+        } else if self.path_edit_mode {
+            " Edit Path │ Tab Complete │ ←/→ Move │ Ctrl+U Clear │ Enter Go │ Esc Cancel".to_string()
+        } else if self.path_jump_pending {
+            " Path Jump │ Press a highlighted hint to go to that directory │ Any other key Cancel".to_string()
+        } else if self.ancestor_jump_pending {
+            if shortcut_hints.is_empty() {
+                " Jump Up │ 1-9 Levels up (see numbered path) │ Any other key Cancel".to_string()
+            } else {
+                format!(" Jump │ 1-9 Levels up │ {} │ Esc Cancel", shortcut_hints)
+            }
+        } else if !self.key_sequence.is_empty() {
+            format!(" {} … │ Waiting for the rest of the key sequence │ Esc Cancel", Keybindings::format_keys(std::slice::from_ref(&self.key_sequence)))
+        } else if self.grep_mode && self.grep_names {
+            format!(" Find Files │ Type to match names up to {} levels down │ Tab/↑/↓ Navigate results │ Enter Go │ Esc Cancel", self.settings.find_max_depth)
+        } else if self.grep_mode {
+            " Grep │ Type to search file contents │ Tab/↑/↓ Navigate results │ Enter Open │ Esc Cancel".to_string()
+        } else if self.history_mode {
+            // History mode help
+            " Frecency Jump │ Type to filter │ Tab/↑/↓ Navigate results │ Enter Jump │ Esc Cancel".to_string()
+        } else if self.fuzzy_mode {
+            // Fuzzy mode help
+            let mode_info = if self.fuzzy_jump_mode { "Jump Mode" } else { "Continuous Mode (Shift+/)" };
+            format!(
+                " {} │ {} Back │ {} Home │ Enter Open │ Esc Cancel │ ! Toggle Help",
+                mode_info,
+                fmt_keys(&self.keybindings.fuzzy_back),
+                fmt_keys(&self.keybindings.fuzzy_home)
+            )
+        } else if self.preview_mode && self.get_selected_path().is_some_and(|path| is_table_file(&path)) {
+            // Table preview help
+            format!(
+                " {}/{} Scroll │ {}/{} Scroll Columns │ {}/{} Resize Pane │ {} Close Preview │ {} Toggle Help",
+                fmt_keys(&self.keybindings.preview_up),
+                fmt_keys(&self.keybindings.preview_down),
+                fmt_keys(&self.keybindings.preview_left),
+                fmt_keys(&self.keybindings.preview_right),
+                fmt_keys(&self.keybindings.preview_height_decrease),
+                fmt_keys(&self.keybindings.preview_height_increase),
+                fmt_keys(&self.keybindings.preview_toggle),
+                fmt_keys(&self.keybindings.help)
+            )
+        } else if self.preview_mode && self.get_selected_path().is_some_and(|path| is_image(&path)) {
+            // Image preview help
+            format!(
+                " {} Nav │ {}/{} Prev/Next Image │ {}/{} Resize Pane │ {} Close Preview │ {} Toggle Help",
+                nav_keys,
+                fmt_keys(&self.keybindings.prev_image),
+                fmt_keys(&self.keybindings.next_image),
+                fmt_keys(&self.keybindings.preview_height_decrease),
+                fmt_keys(&self.keybindings.preview_height_increase),
+                fmt_keys(&self.keybindings.preview_toggle),
+                fmt_keys(&self.keybindings.help)
+            )
+        } else if self.preview_mode {
+            // Preview mode help
+            format!(
+                " {}/{} Scroll │ {}/{} Scroll Page │ {}/{} Resize Pane │ Tab Toggle Line# │ {} Close Preview │ {} Toggle Help",
+                fmt_keys(&self.keybindings.preview_up),
+                fmt_keys(&self.keybindings.preview_down),
+                fmt_keys(&self.keybindings.preview_page_up),
+                fmt_keys(&self.keybindings.preview_page_down),
+                fmt_keys(&self.keybindings.preview_height_decrease),
+                fmt_keys(&self.keybindings.preview_height_increase),
+                fmt_keys(&self.keybindings.preview_toggle),
+                fmt_keys(&self.keybindings.help)
+            )
+        } else if self.list_mode {
+            // List mode help
+            format!(
+                " {} Nav │ {} Forward │ {} Back │ {} Open │ {} Home │ Tab Info │ {} Extra │ {} Grid Mode │ {} Find │ {} Preview │ {} Exit │ {} Exit to Finder │ {} Toggle Help",
+                nav_keys,
+                fmt_keys(&self.keybindings.open),
+                fmt_keys(&self.keybindings.back),
+                fmt_keys(&self.keybindings.select),
+                fmt_keys(&self.keybindings.home),
+                fmt_keys(&self.keybindings.list_action),
+                fmt_keys(&self.keybindings.toggle_mode),
+                fmt_keys(&self.keybindings.fuzzy_find),
+                fmt_keys(&self.keybindings.preview_toggle),
+                fmt_keys(&self.keybindings.quit),
+                fmt_keys(&self.keybindings.quit_then_open_in_finder),
+                fmt_keys(&self.keybindings.help)
+            )
+        } else {
+            // Normal (grid) mode help
+            format!(
+                " {} Nav │ {} Forward │ {} Back │ {} Open │ {} Home │ {}/{} Sibling │ {}1-9 Up N Levels │ {} Find │ {} List Mode │ {} Gallery │ {} Preview │ {} Exit │ {} Exit to Finder │ {} Toggle Help",
+                nav_keys,
+                fmt_keys(&self.keybindings.open),
+                fmt_keys(&self.keybindings.back),
+                fmt_keys(&self.keybindings.select),
+                fmt_keys(&self.keybindings.home),
+                fmt_keys(&self.keybindings.next_sibling),
+                fmt_keys(&self.keybindings.prev_sibling),
+                fmt_keys(&self.keybindings.ancestor_jump),
+                fmt_keys(&self.keybindings.fuzzy_find),
+                fmt_keys(&self.keybindings.toggle_mode),
+                fmt_keys(&self.keybindings.gallery),
+                fmt_keys(&self.keybindings.preview_toggle),
+                fmt_keys(&self.keybindings.quit),
+                fmt_keys(&self.keybindings.quit_then_open_in_finder),
+                fmt_keys(&self.keybindings.help)
+            )
+        };
+
+        // File operations help (second row) - only show in grid/list mode
+        let file_ops_text = if !self.fuzzy_mode && !self.preview_mode && !self.history_mode && !self.grep_mode && !self.path_edit_mode && !self.filter_mode {
+            Some(format!(
+                " File Operations: {} New │ {} Rename │ {}/{} Copy/Paste │ {} Trash │ {} Delete │ {} Undo",
+                fmt_keys(&self.keybindings.create),
+                fmt_keys(&self.keybindings.rename),
+                fmt_keys(&self.keybindings.copy),
+                fmt_keys(&self.keybindings.paste),
+                fmt_keys(&self.keybindings.trash),
+                fmt_keys(&self.keybindings.delete),
+                fmt_keys(&self.keybindings.undo)
+            ))
+        } else {
+            None
+        };
+
+        let help_text = self.plain(&help_text);
+        let file_ops_text = file_ops_text.map(|text| self.plain(&text));
+
+        // Determine rows for help text (account for wrapper warning if present)
+        let wrapper_warning_offset = if self.wrapper_warning { 1 } else { 0 };
+        let (help_row, file_ops_row) = if self.history_mode || self.grep_mode || self.fuzzy_mode || self.filter_mode {
+            (height.saturating_sub(2 + wrapper_warning_offset), None)
+        } else if file_ops_text.is_some() {
+            (height.saturating_sub(2 + wrapper_warning_offset), Some(height.saturating_sub(1 + wrapper_warning_offset)))
+        } else {
+            (height.saturating_sub(1 + wrapper_warning_offset), None)
+        };
+
+        // Draw wrapper warning if needed (above help text)
+        if self.wrapper_warning {
+            let warning_row = height.saturating_sub(1);
+            let warning_text = self.plain(" ⚠ WARNING: Shell wrapper not detected! Navigation (cd) won't work. Run: ils --install");
+            let truncated_warning = Self::truncate_string_safe(&warning_text, width as usize);
+            queue!(
+                stdout,
+                cursor::MoveTo(0, warning_row),
+                SetForegroundColor(Color::Red),
+                crossterm::style::SetAttribute(crossterm::style::Attribute::Bold),
+                Print(&truncated_warning),
+                crossterm::style::SetAttribute(crossterm::style::Attribute::Reset),
+                ResetColor
+            )?;
+        }
+
+        // Draw main help row (with wrapping)
+        let help_lines = Self::wrap_text(&help_text, width as usize);
+        for (i, line) in help_lines.iter().enumerate() {
+            let row = help_row.saturating_sub(help_lines.len().saturating_sub(1) as u16).saturating_add(i as u16);
+            queue!(
+                stdout,
+                cursor::MoveTo(0, row),
+                SetForegroundColor(Color::DarkGrey),
+                Print(line),
+                ResetColor
+            )?;
+        }
+
+        // Draw file operations row if present (with wrapping, use cyan color)
+        if let (Some(ops_text), Some(ops_row)) = (file_ops_text, file_ops_row) {
+            let ops_lines = Self::wrap_text(&ops_text, width as usize);
+            for (i, line) in ops_lines.iter().enumerate() {
+                let row = ops_row.saturating_sub(ops_lines.len().saturating_sub(1) as u16).saturating_add(i as u16);
+                queue!(
+                    stdout,
+                    cursor::MoveTo(0, row),
+                    SetForegroundColor(Color::Cyan),
+                    Print(line),
+                    ResetColor
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn select_up(&mut self) {
+        if self.grep_mode {
+            let count = self.grep_match_count();
+            if count > 0 {
+                self.selected = if self.selected > 0 { self.selected - 1 } else { count - 1 };
+                self.scroll_to_selected();
+            }
+            return;
+        }
+
+        // In history mode, just move up one item (list mode) with wrapping
+        if self.history_mode {
+            if !self.history_filtered.is_empty() {
+                if self.selected > 0 {
+                    self.selected -= 1;
+                } else {
+                    // Wrap to bottom
+                    self.selected = self.history_filtered.len() - 1;
+                }
+
+                self.scroll_to_selected();
+            }
+            return;
+        }
+
+        // Row-major: same column of the row above, or its last entry when shorter
+        let rows = self.listing_rows();
+        let Some((row, col)) = self.selected_cell(&rows) else {
+            return;
+        };
+        if let Some(&ListingRow::Entries(first, last)) = rows[..row].iter().rev().find(|cells| matches!(cells, ListingRow::Entries(..))) {
+            self.selected = (first + col).min(last - 1);
+            self.scroll_to_selected();
+        }
+    }
+
+    fn select_down(&mut self) {
+        if self.grep_mode {
+            let count = self.grep_match_count();
+            if count > 0 {
+                self.selected = if self.selected + 1 < count { self.selected + 1 } else { 0 };
+                self.scroll_to_selected();
+            }
+            return;
+        }
+
+        // In history mode, just move down one item (list mode) with wrapping
+        if self.history_mode {
+            if !self.history_filtered.is_empty() {
+                if self.selected + 1 < self.history_filtered.len() {
+                    self.selected += 1;
+                } else {
+                    // Wrap to top
+                    self.selected = 0;
+                }
+
+                self.scroll_to_selected();
+            }
+            return;
+        }
+
+        // Row-major: same column of the row below. Only a group's last row is
+        // short, so from past its end the move lands on the last entry.
+        let rows = self.listing_rows();
+        let Some((row, col)) = self.selected_cell(&rows) else {
+            return;
+        };
+        if let Some(&ListingRow::Entries(first, last)) = rows[row + 1..].iter().find(|cells| matches!(cells, ListingRow::Entries(..))) {
+            if first + col < last || self.grouped() {
+                self.selected = (first + col).min(last - 1);
+                self.scroll_to_selected();
+            }
+        }
+    }
+
+    fn select_left(&mut self) {
+        // Row-major: move left in same row
+        let rows = self.listing_rows();
+        if let Some((_, col)) = self.selected_cell(&rows) {
+            if col > 0 {
+                self.selected -= 1;
+            }
+        }
+    }
+
+    fn select_right(&mut self) {
+        // Row-major: move right in same row
+        let rows = self.listing_rows();
+        if let Some((row, _)) = self.selected_cell(&rows) {
+            if matches!(rows[row], ListingRow::Entries(_, last) if self.selected + 1 < last) {
+                self.selected += 1;
+            }
+        }
+    }
+
+    fn jump_up(&mut self) {
+        // Jump up by configured amount
+        for _ in 0..self.settings.jump_amount {
+            self.select_up();
+        }
+    }
+
+    fn jump_down(&mut self) {
+        // Jump down by configured amount
+        for _ in 0..self.settings.jump_amount {
+            self.select_down();
+        }
+    }
+
+    fn jump_left(&mut self) {
+        // Jump left by configured amount
+        for _ in 0..self.settings.jump_amount {
+            self.select_left();
+        }
+    }
+
+    fn jump_right(&mut self) {
+        // Jump right by configured amount
+        for _ in 0..self.settings.jump_amount {
+            self.select_right();
+        }
+    }
+
+    fn filter_history(&mut self) {
+        // Filter history based on query
+        let all_entries = self.dir_history.get_sorted_by_frecency();
+
+        if self.history_query.is_empty() {
+            self.history_filtered = Vec::new();
+        } else {
+            let mut filtered: Vec<(DirectoryEntry, bool)> = all_entries
+                .into_iter()
+                .filter_map(|entry| {
+                    let basename = entry.path.rsplit('/').next().unwrap_or("");
+                    let (matches_full, matches_basename) = if self.settings.case_sensitive_search {
+                        (entry.path.contains(&self.history_query), basename.contains(&self.history_query))
+                    } else {
+                        (
+                            entry.path.to_lowercase().contains(&self.history_query.to_lowercase()),
+                            basename.to_lowercase().contains(&self.history_query.to_lowercase())
+                        )
+                    };
+
+                    if matches_full {
+                        Some((entry, matches_basename))
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+
+            // Sort: basename matches first if enabled
+            if self.settings.history_prioritize_basename {
+                filtered.sort_by(|a, b| {
+                    match (b.1, a.1) {
+                        (true, false) => std::cmp::Ordering::Greater,
+                        (false, true) => std::cmp::Ordering::Less,
+                        _ => std::cmp::Ordering::Equal,
+                    }
+                });
+            }
+
+            self.history_filtered = filtered
+                .into_iter()
+                .map(|(entry, _)| entry)
+                .take(self.settings.history_max_results)
+                .collect();
+        }
+
+        // Reset selection
+        self.selected = 0;
+        self.scroll_offset = 0;
+    }
+
+    fn fuzzy_match(&self) -> (Option<usize>, usize) {
+        // Find all entries that fuzzy-match the query and return (best_match, count);
+        // ties go to the entry listed first
+        if self.fuzzy_query.is_empty() {
+            return (None, 0);
+        }
+
+        let mut best: Option<(i64, usize)> = None;
+        let mut count = 0;
+        for (idx, entry) in self.entries.iter().enumerate() {
+            let Some(name) = entry.file_name().and_then(|n| n.to_str()) else { continue };
+            if let Some((score, _)) = fuzzy_score(&self.fuzzy_query, name, self.settings.case_sensitive_search) {
+                count += 1;
+                if best.is_none_or(|(best_score, _)| score > best_score) {
+                    best = Some((score, idx));
+                }
+            }
+        }
+
+        (best.map(|(_, idx)| idx), count)
+    }
+
+    /// Returns true if every character of `query` appears in `name` in order.
+    fn subsequence_match(query: &str, name: &str) -> bool {
+        let mut name_chars = name.chars();
+        query.chars().all(|q| name_chars.any(|c| c == q))
+    }
+
+    /// Open the search view, matching file contents or, with `names`, the names
+    /// of files and directories in subdirectories.
+    fn start_grep(&mut self, names: bool) {
+        self.grep_mode = true;
+        self.grep_names = names;
+        self.grep_editor.start("");
+        self.grep_return_selection = self.selected;
+        self.selected = 0;
+        self.scroll_offset = 0;
+        self.run_grep();
+    }
+
+    /// Restart the content search for the current query, stopping the last one.
+    fn run_grep(&mut self) {
+        self.grep_cancel.store(true, Ordering::Relaxed);
+        self.grep_cancel = Arc::new(AtomicBool::new(false));
+        self.grep_results = Arc::new(Mutex::new(GrepResults::default()));
+        self.selected = 0;
+        self.scroll_offset = 0;
+
+        let query = self.grep_editor.text.clone();
+        if query.is_empty() {
+            if let Ok(mut results) = self.grep_results.lock() {
+                results.done = true;
+            }
+            return;
+        }
+        let root = self.current_dir.clone();
+        let options = SearchOptions {
+            case_sensitive: self.settings.case_sensitive_search,
+            show_hidden: self.show_hidden,
+            max_depth: self.settings.find_max_depth,
+            max_results: self.settings.grep_max_results,
+        };
+        let names = self.grep_names;
+        let results = Arc::clone(&self.grep_results);
+        let cancel = Arc::clone(&self.grep_cancel);
+        thread::spawn(move || {
+            if names {
+                find_names(&root, &query, options, &results, &cancel);
+            } else {
+                grep_tree(&root, &query, options, &results, &cancel);
+            }
+        });
+    }
+
+    fn end_grep(&mut self) {
+        self.grep_cancel.store(true, Ordering::Relaxed);
+        self.grep_mode = false;
+        self.grep_waiting = false;
+        self.selected = self.grep_return_selection.min(self.entries.len().saturating_sub(1));
+        self.scroll_offset = 0;
+        self.scroll_to_selected();
+    }
+
+    fn grep_match_count(&self) -> usize {
+        self.grep_results.lock().map_or(0, |results| results.matches.len())
+    }
+
+    /// Go to the directory of the selected match and preview the file scrolled
+    /// to the matching line. Directories found by name are entered.
+    fn open_grep_match(&mut self) -> io::Result<()> {
+        let Some((path, line)) = self
+            .grep_results
+            .lock()
+            .ok()
+            .and_then(|results| results.matches.get(self.selected).map(|found| (found.path.clone(), found.line)))
+        else {
+            return Ok(());
+        };
+        self.end_grep();
+        if line == 0 && path.is_dir() {
+            self.current_dir = path;
+            self.breadcrumbs.clear();
+            self.load_entries()?;
+            self.directory_entered();
+            return Ok(());
+        }
+        let Some(dir) = path.parent().map(Path::to_path_buf) else {
+            return Ok(());
+        };
+        if dir != self.current_dir {
+            self.current_dir = dir;
+            self.breadcrumbs.clear();
+            self.load_entries()?;
+            self.directory_entered();
+        }
+        self.selected = self.entries.iter().position(|entry| *entry == path).unwrap_or(0);
+        self.scroll_to_selected();
+        if line > 0 {
+            // A couple of lines of context above the match
+            self.preview_scroll_map.insert(path, line.saturating_sub(3));
+            self.preview_mode = true;
+            self.readme_preview_opened = false;
+        }
+        Ok(())
+    }
+
+    fn start_filter(&mut self) {
+        self.filter_mode = true;
+        self.sync_collapsed();
+        self.filter_editor.start("");
+        self.filter_all_entries = self.entries.clone();
+    }
+
+    /// Narrows `entries` to the names matching the filter query. Size and date
+    /// terms (see `FilterPredicate`) are split off and must all match as well.
+    fn apply_filter(&mut self) {
+        let case_sensitive = self.settings.case_sensitive_search;
+        let mut predicates = Vec::new();
+        let mut tags = Vec::new();
+        let mut words = Vec::new();
+        for term in self.filter_editor.text.split(' ') {
+            // tag:name keeps entries with a tag starting so, a bare tag: any tagged entry
+            if let Some(tag) = term.strip_prefix("tag:") {
+                tags.push(tag);
+                continue;
+            }
+            match FilterPredicate::parse(term) {
+                Some(predicate) => predicates.push(predicate),
+                None => words.push(term),
+            }
+        }
+        let query = words.join(" ");
+        let query = if case_sensitive { query } else { query.to_lowercase() };
+        self.entries = self.filter_all_entries.iter()
+            .filter(|path| {
+                if !predicates.is_empty() {
+                    let Ok(metadata) = fs::metadata(path) else { return false };
+                    if !predicates.iter().all(|predicate| predicate.matches(&metadata)) {
+                        return false;
+                    }
+                }
+                let own_tags = self.tags.of(path);
+                let tagged = |tag: &&str| match tag.is_empty() {
+                    true => !own_tags.is_empty(),
+                    false => own_tags.iter().any(|own| own.to_lowercase().starts_with(&tag.to_lowercase())),
+                };
+                if !tags.iter().all(tagged) {
+                    return false;
+                }
+                let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+                if case_sensitive {
+                    Self::subsequence_match(&query, name)
+                } else {
+                    Self::subsequence_match(&query, &name.to_lowercase())
+                }
+            })
+            .cloned()
+            .collect();
+        self.selected = 0;
+        self.scroll_offset = 0;
+    }
+
+    /// Leaves filter mode, restoring the full listing with the cursor kept on
+    /// whatever entry was selected in the filtered view. The filtered entries
+    /// stay around as matches to step through.
+    fn end_filter(&mut self) {
+        let current = self.get_selected_path();
+        let narrowed = self.entries.len() < self.filter_all_entries.len();
+        self.search_matches = if narrowed { self.entries.clone() } else { Vec::new() };
+        self.filter_mode = false;
+        self.filter_editor.start("");
+        self.entries = std::mem::take(&mut self.filter_all_entries);
+        self.selected = current
+            .and_then(|path| self.entries.iter().position(|p| p == &path))
+            .unwrap_or(0);
+        self.scroll_offset = 0;
+        let _ = self.update_layout();
+        self.scroll_to_selected();
+    }
+
+    /// Keep the entries matching the fuzzy query as matches to step through
+    /// once fuzzy mode is left.
+    fn keep_fuzzy_matches(&mut self) {
+        let case_sensitive = self.settings.case_sensitive_search;
+        self.search_matches = if self.fuzzy_query.is_empty() {
+            Vec::new()
+        } else {
+            self.entries
+                .iter()
+                .filter(|path| {
+                    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+                    fuzzy_score(&self.fuzzy_query, name, case_sensitive).is_some()
+                })
+                .cloned()
+                .collect()
+        };
+    }
+
+    /// Matches of the last find or filter that are in the listing, in listing order.
+    fn match_positions(&self) -> Vec<usize> {
+        if self.search_matches.is_empty() {
+            return Vec::new();
+        }
+        let matches: HashSet<&PathBuf> = self.search_matches.iter().collect();
+        (0..self.entries.len()).filter(|&idx| matches.contains(&self.entries[idx])).collect()
+    }
+
+    /// Move to the next (or previous) match after the selection, wrapping around.
+    /// Returns false when there are no matches in this directory.
+    fn jump_to_match(&mut self, forward: bool) -> bool {
+        let positions = self.match_positions();
+        if positions.is_empty() {
+            self.search_matches.clear();
+            return false;
+        }
+        let target = if forward {
+            positions.iter().find(|&&idx| idx > self.selected).or(positions.first())
+        } else {
+            positions.iter().rev().find(|&&idx| idx < self.selected).or(positions.last())
+        };
+        if let Some(&idx) = target {
+            self.selected = idx;
+            self.scroll_to_selected();
+        }
+        true
+    }
+
+    /// Moves the cursor to the next entry (after the current one, wrapping) whose
+    /// name starts with `ch`, so repeating the same letter cycles through matches.
+    fn jump_to_first_letter(&mut self, ch: char) {
+        let matches_letter = |name: &str| {
+            if self.settings.case_sensitive_search {
+                name.starts_with(ch)
+            } else {
+                name.to_lowercase().starts_with(&ch.to_lowercase().to_string())
+            }
+        };
+
+        let len = self.entries.len();
+        let found = (1..=len)
+            .map(|offset| (self.selected + offset) % len)
+            .find(|&idx| {
+                self.entries[idx].file_name()
+                    .and_then(|n| n.to_str())
+                    .map(matches_letter)
+                    .unwrap_or(false)
+            });
+
+        if let Some(idx) = found {
+            self.selected = idx;
+            self.scroll_to_selected();
+        }
+    }
+
+    /// Replace box-drawing and other symbol glyphs with ASCII when `ascii_mode` is on.
+    fn plain(&self, text: &str) -> String {
+        if !self.settings.ascii_mode {
+            return text.to_string();
+        }
+        let mut out = String::with_capacity(text.len());
+        for ch in text.chars() {
+            match ch {
+                '│' => out.push('|'),
+                '─' => out.push('-'),
+                '›' => out.push('>'),
+                '⚠' => out.push('!'),
+                '█' => out.push('_'),
+                '…' => out.push_str("..."),
+                '•' => out.push('*'),
+                '↑' => out.push_str("Up"),
+                '↓' => out.push_str("Down"),
+                '←' => out.push_str("Left"),
+                '→' => out.push_str("Right"),
+                _ => out.push(ch),
+            }
+        }
+        out
+    }
+
+    /// Queue a toast; the oldest is dropped once more than MAX_TOASTS are showing.
+    fn notify(&mut self, severity: Severity, message: impl Into<String>) {
+        let message = message.into();
+        if self.message_log.len() == MESSAGE_LOG_SIZE {
+            self.message_log.pop_front();
+        }
+        self.message_log.push_back((Instant::now(), severity, message.clone()));
+
+        self.toasts.push(Toast {
+            message,
+            severity,
+            expires_at: Instant::now() + severity.lifetime(),
+        });
+        if self.toasts.len() > MAX_TOASTS {
+            self.toasts.remove(0);
+        }
+    }
+
+    fn expire_toasts(&mut self) {
+        let now = Instant::now();
+        self.toasts.retain(|toast| toast.expires_at > now);
+    }
+
+    /// Time until the next toast expires or, while directories are still being
+    /// counted or thumbnails generated, a short tick so they appear once ready.
+    /// With `auto_refresh` it's at most a second, to look for outside changes,
+    /// and a pending key sequence wakes up when its timeout runs out.
+    fn next_wakeup(&self) -> Option<Duration> {
+        let now = Instant::now();
+        let toast = self.toasts.iter().map(|toast| toast.expires_at.saturating_duration_since(now)).min();
+        let tick = if self.item_counts_waiting || self.git_status_waiting || self.git_changes_waiting || self.thumbnails_waiting || self.preview_waiting || self.grep_waiting || self.dir_size_waiting || self.media_info_waiting || self.ignored_pending {
+            Some(Duration::from_millis(100))
+        } else if self.settings.auto_refresh {
+            Some(Duration::from_secs(1))
+        } else {
+            None
+        };
+        let sequence = self.sequence_deadline().map(|deadline| deadline.saturating_duration_since(now));
+        [toast, tick, sequence].into_iter().flatten().min()
+    }
+
+    /// When the keys typed so far run as they are: only if they are bound on their
+    /// own as well as starting a longer sequence, and the timeout is on.
+    fn sequence_deadline(&self) -> Option<Instant> {
+        if self.key_sequence.is_empty() || self.settings.sequence_timeout_ms == 0 || !self.keybindings.is_bound_key(&self.key_sequence) {
+            return None;
+        }
+        Some(self.key_sequence_at + Duration::from_millis(self.settings.sequence_timeout_ms))
+    }
+
+    /// Reload the listing if the current directory changed on disk since it was
+    /// listed, staying on the selected entry by name. Left alone while a mode
+    /// like the filter is working on the listing.
+    fn refresh_if_changed(&mut self) -> io::Result<()> {
+        if !self.settings.auto_refresh || self.has_active_mode() {
+            return Ok(());
+        }
+        let modified = fs::metadata(&self.current_dir).and_then(|m| m.modified()).ok();
+        if modified == self.listing_modified {
+            return Ok(());
+        }
+        self.update_listing()
+    }
+
+    /// Bring the listing in line with the directory on disk: entries that are
+    /// gone are dropped and new ones sorted in, while what's cached about the rest,
+    /// the marks, the selection and the scroll stay as they are.
+    fn update_listing(&mut self) -> io::Result<()> {
+        // A directory that vanishes reloads into the error pane, and out of it once back
+        let read_dir = match fs::read_dir(&self.current_dir) {
+            Ok(read_dir) if self.load_error.is_none() => read_dir,
+            _ => return self.reload_keeping_selection(),
+        };
+        self.listing_modified = fs::metadata(&self.current_dir).and_then(|m| m.modified()).ok();
+        let mut found: HashSet<PathBuf> = read_dir.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+        let total = found.len();
+        if !self.show_hidden {
+            found.retain(|path| !path.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with('.')));
+        }
+        self.hidden_count = total - found.len();
+
+        let listed = self.entries.len() + self.collapsed_entries.len() + self.ignored_entries.len();
+        self.entries.retain(|path| found.contains(path));
+        self.collapsed_entries.retain(|path| found.contains(path));
+        self.ignored_entries.retain(|path| found.contains(path));
+        self.marked.retain(|path| path.parent() != Some(&self.current_dir) || found.contains(path));
+        let kept = self.entries.len() + self.collapsed_entries.len() + self.ignored_entries.len();
+        for path in self.entries.iter().chain(&self.collapsed_entries).chain(&self.ignored_entries) {
+            found.remove(path);
+        }
+        if found.is_empty() && kept == listed {
+            return Ok(());
+        }
+
+        let old_selected = self.selected;
+        let old_scroll = self.scroll_offset;
+        let selected = self.get_selected_path();
+        self.entries.extend(found);
+        self.sort_entries();
+        match selected.and_then(|path| self.entries.iter().position(|entry| *entry == path)) {
+            Some(idx) => self.selected = idx,
+            None => self.keep_selection_near(old_selected),
+        }
+        self.scroll_offset = old_scroll;
+        self.scroll_to_selected();
+
+        // New entries may be ignored, and both kinds of change show in git status
+        self.ignored_pending = self.hide_gitignored && self.git_root.is_some();
+        let check = self.ignored_pending.then(|| self.entries.iter().chain(&self.collapsed_entries).cloned().collect());
+        self.start_git_changes(check);
+        self.size_dirs_for_sort();
+        self.update_layout()
+    }
+
+    /// Read the current directory again, keeping the same entry selected, or
+    /// the one at its place if it's gone.
+    fn reload_keeping_selection(&mut self) -> io::Result<()> {
+        let old_selected = self.selected;
+        let old_scroll = self.scroll_offset;
+        let selected = self.get_selected_path();
+        self.load_entries()?;
+        match selected.and_then(|path| self.entries.iter().position(|entry| *entry == path)) {
+            Some(idx) => self.selected = idx,
+            None => self.keep_selection_near(old_selected),
+        }
+        self.scroll_offset = old_scroll;
+        self.scroll_to_selected();
+        Ok(())
+    }
+
+    /// Forget what's cached about the current directory and its entries (counts,
+    /// sizes, previews, git status) and list it again.
+    fn refresh(&mut self) -> io::Result<()> {
+        let mut paths = self.entries.clone();
+        paths.push(self.current_dir.clone());
+        if let Ok(mut cache) = self.preview_cache.lock() {
+            for path in &paths {
+                // A preview being loaded replaces itself once done
+                if !matches!(cache.peek(path), Some(PreviewState::Loading | PreviewState::Extracting { .. })) {
+                    cache.remove(path);
+                }
+            }
+        }
+        if let Ok(mut counts) = self.item_count_cache.lock() {
+            for path in &paths {
+                counts.remove(path);
+            }
+        }
+        for path in &paths {
+            self.dir_size_cache.remove(path);
+        }
+        self.preview_watch = None;
+        // Git status, project badges and media headers are read afresh by every load
+        self.reload_keeping_selection()?;
+        self.notify(Severity::Info, "Refreshed");
+        Ok(())
+    }
+
+    /// Drop the cached preview of the selected file once it changes on disk, so the
+    /// next draw loads it afresh. Checked with `auto_refresh`, like the listing; a
+    /// preview still loading is left to finish and compared again afterwards.
+    fn refresh_preview_if_changed(&mut self) {
+        if !self.settings.auto_refresh || !self.preview_mode {
+            return;
+        }
+        let Some(selected) = self.get_selected_path().filter(|path| path.is_file()) else {
+            self.preview_watch = None;
+            return;
+        };
+        let Ok(metadata) = fs::metadata(&selected) else {
+            return;
+        };
+        let modified = metadata.modified().ok();
+        let changed = match &self.preview_watch {
+            Some((path, old_modified, old_len)) if *path == selected => *old_modified != modified || *old_len != metadata.len(),
+            _ => false,
+        };
+        if changed {
+            let Ok(mut cache) = self.preview_cache.lock() else {
+                return;
+            };
+            if matches!(cache.peek(&selected), Some(PreviewState::Loading | PreviewState::Extracting { .. })) {
+                return;
+            }
+            cache.remove(&selected);
+        }
+        self.preview_watch = Some((selected, modified, metadata.len()));
+    }
+
+    /// Scroll the preview by a page or by `preview_scroll_amount` lines, stopping
+    /// once the last line of the file is visible.
+    fn scroll_preview(&mut self, down: bool, page: bool) -> io::Result<()> {
+        let Some(selected) = self.get_selected_path() else {
+            return Ok(());
+        };
+        let (_, height) = terminal::size()?;
+        let split_line = self.start_row + ((height - self.start_row) as f32 * (1.0 - self.preview_split_ratio)) as u16;
+        let preview_lines = (height - split_line - 3) as usize;
+        let scroll_amount = if page { preview_lines } else { self.settings.preview_scroll_amount };
+
+        let current = self.preview_scroll_map.get(&selected).copied().unwrap_or(0);
+
+        // Decoded previews (PDF text, plists, notebooks) are bounded by their own lines
+        let decoded = self.preview_cache.lock().ok().and_then(|mut cache| match cache.get(&selected) {
+            Some(PreviewState::Notebook { cells, .. }) => {
+                let (lines, cell_starts) = notebook_lines(cells);
+                Some((lines.len(), Some(cell_starts)))
+            }
+            Some(PreviewState::Loaded(lines)) => Some((lines.len(), None)),
+            _ => None,
+        });
+        let decoded = match self.unstaged_diff(&selected) {
+            Some(diff) => Some((diff.len(), None)),
+            None => decoded
+                .or_else(|| match self.load_data_preview(&selected) {
+                    true => self.preview_data.as_ref().map(|(_, _, _, lines)| (lines.len(), None)),
+                    false => None,
+                })
+                .or_else(|| self.table_rows(&selected).map(|rows| (rows.len(), None)))
+                .or_else(|| self.rendered_markdown(&selected).map(|lines| (lines.len(), None))),
+        };
+
+        // Notebooks step a cell at a time; paging still moves by the screenful
+        if let Some((line_count, Some(cell_starts))) = &decoded {
+            let max_scroll = line_count.saturating_sub(preview_lines);
+            let new_scroll = match (down, page) {
+                (true, true) => (current + preview_lines).min(max_scroll),
+                (false, true) => current.saturating_sub(preview_lines),
+                (true, false) => cell_starts.iter().copied().find(|&start| start > current).unwrap_or(max_scroll).min(max_scroll),
+                (false, false) => cell_starts.iter().copied().rev().find(|&start| start < current).unwrap_or(0),
+            };
+            self.preview_scroll_map.insert(selected, new_scroll);
+            return Ok(());
+        }
+
+        let new_scroll = if down {
+            if !selected.is_file() {
+                return Ok(());
+            }
+            // Get file line count to bound scroll
+            let line_count = if let Some((line_count, _)) = decoded {
+                line_count
+            } else {
+                let Ok(file) = fs::File::open(&selected) else {
+                    return Ok(());
+                };
+                use io::BufRead;
+                io::BufReader::new(file).lines().count()
+            };
+            (current + scroll_amount).min(line_count.saturating_sub(preview_lines))
+        } else {
+            current.saturating_sub(scroll_amount)
+        };
+        self.preview_scroll_map.insert(selected, new_scroll);
+        Ok(())
+    }
+
+    /// Whether a mode or pending key is active that Esc would leave.
+    fn has_active_mode(&self) -> bool {
+        self.history_mode
+            || self.grep_mode
+            || self.fuzzy_mode
+            || self.filter_mode
+            || self.path_edit_mode
+            || self.help_screen_mode
+            || self.messages_mode
+            || self.info_panel.is_some()
+            || self.ancestor_jump_pending
+            || self.path_jump_pending
+            || self.find_char_pending
+            || self.bookmark_add_pending
+            || self.bookmarks_mode
+            || self.open_with.is_some()
+            || !self.key_sequence.is_empty()
+    }
+
+    /// Gallery mode only applies to the grid; list mode and the filter keep their own layout.
+    fn gallery_active(&self) -> bool {
+        self.gallery_mode && !self.list_mode && !self.filter_mode && !self.history_mode && !self.grep_mode
+    }
+
+    /// Rows between the path bar and the entries: the column headers in list mode
+    /// with an info column.
+    fn header_rows(&self) -> usize {
+        if self.list_mode && self.list_info_mode > 0 && !self.history_mode && !self.grep_mode { 1 } else { 0 }
+    }
+
+    /// When launched near the bottom of the terminal, scroll the existing output up
+    /// so the path bar, headers and footer fit on screen and only the entry list
+    /// scrolls beneath them.
+    fn reserve_rows(&mut self, stdout: &mut io::Stdout, height: u16) -> io::Result<()> {
+        let wanted = if self.preview_mode || self.history_mode || self.grep_mode {
+            height as usize
+        } else {
+            let total_rows = self.listing_rows().len().max(1);
+            (total_rows * self.row_height() + self.header_rows() + 2).min(height as usize)
+        };
+        let available = height.saturating_sub(self.start_row) as usize;
+        // The tab bar sits on the row above start_row
+        let keep = self.tab_bar_rows();
+        if wanted > available && self.start_row > keep {
+            let shift = ((wanted - available) as u16).min(self.start_row - keep);
+            execute!(stdout, terminal::ScrollUp(shift))?;
+            self.start_row -= shift;
+            self.update_layout()?;
+        }
+        Ok(())
+    }
+
+    /// Rows above the path bar taken by the tab bar, shown once a second tab is open.
+    fn tab_bar_rows(&self) -> u16 {
+        if self.tabs.len() > 1 { 1 } else { 0 }
+    }
+
+    /// The tab bar: each tab's directory name, the active one highlighted.
+    fn draw_tab_bar(&self, stdout: &mut io::Stdout, width: u16) -> io::Result<()> {
+        if self.tab_bar_rows() == 0 {
+            return Ok(());
+        }
+        queue!(stdout, cursor::MoveTo(0, self.start_row - 1), terminal::Clear(ClearType::CurrentLine))?;
+        let mut used = 0;
+        for (i, tab) in self.tabs.iter().enumerate() {
+            let dir = if i == self.active_tab { &self.current_dir } else { &tab.dir };
+            let name = match dir.file_name() {
+                _ if env::var("HOME").is_ok_and(|home| dir.as_path() == Path::new(&home)) => "~".to_string(),
+                Some(name) => name.to_string_lossy().to_string(),
+                None => "/".to_string(),
+            };
+            let label = format!(" {} {} ", i + 1, Self::truncate_string_safe(&name, 20));
+            used += label.chars().count() + 1;
+            if used > width as usize {
+                break;
+            }
+            if i == self.active_tab {
+                queue!(
+                    stdout,
+                    crossterm::style::SetAttribute(crossterm::style::Attribute::Reverse),
+                    Print(label),
+                    crossterm::style::SetAttribute(crossterm::style::Attribute::Reset),
+                    Print(" ")
+                )?;
+            } else {
+                queue!(stdout, SetForegroundColor(Color::DarkGrey), Print(label), ResetColor, Print(" "))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The active tab as it is now, to restore when switching back to it.
+    fn tab_snapshot(&self) -> Tab {
+        Tab {
+            dir: self.current_dir.clone(),
+            selected: self.get_selected_path(),
+            scroll_offset: self.scroll_offset,
+            preview_mode: self.preview_mode,
+        }
+    }
+
+    /// Open a tab in the current directory, right after the active one.
+    fn new_tab(&mut self) {
+        if self.tabs.len() == 1 {
+            // Make room for the tab bar above the path bar
+            self.start_row += 1;
+        }
+        self.tabs[self.active_tab] = self.tab_snapshot();
+        self.tabs.insert(self.active_tab + 1, self.tab_snapshot());
+        self.active_tab += 1;
+        self.notify(Severity::Info, format!("Tab {} of {}", self.active_tab + 1, self.tabs.len()));
+    }
+
+    /// Close the active tab and show the one before it.
+    fn close_tab(&mut self) -> io::Result<()> {
+        if self.tabs.len() == 1 {
+            self.notify(Severity::Warning, "This is the only tab");
+            return Ok(());
+        }
+        self.tabs.remove(self.active_tab);
+        if self.tabs.len() == 1 {
+            self.start_row -= 1;
+        }
+        self.active_tab = self.active_tab.saturating_sub(1).min(self.tabs.len() - 1);
+        self.restore_tab()
+    }
+
+    /// Switch to the next or previous tab, wrapping around.
+    fn cycle_tab(&mut self, forward: bool) -> io::Result<()> {
+        if self.tabs.len() == 1 {
+            return Ok(());
+        }
+        self.tabs[self.active_tab] = self.tab_snapshot();
+        let count = self.tabs.len();
+        self.active_tab = if forward { (self.active_tab + 1) % count } else { (self.active_tab + count - 1) % count };
+        self.restore_tab()
+    }
+
+    /// Show the active tab as it was left. A directory removed meanwhile falls
+    /// back to its nearest remaining parent.
+    fn restore_tab(&mut self) -> io::Result<()> {
+        self.show_place(self.tabs[self.active_tab].clone())
+    }
+
+    /// Show the directory, selection and scroll saved in `tab`.
+    fn show_place(&mut self, tab: Tab) -> io::Result<()> {
+        let dir = tab.dir.ancestors().find(|dir| dir.is_dir()).map(Path::to_path_buf).unwrap_or(tab.dir);
+        self.current_dir = dir;
+        self.load_entries()?;
+        self.selected = tab.selected.and_then(|path| self.entries.iter().position(|p| *p == path)).unwrap_or(0);
+        self.scroll_offset = tab.scroll_offset;
+        self.preview_mode = tab.preview_mode;
+        self.update_layout()?;
+        self.scroll_to_selected();
+        Ok(())
+    }
+
+    /// Columns given to the active pane: all of them, or the left half in dual pane mode.
+    fn pane_width(&self, width: u16) -> u16 {
+        if self.other_pane.is_some() { width / 2 } else { width }
+    }
+
+    /// Turn dual pane mode on, with the other pane starting in the same
+    /// directory, or off, keeping the active pane.
+    fn toggle_dual_pane(&mut self) -> io::Result<()> {
+        if self.other_pane.take().is_none() {
+            self.other_pane = Some(self.tab_snapshot());
+            self.refresh_other_pane();
+        } else {
+            self.pane_sync = None;
+        }
+        self.update_layout()?;
+        self.scroll_to_selected();
+        Ok(())
+    }
+
+    /// Turn synchronized navigation of the two panes on or off.
+    fn toggle_pane_sync(&mut self) {
+        if self.other_pane.is_none() {
+            self.notify(Severity::Warning, "Dual pane mode is off");
+        } else if self.pane_sync.take().is_some() {
+            self.notify(Severity::Info, "Panes no longer synced");
+        } else {
+            self.pane_sync = Some(self.current_dir.clone());
+            self.notify(Severity::Info, "Panes synced: the other pane follows into same-named directories");
+        }
+    }
+
+    /// With synced panes, repeat the active pane's last move in the other one:
+    /// into the same-named subdirectories, or up as many levels.
+    fn follow_in_other_pane(&mut self) {
+        let Some(from) = self.pane_sync.as_mut().map(|from| std::mem::replace(from, self.current_dir.clone())) else { return };
+        let Some(pane) = self.other_pane.as_mut() else { return };
+        if let Ok(down) = self.current_dir.strip_prefix(&from) {
+            if down.as_os_str().is_empty() {
+                return;
+            }
+            let target = pane.dir.join(down);
+            if target.is_dir() {
+                pane.dir = target;
+                pane.selected = None;
+                pane.scroll_offset = 0;
+            } else {
+                let name = down.display().to_string();
+                self.notify(Severity::Warning, format!("The other pane has no {}", name));
+            }
+        } else if let Ok(up) = from.strip_prefix(&self.current_dir) {
+            for _ in up.components() {
+                let came_from = pane.dir.clone();
+                if !pane.dir.pop() {
+                    break;
+                }
+                pane.selected = Some(came_from);
+            }
+            pane.scroll_offset = 0;
+        }
+    }
+
+    /// Re-read the inactive pane's directory.
+    fn refresh_other_pane(&mut self) {
+        self.follow_in_other_pane();
+        let Some(pane) = &self.other_pane else { return };
+        let entries: Vec<PathBuf> = fs::read_dir(&pane.dir)
+            .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+            .unwrap_or_default();
+        let entries = entries
+            .into_iter()
+            .filter(|path| self.show_hidden || !path.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with('.')))
+            .collect();
+        self.other_pane_entries = self.sorted(entries);
+    }
+
+    /// Make the inactive pane the active one.
+    fn switch_pane(&mut self) -> io::Result<()> {
+        let Some(other) = self.other_pane.take() else { return Ok(()) };
+        self.other_pane = Some(self.tab_snapshot());
+        if self.pane_sync.is_some() {
+            // Trading places is not a move for the other pane to follow
+            self.pane_sync = Some(other.dir.clone());
+        }
+        self.show_place(other)?;
+        self.refresh_other_pane();
+        Ok(())
+    }
+
+    /// The inactive pane in the right half: its path, then its entries in one
+    /// dimmed column with its selection marked.
+    fn draw_other_pane(&self, stdout: &mut io::Stdout, width: u16, display_height: u16) -> io::Result<()> {
+        let Some(pane) = &self.other_pane else { return Ok(()) };
+        let left = self.pane_width(width);
+        let pane_width = (width - left) as usize;
+        let rows = (display_height as usize).saturating_sub(self.start_row as usize + 2);
+        // Synced panes mark the entry named like the active selection, if there is one
+        let synced = self.get_selected_path().filter(|_| self.pane_sync.is_some()).and_then(|active| {
+            self.other_pane_entries.iter().position(|path| path.file_name() == active.file_name())
+        });
+        let selected = synced.or_else(|| pane.selected.as_ref().and_then(|path| self.other_pane_entries.iter().position(|p| p == path)));
+        let offset = selected.map_or(0, |idx| (idx + 1).saturating_sub(rows.saturating_sub(1)));
+
+        queue!(
+            stdout,
+            cursor::MoveTo(left, self.start_row),
+            terminal::Clear(ClearType::UntilNewLine),
+            SetForegroundColor(Color::DarkGrey),
+            Print(Self::truncate_string_safe(
+                &format!("│ {}{}", self.tilde_path(&pane.dir), if self.pane_sync.is_some() { "  [synced]" } else { "" }),
+                pane_width
+            )),
+            ResetColor
+        )?;
+        for row in 0..rows {
+            let y = self.start_row + 1 + row as u16;
+            queue!(stdout, cursor::MoveTo(left, y), terminal::Clear(ClearType::UntilNewLine))?;
+            let Some(path) = self.other_pane_entries.get(offset + row) else {
+                queue!(stdout, SetForegroundColor(Color::DarkGrey), Print("│"), ResetColor)?;
+                continue;
+            };
+            let is_dir = path.is_dir();
+            let mut name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            if is_dir && self.show_dir_slash {
+                name.push('/');
+            }
+            let marker = if selected == Some(offset + row) { ">" } else { " " };
+            let color = if is_dir { Color::DarkBlue } else { Color::DarkGrey };
+            queue!(
+                stdout,
+                SetForegroundColor(Color::DarkGrey),
+                Print(format!("│{} ", marker)),
+                SetForegroundColor(color),
+                Print(Self::truncate_string_safe(&name, pane_width.saturating_sub(3))),
+                ResetColor
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Copy or move the selected or marked entries into the inactive pane's directory.
+    fn transfer_to_other_pane(&mut self, move_entries: bool) -> io::Result<()> {
+        let Some(dest_dir) = self.other_pane.as_ref().map(|pane| pane.dir.clone()) else {
+            self.notify(Severity::Warning, "Dual pane mode is off");
+            return Ok(());
+        };
+        let sources = self.action_targets();
+        if sources.is_empty() {
+            return Ok(());
+        }
+        if !move_entries {
+            self.copy_paths_into(&sources, &dest_dir)?;
+        } else {
+            let mut moves = Vec::new();
+            for src in sources {
+                let Some(name) = src.file_name() else { continue };
+                let dest = dest_dir.join(name);
+                if dest_dir.starts_with(&src) {
+                    self.notify(Severity::Error, format!("Can't move '{}' into itself", name.to_string_lossy()));
+                    continue;
+                }
+                if dest.exists() {
+                    self.notify(Severity::Error, format!("Can't move '{}': {} already exists", name.to_string_lossy(), dest.display()));
+                    continue;
+                }
+                match self.move_path(&src, &dest) {
+                    Ok(()) => moves.push(UndoAction::Move { src, dest }),
+                    Err(e) => {
+                        let command = ["mv".as_ref(), "--".as_ref(), src.as_os_str(), dest.as_os_str()];
+                        if self.retry_elevated(&format!("Moving '{}'", name.to_string_lossy()), &e, &command)? {
+                            moves.push(UndoAction::Move { src, dest });
+                        } else {
+                            self.notify(Severity::Error, format!("Error moving '{}': {}", name.to_string_lossy(), e));
+                        }
+                    }
+                }
+            }
+            let moved = moves.len();
+            if self.tags.follow_renames(&moves.iter().flat_map(UndoAction::moves).collect::<Vec<_>>()) {
+                self.save_tags();
+            }
+            if moved == 1 {
+                self.undo_stack.extend(moves);
+            } else if moved > 1 {
+                self.undo_stack.push(UndoAction::Batch(moves));
+            }
+            if moved > 0 {
+                self.notify(Severity::Success, format!("Moved {} {} to {}", moved, if moved == 1 { "item" } else { "items" }, self.tilde_path(&dest_dir)));
+                self.redo_stack.clear();
+            }
+            self.marked.clear();
+            let old = self.selected;
+            self.load_entries()?;
+            self.keep_selection_near(old);
+        }
+        self.refresh_other_pane();
+        Ok(())
+    }
+
+    /// Terminal rows taken by one row of entries.
+    fn row_height(&self) -> usize {
+        if self.gallery_active() { GALLERY_ROW_HEIGHT } else { 1 }
+    }
+
+    /// Move the selection to the next or previous image in the listing, wrapping
+    /// around. Returns false if there is no other image.
+    fn select_adjacent_image(&mut self, forward: bool) -> bool {
+        let len = self.entries.len();
+        for step in 1..len {
+            let idx = if forward { (self.selected + step) % len } else { (self.selected + len - step) % len };
+            if is_image(&self.entries[idx]) {
+                self.selected = idx;
+                self.scroll_to_selected();
+                return true;
+            }
+        }
+        false
+    }
+
+    fn select_first(&mut self) {
+        self.selected = 0;
+        self.scroll_to_selected();
+    }
+
+    fn select_last(&mut self) {
+        self.selected = self.entries.len().saturating_sub(1);
+        self.scroll_to_selected();
+    }
+
+    /// Adjusts scroll_offset so the selected row is on screen, with `scrolloff`
+    /// rows of context above and below it where the list allows (works for
+    /// history, list, grid and gallery mode).
+    fn scroll_to_selected(&mut self) {
+        let Ok((_, height)) = terminal::size() else { return };
+        let available = (height as usize).saturating_sub(self.start_row as usize);
+        // Scrolling up, a group header right above the selection comes into view with it
+        let (current_row, top_row, total_rows, max_display_rows) = if self.history_mode {
+            (self.selected, self.selected, self.history_filtered.len(), available.saturating_sub(4))
+        } else if self.grep_mode {
+            (self.selected, self.selected, self.grep_match_count(), available.saturating_sub(4))
+        } else {
+            let rows = self.listing_rows();
+            let row = self.selected_cell(&rows).map_or(0, |(row, _)| row);
+            let top_row = if row > 0 && matches!(rows[row - 1], ListingRow::Group(..)) { row - 1 } else { row };
+            (row, top_row, rows.len(), available.saturating_sub(2 + self.header_rows()) / self.row_height())
+        };
+        let max_display_rows = max_display_rows.max(1);
+        // Never more than half the view, or the selection could not move at all
+        let margin = self.settings.scrolloff.min((max_display_rows - 1) / 2);
+
+        if top_row < self.scroll_offset + margin {
+            self.scroll_offset = top_row.saturating_sub(margin);
+        } else if current_row + margin >= self.scroll_offset + max_display_rows {
+            let last_offset = total_rows.saturating_sub(max_display_rows);
+            self.scroll_offset = (current_row + margin + 1 - max_display_rows).min(last_offset.max(self.scroll_offset));
+        }
+    }
+
+    fn open_selected(&mut self) -> io::Result<bool> {
+        if self.entries.is_empty() {
+            return Ok(false);
+        }
+
+        let selected_path = &self.entries[self.selected];
+        if selected_path.is_dir() {
+            // Add the selected folder to breadcrumbs before navigating
+            if let Some(folder_name) = selected_path.file_name().and_then(|n| n.to_str()) {
+                self.breadcrumbs.push(folder_name.to_string());
+            }
+            self.current_dir = selected_path.clone();
+
+            // A directory that can't be read shows the error pane instead of its entries
+            self.load_entries()?;
+            self.directory_entered();
+            Ok(false)
+        } else {
+            // For a file, we return true, signaling main to write the path and exit.
+            Ok(true)
+        }
+    }
+
+    fn go_back(&mut self) -> io::Result<()> {
+        if self.load_error.is_some() {
+            return self.leave_unreadable();
+        }
+        if let Some(parent) = self.current_dir.parent() {
+            // Pop the last breadcrumb when going back
+            self.breadcrumbs.pop();
+            self.current_dir = parent.to_path_buf();
+            self.load_entries()?;
+            self.directory_entered();
+        }
+        Ok(())
+    }
+
+    /// Climbs from a directory that failed to load to the nearest ancestor that can
+    /// be listed, falling back to home when none can.
+    fn leave_unreadable(&mut self) -> io::Result<()> {
+        let levels = self.current_dir.ancestors().skip(1).position(|dir| fs::read_dir(dir).is_ok());
+        match levels {
+            Some(levels) => self.go_up_levels(levels + 1),
+            None => self.go_home(),
+        }
+    }
+
+    /// Where leaving the failed directory would land, for the error pane.
+    fn readable_ancestor(&self) -> Option<&Path> {
+        self.current_dir.ancestors().skip(1).find(|dir| fs::read_dir(dir).is_ok())
+    }
+
+    /// Lists the current directory again after it failed to load.
+    fn retry_load(&mut self) -> io::Result<()> {
+        self.load_entries()?;
+        match &self.load_error {
+            Some(e) => self.notify(Severity::Warning, format!("Still can't open: {}", e)),
+            None => self.directory_entered(),
+        }
+        Ok(())
+    }
+
+    /// Describes why the current directory failed to load, in the terms its fix needs.
+    fn load_error_reason(&self) -> Option<String> {
+        let e = self.load_error.as_ref()?;
+        Some(match e.kind() {
+            io::ErrorKind::PermissionDenied if cfg!(target_os = "macos") => {
+                "Permission denied. Grant Full Disk Access to your terminal in System Settings > Privacy & Security.".to_string()
+            }
+            io::ErrorKind::PermissionDenied => "Permission denied. You don't have read access to this directory.".to_string(),
+            io::ErrorKind::NotFound => "This directory no longer exists. It may have been removed, or its drive unmounted.".to_string(),
+            _ => format!("Cannot access: {}", e),
+        })
+    }
+
+    fn go_up_levels(&mut self, levels: usize) -> io::Result<()> {
+        if levels == 0 {
+            return Ok(());
+        }
+
+        // Clamp to the filesystem root when asked to go higher than possible
+        if let Some(target) = self.current_dir.ancestors().take(levels + 1).last() {
+            if target == self.current_dir {
+                return Ok(());
+            }
+            self.current_dir = target.to_path_buf();
+            let remaining = self.breadcrumbs.len().saturating_sub(levels);
+            self.breadcrumbs.truncate(remaining);
+            self.load_entries()?;
+            self.directory_entered();
+        }
+        Ok(())
+    }
+
+    fn go_home(&mut self) -> io::Result<()> {
+        if let Some(home) = env::var_os("HOME") {
+            self.current_dir = PathBuf::from(home);
+            self.breadcrumbs.clear();
+            self.load_entries()?;
+            self.directory_entered();
+        }
+        Ok(())
+    }
+
+    fn start_path_edit(&mut self) {
+        self.path_edit_mode = true;
+        let mut path = self.format_path_display();
+        if !path.ends_with('/') {
+            path.push('/');
+        }
+        self.path_editor.start(&path);
+    }
+
+    /// Replaces a leading shortcut name (`dl` or `dl/sub`) with its configured path.
+    fn expand_shortcut(&self, input: &str) -> Option<String> {
+        let (name, rest) = match input.find('/') {
+            Some(pos) => (&input[..pos], &input[pos..]),
+            None => (input, ""),
+        };
+        self.shortcuts.get(name).map(|path| format!("{}{}", path, rest))
+    }
+
+    /// The command of the longest `[openers]` pattern `path`'s name matches.
+    fn opener_for(&self, path: &Path) -> Option<String> {
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        self.openers.iter()
+            .map(|(pattern, command)| match pattern.contains(['*', '?']) {
+                true => (pattern.clone(), command),
+                false => (format!("*.{}", pattern.trim_start_matches('.')), command),
+            })
+            .filter(|(pattern, _)| wildcard_match(pattern, &name))
+            .max_by_key(|(pattern, _)| pattern.len())
+            .map(|(_, command)| command.clone())
+    }
+
+    /// Before Enter opens a huge or binary file in the editor, ask whether to
+    /// rather page through it or look at a hex dump. Returns whether to go on
+    /// and edit it; the other choices are carried out here.
+    fn confirm_edit(&mut self, path: &Path) -> io::Result<bool> {
+        if self.settings.editor_confirm_mb == 0 || self.opener_for(path).is_some() {
+            return Ok(true);
+        }
+        let size = fs::metadata(path).map_or(0, |m| m.len());
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let reason = if size >= self.settings.editor_confirm_mb.saturating_mul(1024 * 1024) {
+            format!("'{}' is {}", name, self.locale.format_size(size, &["B", "KB", "MB", "GB", "TB"]))
+        } else if is_binary_file(path) {
+            format!("'{}' looks binary", name)
+        } else {
+            return Ok(true);
+        };
+        let question = format!("{}. e edit anyway, p pager, h hex dump, Esc cancel: ", reason);
+        let Some(answer) = self.prompt("confirm", &question, "", false)? else {
+            return Ok(false);
+        };
+        match answer.trim().to_lowercase().as_str() {
+            "e" => return Ok(true),
+            "p" => self.open_in_pager()?,
+            "h" => {
+                // xxd where it's installed, hexdump on systems without vim
+                let dump = "{ xxd -- \"$1\" 2>/dev/null || hexdump -C -- \"$1\"; } | ${PAGER:-less}";
+                if let Err(e) = run_suspended(std::process::Command::new("sh").arg("-c").arg(dump).arg("ils").arg(path)) {
+                    self.notify(Severity::Error, format!("Couldn't run sh: {}", e));
+                }
+            }
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    /// The command Enter opens `path` with: the longest `[openers]` pattern its
+    /// name matches, or `$EDITOR` when none does.
+    fn open_command(&self, path: &Path) -> std::process::Command {
+        let line = self.opener_for(path).unwrap_or_else(|| env::var("EDITOR").unwrap_or_else(|_| "vim".to_string()));
+        let mut words = line.split_whitespace();
+        let mut command = std::process::Command::new(words.next().unwrap_or("vim"));
+        command.args(words).arg(path);
+        command
+    }
+
+    /// Commands the "open with" chooser offers for `path`: the `[openers]` that
+    /// match it first, then the editor, pager and the platform's own launchers,
+    /// then the remaining openers. Each comes with where it came from.
+    fn open_with_choices(&self, path: &Path) -> Vec<(String, String)> {
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let mut matching: Vec<(&String, &String)> = self.openers.iter()
+            .filter(|(pattern, _)| match pattern.contains(['*', '?']) {
+                true => wildcard_match(pattern, &name),
+                false => wildcard_match(&format!("*.{}", pattern.trim_start_matches('.')), &name),
+            })
+            .collect();
+        matching.sort_by_key(|(pattern, _)| std::cmp::Reverse(pattern.len()));
+
+        let mut choices: Vec<(String, String)> = matching.iter()
+            .map(|(pattern, command)| (command.to_string(), format!("[openers] {}", pattern)))
+            .collect();
+        let editor = env::var("EDITOR").ok().filter(|editor| !editor.trim().is_empty()).unwrap_or_else(|| "vim".to_string());
+        choices.push((editor, "$EDITOR".to_string()));
+        if path.is_file() {
+            let pager = env::var("PAGER").ok().filter(|pager| !pager.trim().is_empty()).unwrap_or_else(|| "less".to_string());
+            choices.push((pager, "$PAGER".to_string()));
+        }
+        if !self.settings.ide_command.trim().is_empty() {
+            choices.push((self.settings.ide_command.clone(), "ide_command".to_string()));
+        }
+        #[cfg(target_os = "macos")]
+        {
+            choices.push(("open".to_string(), "Default app".to_string()));
+            choices.push(("open -a TextEdit".to_string(), "TextEdit".to_string()));
+            choices.push(("open -R".to_string(), "Reveal in Finder".to_string()));
+        }
+        #[cfg(not(target_os = "macos"))]
+        choices.push(("xdg-open".to_string(), "Default app".to_string()));
+        for (pattern, command) in &self.openers {
+            choices.push((command.clone(), format!("[openers] {}", pattern)));
+        }
+
+        let mut seen = HashSet::new();
+        choices.retain(|(command, _)| seen.insert(command.split_whitespace().collect::<Vec<_>>().join(" ")));
+        choices
+    }
+
+    /// Open the "open with" chooser for the selected entry.
+    fn show_open_with(&mut self) {
+        let Some(path) = self.get_selected_path() else {
+            self.notify(Severity::Warning, "Select something to open");
+            return;
+        };
+        let choices = self.open_with_choices(&path);
+        self.open_with = Some((path, choices));
+        self.open_with_selected = 0;
+    }
+
+    /// Run the chooser's row `index`, or ask for a command on the row past the
+    /// last, with the entry as its last argument.
+    fn run_open_with(&mut self, index: usize) -> io::Result<()> {
+        let Some((path, choices)) = self.open_with.take() else {
+            return Ok(());
+        };
+        let line = match choices.get(index) {
+            Some((command, _)) => command.clone(),
+            None => match self.prompt("open_with", "Open with: ", "", false)? {
+                Some(line) if !line.trim().is_empty() => line,
+                _ => return Ok(()),
+            },
+        };
+        let mut words = line.split_whitespace();
+        let Some(program) = words.next() else {
+            return Ok(());
+        };
+
+        let status = run_suspended(std::process::Command::new(program).args(words).arg(&path).current_dir(&self.current_dir));
+
+        match status {
+            Ok(status) if !status.success() => self.notify(Severity::Error, format!("{} exited with {}", program, status)),
+            Ok(_) => {}
+            Err(e) => self.notify(Severity::Error, format!("Couldn't run '{}': {}", program, e)),
+        }
+        Ok(())
+    }
+
+    /// Resolves user-typed path input against the current directory, expanding
+    /// `~` and environment variables first.
+    fn resolve_input_path(&self, input: &str) -> PathBuf {
+        self.current_dir.join(expand_path_vars(input))
+    }
+
+    /// Completes the last component of `input` against the filesystem, extending it
+    /// to the longest common prefix of the candidates (and adding `/` for a unique directory).
+    fn complete_path_input(&self, input: &str) -> Option<String> {
+        let (dir_part, partial) = match input.rfind('/') {
+            Some(pos) => (&input[..=pos], &input[pos + 1..]),
+            None => ("", input),
+        };
+        let search_dir = if dir_part.is_empty() {
+            self.current_dir.clone()
+        } else {
+            self.resolve_input_path(dir_part)
+        };
+
+        let candidates: Vec<(String, bool)> = fs::read_dir(&search_dir)
+            .ok()?
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let name = e.file_name().to_str()?.to_string();
+                let hidden_ok = self.show_hidden || partial.starts_with('.') || !name.starts_with('.');
+                if name.starts_with(partial) && hidden_ok {
+                    Some((name, e.path().is_dir()))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let first = &candidates.first()?.0;
+        let mut common = first.clone();
+        for (name, _) in &candidates[1..] {
+            while !name.starts_with(common.as_str()) {
+                common.pop();
+            }
+        }
+
+        let mut completed = format!("{}{}", dir_part, common);
+        if candidates.len() == 1 && candidates[0].1 {
+            completed.push('/');
+        }
+        Some(completed)
+    }
+
+    /// Navigates to `target`: directories are entered, files reveal their parent with
+    /// the file selected.
+    fn go_to_path(&mut self, target: PathBuf) -> io::Result<()> {
+        let target = fs::canonicalize(&target).unwrap_or(target);
+        let (dir, select) = if target.is_dir() {
+            (target, None)
+        } else if target.exists() {
+            match target.parent() {
+                Some(parent) => (parent.to_path_buf(), Some(target.clone())),
+                None => return Ok(()),
+            }
+        } else {
+            self.notify(Severity::Error, format!("No such file or directory: {}", target.display()));
+            return Ok(());
+        };
+
+        self.current_dir = dir;
+        self.load_entries()?;
+        self.breadcrumbs.clear();
+        if let Some(path) = select {
+            if let Some(idx) = self.entries.iter().position(|p| p == &path) {
+                self.selected = idx;
+            }
+        }
+        self.directory_entered();
+        Ok(())
+    }
+
+    /// Bookkeeping after moving to another directory.
+    fn directory_entered(&mut self) {
+        if self.load_error.is_some() {
+            return;
+        }
+        self.record_directory_visit();
+        self.select_on_enter();
+        self.preview_readme();
+    }
+
+    /// Place the selection in a newly entered directory as `enter_selects` says.
+    /// A selection already made (revealing a file) is left alone.
+    fn select_on_enter(&mut self) {
+        if self.selected != 0 {
+            return;
+        }
+        let name = |path: &PathBuf| path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let found = match self.settings.enter_selects.as_str() {
+            "first" => None,
+            "first_file" => self.entries.iter().position(|path| !path.is_dir()),
+            "last" => self.last_selected.get(&self.current_dir).and_then(|last| self.entries.iter().position(|path| path == last)),
+            patterns => patterns
+                .split(',')
+                .map(str::trim)
+                .filter(|pattern| !pattern.is_empty())
+                .find_map(|pattern| self.entries.iter().position(|path| wildcard_match(pattern, &name(path)))),
+        };
+        if let Some(idx) = found {
+            self.selected = idx;
+            self.scroll_to_selected();
+        }
+    }
+
+    fn record_directory_visit(&mut self) {
+        self.dir_history.record_visit(&self.current_dir, self.settings.history_max_storage);
+        let _ = self.dir_history.save();
+    }
+
+    /// With `preview_readme` on, select the directory's README and show it in the
+    /// preview pane. A pane opened this way closes again where there is no README;
+    /// a selection already made (revealing a file) is left alone.
+    fn preview_readme(&mut self) {
+        if !self.settings.preview_readme || self.selected != 0 {
+            return;
+        }
+        let readme = self.entries.iter().position(|path| {
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("").to_lowercase();
+            matches!(name.as_str(), "readme.md" | "readme.txt" | "readme") && path.is_file()
+        });
+        match readme {
+            Some(idx) => {
+                self.selected = idx;
+                self.scroll_to_selected();
+                if !self.preview_mode {
+                    self.preview_mode = true;
+                    self.readme_preview_opened = true;
+                }
+            }
+            None if self.readme_preview_opened => {
+                self.preview_mode = false;
+                self.readme_preview_opened = false;
+            }
+            None => {}
+        }
+    }
 
-                            if is_dir {
-                                dirs += 1;
-                                // Check if size is cached, otherwise calculate and cache it
-                                if let Some(&cached_size) = self.dir_size_cache.get(&path) {
-                                    total_size += cached_size;
-                                } else {
-                                    // Calculate in preview without blocking
-                                    has_uncached_dirs = true;
-                                }
-                                items.push((name, true));
-                            } else {
-                                files += 1;
-                                if let Ok(metadata) = entry.metadata() {
-                                    total_size += metadata.len();
-                                }
-                                items.push((name, false));
-                            }
-                        }
+    fn get_current_dir(&self) -> &PathBuf {
+        &self.current_dir
+    }
 
-                        // Sort: directories first, then files
-                        items.sort_by(|a, b| {
-                            if a.1 == b.1 {
-                                a.0.cmp(&b.0)
-                            } else {
-                                b.1.cmp(&a.1)
-                            }
-                        });
+    fn get_selected_path(&self) -> Option<PathBuf> {
+        self.entries.get(self.selected).cloned()
+    }
 
-                        // Display stats
-                        queue!(stdout, cursor::MoveTo(0, split_line + 1))?;
-                        queue!(
-                            stdout,
-                            SetForegroundColor(Color::Cyan),
-                            Print(format!("[DIR] {} items ({} dirs, {} files)", dirs + files, dirs, files)),
-                            ResetColor
-                        )?;
+    /// The exit described by a quit_action-style setting. Unknown values
+    /// fall back to cd so a typo never leaves the user stuck.
+    fn exit_action(&self, action: &str) -> ExitAction {
+        let dir = self.get_current_dir().clone();
+        match action {
+            "none" => ExitAction::None,
+            "finder" => ExitAction::OpenInFinder(dir),
+            "print" => ExitAction::PrintPath(self.get_selected_path().unwrap_or(dir)),
+            _ => match action.strip_prefix('!') {
+                Some(command) => ExitAction::Run(command.to_string(), dir),
+                None => ExitAction::Cd(dir),
+            },
+        }
+    }
 
-                        // Display size (only files, subdirectories would require recursive calculation)
-                        let size_str = if total_size < 1024 {
-                            format!("{} B", total_size)
-                        } else if total_size < 1024 * 1024 {
-                            format!("{:.1} KB", total_size as f64 / 1024.0)
-                        } else if total_size < 1024 * 1024 * 1024 {
-                            format!("{:.1} MB", total_size as f64 / (1024.0 * 1024.0))
-                        } else {
-                            format!("{:.1} GB", total_size as f64 / (1024.0 * 1024.0 * 1024.0))
-                        };
+    fn go_to_next_sibling(&mut self) -> io::Result<()> {
+        // Go up to parent, then navigate to next sibling directory
+        if let Some(parent) = self.current_dir.parent() {
+            let current_name = self.current_dir.file_name();
 
-                        let size_display = if has_uncached_dirs {
-                            format!("{} (files only, dirs not calculated)", size_str)
-                        } else {
-                            size_str
-                        };
+            // Read parent directory
+            let mut siblings: Vec<PathBuf> = fs::read_dir(parent)?
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.is_dir())
+                .collect();
 
-                        queue!(stdout, cursor::MoveTo(0, split_line + 2))?;
-                        queue!(
-                            stdout,
-                            SetForegroundColor(Color::DarkGrey),
-                            Print(format!("Size: {}", size_display)),
-                            ResetColor
-                        )?;
+            // If there are no siblings or only one (current), do nothing
+            if siblings.len() <= 1 {
+                return Ok(());
+            }
 
-                        // Display first few items
-                        for (i, (name, is_dir)) in items.iter().take(preview_lines.saturating_sub(3)).enumerate() {
-                            queue!(stdout, cursor::MoveTo(0, split_line + 4 + i as u16))?;
-                            if *is_dir {
-                                queue!(
-                                    stdout,
-                                    SetForegroundColor(Color::Blue),
-                                    Print(format!("  {}/", name)),
-                                    ResetColor
-                                )?;
-                            } else {
-                                queue!(stdout, Print(format!("  {}", name)))?;
-                            }
-                        }
-                    }
-                } else if selected.is_file() {
-                    let preview_lines = (height - split_line - 3) as usize;
-                    let preview_width = width as u32;
-                    let preview_height = preview_lines as u32;
+            // Sort siblings
+            siblings.sort();
 
-                    // Check file extension for special handling
-                    let extension = selected.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+            // Find current directory in siblings
+            if let Some(current_idx) = siblings.iter().position(|p| p.file_name() == current_name) {
+                // Go to next sibling (wrap around)
+                let next_idx = (current_idx + 1) % siblings.len();
+                self.current_dir = siblings[next_idx].clone();
+                self.breadcrumbs.pop();
+                if let Some(name) = self.current_dir.file_name().and_then(|n| n.to_str()) {
+                    self.breadcrumbs.push(name.to_string());
+                }
+                self.load_entries()?;
+                self.directory_entered();
+            }
+        }
+        Ok(())
+    }
 
-                    if matches!(extension.as_str(), "png" | "jpg" | "jpeg" | "gif" | "bmp" | "webp") {
-                        // Image preview - render directly (can't cache)
-                        // Move cursor to preview area and flush before viuer renders
-                        queue!(stdout, cursor::MoveTo(0, split_line + 1))?;
-                        stdout.flush()?;
-
-                        let conf = ViuerConfig {
-                            transparent: true,
-                            absolute_offset: false,
-                            x: 0,
-                            y: 0,
-                            width: Some(preview_width),
-                            height: Some(preview_height),
-                            ..Default::default()
-                        };
+    fn go_to_prev_sibling(&mut self) -> io::Result<()> {
+        // Go up to parent, then navigate to previous sibling directory
+        if let Some(parent) = self.current_dir.parent() {
+            let current_name = self.current_dir.file_name();
 
-                        if let Err(_) = print_from_file(&selected, &conf) {
-                            queue!(stdout, cursor::MoveTo(0, split_line + 1))?;
-                            queue!(stdout, Print("(cannot preview image)"))?;
-                        }
-                    } else if extension == "pdf" {
-                        // PDF preview - use cache with background loading
-                        let cache_state = if let Ok(cache_lock) = self.preview_cache.lock() {
-                            cache_lock.get(&selected).cloned()
-                        } else {
-                            None
-                        };
+            // Read parent directory
+            let mut siblings: Vec<PathBuf> = fs::read_dir(parent)?
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.is_dir())
+                .collect();
 
-                        match cache_state {
-                            Some(PreviewState::Loaded(lines)) => {
-                                let scroll_pos = self.preview_scroll_map.get(&selected).copied().unwrap_or(0);
-                                let display_lines: Vec<&String> = lines.iter()
-                                    .skip(scroll_pos)
-                                    .take(preview_lines)
-                                    .collect();
+            // If there are no siblings or only one (current), do nothing
+            if siblings.len() <= 1 {
+                return Ok(());
+            }
 
-                                for (i, line) in display_lines.iter().enumerate() {
-                                    queue!(stdout, cursor::MoveTo(0, split_line + 1 + i as u16))?;
+            // Sort siblings
+            siblings.sort();
 
-                                    if self.show_line_numbers {
-                                        let line_num = scroll_pos + i + 1;
-                                        let line_color = self.color_config.parse_line_number_fg()
-                                            .unwrap_or(Color::DarkGrey);
-                                        queue!(
-                                            stdout,
-                                            SetForegroundColor(line_color),
-                                            Print(format!("{:4} │ ", line_num)),
-                                            ResetColor
-                                        )?;
-                                    }
+            // Find current directory in siblings
+            if let Some(current_idx) = siblings.iter().position(|p| p.file_name() == current_name) {
+                // Go to previous sibling (wrap around)
+                let prev_idx = if current_idx == 0 {
+                    siblings.len() - 1
+                } else {
+                    current_idx - 1
+                };
+                self.current_dir = siblings[prev_idx].clone();
+                self.breadcrumbs.pop();
+                if let Some(name) = self.current_dir.file_name().and_then(|n| n.to_str()) {
+                    self.breadcrumbs.push(name.to_string());
+                }
+                self.load_entries()?;
+                self.directory_entered();
+            }
+        }
+        Ok(())
+    }
 
-                                    queue!(stdout, Print(line.as_str()))?;
-                                }
-                            }
-                            Some(PreviewState::Loading) => {
-                                queue!(stdout, cursor::MoveTo(0, split_line + 1))?;
-                                queue!(stdout, Print("Loading PDF..."))?;
-                            }
-                            Some(PreviewState::Error(msg)) => {
-                                queue!(stdout, cursor::MoveTo(0, split_line + 1))?;
-                                queue!(stdout, Print(format!("({})", msg)))?;
-                            }
-                            None | Some(PreviewState::NotLoaded) => {
-                                // Start loading in background
-                                self.start_preview_load(selected.clone());
-                                queue!(stdout, cursor::MoveTo(0, split_line + 1))?;
-                                queue!(stdout, Print("Loading PDF..."))?;
-                            }
-                        }
-                    } else {
-                        // Text file preview with syntax highlighting
-                        if let Ok(file) = fs::File::open(&selected) {
-                            use io::BufRead;
-                            let reader = io::BufReader::new(file);
-                            let scroll_pos = self.preview_scroll_map.get(&selected).copied().unwrap_or(0);
+    fn toggle_mark(&mut self) {
+        if let Some(path) = self.get_selected_path() {
+            if let Some(pos) = self.marked.iter().position(|p| p == &path) {
+                self.marked.remove(pos);
+            } else {
+                self.marked.push(path);
+            }
+        }
+    }
 
-                            // Lazy-load syntax highlighting on first use
-                            self.ensure_syntax_loaded();
+    /// What copy, trash and delete act on: the marked entries that still
+    /// exist, or else the selected one.
+    fn action_targets(&mut self) -> Vec<PathBuf> {
+        self.marked.retain(|path| path.symlink_metadata().is_ok());
+        if self.marked.is_empty() {
+            self.get_selected_path().into_iter().collect()
+        } else {
+            self.marked.clone()
+        }
+    }
 
-                            // Try to detect syntax
-                            let syntax = self.syntax_set.as_ref().unwrap()
-                                .find_syntax_for_file(&selected)
-                                .ok()
-                                .flatten()
-                                .unwrap_or_else(|| self.syntax_set.as_ref().unwrap().find_syntax_plain_text());
+    fn toggle_copy_selection(&mut self) {
+        if !self.marked.is_empty() {
+            // Copy the marked set as a whole
+            self.clipboard_selection = std::mem::take(&mut self.marked);
+            return;
+        }
+        if let Some(path) = self.get_selected_path() {
+            // Toggle the item in the clipboard_selection
+            if let Some(pos) = self.clipboard_selection.iter().position(|p| p == &path) {
+                // Already in selection, remove it
+                self.clipboard_selection.remove(pos);
+            } else {
+                // Not in selection, add it
+                self.clipboard_selection.push(path);
+            }
+        }
+    }
 
-                            let theme = &self.theme_set.as_ref().unwrap().themes["base16-ocean.dark"];
-                            let mut highlighter = HighlightLines::new(syntax, theme);
+    fn clear_copy_selection(&mut self) {
+        self.clipboard_selection.clear();
+        self.marked.clear();
+    }
 
-                            // Only read the lines we need
-                            let lines_to_display: Vec<String> = reader
-                                .lines()
-                                .skip(scroll_pos)
-                                .take(preview_lines)
-                                .filter_map(|l| l.ok())
-                                .collect();
+    fn paste_from_clipboard(&mut self) -> io::Result<()> {
+        if self.clipboard_selection.is_empty() {
+            return Ok(());
+        }
+        let sources = self.clipboard_selection.clone();
+        let dir = self.current_dir.clone();
+        self.copy_paths_into(&sources, &dir)?;
+        self.load_entries()?;
+        Ok(())
+    }
 
-                            for (i, line) in lines_to_display.iter().enumerate() {
-                                queue!(stdout, cursor::MoveTo(0, split_line + 1 + i as u16))?;
+    /// Copy `sources` into `dir`, numbering names that already exist there, as
+    /// one undoable step.
+    fn copy_paths_into(&mut self, sources: &[PathBuf], dir: &Path) -> io::Result<()> {
+        // Set up progress tracking
+        let started = Instant::now();
+        self.copy_in_progress = true;
+        self.copy_progress_total = sources.len();
+        let mut copies = Vec::new();
 
-                                // Print line number if enabled
-                                if self.show_line_numbers {
-                                    let line_num = scroll_pos + i + 1;
-                                    let line_color = self.color_config.parse_line_number_fg()
-                                        .unwrap_or(Color::DarkGrey);
-                                    queue!(
-                                        stdout,
-                                        SetForegroundColor(line_color),
-                                        Print(format!("{:4} │ ", line_num)),
-                                        ResetColor
-                                    )?;
-                                }
+        for (idx, src) in sources.iter().enumerate() {
+            self.copy_progress_current = idx + 1;
+
+            if !src.exists() {
+                continue; // Skip files that no longer exist
+            }
 
-                                // Highlight the line
-                                let ranges = highlighter.highlight_line(line, self.syntax_set.as_ref().unwrap()).unwrap_or_default();
-                                let escaped = as_24_bit_terminal_escaped(&ranges[..], false);
+            let file_name = src.file_name().unwrap();
+            let mut dest = dir.join(file_name);
 
-                                queue!(stdout, Print(escaped), ResetColor)?;
-                            }
-                        } else {
-                            queue!(stdout, cursor::MoveTo(0, split_line + 1))?;
-                            queue!(stdout, Print("(binary file or cannot read)"))?;
-                        }
-                    }
-                }
+            // Handle name conflicts
+            let mut counter = 1;
+            while dest.exists() {
+                let stem = src.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+                let ext = src.extension().and_then(|s| s.to_str()).unwrap_or("");
+                let new_name = if ext.is_empty() {
+                    format!("{} ({})", stem, counter)
+                } else {
+                    format!("{} ({}).{}", stem, counter, ext)
+                };
+                dest = dir.join(new_name);
+                counter += 1;
             }
-        }
 
-        // 4. Draw footer with filename if in preview mode
-        if self.preview_mode {
-            if let Some(selected) = self.get_selected_path() {
-                if selected.is_file() {
-                    queue!(stdout, cursor::MoveTo(0, height.saturating_sub(1)))?;
-                    queue!(
-                        stdout,
-                        ResetColor,
-                        SetForegroundColor(Color::DarkGrey),
-                        Print(format!("{}", selected.file_name().and_then(|n| n.to_str()).unwrap_or(""))),
-                        ResetColor
-                    )?;
+            // Redraw to show progress
+            self.draw()?;
+
+            // Copy file or directory recursively
+            let result = if src.is_dir() {
+                self.copy_dir_recursive(src, &dest)
+            } else {
+                fs::copy(src, &dest).map(|_| ())
+            };
+            if let Err(e) = result {
+                let command = ["cp".as_ref(), "-R".as_ref(), "--".as_ref(), src.as_os_str(), dest.as_os_str()];
+                if !self.retry_elevated(&format!("Copying '{}'", file_name.to_string_lossy()), &e, &command)? {
+                    self.notify(Severity::Error, format!("Error copying '{}': {}", file_name.to_string_lossy(), e));
+                    continue;
                 }
             }
+            copies.push(UndoAction::Copy {
+                src: src.clone(),
+                dest: dest.clone()
+            });
         }
-
-        // 5. Draw fuzzy search bar if in fuzzy mode
-        if self.fuzzy_mode {
-            queue!(stdout, cursor::MoveTo(0, height.saturating_sub(1)))?;
-            queue!(
-                stdout,
-                ResetColor,
-                SetForegroundColor(Color::Yellow),
-                Print(format!("Find: {}_", self.fuzzy_query)),
-                ResetColor
-            )?;
+        let copied = copies.len();
+        if copied == 1 {
+            self.undo_stack.extend(copies);
+        } else if copied > 1 {
+            self.undo_stack.push(UndoAction::Batch(copies));
         }
-
-        // Display copy/paste status bar if there are items selected or operation in progress
-        if self.copy_in_progress {
-            // Show paste progress with background
-            queue!(
-                stdout,
-                cursor::MoveTo(0, height - 1),
-                SetForegroundColor(Color::Black),
-                crossterm::style::SetBackgroundColor(Color::Cyan),
-                Print(format!(" Pasting {}/{} items... ", self.copy_progress_current, self.copy_progress_total)),
-                ResetColor
-            )?;
-        } else if self.calculating_sizes {
-            // Show directory size calculation in progress
-            queue!(
-                stdout,
-                cursor::MoveTo(0, height - 1),
-                SetForegroundColor(Color::Black),
-                crossterm::style::SetBackgroundColor(Color::Cyan),
-                Print(" Calculating directory sizes... "),
-                ResetColor
-            )?;
-        } else if self.list_mode && self.list_info_mode == 3 {
-            // In size mode but not calculating - show hint
-            queue!(
-                stdout,
-                cursor::MoveTo(0, height - 1),
-                SetForegroundColor(Color::Black),
-                crossterm::style::SetBackgroundColor(Color::Blue),
-                Print(" Press 'e' to calculate directory sizes "),
-                ResetColor
-            )?;
-        } else if self.list_mode && self.list_info_mode == 2 {
-            // In permissions mode - show hint
-            queue!(
-                stdout,
-                cursor::MoveTo(0, height - 1),
-                SetForegroundColor(Color::Black),
-                crossterm::style::SetBackgroundColor(Color::Blue),
-                Print(" Press 'e' to edit permissions "),
-                ResetColor
-            )?;
-        } else if self.list_mode && self.list_info_mode == 1 {
-            // In date mode - show hint about toggling
-            let date_type = if self.show_created_date { "created" } else { "modified" };
-            queue!(
-                stdout,
-                cursor::MoveTo(0, height - 1),
-                SetForegroundColor(Color::Black),
-                crossterm::style::SetBackgroundColor(Color::Blue),
-                Print(format!(" Showing {} time (press 'e' to toggle) ", date_type)),
-                ResetColor
-            )?;
-        } else if !self.clipboard_selection.is_empty() {
-            // Show number of items in copy selection with background
-            let count = self.clipboard_selection.len();
-            let item_word = if count == 1 { "item" } else { "items" };
-            queue!(
-                stdout,
-                cursor::MoveTo(0, height - 1),
-                SetForegroundColor(Color::Black),
-                crossterm::style::SetBackgroundColor(Color::Yellow),
-                Print(format!(" {} {} selected for copy (c: add/remove, C: clear, v: paste) ", count, item_word)),
-                ResetColor
-            )?;
-        } else if let Some(ref error_msg) = self.error_message {
-            // Display error message if present
-            queue!(
-                stdout,
-                cursor::MoveTo(0, height - 1),
-                SetForegroundColor(Color::Red),
-                Print(format!(" ERROR: {} ", error_msg)),
-                ResetColor
-            )?;
-        } else if self.show_help {
-            // Show contextual help in footer
-            self.draw_footer_help(&mut stdout, width, height)?;
+        if copied > 0 {
+            let item_word = if copied == 1 { "item" } else { "items" };
+            self.notify(Severity::Success, format!("Copied {} {}", copied, item_word));
+            self.job_finished(started, &format!("Copied {} {}", copied, item_word));
         }
 
-        // Flush all queued commands simultaneously to minimize flicker
-        stdout.flush()?;
+        // Clear progress and reset
+        self.copy_in_progress = false;
+        self.copy_progress_current = 0;
+        self.copy_progress_total = 0;
+        self.redo_stack.clear();
         Ok(())
     }
 
-    fn draw_footer_help(&mut self, stdout: &mut io::Stdout, width: u16, height: u16) -> io::Result<()> {
-        // Helper to format keybindings
-        let fmt_keys = |keys: &Vec<char>| -> String {
-            keys.iter().map(|c| format!("{}", c)).collect::<Vec<_>>().join("/")
-        };
+    /// Show `label` with a percentage in the status bar, redrawing only when the
+    /// percentage moves.
+    fn show_archive_progress(&mut self, label: &str, done: u64, total: u64) {
+        let percent = done.saturating_mul(100).checked_div(total).unwrap_or(100);
+        let text = format!("{}... {}%", label, percent.min(100));
+        if self.archive_progress.as_ref() != Some(&text) {
+            self.archive_progress = Some(text);
+            let _ = self.draw();
+        }
+    }
 
-        let help_text = if self.history_mode {
-            // History mode help
-            format!(
-                " Frecency Jump │ Type to filter │ Tab/↑/↓ Navigate results │ Enter Jump │ Esc Cancel"
-            )
-        } else if self.fuzzy_mode {
-            // Fuzzy mode help
-            let mode_info = if self.fuzzy_jump_mode { "Jump Mode" } else { "Continuous Mode (Shift+/)" };
-            format!(
-                " {} │ {} Back │ {} Home │ Enter Open │ Esc Cancel │ ! Toggle Help",
-                mode_info,
-                fmt_keys(&self.keybindings.fuzzy_back),
-                fmt_keys(&self.keybindings.fuzzy_home)
-            )
-        } else if self.preview_mode {
-            // Preview mode help
-            format!(
-                " {}/{} Scroll │ Shift+{}/{} Scroll Page │ {}/{} Resize Pane │ Space Toggle Line# │ {} Close Preview │ ! Toggle Help",
-                fmt_keys(&self.keybindings.preview_up),
-                fmt_keys(&self.keybindings.preview_down),
-                fmt_keys(&self.keybindings.preview_up),
-                fmt_keys(&self.keybindings.preview_down),
-                fmt_keys(&self.keybindings.preview_height_decrease),
-                fmt_keys(&self.keybindings.preview_height_increase),
-                fmt_keys(&self.keybindings.preview_toggle)
-            )
-        } else if self.list_mode {
-            // List mode help
-            format!(
-                " {}/{}/{}/{} Nav │ {} Forward │ {} Back │ Enter Open │ {} Home │ Space Info │ e Extra │ {} Grid Mode │ {} Find │ {} Preview │ {} Exit │ Shift+{} Exit to Finder │ ! Toggle Help",
-                fmt_keys(&self.keybindings.up),
-                fmt_keys(&self.keybindings.down),
-                fmt_keys(&self.keybindings.left),
-                fmt_keys(&self.keybindings.right),
-                fmt_keys(&self.keybindings.open),
-                fmt_keys(&self.keybindings.back),
-                fmt_keys(&self.keybindings.home),
-                fmt_keys(&self.keybindings.toggle_mode),
-                fmt_keys(&self.keybindings.fuzzy_find),
-                fmt_keys(&self.keybindings.preview_toggle),
-                fmt_keys(&self.keybindings.quit),
-                fmt_keys(&self.keybindings.quit)
-            )
-        } else {
-            // Normal (grid) mode help
-            format!(
-                " {}/{}/{}/{} Nav │ {} Forward │ {} Back │ Enter Open │ {} Home │ {}/{} Sibling │ {} Find │ {} List Mode │ {} Preview │ {} Exit │ Shift+{} Exit to Finder │ ! Toggle Help",
-                fmt_keys(&self.keybindings.up),
-                fmt_keys(&self.keybindings.down),
-                fmt_keys(&self.keybindings.left),
-                fmt_keys(&self.keybindings.right),
-                fmt_keys(&self.keybindings.open),
-                fmt_keys(&self.keybindings.back),
-                fmt_keys(&self.keybindings.home),
-                fmt_keys(&self.keybindings.next_sibling),
-                fmt_keys(&self.keybindings.prev_sibling),
-                fmt_keys(&self.keybindings.fuzzy_find),
-                fmt_keys(&self.keybindings.toggle_mode),
-                fmt_keys(&self.keybindings.preview_toggle),
-                fmt_keys(&self.keybindings.quit),
-                fmt_keys(&self.keybindings.quit)
-            )
+    /// Unpack the selected archive into the current directory. Its entries go
+    /// into a new directory named after it, which is dropped again when the
+    /// archive holds a single entry that doesn't clash with anything here.
+    fn extract_selected(&mut self) -> io::Result<()> {
+        let Some(archive) = self.get_selected_path() else { return Ok(()) };
+        if archive_kind(&archive).is_none() || !archive.is_file() {
+            self.notify(Severity::Warning, "Not a zip, tar or tar.gz archive");
+            return Ok(());
+        }
+        let file_name = archive.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let lower = file_name.to_lowercase();
+        let suffix = [".tar.gz", ".tgz", ".tar", ".zip"].into_iter().find(|suffix| lower.ends_with(suffix)).unwrap_or("");
+        let stem = file_name.get(..file_name.len() - suffix.len()).unwrap_or(&file_name).to_string();
+        // Unpack into a hidden directory first, so nothing half-written shows under a real name
+        let dest = numbered_path(&self.current_dir, &format!(".{}.extracting", stem), "");
+
+        let started = Instant::now();
+        let label = format!("Extracting {}", file_name);
+        let result = fs::create_dir(&dest).and_then(|_| extract_archive(&archive, &dest, &mut |done, total| self.show_archive_progress(&label, done, total)));
+        self.archive_progress = None;
+        let (written, skipped) = match result {
+            Ok(counts) => counts,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&dest);
+                self.notify(Severity::Error, format!("Cannot extract {}: {}", file_name, e));
+                return Ok(());
+            }
         };
 
-        // File operations help (second row) - only show in grid/list mode
-        let file_ops_text = if !self.fuzzy_mode && !self.preview_mode && !self.history_mode {
-            Some(format!(
-                " File Operations: {} New │ {} Rename │ {}/{} Copy/Paste │ {} Trash │ {} Delete │ {} Undo",
-                fmt_keys(&self.keybindings.create),
-                fmt_keys(&self.keybindings.rename),
-                fmt_keys(&self.keybindings.copy),
-                fmt_keys(&self.keybindings.paste),
-                fmt_keys(&self.keybindings.trash),
-                fmt_keys(&self.keybindings.delete),
-                fmt_keys(&self.keybindings.undo)
-            ))
-        } else {
-            None
+        // A lone top-level entry needs no directory around it
+        let children: Vec<PathBuf> = fs::read_dir(&dest).into_iter().flatten().filter_map(|e| e.ok()).map(|e| e.path()).collect();
+        let lifted = match children.as_slice() {
+            [only] => Some(self.current_dir.join(only.file_name().unwrap_or_default())).filter(|lifted| lifted.symlink_metadata().is_err()),
+            _ => None,
         };
-
-        // Determine rows for help text (account for wrapper warning if present)
-        let wrapper_warning_offset = if self.wrapper_warning { 1 } else { 0 };
-        let (help_row, file_ops_row) = if self.history_mode || self.fuzzy_mode {
-            (height.saturating_sub(2 + wrapper_warning_offset), None)
-        } else if file_ops_text.is_some() {
-            (height.saturating_sub(2 + wrapper_warning_offset), Some(height.saturating_sub(1 + wrapper_warning_offset)))
-        } else {
-            (height.saturating_sub(1 + wrapper_warning_offset), None)
+        let (result, lifted) = match lifted {
+            Some(lifted) => (lifted, true),
+            None => (numbered_path(&self.current_dir, &stem, ""), false),
         };
-
-        // Draw wrapper warning if needed (above help text)
-        if self.wrapper_warning {
-            let warning_row = height.saturating_sub(1);
-            let warning_text = " ⚠ WARNING: Shell wrapper not detected! Navigation (cd) won't work. Run: ils --install";
-            let truncated_warning = Self::truncate_string_safe(warning_text, width as usize);
-            queue!(
-                stdout,
-                cursor::MoveTo(0, warning_row),
-                SetForegroundColor(Color::Red),
-                crossterm::style::SetAttribute(crossterm::style::Attribute::Bold),
-                Print(&truncated_warning),
-                crossterm::style::SetAttribute(crossterm::style::Attribute::Reset),
-                ResetColor
-            )?;
+        if let Err(e) = place_extracted(&dest, &result, lifted) {
+            let _ = fs::remove_dir_all(&dest);
+            self.notify(Severity::Error, format!("Cannot extract {}: {}", file_name, e));
+            return Ok(());
         }
 
-        // Draw main help row (with wrapping)
-        let help_lines = Self::wrap_text(&help_text, width as usize);
-        for (i, line) in help_lines.iter().enumerate() {
-            let row = help_row.saturating_sub(help_lines.len().saturating_sub(1) as u16).saturating_add(i as u16);
-            queue!(
-                stdout,
-                cursor::MoveTo(0, row),
-                SetForegroundColor(Color::DarkGrey),
-                Print(line),
-                ResetColor
-            )?;
+        self.undo_stack.push(UndoAction::Extract { archive, dest: result.clone(), lifted });
+        self.redo_stack.clear();
+        let shown = result.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let mut message = format!("Extracted {} {} into {}", written, if written == 1 { "entry" } else { "entries" }, shown);
+        if skipped > 0 {
+            message.push_str(&format!(" ({} skipped)", skipped));
+        }
+        self.job_finished(started, &message);
+        self.notify(if skipped > 0 { Severity::Warning } else { Severity::Success }, message);
+        self.load_entries()?;
+        if let Some(idx) = self.entries.iter().position(|p| p == &result) {
+            self.selected = idx;
+            self.scroll_to_selected();
         }
+        Ok(())
+    }
 
-        // Draw file operations row if present (with wrapping, use cyan color)
-        if let (Some(ops_text), Some(ops_row)) = (file_ops_text, file_ops_row) {
-            let ops_lines = Self::wrap_text(&ops_text, width as usize);
-            for (i, line) in ops_lines.iter().enumerate() {
-                let row = ops_row.saturating_sub(ops_lines.len().saturating_sub(1) as u16).saturating_add(i as u16);
-                queue!(
-                    stdout,
-                    cursor::MoveTo(0, row),
-                    SetForegroundColor(Color::Cyan),
-                    Print(line),
-                    ResetColor
-                )?;
+    /// Pack the selected or marked entries into a zip or tar.gz in the current
+    /// directory, named after the entry or, for several, after the directory.
+    fn compress_targets(&mut self) -> io::Result<()> {
+        let sources = self.action_targets();
+        if sources.is_empty() {
+            return Ok(());
+        }
+        let Some(format) = self.prompt("compress", "Format (zip or tar.gz): ", "zip", false)? else {
+            return Ok(());
+        };
+        let format = match format.trim().trim_start_matches('.').to_lowercase().as_str() {
+            "zip" => "zip",
+            "tar.gz" | "tgz" | "gz" => "tar.gz",
+            other => {
+                self.notify(Severity::Error, format!("Unknown archive format: {}", other));
+                return Ok(());
+            }
+        };
+        let stem = match sources.as_slice() {
+            [only] => only.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
+            _ => self.current_dir.file_name().map_or_else(|| String::from("archive"), |n| n.to_string_lossy().to_string()),
+        };
+        let archive = numbered_path(&self.current_dir, &stem, &format!(".{}", format));
+        let archive_name = archive.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+
+        let started = Instant::now();
+        let label = format!("Compressing into {}", archive_name);
+        let base = self.current_dir.clone();
+        let result = write_archive(&sources, &base, &archive, format, &mut |done, total| self.show_archive_progress(&label, done, total));
+        self.archive_progress = None;
+        let files = match result {
+            Ok(files) => files,
+            Err(e) => {
+                let _ = fs::remove_file(&archive);
+                self.notify(Severity::Error, format!("Cannot create {}: {}", archive_name, e));
+                return Ok(());
             }
+        };
+
+        self.undo_stack.push(UndoAction::Compress { sources, base, archive: archive.clone(), format });
+        self.redo_stack.clear();
+        let message = format!("Compressed {} {} into {}", files, if files == 1 { "file" } else { "files" }, archive_name);
+        self.job_finished(started, &message);
+        self.notify(Severity::Success, message);
+        self.load_entries()?;
+        if let Some(idx) = self.entries.iter().position(|p| p == &archive) {
+            self.selected = idx;
+            self.scroll_to_selected();
         }
+        Ok(())
+    }
+
+    fn copy_dir_recursive(&self, src: &Path, dest: &Path) -> io::Result<()> {
+        fs::create_dir_all(dest)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            let src_path = entry.path();
+            let dest_path = dest.join(entry.file_name());
 
+            if src_path.is_dir() {
+                self.copy_dir_recursive(&src_path, &dest_path)?;
+            } else {
+                fs::copy(&src_path, &dest_path)?;
+            }
+        }
         Ok(())
     }
 
-    fn select_up(&mut self) {
-        // In history mode, just move up one item (list mode) with wrapping
-        if self.history_mode {
-            if !self.history_filtered.is_empty() {
-                if self.selected > 0 {
-                    self.selected -= 1;
+    /// Moves `src` to `dest`, creating intermediate directories. Falls back to
+    /// copy + remove when the destination is on another filesystem.
+    fn move_path(&self, src: &Path, dest: &Path) -> io::Result<()> {
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match fs::rename(src, dest) {
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                if src.is_dir() {
+                    self.copy_dir_recursive(src, dest)?;
+                    fs::remove_dir_all(src)
                 } else {
-                    // Wrap to bottom
-                    self.selected = self.history_filtered.len() - 1;
-                }
-
-                // Update scroll if needed
-                if self.selected < self.scroll_offset {
-                    self.scroll_offset = self.selected;
+                    fs::copy(src, dest)?;
+                    fs::remove_file(src)
                 }
             }
-            return;
+            result => result,
         }
+    }
+
+    fn rename_selected(&mut self) -> io::Result<()> {
+        let Some(selected_path) = self.get_selected_path() else {
+            return Ok(());
+        };
+        let Some(old_name) = selected_path.file_name().and_then(|n| n.to_str()).map(|n| n.to_string()) else {
+            return Ok(());
+        };
 
-        // Row-major: move up one row (subtract num_cols)
-        if self.selected >= self.num_cols {
-            self.selected -= self.num_cols;
+        let check = |browser: &Self, input: &str| browser.check_rename_input(&selected_path, input);
+        if let Some(new_name) = self.prompt_checked("rename", "Rename to (or path to move): ", &old_name, true, &check)? {
+            let new_name = expand_path_vars(new_name.trim());
+            let new_name = new_name.as_str();
 
-            // Update scroll if needed (works for both list and grid mode)
-            let current_row = self.selected / self.num_cols;
-            if current_row < self.scroll_offset {
-                self.scroll_offset = current_row;
+            if new_name.is_empty() || new_name == old_name {
+                return Ok(());
             }
-        }
-    }
 
-    fn select_down(&mut self) {
-        // In history mode, just move down one item (list mode) with wrapping
-        if self.history_mode {
-            if !self.history_filtered.is_empty() {
-                if self.selected + 1 < self.history_filtered.len() {
-                    self.selected += 1;
-                } else {
-                    // Wrap to top
-                    self.selected = 0;
-                }
+            let parent = selected_path.parent().unwrap_or(&self.current_dir).to_path_buf();
 
-                // Update scroll if needed
-                if let Ok((_, height)) = terminal::size() {
-                    let max_display_rows = (height as usize).saturating_sub(self.start_row as usize).saturating_sub(2);
-                    if self.selected >= self.scroll_offset + max_display_rows {
-                        self.scroll_offset = self.selected - max_display_rows + 1;
-                    } else if self.selected < self.scroll_offset {
-                        self.scroll_offset = self.selected;
-                    }
-                }
+            if !new_name.contains('/') {
+                // Plain rename within the same directory
+                return self.apply_renames(vec![(selected_path.clone(), parent.join(new_name))]);
             }
-            return;
-        }
 
-        // Row-major: move down one row (add num_cols)
-        let new_idx = self.selected + self.num_cols;
-        if new_idx < self.entries.len() {
-            self.selected = new_idx;
+            // Path given: move, keeping the name when the target is a directory
+            let mut dest = parent.join(new_name);
+            if new_name.ends_with('/') || dest.is_dir() {
+                dest = dest.join(&old_name);
+            }
 
-            // Update scroll if needed (works for both list and grid mode)
-            if let Ok((_, height)) = terminal::size() {
-                let max_display_rows = (height as usize).saturating_sub(self.start_row as usize).saturating_sub(2);
-                let current_row = self.selected / self.num_cols;
-                if current_row >= self.scroll_offset + max_display_rows {
-                    self.scroll_offset = current_row - max_display_rows + 1;
+            if !self.check_portable_name(&dest) {
+                return Ok(());
+            }
+            let mut result = if dest.exists() {
+                Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("'{}' already exists", dest.display())))
+            } else {
+                self.move_path(&selected_path, &dest)
+            };
+            if let Err(e) = &result {
+                let command = ["mv".as_ref(), "--".as_ref(), selected_path.as_os_str(), dest.as_os_str()];
+                if self.retry_elevated(&format!("Moving '{}'", old_name), e, &command)? {
+                    result = Ok(());
                 }
             }
-        }
-    }
 
-    fn select_left(&mut self) {
-        // Row-major: move left in same row
-        if self.selected > 0 && self.selected % self.num_cols != 0 {
-            self.selected -= 1;
+            if let Err(e) = result {
+                self.notify(Severity::Error, format!("Error moving: {}", e));
+            } else {
+                self.notify(Severity::Success, format!("Moved to {}", dest.display()));
+                if self.tags.follow_renames(&[(selected_path.clone(), dest.clone())]) {
+                    self.save_tags();
+                }
+                self.undo_stack.push(UndoAction::Move {
+                    src: selected_path,
+                    dest,
+                });
+                self.redo_stack.clear();
+                self.load_entries()?;
+            }
         }
+        Ok(())
     }
 
-    fn select_right(&mut self) {
-        // Row-major: move right in same row
-        if self.selected + 1 < self.entries.len() && (self.selected + 1) % self.num_cols != 0 {
-            self.selected += 1;
-        }
-    }
+    /// Set the tags of the selected or marked entries from a prompt, as names
+    /// separated by spaces or commas; an empty answer clears them. Marked
+    /// entries start from the tags they share and keep their others.
+    fn edit_tags(&mut self) -> io::Result<()> {
+        let targets = self.action_targets();
+        let Some(first) = targets.first() else {
+            return Ok(());
+        };
+        let shared: Vec<String> = self.tags.of(first).iter()
+            .filter(|tag| targets.iter().all(|target| self.tags.of(target).contains(tag)))
+            .cloned()
+            .collect();
+        let label = match targets.len() {
+            1 => "Tags: ".to_string(),
+            n => format!("Tags of {} items: ", n),
+        };
+        let Some(answer) = self.prompt("tags", &label, &shared.join(" "), false)? else {
+            return Ok(());
+        };
 
-    fn jump_up(&mut self) {
-        // Jump up by configured amount
-        for _ in 0..self.settings.jump_amount {
-            self.select_up();
+        let mut tags: Vec<String> = Vec::new();
+        for tag in answer.split(|c: char| c == ',' || c.is_whitespace()).map(|tag| tag.trim_start_matches('#')) {
+            if !tag.is_empty() && !tags.iter().any(|t| t == tag) {
+                tags.push(tag.to_string());
+            }
         }
-    }
-
-    fn jump_down(&mut self) {
-        // Jump down by configured amount
-        for _ in 0..self.settings.jump_amount {
-            self.select_down();
+        for target in &targets {
+            let mut own: Vec<String> = self.tags.of(target).iter()
+                .filter(|tag| !shared.contains(tag) || tags.contains(tag))
+                .cloned()
+                .collect();
+            for tag in &tags {
+                if !own.contains(tag) {
+                    own.push(tag.clone());
+                }
+            }
+            self.tags.set(target, own);
         }
-    }
-
-    fn jump_left(&mut self) {
-        // Jump left by configured amount
-        for _ in 0..self.settings.jump_amount {
-            self.select_left();
+        if !self.save_tags() {
+            return Ok(());
         }
+
+        let message = match (tags.is_empty(), targets.len()) {
+            (true, 1) => "Tags cleared".to_string(),
+            (true, n) => format!("Tags cleared from {} items", n),
+            (false, 1) => format!("Tagged {}", tags.join(", ")),
+            (false, n) => format!("Tagged {} items {}", n, tags.join(", ")),
+        };
+        self.notify(Severity::Success, message);
+        Ok(())
     }
 
-    fn jump_right(&mut self) {
-        // Jump right by configured amount
-        for _ in 0..self.settings.jump_amount {
-            self.select_right();
+    /// Write the tags to tags.toml, saying so if that fails.
+    fn save_tags(&mut self) -> bool {
+        if let Err(e) = self.tags.save() {
+            self.notify(Severity::Error, format!("Could not save tags: {}", e));
+            return false;
         }
+        true
     }
 
-    fn filter_history(&mut self) {
-        // Filter history based on query
-        let all_entries = self.dir_history.get_sorted_by_frecency();
-
-        if self.history_query.is_empty() {
-            self.history_filtered = Vec::new();
-        } else {
-            let mut filtered: Vec<(DirectoryEntry, bool)> = all_entries
-                .into_iter()
-                .filter_map(|entry| {
-                    let basename = entry.path.rsplit('/').next().unwrap_or("");
-                    let (matches_full, matches_basename) = if self.settings.case_sensitive_search {
-                        (entry.path.contains(&self.history_query), basename.contains(&self.history_query))
-                    } else {
-                        (
-                            entry.path.to_lowercase().contains(&self.history_query.to_lowercase()),
-                            basename.to_lowercase().contains(&self.history_query.to_lowercase())
-                        )
-                    };
+    /// Validate and apply a set of renames. Batches are listed for confirmation
+    /// first; anything with conflicts is shown with the problems flagged and
+    /// nothing is renamed.
+    fn apply_renames(&mut self, plan: Vec<(PathBuf, PathBuf)>) -> io::Result<()> {
+        let plan: Vec<(PathBuf, PathBuf)> = plan.into_iter().filter(|(old, new)| old != new).collect();
+        if plan.is_empty() {
+            return Ok(());
+        }
 
-                    if matches_full {
-                        Some((entry, matches_basename))
+        let mut conflicts = rename_conflicts(&plan);
+        let mut warnings = Vec::new();
+        if self.settings.filename_check != "off" {
+            for (i, (_, new)) in plan.iter().enumerate() {
+                let name = new.file_name().unwrap_or_default().to_string_lossy();
+                if let Some(problem) = portability_problem(&name) {
+                    if self.settings.filename_check == "strict" {
+                        conflicts.push((i, problem));
                     } else {
-                        None
-                    }
-                })
-                .collect();
-
-            // Sort: basename matches first if enabled
-            if self.settings.history_prioritize_basename {
-                filtered.sort_by(|a, b| {
-                    match (b.1, a.1) {
-                        (true, false) => std::cmp::Ordering::Greater,
-                        (false, true) => std::cmp::Ordering::Less,
-                        _ => std::cmp::Ordering::Equal,
+                        warnings.push(problem);
                     }
-                });
+                }
             }
-
-            self.history_filtered = filtered
-                .into_iter()
-                .map(|(entry, _)| entry)
-                .take(self.settings.history_max_results)
-                .collect();
         }
-
-        // Reset selection
-        self.selected = 0;
-        self.scroll_offset = 0;
-    }
-
-    fn fuzzy_match(&self) -> (Option<usize>, usize) {
-        // Find all entries that match the fuzzy query and return (first_match, count)
-        if self.fuzzy_query.is_empty() {
-            return (None, 0);
+        if (plan.len() > 1 || !conflicts.is_empty()) && !self.review_renames(&plan, &conflicts)? {
+            return Ok(());
         }
 
-        // Normal mode: match against file names
-        let matches: Vec<usize> = self.entries.iter().enumerate()
-            .filter_map(|(idx, entry)| {
-                if let Some(name) = entry.file_name().and_then(|n| n.to_str()) {
-                    let matches = if self.settings.case_sensitive_search {
-                        name.starts_with(&self.fuzzy_query)
-                    } else {
-                        name.to_lowercase().starts_with(&self.fuzzy_query.to_lowercase())
-                    };
-                    if matches {
-                        Some(idx)
-                    } else {
-                        None
-                    }
-                } else {
-                    None
+        if let Err(e) = rename_all(&plan) {
+            // A batch is left to fail whole; one rename can be retried as administrator
+            let elevated = match plan.as_slice() {
+                [(old, new)] => {
+                    let command = ["mv".as_ref(), "--".as_ref(), old.as_os_str(), new.as_os_str()];
+                    let name = old.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                    self.retry_elevated(&format!("Renaming '{}'", name), &e, &command)?
                 }
-            })
-            .collect();
-
-        (matches.first().copied(), matches.len())
+                _ => false,
+            };
+            if !elevated {
+                self.notify(Severity::Error, format!("Error renaming: {}", e));
+                return Ok(());
+            }
+        }
+        if self.tags.follow_renames(&plan) {
+            self.save_tags();
+        }
+        let message = match plan.as_slice() {
+            [(_, new)] => format!("Renamed to '{}'", new.file_name().unwrap_or_default().to_string_lossy()),
+            _ => format!("Renamed {} items", plan.len()),
+        };
+        let action = match <[_; 1]>::try_from(plan) {
+            Ok([(old_path, new_path)]) => UndoAction::Rename { old_path, new_path },
+            Err(renames) => UndoAction::RenameBatch { renames },
+        };
+        self.undo_stack.push(action);
+        self.redo_stack.clear();
+        self.load_entries()?;
+        self.notify(Severity::Success, message);
+        for warning in warnings {
+            self.notify(Severity::Warning, warning);
+        }
+        Ok(())
     }
 
-    fn open_selected(&mut self) -> io::Result<bool> {
-        if self.entries.is_empty() {
-            return Ok(false);
+    /// Rename the marked entries, or every listed one, by editing their names one
+    /// per line in $EDITOR. The edited lines go through `apply_renames`, so the
+    /// batch is reviewed for conflicts and undone as one.
+    fn bulk_rename(&mut self) -> io::Result<()> {
+        self.marked.retain(|path| path.symlink_metadata().is_ok());
+        let targets = if self.marked.is_empty() { self.entries.clone() } else { self.marked.clone() };
+        if targets.is_empty() {
+            return Ok(());
+        }
+        let name = |path: &Path| path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        if let Some(path) = targets.iter().find(|path| name(path).contains('\n')) {
+            self.notify(Severity::Error, format!("Can't bulk rename '{}': its name has a line break", name(path).escape_debug()));
+            return Ok(());
         }
 
-        let selected_path = &self.entries[self.selected];
-        if selected_path.is_dir() {
-            // Save the old state before trying to navigate
-            let old_dir = self.current_dir.clone();
-            let old_entries = self.entries.clone();
-            let old_selected = self.selected;
-            let old_scroll = self.scroll_offset;
-
-            // Add the selected folder to breadcrumbs before navigating
-            if let Some(folder_name) = selected_path.file_name().and_then(|n| n.to_str()) {
-                self.breadcrumbs.push(folder_name.to_string());
+        let editor = env::var("EDITOR").ok().filter(|editor| !editor.trim().is_empty()).unwrap_or_else(|| "vim".to_string());
+        let mut words = editor.split_whitespace();
+        let Some(program) = words.next() else {
+            return Ok(());
+        };
+        let names: Vec<String> = targets.iter().map(|path| name(path)).collect();
+        let (list_path, mut list) = create_temp_file("ils-rename", "txt")?;
+        let edit = || -> io::Result<(io::Result<std::process::ExitStatus>, io::Result<String>)> {
+            list.write_all(format!("{}\n", names.join("\n")).as_bytes())?;
+            drop(list);
+            let status = run_suspended(std::process::Command::new(program).args(words).arg(&list_path).current_dir(&self.current_dir));
+            Ok((status, fs::read_to_string(&list_path)))
+        };
+        // The list goes again however editing it ended
+        let result = edit();
+        let _ = fs::remove_file(&list_path);
+        let (status, edited) = result?;
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(_) => {
+                self.notify(Severity::Warning, "Editor exited with an error, nothing renamed");
+                return Ok(());
             }
-            self.current_dir = selected_path.clone();
-
-            // Try to load entries, if it fails, restore previous state
-            if let Err(e) = self.load_entries() {
-                self.current_dir = old_dir;
-                self.entries = old_entries;
-                self.selected = old_selected;
-                self.scroll_offset = old_scroll;
-                self.breadcrumbs.pop();
-
-                // Set error message based on error kind
-                if e.kind() == io::ErrorKind::PermissionDenied {
-                    self.error_message = Some("Permission denied. Grant Full Disk Access to your terminal in System Settings > Privacy & Security.".to_string());
-                } else {
-                    self.error_message = Some(format!("Cannot access: {}", e));
-                }
-            } else {
-                // Successfully loaded directory, record visit
-                self.record_directory_visit();
+            Err(e) => {
+                self.notify(Severity::Error, format!("Couldn't run '{}': {} (see $EDITOR)", program, e));
+                return Ok(());
             }
-            Ok(false)
-        } else {
-            // For a file, we return true, signaling main to write the path and exit.
-            Ok(true)
         }
+        let edited = edited?;
+        let new_names: Vec<&str> = edited.lines().collect();
+        if new_names.len() != targets.len() {
+            self.notify(
+                Severity::Error,
+                format!("Expected {} names but got {} lines, nothing renamed. Keep one line per entry.", targets.len(), new_names.len()),
+            );
+            return Ok(());
+        }
+        if let Some(line) = new_names.iter().position(|new_name| new_name.trim().is_empty()) {
+            self.notify(Severity::Error, format!("Line {} is empty, nothing renamed", line + 1));
+            return Ok(());
+        }
+        if let Some(new_name) = new_names.iter().find(|new_name| new_name.contains('/')) {
+            self.notify(Severity::Error, format!("'{}' is a path, nothing renamed. Bulk rename only changes names.", new_name));
+            return Ok(());
+        }
+
+        let plan = targets
+            .iter()
+            .zip(new_names)
+            .map(|(old, new_name)| (old.clone(), old.parent().unwrap_or(&self.current_dir).join(new_name)))
+            .collect();
+        self.apply_renames(plan)
     }
 
-    fn go_back(&mut self) -> io::Result<()> {
-        if let Some(parent) = self.current_dir.parent() {
-            // Pop the last breadcrumb when going back
-            self.breadcrumbs.pop();
-            self.current_dir = parent.to_path_buf();
-            self.load_entries()?;
-            self.record_directory_visit();
+    /// Rename the marked (or selected) entries with a sed-style substitution. The
+    /// prompt previews the new names as the expression is typed; the batch then
+    /// goes through `apply_renames` for review and a single undo step.
+    fn pattern_rename(&mut self) -> io::Result<()> {
+        let targets = self.action_targets();
+        if targets.is_empty() {
+            return Ok(());
         }
-        Ok(())
+        let check = |_: &Self, input: &str| Self::check_substitution(&targets, input);
+        let Some(expr) = self.prompt_checked("pattern_rename", "Rename with s/pattern/replacement/: ", "s/", false, &check)? else {
+            return Ok(());
+        };
+        let Ok(substitution) = Substitution::parse(expr.trim()) else {
+            return Ok(());
+        };
+        let plan = targets
+            .iter()
+            .map(|old| {
+                let name = old.file_name().unwrap_or_default().to_string_lossy();
+                (old.clone(), old.with_file_name(substitution.apply(&name)))
+            })
+            .collect();
+        self.apply_renames(plan)
     }
 
-    fn go_home(&mut self) -> io::Result<()> {
-        if let Some(home) = env::var_os("HOME") {
-            self.current_dir = PathBuf::from(home);
-            self.breadcrumbs.clear();
-            self.load_entries()?;
-            self.record_directory_visit();
+    /// Prompt feedback for `pattern_rename`: how many names change and the first
+    /// few results, or what is wrong with the expression.
+    fn check_substitution(targets: &[PathBuf], input: &str) -> Option<(Severity, String)> {
+        let input = input.trim();
+        if input.is_empty() || input == "s/" {
+            return None;
         }
-        Ok(())
+        let substitution = match Substitution::parse(input) {
+            Ok(substitution) => substitution,
+            Err(message) => return Some((Severity::Error, message)),
+        };
+        let renamed: Vec<(String, String)> = targets
+            .iter()
+            .map(|path| path.file_name().unwrap_or_default().to_string_lossy().to_string())
+            .map(|name| {
+                let new_name = substitution.apply(&name);
+                (name, new_name)
+            })
+            .filter(|(name, new_name)| name != new_name)
+            .collect();
+        if renamed.is_empty() {
+            return Some((Severity::Warning, "No names match".to_string()));
+        }
+        if let Some((_, new_name)) = renamed.iter().find(|(_, new_name)| new_name.is_empty() || new_name.contains('/')) {
+            return Some((Severity::Error, format!("'{}' is not a valid name", new_name)));
+        }
+        let examples: Vec<String> = renamed.iter().take(3).map(|(name, new_name)| format!("{} → {}", name, new_name)).collect();
+        let more = if renamed.len() > examples.len() { ", …" } else { "" };
+        Some((Severity::Info, format!("{} of {}: {}{}", renamed.len(), targets.len(), examples.join(", "), more)))
     }
 
-    fn record_directory_visit(&mut self) {
-        self.dir_history.record_visit(&self.current_dir, self.settings.history_max_storage);
-        let _ = self.dir_history.save();
+    /// Apply the `filename_check` setting to a name about to be created or moved
+    /// to. Warns about names other filesystems reject; returns false if `strict`
+    /// says to refuse it.
+    fn check_portable_name(&mut self, path: &Path) -> bool {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        if self.settings.filename_check == "off" {
+            return true;
+        }
+        let Some(problem) = portability_problem(&name) else {
+            return true;
+        };
+        if self.settings.filename_check == "strict" {
+            self.notify(Severity::Error, format!("{} (filename_check = \"strict\")", problem));
+            false
+        } else {
+            self.notify(Severity::Warning, problem);
+            true
+        }
     }
 
-    fn get_current_dir(&self) -> &PathBuf {
-        &self.current_dir
+    /// List planned renames with their conflicts in the details panel and ask
+    /// about them. Returns true if the user confirmed; a plan with conflicts can
+    /// only be dismissed.
+    fn review_renames(&mut self, plan: &[(PathBuf, PathBuf)], conflicts: &[(usize, String)]) -> io::Result<bool> {
+        let name = |path: &Path| path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let rows = plan
+            .iter()
+            .enumerate()
+            .map(|(i, (old, new))| {
+                let mut value = format!("-> {}", name(new));
+                for (_, problem) in conflicts.iter().filter(|(idx, _)| *idx == i) {
+                    value.push_str(&format!("  ! {}", problem));
+                }
+                (name(old), value)
+            })
+            .collect();
+        self.info_panel = Some(("Renames".to_string(), rows));
+        let confirmed = if conflicts.is_empty() {
+            self.confirm(&format!("Apply {} renames?", plan.len()))
+        } else {
+            let label = format!("{} conflict(s), nothing renamed. Press Enter to go back: ", conflicts.len());
+            self.prompt("confirm", &label, "", false).map(|_| false)
+        };
+        self.info_panel = None;
+        confirmed
     }
 
-    fn get_selected_path(&self) -> Option<PathBuf> {
-        self.entries.get(self.selected).cloned()
+    /// Ask for a shell command, run it in the current directory with the
+    /// terminal handed back, and list the directory again afterwards.
+    fn run_shell_command(&mut self) -> io::Result<()> {
+        let Some(command) = self.prompt("shell_command", "Run: ", "", true)? else {
+            return Ok(());
+        };
+        if command.trim().is_empty() {
+            return Ok(());
+        }
+        self.run_command_line(command.trim(), true)
     }
 
-    fn go_to_next_sibling(&mut self) -> io::Result<()> {
-        // Go up to parent, then navigate to next sibling directory
-        if let Some(parent) = self.current_dir.parent() {
-            let current_name = self.current_dir.file_name();
-
-            // Read parent directory
-            let mut siblings: Vec<PathBuf> = fs::read_dir(parent)?
-                .filter_map(|e| e.ok())
-                .map(|e| e.path())
-                .filter(|p| p.is_dir())
-                .collect();
-
-            // If there are no siblings or only one (current), do nothing
-            if siblings.len() <= 1 {
-                return Ok(());
+    /// Run a shell command line with its placeholders filled in, in the current
+    /// directory and with the terminal handed back. With `wait` its output stays
+    /// up until Enter. The listing is read again afterwards.
+    fn run_command_line(&mut self, command: &str, wait: bool) -> io::Result<()> {
+        self.marked.retain(|path| path.symlink_metadata().is_ok());
+        let command = expand_placeholders(command, self.get_selected_path().as_deref(), &self.marked);
+
+        let status = suspended(|| -> io::Result<_> {
+            let mut stdout = io::stdout();
+            if wait {
+                println!();
+                execute!(stdout, SetForegroundColor(Color::DarkGrey), Print(format!("$ {}\n", command)), ResetColor)?;
             }
-
-            // Sort siblings
-            siblings.sort();
-
-            // Find current directory in siblings
-            if let Some(current_idx) = siblings.iter().position(|p| p.file_name() == current_name) {
-                // Go to next sibling (wrap around)
-                let next_idx = (current_idx + 1) % siblings.len();
-                self.current_dir = siblings[next_idx].clone();
-                self.breadcrumbs.pop();
-                if let Some(name) = self.current_dir.file_name().and_then(|n| n.to_str()) {
-                    self.breadcrumbs.push(name.to_string());
+            let status = std::process::Command::new("sh").arg("-c").arg(&command).current_dir(&self.current_dir).status();
+            if wait {
+                match &status {
+                    Ok(status) if !status.success() => print!("\n{}. Press Enter to go back: ", status),
+                    Err(e) => print!("\nCouldn't run sh: {}. Press Enter to go back: ", e),
+                    Ok(_) => print!("\nPress Enter to go back: "),
                 }
-                self.load_entries()?;
-                self.record_directory_visit();
+                stdout.flush()?;
+                let mut response = String::new();
+                io::stdin().read_line(&mut response)?;
             }
+            Ok(status)
+        })??;
+
+        match status {
+            Ok(status) if !status.success() => self.notify(Severity::Error, format!("`{}` {}", command, status)),
+            Err(e) if !wait => self.notify(Severity::Error, format!("Couldn't run sh: {}", e)),
+            _ => {}
         }
-        Ok(())
+        self.reload_keeping_selection()
     }
 
-    fn go_to_prev_sibling(&mut self) -> io::Result<()> {
-        // Go up to parent, then navigate to previous sibling directory
-        if let Some(parent) = self.current_dir.parent() {
-            let current_name = self.current_dir.file_name();
-
-            // Read parent directory
-            let mut siblings: Vec<PathBuf> = fs::read_dir(parent)?
-                .filter_map(|e| e.ok())
-                .map(|e| e.path())
-                .filter(|p| p.is_dir())
-                .collect();
-
-            // If there are no siblings or only one (current), do nothing
-            if siblings.len() <= 1 {
-                return Ok(());
+    fn move_to_trash(&mut self) -> io::Result<()> {
+        let targets = self.action_targets();
+        if targets.is_empty() {
+            return Ok(());
+        }
+        let old_selected = self.selected;
+        let mut trashed = Vec::new();
+
+        for path in &targets {
+            let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            match trash_path(path) {
+                Ok(()) => trashed.push(path.clone()),
+                // Without a trash at all there's no point trying the rest
+                Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                    self.notify(Severity::Warning, format!("Trash is unavailable: {}", e));
+                    break;
+                }
+                Err(e) => self.notify(Severity::Error, format!("Could not move '{}' to Trash: {}", name, e)),
             }
+        }
 
-            // Sort siblings
-            siblings.sort();
-
-            // Find current directory in siblings
-            if let Some(current_idx) = siblings.iter().position(|p| p.file_name() == current_name) {
-                // Go to previous sibling (wrap around)
-                let prev_idx = if current_idx == 0 {
-                    siblings.len() - 1
-                } else {
-                    current_idx - 1
-                };
-                self.current_dir = siblings[prev_idx].clone();
-                self.breadcrumbs.pop();
-                if let Some(name) = self.current_dir.file_name().and_then(|n| n.to_str()) {
-                    self.breadcrumbs.push(name.to_string());
+        if !trashed.is_empty() {
+            let gone: HashSet<&PathBuf> = trashed.iter().collect();
+            self.marked.retain(|path| !gone.contains(path));
+            match targets.as_slice() {
+                [path] => {
+                    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+                    self.notify(Severity::Success, format!("Moved '{}' to Trash", name));
                 }
-                self.load_entries()?;
-                self.record_directory_visit();
+                _ => self.notify(Severity::Success, format!("Moved {} items to Trash", trashed.len())),
+            }
+            if self.tags.forget(&trashed) {
+                self.save_tags();
             }
+
+            // Don't add to undo stack - can't reliably restore from trash
+            self.load_entries()?;
+            self.keep_selection_near(old_selected);
         }
         Ok(())
     }
 
-    #[allow(dead_code)]
-    fn copy_to_clipboard(&mut self) {
-        if let Some(path) = self.get_selected_path() {
-            self.clipboard = Some(path);
+    /// Whether the listing is the trash, whose size the path bar then shows.
+    fn in_trash(&self) -> bool {
+        trashed_files_dir().is_some_and(|dir| dir == self.current_dir)
+    }
+
+    fn open_trash(&mut self) -> io::Result<()> {
+        let Some(dir) = trashed_files_dir() else {
+            return Ok(());
+        };
+        if !dir.exists() {
+            self.notify(Severity::Info, "The trash is empty");
+            return Ok(());
         }
+        self.go_to_path(dir)
     }
 
-    fn toggle_copy_selection(&mut self) {
-        if let Some(path) = self.get_selected_path() {
-            // Toggle the item in the clipboard_selection
-            if let Some(pos) = self.clipboard_selection.iter().position(|p| p == &path) {
-                // Already in selection, remove it
-                self.clipboard_selection.remove(pos);
-            } else {
-                // Not in selection, add it
-                self.clipboard_selection.push(path);
-            }
+    /// Permanently delete everything in the trash once confirmed.
+    fn empty_trash(&mut self) -> io::Result<()> {
+        let Some(dir) = trashed_files_dir() else {
+            return Ok(());
+        };
+        // Finder's trash can't be listed without Full Disk Access, so an unknown count is asked about anyway
+        let count = fs::read_dir(&dir).ok().map(|entries| entries.count());
+        // What was trashed from other filesystems is emptied along with it
+        #[cfg(all(unix, not(target_os = "macos")))]
+        let count = Some(count.unwrap_or(0) + volume_trashes().iter().filter_map(|trash| fs::read_dir(trash.join("files")).ok()).map(|entries| entries.count()).sum::<usize>());
+        if count == Some(0) || (count.is_none() && !cfg!(target_os = "macos")) {
+            self.notify(Severity::Info, "The trash is already empty");
+            return Ok(());
+        }
+        let units = ["B", "KB", "MB", "GB", "TB"];
+        let size = self.dir_size_cache.peek(&dir).map(|usage| self.locale.format_size(usage.apparent, &units));
+        let contents = match (count, size) {
+            (Some(count), Some(size)) => format!(" ({} {}, {})", count, if count == 1 { "item" } else { "items" }, size),
+            (Some(count), None) => format!(" ({} {})", count, if count == 1 { "item" } else { "items" }),
+            _ => String::new(),
+        };
+        if !self.confirm(&format!("Empty the trash{}? This cannot be undone!", contents))? {
+            return Ok(());
+        }
+        match empty_system_trash() {
+            Ok(()) => self.notify(Severity::Success, "Emptied the trash"),
+            Err(e) => self.notify(Severity::Error, format!("Could not empty the trash: {}", e)),
+        }
+        self.dir_size_cache.remove(&dir);
+        if self.in_trash() {
+            self.load_entries()?;
         }
+        Ok(())
     }
 
-    fn clear_copy_selection(&mut self) {
-        self.clipboard_selection.clear();
+    /// After entries were removed, stay on the same index, or the last entry if
+    /// the list got shorter than that.
+    fn keep_selection_near(&mut self, old_selected: usize) {
+        if old_selected >= self.entries.len() && old_selected > 0 {
+            self.selected = self.entries.len().saturating_sub(1);
+        } else if old_selected < self.entries.len() {
+            self.selected = old_selected;
+        }
     }
 
-    fn paste_from_clipboard(&mut self) -> io::Result<()> {
-        // Use multi-select clipboard if it has items, otherwise fall back to single clipboard
-        let sources: Vec<PathBuf> = if !self.clipboard_selection.is_empty() {
-            self.clipboard_selection.clone()
-        } else if let Some(src) = &self.clipboard {
-            vec![src.clone()]
-        } else {
+    /// Append a pattern to the .gitignore at the root of the repository, offering
+    /// the selected entry's path from the root for editing into a glob first.
+    fn add_to_gitignore(&mut self) -> io::Result<()> {
+        let Some(root) = git_root(&self.current_dir) else {
+            self.notify(Severity::Warning, "Not inside a git repository");
             return Ok(());
         };
-
-        // Set up progress tracking
-        self.copy_in_progress = true;
-        self.copy_progress_total = sources.len();
-
-        for (idx, src) in sources.iter().enumerate() {
-            self.copy_progress_current = idx + 1;
-
-            if !src.exists() {
-                continue; // Skip files that no longer exist
-            }
-
-            let file_name = src.file_name().unwrap();
-            let mut dest = self.current_dir.join(file_name);
-
-            // Handle name conflicts
-            let mut counter = 1;
-            while dest.exists() {
-                let stem = src.file_stem().and_then(|s| s.to_str()).unwrap_or("");
-                let ext = src.extension().and_then(|s| s.to_str()).unwrap_or("");
-                let new_name = if ext.is_empty() {
-                    format!("{} ({})", stem, counter)
-                } else {
-                    format!("{} ({}).{}", stem, counter, ext)
-                };
-                dest = self.current_dir.join(new_name);
-                counter += 1;
-            }
-
-            // Redraw to show progress
-            self.draw()?;
-
-            // Copy file or directory recursively
-            if src.is_dir() {
-                self.copy_dir_recursive(src, &dest)?;
-            } else {
-                fs::copy(src, &dest)?;
+        let initial = match self.get_selected_path() {
+            Some(path) => {
+                let relative = path.strip_prefix(&root).unwrap_or(&path).to_string_lossy().to_string();
+                if path.is_dir() { format!("/{}/", relative) } else { format!("/{}", relative) }
             }
+            None => String::new(),
+        };
+        let Some(pattern) = self.prompt("gitignore", "Add to .gitignore: ", &initial, false)? else {
+            return Ok(());
+        };
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            return Ok(());
+        }
 
-            self.undo_stack.push(UndoAction::Copy {
-                src: src.clone(),
-                dest: dest.clone()
-            });
+        let gitignore = root.join(".gitignore");
+        let before = fs::read_to_string(&gitignore).ok();
+        let existing = before.as_deref().unwrap_or("");
+        if existing.lines().any(|line| line.trim() == pattern) {
+            self.notify(Severity::Info, format!("'{}' is already in .gitignore", pattern));
+            return Ok(());
+        }
+        let separator = if existing.is_empty() || existing.ends_with('\n') { "" } else { "\n" };
+        let after = format!("{}{}{}\n", existing, separator, pattern);
+        if let Err(e) = fs::write(&gitignore, &after) {
+            self.notify(Severity::Error, format!("Error writing .gitignore: {}", e));
+            return Ok(());
         }
 
-        // Clear progress and reset
-        self.copy_in_progress = false;
-        self.copy_progress_current = 0;
-        self.copy_progress_total = 0;
+        self.undo_stack.push(UndoAction::Edit { path: gitignore, before, after });
         self.redo_stack.clear();
         self.load_entries()?;
+        self.notify(Severity::Success, format!("Added '{}' to .gitignore", pattern));
         Ok(())
     }
 
-    fn copy_dir_recursive(&self, src: &PathBuf, dest: &PathBuf) -> io::Result<()> {
-        fs::create_dir_all(dest)?;
-        for entry in fs::read_dir(src)? {
-            let entry = entry?;
-            let src_path = entry.path();
-            let dest_path = dest.join(entry.file_name());
+    fn open_in_ide(&mut self) -> io::Result<()> {
+        let mut words = self.settings.ide_command.split_whitespace();
+        let Some(program) = words.next() else {
+            self.notify(Severity::Warning, "Set ide_command in the config to open directories in your IDE");
+            return Ok(());
+        };
 
-            if src_path.is_dir() {
-                self.copy_dir_recursive(&src_path, &dest_path)?;
-            } else {
-                fs::copy(&src_path, &dest_path)?;
+        // Run in the foreground so terminal editors like nvim work too; GUI
+        // launchers return right away
+        let status = run_suspended(std::process::Command::new(program).args(words).arg(&self.current_dir).current_dir(&self.current_dir));
+
+        match status {
+            Ok(status) if status.success() => {
+                self.notify(Severity::Success, format!("Opened {} in {}", self.current_dir.display(), program));
             }
+            Ok(status) => self.notify(Severity::Error, format!("{} exited with {}", program, status)),
+            Err(e) => self.notify(Severity::Error, format!("Couldn't run '{}': {} (see ide_command)", program, e)),
         }
         Ok(())
     }
 
-    fn move_to_trash(&mut self) -> io::Result<()> {
-        if let Some(path) = self.get_selected_path() {
-            let old_selected = self.selected;
-
-            // Use macOS trash command (stderr redirected to suppress sound)
-            let output = std::process::Command::new("osascript")
-                .arg("-e")
-                .arg(format!("tell application \"Finder\" to delete POSIX file \"{}\"", path.display()))
-                .stderr(std::process::Stdio::null())
-                .output()?;
+    /// Page through the selected file with $PAGER (less if unset), for reading
+    /// a long file whole without the risk of editing it.
+    fn open_in_pager(&mut self) -> io::Result<()> {
+        let Some(path) = self.get_selected_path().filter(|path| path.is_file()) else {
+            self.notify(Severity::Warning, "Select a file to read");
+            return Ok(());
+        };
+        let pager = env::var("PAGER").ok().filter(|pager| !pager.trim().is_empty()).unwrap_or_else(|| "less".to_string());
+        let mut words = pager.split_whitespace();
+        let Some(program) = words.next() else {
+            return Ok(());
+        };
 
-            if output.status.success() {
-                // Don't add to undo stack - can't reliably restore from trash
-                self.load_entries()?;
+        let status = run_suspended(std::process::Command::new(program).args(words).arg(&path).current_dir(&self.current_dir));
 
-                // Keep selection on same index, or previous if at end
-                if old_selected >= self.entries.len() && old_selected > 0 {
-                    self.selected = old_selected - 1;
-                } else if old_selected < self.entries.len() {
-                    self.selected = old_selected;
-                }
-            }
+        if let Err(e) = status {
+            self.notify(Severity::Error, format!("Couldn't run '{}': {} (see $PAGER)", program, e));
         }
         Ok(())
     }
@@ -2999,184 +13038,522 @@ impl FileBrowser {
     }
 
     fn delete_permanent(&mut self) -> io::Result<()> {
-        if let Some(path) = self.get_selected_path() {
-            let old_selected = self.selected;
+        let targets = self.action_targets();
+        if targets.is_empty() {
+            return Ok(());
+        }
+        let old_selected = self.selected;
+        let name = |path: &Path| path.file_name().unwrap_or_default().to_string_lossy().to_string();
 
-            // Disable raw mode to show confirmation
-            terminal::disable_raw_mode()?;
-            execute!(io::stdout(), cursor::Show)?;
+        // The marked entries stay highlighted in the listing while this is asked
+        let question = if let [path] = targets.as_slice() {
+            format!("Permanently delete '{}'? This cannot be undone!", name(path))
+        } else {
+            format!("Permanently delete these {} marked items? This cannot be undone!", targets.len())
+        };
+        if !self.confirm(&question)? {
+            return Ok(());
+        }
 
-            print!("\nPermanently delete '{}'? This cannot be undone! (y/N): ",
-                path.file_name().unwrap().to_str().unwrap());
-            io::stdout().flush()?;
+        let mut deleted = Vec::new();
+        for path in &targets {
+            let result = if path.is_dir() && !path.is_symlink() {
+                fs::remove_dir_all(path)
+            } else {
+                fs::remove_file(path)
+            };
+            match result {
+                Ok(()) => deleted.push(path.clone()),
+                Err(e) => {
+                    let command = ["rm".as_ref(), "-rf".as_ref(), "--".as_ref(), path.as_os_str()];
+                    if self.retry_elevated(&format!("Deleting '{}'", name(path)), &e, &command)? {
+                        deleted.push(path.clone());
+                    } else {
+                        self.notify(Severity::Error, format!("Error deleting '{}': {}", name(path), e));
+                    }
+                }
+            }
+        }
 
-            let mut response = String::new();
-            io::stdin().read_line(&mut response)?;
+        if !deleted.is_empty() {
+            let gone: HashSet<&PathBuf> = deleted.iter().collect();
+            self.marked.retain(|path| !gone.contains(path));
+            match targets.as_slice() {
+                [path] => self.notify(Severity::Success, format!("Deleted '{}'", name(path))),
+                _ => self.notify(Severity::Success, format!("Deleted {} items", deleted.len())),
+            }
+            if self.tags.forget(&deleted) {
+                self.save_tags();
+            }
 
-            // Re-enable raw mode
-            terminal::enable_raw_mode()?;
-            execute!(io::stdout(), cursor::Hide)?;
+            // Don't add to undo stack - can't restore deleted files
+            self.load_entries()?;
+            self.keep_selection_near(old_selected);
+        }
+        Ok(())
+    }
 
-            if response.trim().to_lowercase() == "y" {
-                let was_dir = path.is_dir();
-                if was_dir {
-                    fs::remove_dir_all(&path)?;
-                } else {
-                    fs::remove_file(&path)?;
+    fn undo(&mut self) -> io::Result<()> {
+        if let Some(action) = self.undo_stack.pop() {
+            let label = action.label();
+            let (undone, left, error) = self.step_parts(action, true, Self::revert);
+            let partly = undone.is_some();
+            if let Some(undone) = undone {
+                // Tags go back with the entries that made it back
+                let plan: Vec<(PathBuf, PathBuf)> = undone.moves().into_iter().map(|(from, to)| (to, from)).filter(|(_, to)| to.symlink_metadata().is_ok()).collect();
+                if self.tags.follow_renames(&plan) {
+                    self.save_tags();
                 }
+                self.redo_stack.push(undone);
+            }
+            if let Some(left) = left {
+                self.undo_stack.push(left);
+            }
+            match error {
+                None => self.notify(Severity::Info, format!("Undid {}", label)),
+                Some(e) if partly => self.notify(Severity::Error, format!("Undid some {}, the rest couldn't be: {}", label, e)),
+                Some(e) => self.notify(Severity::Error, format!("Couldn't undo {}: {}", label, e)),
+            }
+            self.load_entries()?;
+        } else {
+            self.notify(Severity::Info, "Nothing to undo");
+        }
+        Ok(())
+    }
 
-                // Don't add to undo stack - can't restore deleted files
-                self.load_entries()?;
-
-                // Keep selection on same index, or previous if at end
-                if old_selected >= self.entries.len() && old_selected > 0 {
-                    self.selected = old_selected - 1;
-                } else if old_selected < self.entries.len() {
-                    self.selected = old_selected;
+    fn redo(&mut self) -> io::Result<()> {
+        if let Some(action) = self.redo_stack.pop() {
+            let label = action.label();
+            let (redone, left, error) = self.step_parts(action, false, Self::reapply);
+            let partly = redone.is_some();
+            if let Some(redone) = redone {
+                let plan: Vec<(PathBuf, PathBuf)> = redone.moves().into_iter().filter(|(_, to)| to.symlink_metadata().is_ok()).collect();
+                if self.tags.follow_renames(&plan) {
+                    self.save_tags();
                 }
+                self.undo_stack.push(redone);
+            }
+            if let Some(left) = left {
+                self.redo_stack.push(left);
+            }
+            match error {
+                None => self.notify(Severity::Info, format!("Redid {}", label)),
+                Some(e) if partly => self.notify(Severity::Error, format!("Redid some {}, the rest couldn't be: {}", label, e)),
+                Some(e) => self.notify(Severity::Error, format!("Couldn't redo {}: {}", label, e)),
             }
+            self.load_entries()?;
+        } else {
+            self.notify(Severity::Info, "Nothing to redo");
         }
         Ok(())
     }
 
-    fn undo(&mut self) -> io::Result<()> {
-        if let Some(action) = self.undo_stack.pop() {
-            match &action {
-                UndoAction::Copy { dest, .. } => {
-                    // Undo copy: delete the destination
-                    if dest.is_dir() {
-                        fs::remove_dir_all(dest)?;
+    /// Runs `step` over a batch part by part (newest first when `reverse`), so
+    /// one that partly fails keeps its failed parts to try again. Returns the
+    /// parts that went through and the ones that failed, each as one action, and
+    /// the first error.
+    fn step_parts(
+        &self,
+        action: UndoAction,
+        reverse: bool,
+        step: fn(&Self, &UndoAction) -> io::Result<bool>,
+    ) -> (Option<UndoAction>, Option<UndoAction>, Option<io::Error>) {
+        let batch = matches!(action, UndoAction::Batch(_));
+        let mut parts = match action {
+            UndoAction::Batch(actions) => actions,
+            action => vec![action],
+        };
+        if reverse {
+            parts.reverse();
+        }
+        let mut done = Vec::new();
+        let mut failed = Vec::new();
+        let mut error = None;
+        for part in parts {
+            match step(self, &part) {
+                Ok(true) => done.push(part),
+                Ok(false) => {}
+                Err(e) => {
+                    error.get_or_insert(e);
+                    failed.push(part);
+                }
+            }
+        }
+        let join = |mut parts: Vec<UndoAction>| {
+            if reverse {
+                parts.reverse();
+            }
+            match parts.len() {
+                0 => None,
+                1 if !batch => parts.pop(),
+                _ => Some(UndoAction::Batch(parts)),
+            }
+        };
+        (join(done), join(failed), error)
+    }
+
+    /// Undo one action. Returns false if there was nothing left to undo, in
+    /// which case it isn't offered for redo.
+    fn revert(&self, action: &UndoAction) -> io::Result<bool> {
+        match action {
+            UndoAction::Copy { dest, .. } => {
+                // Undo copy: delete the destination
+                if dest.is_dir() {
+                    fs::remove_dir_all(dest)?;
+                } else {
+                    fs::remove_file(dest)?;
+                }
+                Ok(true)
+            }
+            UndoAction::Rename { old_path, new_path } => {
+                // Undo rename: rename back to old name
+                if new_path.exists() {
+                    fs::rename(new_path, old_path)?;
+                    return Ok(true);
+                }
+                Ok(false)
+            }
+            UndoAction::RenameBatch { renames } => {
+                // Undo batch rename: apply the reversed plan
+                let reversed: Vec<(PathBuf, PathBuf)> = renames.iter().map(|(old, new)| (new.clone(), old.clone())).collect();
+                rename_all(&reversed)?;
+                Ok(true)
+            }
+            UndoAction::Create { path, was_dir } => {
+                // Undo create: delete the created file/directory
+                if path.exists() {
+                    if *was_dir {
+                        fs::remove_dir_all(path)?;
                     } else {
-                        fs::remove_file(dest)?;
+                        fs::remove_file(path)?;
                     }
-                    self.redo_stack.push(action);
+                    return Ok(true);
+                }
+                Ok(false)
+            }
+            UndoAction::Extract { dest: path, .. } | UndoAction::Compress { archive: path, .. } => {
+                // Undo extract or compress: delete what it made
+                match path.symlink_metadata() {
+                    Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path)?,
+                    Ok(_) => fs::remove_file(path)?,
+                    Err(_) => return Ok(false),
+                }
+                Ok(true)
+            }
+            UndoAction::Move { src, dest } => {
+                // Undo move: put the item back where it came from
+                if dest.symlink_metadata().is_err() {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, format!("'{}' is no longer there", dest.display())));
+                }
+                self.move_path(dest, src)?;
+                Ok(true)
+            }
+            UndoAction::Batch(actions) => {
+                let mut any = false;
+                for action in actions.iter().rev() {
+                    any |= self.revert(action)?;
+                }
+                Ok(any)
+            }
+            UndoAction::Edit { path, before, .. } => {
+                // Undo edit: restore the old contents, or remove the file it created
+                match before {
+                    Some(before) => fs::write(path, before)?,
+                    None => fs::remove_file(path)?,
+                }
+                Ok(true)
+            }
+            UndoAction::Delete { .. } => {
+                // These shouldn't be in the stack, but if they are, ignore them
+                Ok(false)
+            }
+        }
+    }
+
+    /// Redo one undone action. Returns false if it couldn't be applied again.
+    fn reapply(&self, action: &UndoAction) -> io::Result<bool> {
+        match action {
+            UndoAction::Copy { src, dest } => {
+                // Redo copy
+                if src.is_dir() {
+                    self.copy_dir_recursive(src, dest)?;
+                } else {
+                    fs::copy(src, dest)?;
                 }
-                UndoAction::Rename { old_path, new_path } => {
-                    // Undo rename: rename back to old name
-                    if new_path.exists() {
-                        fs::rename(new_path, old_path)?;
-                        self.redo_stack.push(action);
+                Ok(true)
+            }
+            UndoAction::Rename { old_path, new_path } => {
+                // Redo rename: rename to new name
+                if old_path.exists() {
+                    fs::rename(old_path, new_path)?;
+                    return Ok(true);
+                }
+                Ok(false)
+            }
+            UndoAction::RenameBatch { renames } => {
+                rename_all(renames)?;
+                Ok(true)
+            }
+            UndoAction::Create { path, was_dir } => {
+                // Redo create: recreate the file/directory
+                if *was_dir {
+                    fs::create_dir_all(path)?;
+                } else {
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent)?;
                     }
+                    fs::File::create(path)?;
                 }
-                UndoAction::Create { path, was_dir } => {
-                    // Undo create: delete the created file/directory
-                    if path.exists() {
-                        if *was_dir {
-                            fs::remove_dir_all(path)?;
-                        } else {
-                            fs::remove_file(path)?;
-                        }
-                        self.redo_stack.push(action);
-                    }
+                Ok(true)
+            }
+            UndoAction::Extract { archive, dest, lifted } => {
+                // Redo extract: unpack the archive again the same way
+                if dest.symlink_metadata().is_ok() {
+                    return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("'{}' is in the way", dest.display())));
                 }
-                UndoAction::Move { .. } | UndoAction::Delete { .. } => {
-                    // These shouldn't be in the stack, but if they are, ignore them
+                let name = dest.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+                let unpacked = numbered_path(dest.parent().unwrap_or(Path::new(".")), &format!(".{}.extracting", name), "");
+                let result = fs::create_dir(&unpacked)
+                    .and_then(|_| extract_archive(archive, &unpacked, &mut |_, _| {}))
+                    .and_then(|_| place_extracted(&unpacked, dest, *lifted));
+                if result.is_err() {
+                    let _ = fs::remove_dir_all(&unpacked);
                 }
+                result.map(|_| true)
             }
-            self.load_entries()?;
-        }
-        Ok(())
-    }
-
-    fn redo(&mut self) -> io::Result<()> {
-        if let Some(action) = self.redo_stack.pop() {
-            match &action {
-                UndoAction::Copy { src, dest } => {
-                    // Redo copy
-                    if src.is_dir() {
-                        self.copy_dir_recursive(src, dest)?;
-                    } else {
-                        fs::copy(src, dest)?;
-                    }
-                    self.undo_stack.push(action);
+            UndoAction::Compress { sources, base, archive, format } => {
+                // Redo compress: pack the sources again
+                if archive.symlink_metadata().is_ok() {
+                    return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("'{}' is in the way", archive.display())));
                 }
-                UndoAction::Rename { old_path, new_path } => {
-                    // Redo rename: rename to new name
-                    if old_path.exists() {
-                        fs::rename(old_path, new_path)?;
-                        self.undo_stack.push(action);
-                    }
+                if let Err(e) = write_archive(sources, base, archive, format, &mut |_, _| {}) {
+                    let _ = fs::remove_file(archive);
+                    return Err(e);
                 }
-                UndoAction::Create { path, was_dir } => {
-                    // Redo create: recreate the file/directory
-                    if *was_dir {
-                        fs::create_dir_all(path)?;
-                    } else {
-                        if let Some(parent) = path.parent() {
-                            fs::create_dir_all(parent)?;
-                        }
-                        fs::File::create(path)?;
-                    }
-                    self.undo_stack.push(action);
+                Ok(true)
+            }
+            UndoAction::Move { src, dest } => {
+                // Redo move
+                if src.symlink_metadata().is_err() {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, format!("'{}' is no longer there", src.display())));
                 }
-                UndoAction::Move { .. } | UndoAction::Delete { .. } => {
-                    // These shouldn't be in the stack, but if they are, ignore them
+                self.move_path(src, dest)?;
+                Ok(true)
+            }
+            UndoAction::Batch(actions) => {
+                let mut any = false;
+                for action in actions {
+                    any |= self.reapply(action)?;
                 }
+                Ok(any)
+            }
+            UndoAction::Edit { path, after, .. } => {
+                fs::write(path, after)?;
+                Ok(true)
+            }
+            UndoAction::Delete { .. } => {
+                // These shouldn't be in the stack, but if they are, ignore them
+                Ok(false)
             }
-            self.load_entries()?;
         }
-        Ok(())
     }
 
-    fn read_input_with_escape(prompt: &str) -> io::Result<Option<String>> {
-        use crossterm::event::{self, Event, KeyCode};
+    /// Ask for a line of input on the bottom row, over the listing. Each `kind` of
+    /// prompt keeps its own Up/Down history; `complete_paths` turns on Tab
+    /// completion of file names. Returns None if cancelled with Esc.
+    fn prompt(&mut self, kind: &'static str, label: &str, initial: &str, complete_paths: bool) -> io::Result<Option<String>> {
+        self.prompt_checked(kind, label, initial, complete_paths, &|_, _| None)
+    }
 
-        execute!(io::stdout(), cursor::Show)?;
+    /// Ask a yes/no question on the bottom row; only an answer of y confirms.
+    fn confirm(&mut self, question: &str) -> io::Result<bool> {
+        let answer = self.prompt("confirm", &format!("{} (y/N): ", question), "", false)?;
+        Ok(answer.is_some_and(|answer| answer.trim().eq_ignore_ascii_case("y")))
+    }
 
-        print!("{}", prompt);
-        io::stdout().flush()?;
+    /// When `error` is a permission error (EPERM or EACCES), offer to run
+    /// `command` as administrator instead: sudo, or the password dialog on macOS.
+    /// Whatever was run, and how it went, is kept in the message history.
+    /// Returns whether the command ran and succeeded.
+    fn retry_elevated(&mut self, what: &str, error: &io::Error, command: &[&std::ffi::OsStr]) -> io::Result<bool> {
+        if error.kind() != io::ErrorKind::PermissionDenied {
+            return Ok(false);
+        }
+        if !self.confirm(&format!("{}: {}. Retry as administrator?", what, error))? {
+            return Ok(false);
+        }
+        let line = shell_line(command);
+        let (_, height) = terminal::size()?;
+
+        // sudo asks for a password on the terminal, below the listing
+        let result = suspended(|| {
+            execute!(
+                io::stdout(),
+                cursor::MoveTo(0, height.saturating_sub(1)),
+                terminal::Clear(ClearType::CurrentLine),
+                Print(format!("Running as administrator: {}\r\n", line))
+            )?;
+            run_elevated(command)
+        })?;
 
-        let mut input = String::new();
-        loop {
-            if event::poll(std::time::Duration::from_millis(100))? {
-                if let Event::Key(key_event) = event::read()? {
-                    match key_event.code {
-                        KeyCode::Esc => {
-                            println!();
-                            execute!(io::stdout(), cursor::Hide)?;
-                            return Ok(None);
-                        }
-                        KeyCode::Enter => {
-                            println!();
-                            execute!(io::stdout(), cursor::Hide)?;
-                            return Ok(Some(input));
-                        }
-                        KeyCode::Char(c) => {
-                            input.push(c);
-                            print!("{}", c);
-                            io::stdout().flush()?;
-                        }
-                        KeyCode::Backspace => {
-                            if !input.is_empty() {
-                                input.pop();
-                                print!("\x08 \x08");
-                                io::stdout().flush()?;
-                            }
-                        }
-                        _ => {}
+        let outcome = match &result {
+            Ok(()) => "ok".to_string(),
+            Err(e) => format!("failed: {}", e),
+        };
+        if let Err(e) = log_elevated(&line, &outcome) {
+            self.notify(Severity::Warning, format!("Couldn't log the administrator command: {}", e));
+        }
+        match result {
+            Ok(()) => {
+                self.notify(Severity::Info, format!("Ran as administrator: {}", line));
+                Ok(true)
+            }
+            Err(e) => {
+                self.notify(Severity::Error, format!("Running as administrator failed ({}): {}", e, line));
+                Ok(false)
+            }
+        }
+    }
+
+    /// A `prompt` that runs `check` on the text after every key and shows what it
+    /// says next to the input. Enter does nothing while the check reports an error.
+    fn prompt_checked(
+        &mut self,
+        kind: &'static str,
+        label: &str,
+        initial: &str,
+        complete_paths: bool,
+        check: PromptCheck,
+    ) -> io::Result<Option<String>> {
+        self.prompt_editors.entry(kind).or_default().start(initial);
+        self.prompt = Some((label.to_string(), kind));
+        let result = loop {
+            let text = self.prompt_editors.get(kind).map(|editor| editor.text.clone()).unwrap_or_default();
+            self.prompt_feedback = check(self, &text);
+            self.draw()?;
+            let Event::Key(KeyEvent { code, modifiers, .. }) = event::read()? else {
+                continue;
+            };
+            let blocked = matches!(self.prompt_feedback, Some((Severity::Error, _)));
+            let editor = self.prompt_editors.entry(kind).or_default();
+            match editor.handle_key(code, modifiers) {
+                LineEdit::Submit if blocked => {}
+                LineEdit::Submit => break Some(editor.submit()),
+                LineEdit::Cancel => break None,
+                LineEdit::Complete if complete_paths => {
+                    let before = editor.before_cursor().to_string();
+                    if let Some(completed) = self.complete_path_input(&before) {
+                        self.prompt_editors.entry(kind).or_default().complete_with(completed);
                     }
                 }
+                _ => {}
             }
+        };
+        self.prompt = None;
+        self.prompt_feedback = None;
+        Ok(result)
+    }
+
+    /// The portability problem of a name about to be created, as an error under
+    /// `filename_check = "strict"` and a warning otherwise.
+    fn portability_feedback(&self, name: &str) -> Option<(Severity, String)> {
+        if self.settings.filename_check == "off" {
+            return None;
+        }
+        let problem = portability_problem(name)?;
+        let severity = if self.settings.filename_check == "strict" { Severity::Error } else { Severity::Warning };
+        Some((severity, problem))
+    }
+
+    /// Live feedback for the create prompt: what will be made, or why it can't be.
+    fn check_create_input(&self, input: &str) -> Option<(Severity, String)> {
+        let input = input.trim();
+        if input.is_empty() {
+            return None;
+        }
+        let path = self.resolve_input_path(input);
+        let Some(name) = path.file_name().map(|name| name.to_string_lossy().into_owned()) else {
+            return Some((Severity::Error, "Type a name to create".to_string()));
+        };
+        if let Ok(metadata) = path.symlink_metadata() {
+            let kind = if metadata.is_dir() { "directory" } else { "file" };
+            return Some((Severity::Error, format!("A {} named '{}' already exists", kind, name)));
         }
+        // The nearest existing ancestor has to be a directory for the rest to go in it
+        let existing = path.ancestors().skip(1).find(|ancestor| ancestor.symlink_metadata().is_ok());
+        if let Some(blocker) = existing.filter(|ancestor| !ancestor.is_dir()) {
+            return Some((Severity::Error, format!("'{}' is a file, not a directory", self.tilde_path(blocker))));
+        }
+        if let Some(problem) = self.portability_feedback(&name) {
+            return Some(problem);
+        }
+        let kind = if input.ends_with('/') { "directory" } else { "file" };
+        let parents = if existing == path.parent() { "" } else { " and its missing parent directories" };
+        Some((Severity::Info, format!("New {} '{}'{}", kind, name, parents)))
+    }
+
+    /// Live feedback for the rename prompt: where a move goes, or why the new
+    /// name can't be used.
+    fn check_rename_input(&self, from: &Path, input: &str) -> Option<(Severity, String)> {
+        let input = expand_path_vars(input.trim());
+        let old_name = from.file_name()?;
+        if input.is_empty() || *input == *old_name {
+            return None;
+        }
+        let parent = from.parent().unwrap_or(&self.current_dir);
+        let mut dest = parent.join(&input);
+        let moving = input.contains('/');
+        if moving && (input.ends_with('/') || dest.is_dir()) {
+            dest = dest.join(old_name);
+        }
+        let name = dest.file_name().map(|name| name.to_string_lossy().into_owned());
+        let Some(name) = name.filter(|name| name != "." && name != "..") else {
+            return Some((Severity::Error, "Type a name or a path to move to".to_string()));
+        };
+        if moving && !dest.parent().is_some_and(|dir| dir.is_dir()) {
+            let dir = dest.parent().map(|dir| self.tilde_path(dir)).unwrap_or_default();
+            return Some((Severity::Error, format!("There is no directory {}", dir)));
+        }
+        // A case-only rename on a case-insensitive filesystem finds the file itself
+        let same_file = fs::canonicalize(&dest).ok().is_some_and(|dest| fs::canonicalize(from).is_ok_and(|from| from == dest));
+        if dest.symlink_metadata().is_ok() && !same_file {
+            return Some((Severity::Error, format!("'{}' already exists", name)));
+        }
+        if let Some(problem) = self.portability_feedback(&name) {
+            return Some(problem);
+        }
+        moving.then(|| (Severity::Info, format!("Moves to {}", self.tilde_path(&dest))))
     }
 
     fn create_new(&mut self) -> io::Result<()> {
-        if let Some(input) = Self::read_input_with_escape("\nCreate (end with / for directory): ")? {
+        let check = |browser: &Self, input: &str| browser.check_create_input(input);
+        if let Some(input) = self.prompt_checked("create", "Create (end with / for directory): ", "", true, &check)? {
             let input = input.trim();
 
             if !input.is_empty() {
-                let path = self.current_dir.join(input);
+                let path = self.resolve_input_path(input);
                 let is_dir = input.ends_with('/');
+                if !self.check_portable_name(&path) {
+                    return Ok(());
+                }
 
-                if is_dir {
+                let result = if is_dir {
                     // Create directory
-                    fs::create_dir_all(&path)?;
+                    fs::create_dir_all(&path)
                 } else {
                     // Create file (touch)
-                    if let Some(parent) = path.parent() {
-                        fs::create_dir_all(parent)?;
+                    path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::File::create(&path).map(|_| ()))
+                };
+                if let Err(e) = result {
+                    let command: [&std::ffi::OsStr; 3] = match is_dir {
+                        true => ["mkdir".as_ref(), "-p".as_ref(), path.as_os_str()],
+                        false => ["touch".as_ref(), "--".as_ref(), path.as_os_str()],
+                    };
+                    let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                    if !self.retry_elevated(&format!("Creating '{}'", name), &e, &command)? {
+                        self.notify(Severity::Error, format!("Error creating '{}': {}", name, e));
+                        return Ok(());
                     }
-                    fs::File::create(&path)?;
                 }
 
                 self.undo_stack.push(UndoAction::Create {
@@ -3192,6 +13569,33 @@ impl FileBrowser {
     }
 }
 
+/// Print numbered options and read a choice; Enter alone picks the first.
+fn prompt_choice(title: &str, options: &[(&'static str, &str)]) -> io::Result<&'static str> {
+    println!("\n{}\n", title);
+    for (i, (name, description)) in options.iter().enumerate() {
+        println!("  {}) {:<13} {}", i + 1, name, description);
+    }
+    loop {
+        print!("\nSelect 1-{} [1]: ", options.len());
+        io::stdout().flush()?;
+        let mut response = String::new();
+        io::stdin().read_line(&mut response)?;
+        let response = response.trim();
+        if response.is_empty() {
+            return Ok(options[0].0);
+        }
+        let choice = response
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| options.get(n.wrapping_sub(1)))
+            .or_else(|| options.iter().find(|(name, _)| *name == response));
+        if let Some((name, _)) = choice {
+            return Ok(name);
+        }
+        println!("Please enter a number between 1 and {}.", options.len());
+    }
+}
+
 fn show_welcome_pages() -> io::Result<()> {
     use crossterm::event::{self, Event};
 
@@ -3227,8 +13631,6 @@ ils() {
         rm /tmp/ils_cd
         if [ -d "$target" ]; then
             cd "$target"
-        else
-            echo "$target"
         fi
     fi
 }
@@ -3239,7 +13641,7 @@ ils() {
             let mut file = OpenOptions::new()
                 .create(true)
                 .append(true)
-                .open(&rc_path)?;
+                .open(rc_path)?;
 
             file.write_all(shell_function.as_bytes())?;
             println!("\n✓ Shell integration added to {}", rc_path);
@@ -3260,7 +13662,27 @@ ils() {
     }
     terminal::disable_raw_mode()?;
 
-    // Page 2: Getting Started
+    // Page 2: Keybindings and theme
+    execute!(io::stdout(), terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+    println!("\n{}", "=".repeat(60));
+    println!("  Keybindings and Colors");
+    println!("{}", "=".repeat(60));
+    let keymap = prompt_choice("Choose a keybinding preset:", &[
+        ("wasd", "w/a/s/d to move, l to open, j to go back (default)"),
+        ("vim", "h/j/k/l, gg/G, dd to trash, yy/p to copy and paste, : for a path"),
+        ("emacs", "Ctrl+n/p/f/b to move, Ctrl+s search, Ctrl+g cancel"),
+        ("arrows", "arrow keys to move; w/a/s/d jump to matching entries instead"),
+    ])?;
+    let theme = prompt_choice("Choose a color theme:", &[
+        ("default", "cyan directories on a dark path bar (default)"),
+        ("light", "darker colors for light terminal backgrounds"),
+        ("mono", "no accent colors"),
+        ("high-contrast", "black/white/yellow for low vision"),
+        ("colorblind", "blue/orange/yellow, no red-green cues (deuteranopia, protanopia)"),
+    ])?;
+    println!("\nUsing keymap \"{}\" and theme \"{}\". Change them any time in [settings] with 'ils config'.", keymap, theme);
+
+    // Page 3: Getting Started
     execute!(io::stdout(), terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
     println!("\n{}", "=".repeat(60));
     println!("  Welcome to ils - Interactive File Browser");
@@ -3285,10 +13707,15 @@ ils() {
     }
     terminal::disable_raw_mode()?;
 
-    // Create default config only if it doesn't exist
+    // Create default config with the chosen presets, or record them in an existing one
     if let Some(config_path) = Config::path() {
-        if !config_path.exists() {
-            Config::create_default()?;
+        if config_path.exists() {
+            Config::update_settings(|settings| {
+                settings.keymap = keymap.to_string();
+                settings.theme = theme.to_string();
+            })?;
+        } else {
+            Config::create_default_with(keymap, theme)?;
         }
     }
     execute!(io::stdout(), terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
@@ -3299,12 +13726,18 @@ ils() {
 fn print_help() {
     println!("ils v0.1.1 - Interactive file browser for the terminal\n");
     println!("USAGE:");
-    println!("    ils [OPTIONS]\n");
+    println!("    ils [OPTIONS] [DIRECTORY]");
+    println!("    ils --profile-startup [DIRECTORY]\n");
+    println!("ARGS:");
+    println!("    DIRECTORY        Directory to start in (default: the current directory)\n");
     println!("OPTIONS:");
     println!("    -h, --help       Show this help message");
     println!("    -v, --version    Show version information");
     println!("    --install        Install shell integration and create default config");
-    println!("    --config, config Open configuration file in $EDITOR\n");
+    println!("    --profile-startup  Time each startup phase up to the first frame, then quit");
+    println!("    --config, config Open configuration file in $EDITOR");
+    println!("    bookmarks export [FILE]  Write bookmarks and shortcuts to FILE (or print them)");
+    println!("    bookmarks import FILE    Merge bookmarks and shortcuts from FILE, skipping duplicates\n");
     println!("INTERACTIVE KEYS:");
     println!("    Press '!' inside ils to toggle the help menu with all keybindings\n");
     println!("CONFIGURATION:");
@@ -3314,7 +13747,8 @@ fn print_help() {
 }
 
 fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
+    let started = Instant::now();
+    let mut args: Vec<String> = env::args().collect();
 
     // Check for --help flag
     if args.len() > 1 && (args[1] == "--help" || args[1] == "-h" || args[1] == "help") {
@@ -3354,6 +13788,23 @@ fn main() -> io::Result<()> {
         return Ok(());
     }
 
+    // Bookmarks and shortcuts, to carry over to another machine
+    if args.len() > 1 && args[1] == "bookmarks" {
+        let result = match (args.get(2).map(String::as_str), args.get(3)) {
+            (Some("export"), file) => BookmarkExport::export(file.map(String::as_str)),
+            (Some("import"), Some(file)) => BookmarkExport::import(file),
+            _ => {
+                eprintln!("usage: ils bookmarks export [FILE] | ils bookmarks import FILE");
+                std::process::exit(2);
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("ils: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     // Check for --init flag (legacy)
     if args.len() > 1 && args[1] == "--init" {
         println!(r#"# Interactive ls (ils) - Add this to your ~/.zshrc or ~/.bashrc
@@ -3365,8 +13816,6 @@ ils() {{
         rm /tmp/ils_cd
         if [ -d "$target" ]; then
             cd "$target"
-        else
-            echo "$target"
         fi
     fi
 }}
@@ -3374,6 +13823,39 @@ ils() {{
         return Ok(());
     }
 
+    // --profile-startup times each phase up to the first frame, then quits
+    let profile_startup = args.get(1).is_some_and(|arg| arg == "--profile-startup");
+    if profile_startup {
+        args.remove(1);
+    }
+    let mut phases: Vec<(&str, Duration)> = Vec::new();
+    let mut phase_start = started;
+    let mut end_phase = |name| {
+        phases.push((name, phase_start.elapsed()));
+        phase_start = Instant::now();
+    };
+
+    // Optional starting directory, checked before anything touches the terminal
+    let start_dir = match args.get(1) {
+        Some(arg) if arg.starts_with('-') => {
+            eprintln!("ils: unknown option '{}' (see ils --help)", arg);
+            std::process::exit(2);
+        }
+        Some(arg) => match PathBuf::from(expand_path_vars(arg)).canonicalize() {
+            Ok(path) if path.is_dir() => path,
+            Ok(_) => {
+                eprintln!("ils: '{}' is not a directory", arg);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("ils: can't open '{}': {}", arg, e);
+                std::process::exit(1);
+            }
+        },
+        None => env::current_dir()?,
+    };
+    end_phase("arguments");
+
     // Check for first run and show welcome pages
     let first_run = Config::path().map(|p| !p.exists()).unwrap_or(true);
 
@@ -3391,6 +13873,7 @@ ils() {{
     if first_run || config.settings.debug_show_welcome {
         show_welcome_pages()?;
     }
+    end_phase("config");
 
     // Check if wrapper is installed (with caching)
     let mut wrapper_installed = config.settings.wrapper_validation_cache_valid;
@@ -3399,18 +13882,16 @@ ils() {{
         wrapper_installed = check_wrapper_installed();
 
         // Update cache if wrapper is installed
-        if wrapper_installed {
-            if Config::path().is_some() {
-                // Reload config, update cache flag, and save
-                let (mut updated_config, _) = Config::load();
-                updated_config.settings.wrapper_validation_cache_valid = true;
-                let _ = updated_config.save();
-            }
+        if wrapper_installed && Config::path().is_some() {
+            // Update cache flag in the saved config
+            let _ = Config::update_settings(|settings| settings.wrapper_validation_cache_valid = true);
         }
     }
 
-    let start_dir = env::current_dir()?;
+    end_phase("shell wrapper check");
+
     let mut browser = FileBrowser::new(start_dir)?;
+    end_phase("browser state and listing");
 
     // Set wrapper warning flag if not installed
     browser.wrapper_warning = !wrapper_installed;
@@ -3418,25 +13899,51 @@ ils() {{
     terminal::enable_raw_mode()?;
     let mut stdout = io::stdout();
     // Use execute! for initial setup commands that should happen before the loop starts
-    execute!(stdout, cursor::Hide)?;
+    execute!(stdout, cursor::Hide, EnableFocusChange)?;
+    // Ask the terminal about graphics before the event loop starts reading its input
+    browser.image_protocol();
+    end_phase("graphics detection");
+
+    if profile_startup {
+        browser.draw()?;
+        end_phase("first draw");
+        execute!(stdout, cursor::Show, DisableFocusChange)?;
+        terminal::disable_raw_mode()?;
+        execute!(stdout, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+        for (name, time) in &phases {
+            println!("{:<28}{:>8.1} ms", name, time.as_secs_f64() * 1000.0);
+        }
+        println!("{:<28}{:>8.1} ms", "total", started.elapsed().as_secs_f64() * 1000.0);
+        return Ok(());
+    }
 
     // We store the result as an Option<PathBuf> now
     let result = run_browser(&mut browser);
+    let _ = browser.save_preview_positions();
 
     // Clean up
-    execute!(stdout, cursor::Show)?;
+    execute!(stdout, cursor::Show, DisableFocusChange)?;
     terminal::disable_raw_mode()?;
 
+    let mut printed_path = None;
+    let mut exit_command = None;
+    let final_dir = match &result {
+        Ok(ExitAction::Cd(path)) => path.clone(),
+        _ => browser.get_current_dir().clone(),
+    };
     match result {
         Ok(ExitAction::Cd(final_path)) => {
             // Write to temp file for the shell wrapper to read.
             let _ = fs::write("/tmp/ils_cd", final_path.display().to_string());
         }
-        Ok(ExitAction::OpenInFinder(final_path)) => {
-            // Open directory in Finder
-            let _ = std::process::Command::new("open")
-                .arg(&final_path)
-                .spawn();
+        Ok(ExitAction::OpenInFinder(final_path)) => open_in_file_manager(&final_path),
+        Ok(ExitAction::PrintPath(path)) => {
+            // Printed after the screen is cleared below so it stays visible
+            printed_path = Some(path);
+        }
+        Ok(ExitAction::Run(command, dir)) => {
+            // Also run after the screen is cleared so its output stays visible
+            exit_command = Some((command, dir));
         }
         Ok(ExitAction::None) => {
             // Quit without action (q)
@@ -3452,6 +13959,26 @@ ils() {{
     // Clear screen after delay
     execute!(stdout, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
 
+    if let Some(path) = printed_path {
+        let quoted = shell_quote(&path);
+        if browser.settings.path_output == "clipboard" {
+            if copy_to_system_clipboard(&quoted) {
+                println!("Copied {} to the clipboard", quoted);
+            } else {
+                eprintln!("No clipboard tool found (pbcopy, wl-copy, xclip or xsel); path: {}", quoted);
+            }
+        } else {
+            println!("{}", quoted);
+        }
+    }
+
+    if let Some((command, dir)) = exit_command {
+        run_exit_command(&command, &dir);
+    }
+    if !browser.settings.on_exit.is_empty() {
+        run_exit_command(&browser.settings.on_exit, &final_dir);
+    }
+
     Ok(())
 }
 
@@ -3459,13 +13986,39 @@ enum ExitAction {
     None,
     Cd(PathBuf),
     OpenInFinder(PathBuf),
+    PrintPath(PathBuf),
+    Run(String, PathBuf),
 }
 
 fn run_browser(browser: &mut FileBrowser) -> io::Result<ExitAction> {
     loop {
+        browser.expire_toasts();
+        browser.refresh_if_changed()?;
+        browser.refresh_preview_if_changed();
         browser.draw()?;
 
-        match event::read()? {
+        let event = match browser.replay_event.take() {
+            Some(event) => event,
+            None => {
+                // Nothing typed yet: use the idle moment to preload neighbouring previews
+                if !event::poll(Duration::ZERO)? {
+                    browser.flush_desktop_notification();
+                    browser.prefetch_adjacent_previews();
+                }
+                // Wake up when the next toast expires so it disappears on its own
+                if let Some(wait) = browser.next_wakeup() {
+                    if !event::poll(wait)? {
+                        // No second key in time: the pending sequence runs as typed
+                        if browser.sequence_deadline().is_some_and(|deadline| deadline <= Instant::now()) {
+                            browser.replay_event = Some(Event::Key(KeyEvent::new(KeyCode::Null, KeyModifiers::NONE)));
+                        }
+                        continue;
+                    }
+                }
+                event::read()?
+            }
+        };
+        match event {
             Event::Key(KeyEvent { code, modifiers, .. }) => {
                 // Check if input is blocked
                 if let Some(block_until) = browser.input_block_until {
@@ -3478,8 +14031,28 @@ fn run_browser(browser: &mut FileBrowser) -> io::Result<ExitAction> {
                     }
                 }
 
-                // Clear error message on any key press
-                browser.error_message = None;
+                // A configured cancel key (e.g. Ctrl+g) works like Esc inside any mode
+                let code = match code {
+                    KeyCode::Char(ch) if browser.has_active_mode()
+                        && browser.keybindings.matches(&browser.keybindings.cancel, &Keybindings::key_name(ch, modifiers)) => KeyCode::Esc,
+                    _ => code,
+                };
+
+                // Handle content search
+                if browser.grep_mode {
+                    match code {
+                        KeyCode::Esc => browser.end_grep(),
+                        KeyCode::Enter => browser.open_grep_match()?,
+                        KeyCode::Up | KeyCode::BackTab => browser.select_up(),
+                        KeyCode::Down | KeyCode::Tab => browser.select_down(),
+                        _ => {
+                            if browser.grep_editor.handle_key(code, modifiers) == LineEdit::Changed {
+                                browser.run_grep();
+                            }
+                        }
+                    }
+                    continue;
+                }
 
                 // Handle history mode (zoxide-style)
                 if browser.history_mode {
@@ -3494,7 +14067,7 @@ fn run_browser(browser: &mut FileBrowser) -> io::Result<ExitAction> {
                         KeyCode::Char('q') => {
                             // Exit both history mode and app
                             browser.history_mode = false;
-                            return Ok(ExitAction::Cd(browser.get_current_dir().clone()));
+                            return Ok(browser.exit_action(&browser.settings.quit_action));
                         }
                         KeyCode::Enter => {
                             // Jump to selected directory
@@ -3506,7 +14079,7 @@ fn run_browser(browser: &mut FileBrowser) -> io::Result<ExitAction> {
                                     browser.current_dir = target_path;
                                     browser.breadcrumbs.clear();
                                     let _ = browser.load_entries();
-                                    browser.record_directory_visit();
+                                    browser.directory_entered();
                                 }
                             }
                             continue;
@@ -3543,29 +14116,132 @@ fn run_browser(browser: &mut FileBrowser) -> io::Result<ExitAction> {
                     }
                 }
 
+                // Handle full help screen
+                if browser.help_screen_mode {
+                    let page = terminal::size().map(|(_, h)| h as usize).unwrap_or(20).saturating_sub(4).max(1);
+                    match code {
+                        KeyCode::Esc => {
+                            browser.help_screen_mode = false;
+                            browser.help_screen_query.clear();
+                        }
+                        KeyCode::Up => browser.help_screen_scroll = browser.help_screen_scroll.saturating_sub(1),
+                        KeyCode::Down => browser.help_screen_scroll += 1,
+                        KeyCode::PageUp => browser.help_screen_scroll = browser.help_screen_scroll.saturating_sub(page),
+                        KeyCode::PageDown => browser.help_screen_scroll += page,
+                        KeyCode::Backspace => {
+                            browser.help_screen_query.pop();
+                            browser.help_screen_scroll = 0;
+                        }
+                        KeyCode::Char(ch) => {
+                            browser.help_screen_query.push(ch);
+                            browser.help_screen_scroll = 0;
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
+
+                // Handle message history panel
+                if browser.messages_mode {
+                    let page = terminal::size().map(|(_, h)| h as usize).unwrap_or(20).saturating_sub(4).max(1);
+                    match code {
+                        KeyCode::Esc | KeyCode::Char('q') => browser.messages_mode = false,
+                        KeyCode::Up => browser.messages_scroll = browser.messages_scroll.saturating_sub(1),
+                        KeyCode::Down => browser.messages_scroll += 1,
+                        KeyCode::PageUp => browser.messages_scroll = browser.messages_scroll.saturating_sub(page),
+                        KeyCode::PageDown => browser.messages_scroll += page,
+                        KeyCode::Char(ch) if browser.keybindings.contains(&browser.keybindings.messages, ch) => {
+                            browser.messages_mode = false;
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
+
+                // Handle details panel
+                if browser.info_panel.is_some() {
+                    match code {
+                        KeyCode::Esc | KeyCode::Char('q') => browser.info_panel = None,
+                        KeyCode::Char(ch)
+                            if browser.keybindings.contains(&browser.keybindings.filesystem_info, ch)
+                                || browser.keybindings.contains(&browser.keybindings.file_info, ch)
+                                || browser.keybindings.contains(&browser.keybindings.diagnostics, ch) =>
+                        {
+                            browser.info_panel = None;
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
+
+                // Handle editable path bar
+                if browser.path_edit_mode {
+                    match browser.path_editor.handle_key(code, modifiers) {
+                        LineEdit::Cancel => browser.path_edit_mode = false,
+                        LineEdit::Submit => {
+                            browser.path_edit_mode = false;
+                            let input = browser.path_editor.submit().trim().to_string();
+                            if !input.is_empty() {
+                                let input = browser.expand_shortcut(&input).unwrap_or(input);
+                                let target = browser.resolve_input_path(&input);
+                                browser.go_to_path(target)?;
+                            }
+                        }
+                        LineEdit::Complete => {
+                            if let Some(completed) = browser.complete_path_input(browser.path_editor.before_cursor()) {
+                                browser.path_editor.complete_with(completed);
+                            }
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
+
+                // Handle incremental filter mode
+                if browser.filter_mode {
+                    match code {
+                        KeyCode::Esc => browser.end_filter(),
+                        KeyCode::Enter => {
+                            // Keep the match selected; enter it if it's a directory
+                            browser.end_filter();
+                            if browser.get_selected_path().map(|p| p.is_dir()).unwrap_or(false) {
+                                browser.open_selected()?;
+                            }
+                        }
+                        KeyCode::Up => browser.select_up(),
+                        KeyCode::Down | KeyCode::Tab => browser.select_down(),
+                        _ => {
+                            if browser.filter_editor.handle_key(code, modifiers) == LineEdit::Changed {
+                                browser.apply_filter();
+                            }
+                        }
+                    }
+                    continue;
+                }
+
                 // Handle fuzzy find mode
                 if browser.fuzzy_mode {
                     match code {
                         KeyCode::Esc => {
-                            // Esc: exit fuzzy mode (don't exit the app)
+                            // Esc: exit fuzzy mode (don't exit the app), keeping the matches for match_next
+                            browser.keep_fuzzy_matches();
                             browser.fuzzy_mode = false;
                             browser.fuzzy_query.clear();
                             browser.fuzzy_prev_count = 0;
                             continue;
                         }
                         KeyCode::Char('q') => {
-                            // q: cd to current directory and exit
+                            // q: exit with the configured quit action
                             browser.fuzzy_mode = false;
                             browser.fuzzy_query.clear();
                             browser.fuzzy_prev_count = 0;
-                            return Ok(ExitAction::Cd(browser.get_current_dir().clone()));
+                            return Ok(browser.exit_action(&browser.settings.quit_action));
                         }
                         KeyCode::Char(ch) if browser.keybindings.contains(&browser.keybindings.quit_then_open_in_finder, ch) => {
-                            // Open current directory in Finder and exit
                             browser.fuzzy_mode = false;
                             browser.fuzzy_query.clear();
                             browser.fuzzy_prev_count = 0;
-                            return Ok(ExitAction::OpenInFinder(browser.get_current_dir().clone()));
+                            return Ok(browser.exit_action(&browser.settings.alt_quit_action));
                         }
                         KeyCode::Char(ch) if browser.keybindings.contains(&browser.keybindings.fuzzy_back, ch) => {
                             // Go back up a directory but stay in fuzzy mode
@@ -3584,11 +14260,10 @@ fn run_browser(browser: &mut FileBrowser) -> io::Result<ExitAction> {
                         KeyCode::Backspace => {
                             browser.fuzzy_query.pop();
                             let (match_idx, count) = browser.fuzzy_match();
-                            // Only update selection if there's exactly one match
-                            if count == 1 {
-                                if let Some(idx) = match_idx {
-                                    browser.selected = idx;
-                                }
+                            // Keep the cursor on the best match
+                            if let Some(idx) = match_idx {
+                                browser.selected = idx;
+                                browser.scroll_to_selected();
                             }
                             browser.fuzzy_prev_count = count;
                             continue;
@@ -3601,26 +14276,22 @@ fn run_browser(browser: &mut FileBrowser) -> io::Result<ExitAction> {
 
                             if let Some(selected_path) = browser.get_selected_path() {
                                 if selected_path.is_file() {
+                                    if !browser.confirm_edit(&selected_path)? {
+                                        continue;
+                                    }
                                     // Write current directory to temp file for shell wrapper
                                     let _ = fs::write("/tmp/ils_cd", browser.get_current_dir().display().to_string());
 
-                                    // Disable raw mode and open in default editor
-                                    terminal::disable_raw_mode()?;
-                                    execute!(io::stdout(), cursor::Show)?;
-
-                                    let editor = env::var("EDITOR").unwrap_or_else(|_| "vim".to_string());
-                                    let _ = std::process::Command::new(editor)
-                                        .arg(&selected_path)
-                                        .status();
+                                    // Open with its configured opener or the editor
+                                    let opened = run_suspended(&mut browser.open_command(&selected_path));
 
                                     // Check if we should exit after editing
                                     if browser.settings.exit_after_edit {
                                         return Ok(ExitAction::Cd(browser.get_current_dir().clone()));
                                     }
-
-                                    // Re-enable raw mode
-                                    execute!(io::stdout(), cursor::Hide)?;
-                                    terminal::enable_raw_mode()?;
+                                    if let Err(e) = opened {
+                                        browser.notify(Severity::Error, format!("Could not open {}: {}", selected_path.display(), e));
+                                    }
                                 } else {
                                     // It's a directory, exit with it
                                     return Ok(ExitAction::Cd(selected_path));
@@ -3650,13 +14321,14 @@ fn run_browser(browser: &mut FileBrowser) -> io::Result<ExitAction> {
                         KeyCode::Char(ch) => {
                             browser.fuzzy_query.push(ch);
                             let (match_idx, count) = browser.fuzzy_match();
-                            // Only update selection if there's exactly one match
+                            // Keep the cursor on the best match as the query grows
+                            if let Some(idx) = match_idx {
+                                browser.selected = idx;
+                                browser.scroll_to_selected();
+                            }
                             if count == 1 {
-                                if let Some(idx) = match_idx {
-                                    browser.selected = idx;
-                                }
                                 // Auto-open if we narrowed down to 1 match
-                                if browser.fuzzy_prev_count > 1 || browser.fuzzy_prev_count == 1 {
+                                if browser.fuzzy_prev_count >= 1 {
                                     browser.fuzzy_query.clear();
                                     browser.open_selected()?;
 
@@ -3669,103 +14341,420 @@ fn run_browser(browser: &mut FileBrowser) -> io::Result<ExitAction> {
                                         browser.fuzzy_prev_count = browser.entries.len();
                                     }
 
-                                    // Block input for 500ms to prevent accidental typing
-                                    browser.input_block_until = Some(std::time::Instant::now() + Duration::from_millis(500));
-                                } else {
-                                    browser.fuzzy_prev_count = count;
-                                }
-                            } else {
-                                browser.fuzzy_prev_count = count;
-                            }
+                                    // Block input for 500ms to prevent accidental typing
+                                    browser.input_block_until = Some(std::time::Instant::now() + Duration::from_millis(500));
+                                } else {
+                                    browser.fuzzy_prev_count = count;
+                                }
+                            } else {
+                                browser.fuzzy_prev_count = count;
+                            }
+                            continue;
+                        }
+                        _ => continue,
+                    }
+                }
+
+                // Path jump: a hint shown in the path bar goes to its directory,
+                // any other key cancels
+                if browser.path_jump_pending {
+                    browser.path_jump_pending = false;
+                    if let KeyCode::Char(ch) = code {
+                        let segments = browser.path_segments(PATH_JUMP_HINTS);
+                        if segments.iter().any(|(hint, _)| *hint == Some(ch)) {
+                            if let Some(levels) = PATH_JUMP_HINTS.chars().position(|hint| hint == ch) {
+                                browser.go_up_levels(levels + 1)?;
+                            }
+                        }
+                    }
+                    continue;
+                }
+
+                // Ancestor jump: a digit after the jump key goes up that many levels,
+                // otherwise keys spell out a shortcut name until it matches exactly
+                if browser.ancestor_jump_pending {
+                    let KeyCode::Char(ch) = code else {
+                        // Enter accepts a shortcut that is also a prefix of a longer one
+                        browser.ancestor_jump_pending = false;
+                        let typed = std::mem::take(&mut browser.ancestor_jump_buffer);
+                        if code == KeyCode::Enter {
+                            if let Some(path) = browser.shortcuts.get(&typed).cloned() {
+                                let target = browser.resolve_input_path(&path);
+                                browser.go_to_path(target)?;
+                            }
+                        }
+                        continue;
+                    };
+
+                    if browser.ancestor_jump_buffer.is_empty() {
+                        if let Some(levels) = ch.to_digit(10) {
+                            browser.ancestor_jump_pending = false;
+                            browser.go_up_levels(levels as usize)?;
+                            continue;
+                        }
+                    }
+
+                    browser.ancestor_jump_buffer.push(ch);
+                    let typed = browser.ancestor_jump_buffer.clone();
+                    let longer_match = browser.shortcuts.keys().any(|name| name.len() > typed.len() && name.starts_with(&typed));
+                    if let Some(path) = browser.shortcuts.get(&typed).cloned().filter(|_| !longer_match) {
+                        browser.ancestor_jump_pending = false;
+                        browser.ancestor_jump_buffer.clear();
+                        let target = browser.resolve_input_path(&path);
+                        browser.go_to_path(target)?;
+                    } else if !longer_match && !browser.shortcuts.contains_key(&typed) {
+                        // Nothing can match any more
+                        browser.ancestor_jump_pending = false;
+                        browser.ancestor_jump_buffer.clear();
+                    }
+                    continue;
+                }
+                if browser.find_char_pending {
+                    browser.find_char_pending = false;
+                    if let KeyCode::Char(ch) = code {
+                        browser.jump_to_first_letter(ch);
+                    }
+                    continue;
+                }
+                if browser.bookmark_add_pending {
+                    browser.bookmark_add_pending = false;
+                    match code {
+                        KeyCode::Char(ch) if ch.is_alphanumeric() => browser.add_bookmark(ch),
+                        KeyCode::Char(_) => browser.notify(Severity::Warning, "Bookmarks are saved under a letter or digit"),
+                        _ => {}
+                    }
+                    continue;
+                }
+
+                // Bookmarks picker
+                if browser.bookmarks_mode {
+                    let count = browser.bookmarks.bookmarks.len();
+                    let selected = browser.bookmarks.bookmarks.keys().nth(browser.bookmarks_selected).cloned();
+                    match code {
+                        KeyCode::Esc => browser.bookmarks_mode = false,
+                        KeyCode::Up => browser.bookmarks_selected = browser.bookmarks_selected.saturating_sub(1),
+                        KeyCode::Down => browser.bookmarks_selected = (browser.bookmarks_selected + 1).min(count.saturating_sub(1)),
+                        KeyCode::Enter => {
+                            if let Some(letter) = selected {
+                                browser.jump_to_bookmark(&letter)?;
+                            }
+                        }
+                        KeyCode::Delete | KeyCode::Backspace => {
+                            if let Some(letter) = selected {
+                                browser.bookmarks.bookmarks.remove(&letter);
+                                browser.bookmarks_selected = browser.bookmarks_selected.min(count.saturating_sub(2));
+                                if let Err(e) = browser.bookmarks.save() {
+                                    browser.notify(Severity::Error, format!("Couldn't save bookmarks: {}", e));
+                                }
+                            }
+                        }
+                        KeyCode::Char(ch) if browser.bookmarks.bookmarks.contains_key(&ch.to_string()) => {
+                            browser.jump_to_bookmark(&ch.to_string())?;
+                        }
+                        KeyCode::Char('q') => browser.bookmarks_mode = false,
+                        KeyCode::Char(ch) if browser.keybindings.contains(&browser.keybindings.bookmarks, ch) => {
+                            browser.bookmarks_mode = false;
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
+                // "Open with" chooser
+                if let Some((_, choices)) = &browser.open_with {
+                    let count = choices.len();
+                    match code {
+                        KeyCode::Esc | KeyCode::Char('q') => browser.open_with = None,
+                        KeyCode::Up => browser.open_with_selected = browser.open_with_selected.saturating_sub(1),
+                        KeyCode::Down => browser.open_with_selected = (browser.open_with_selected + 1).min(count),
+                        KeyCode::Enter => browser.run_open_with(browser.open_with_selected)?,
+                        KeyCode::Char('o') => browser.run_open_with(count)?,
+                        KeyCode::Char(ch @ '1'..='9') if (ch as usize - '1' as usize) < count => {
+                            browser.run_open_with(ch as usize - '1' as usize)?;
+                        }
+                        KeyCode::Char(ch) if browser.keybindings.matches(&browser.keybindings.up, &Keybindings::key_name(ch, modifiers)) => {
+                            browser.open_with_selected = browser.open_with_selected.saturating_sub(1);
+                        }
+                        KeyCode::Char(ch) if browser.keybindings.matches(&browser.keybindings.down, &Keybindings::key_name(ch, modifiers)) => {
+                            browser.open_with_selected = (browser.open_with_selected + 1).min(count);
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
+                if let KeyCode::Char(ch) = code {
+                    if modifiers.contains(KeyModifiers::ALT) {
+                        if let Some(levels) = ch.to_digit(10) {
+                            browser.go_up_levels(levels as usize)?;
+                            continue;
+                        }
+                    }
+                }
+
+                // Key sequences: while the keys typed so far start a longer binding
+                // (like "gg"), wait for more; the completed sequence is then
+                // dispatched below as a single key
+                let pressed = match code {
+                    KeyCode::Char(ch) => Some(Keybindings::key_name(ch, modifiers)),
+                    KeyCode::Enter => Some("<Enter>".to_string()),
+                    _ => None,
+                };
+                let mut key = String::new();
+                if code == KeyCode::Null && !browser.key_sequence.is_empty() {
+                    // Timed out (see sequence_deadline), so what was typed is bound on its own
+                    key = std::mem::take(&mut browser.key_sequence);
+                } else if !browser.key_sequence.is_empty() && pressed.is_none() {
+                    browser.key_sequence.clear();
+                    if code == KeyCode::Esc {
+                        continue;
+                    }
+                }
+                if let Some(pressed) = pressed {
+                    let typed = format!("{}{}", browser.key_sequence, pressed);
+                    if browser.keybindings.is_sequence_prefix(&typed) {
+                        browser.key_sequence = typed;
+                        browser.key_sequence_at = Instant::now();
+                        continue;
+                    } else if browser.key_sequence.is_empty() || browser.keybindings.is_bound_key(&typed) {
+                        browser.key_sequence.clear();
+                        key = typed;
+                    } else {
+                        // The sequence broke off: run what was typed so far if it is bound
+                        // on its own (e.g. "g" before a digit), then replay this key
+                        let pending = std::mem::take(&mut browser.key_sequence);
+                        browser.replay_event = Some(Event::Key(KeyEvent::new(code, modifiers)));
+                        if !browser.keybindings.is_bound_key(&pending) {
                             continue;
                         }
-                        _ => continue,
+                        key = pending;
                     }
                 }
 
-                // Check character-based bindings first
-                if let KeyCode::Char(ch) = code {
-                    if browser.keybindings.contains(&browser.keybindings.help, ch) || ch == '!' {
+                // Check configurable bindings first
+                if browser.load_error.is_some() && browser.keybindings.matches(&browser.keybindings.retry_load, &key) {
+                    browser.retry_load()?;
+                    continue;
+                }
+                if let Some(command) = browser.keybindings.commands.get(&key).cloned() {
+                    browser.run_command_line(&command, false)?;
+                    continue;
+                }
+                if !key.is_empty() {
+                    if browser.keybindings.matches(&browser.keybindings.help, &key) {
                         browser.show_help = !browser.show_help;
                         let _ = browser.save_show_help_state();
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.quit, ch) {
-                        return Ok(ExitAction::Cd(browser.get_current_dir().clone()));
+                    if browser.keybindings.matches(&browser.keybindings.quit, &key) {
+                        return Ok(browser.exit_action(&browser.settings.quit_action));
                     }
-                    if browser.keybindings.contains(&browser.keybindings.quit_then_open_in_finder, ch) {
-                        // Open current directory in Finder and exit
-                        return Ok(ExitAction::OpenInFinder(browser.get_current_dir().clone()));
+                    if browser.keybindings.matches(&browser.keybindings.quit_then_open_in_finder, &key) {
+                        return Ok(browser.exit_action(&browser.settings.alt_quit_action));
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.print_path, &key) {
+                        if let Some(path) = browser.get_selected_path() {
+                            return Ok(ExitAction::PrintPath(path));
+                        }
+                        continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.up, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.up, &key) {
                         browser.select_up();
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.down, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.down, &key) {
                         browser.select_down();
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.left, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.left, &key) {
                         browser.select_left();
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.right, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.right, &key) {
                         browser.select_right();
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.jump_up, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.jump_up, &key) {
                         browser.jump_up();
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.jump_down, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.jump_down, &key) {
                         browser.jump_down();
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.jump_left, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.jump_left, &key) {
                         browser.jump_left();
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.jump_right, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.jump_right, &key) {
                         browser.jump_right();
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.open, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.open, &key) {
                         browser.open_selected()?;
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.back, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.back, &key) {
                         browser.go_back()?;
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.home, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.home, &key) {
                         browser.go_home()?;
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.next_sibling, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.edit_path, &key) {
+                        browser.start_path_edit();
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.help_screen, &key) {
+                        browser.help_screen_mode = true;
+                        browser.help_screen_query.clear();
+                        browser.help_screen_scroll = 0;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.filesystem_info, &key) {
+                        browser.show_filesystem_info();
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.file_info, &key) {
+                        browser.show_file_info();
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.diagnostics, &key) {
+                        browser.show_diagnostics();
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.messages, &key) {
+                        // Open scrolled to the newest messages
+                        browser.messages_mode = true;
+                        browser.messages_scroll = usize::MAX;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.filter, &key) {
+                        browser.start_filter();
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.first, &key) {
+                        browser.select_first();
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.last, &key) {
+                        browser.select_last();
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.find_char, &key) {
+                        browser.find_char_pending = true;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.ancestor_jump, &key) {
+                        browser.ancestor_jump_pending = true;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.path_jump, &key) {
+                        browser.path_jump_pending = true;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.tab_new, &key) {
+                        browser.new_tab();
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.tab_close, &key) {
+                        browser.close_tab()?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.tab_next, &key) {
+                        browser.cycle_tab(true)?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.tab_prev, &key) {
+                        browser.cycle_tab(false)?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.dual_pane, &key) {
+                        browser.toggle_dual_pane()?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.pane_switch, &key) {
+                        browser.switch_pane()?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.pane_copy, &key) {
+                        browser.transfer_to_other_pane(false)?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.pane_move, &key) {
+                        browser.transfer_to_other_pane(true)?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.pane_sync, &key) {
+                        browser.toggle_pane_sync();
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.bookmark_add, &key) {
+                        browser.bookmark_add_pending = true;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.bookmarks, &key) {
+                        browser.bookmarks_mode = true;
+                        browser.bookmarks_selected = 0;
+                        continue;
+                    }
+                    // Search matches take n and N over the sibling keys while there are any
+                    if browser.keybindings.matches(&browser.keybindings.match_next, &key) && browser.jump_to_match(true) {
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.match_prev, &key) && browser.jump_to_match(false) {
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.next_sibling, &key) {
                         browser.go_to_next_sibling()?;
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.prev_sibling, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.prev_sibling, &key) {
                         browser.go_to_prev_sibling()?;
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.preview_toggle, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.preview_toggle, &key) {
                         browser.preview_mode = !browser.preview_mode;
+                        browser.readme_preview_opened = false;
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.toggle_hidden, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.toggle_hidden, &key) {
                         browser.show_hidden = !browser.show_hidden;
                         browser.load_entries()?;
                         browser.update_layout()?;
                         let _ = browser.save_show_hidden();
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.toggle_mode, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.fold_preview, &key) {
+                        browser.toggle_data_fold();
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.toggle_markdown, &key) {
+                        browser.toggle_markdown_preview();
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.toggle_gitignored, &key) {
+                        browser.hide_gitignored = !browser.hide_gitignored;
+                        browser.load_entries()?;
+                        browser.update_layout()?;
+                        let _ = browser.save_hide_gitignored();
+                        if browser.git_root.is_none() {
+                            browser.notify(Severity::Info, "Not inside a git repository, so nothing is ignored here");
+                        }
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.toggle_mode, &key) {
                         browser.list_mode = !browser.list_mode;
                         browser.update_layout()?;
                         continue;
                     }
-                    if ch == 'e' && browser.list_mode {
+                    if browser.keybindings.matches(&browser.keybindings.gallery, &key) {
+                        browser.gallery_mode = !browser.gallery_mode || browser.list_mode;
+                        browser.list_mode = false;
+                        browser.update_layout()?;
+                        browser.scroll_to_selected();
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.list_action, &key) && browser.list_mode {
                         if browser.list_info_mode == 1 {
                             // Toggle between modified and created date when in date mode
                             browser.show_created_date = !browser.show_created_date;
@@ -3778,21 +14767,25 @@ fn run_browser(browser: &mut FileBrowser) -> io::Result<ExitAction> {
                                     if let Ok(metadata) = selected_path.metadata() {
                                         let current_mode = metadata.permissions().mode() & 0o777;
 
-                                        if let Ok(Some(input)) = FileBrowser::read_input_with_escape(&format!("\nCurrent permissions: {:o}\nEnter new permissions (octal, e.g., 755): ", current_mode)) {
+                                        if let Some(input) = browser.prompt("chmod", "Permissions (octal, e.g. 755): ", &format!("{:o}", current_mode), false)? {
                                             if let Ok(new_mode) = u32::from_str_radix(input.trim(), 8) {
                                                 if new_mode <= 0o777 {
                                                     use std::fs::Permissions;
-                                                    if let Err(e) = fs::set_permissions(&selected_path, Permissions::from_mode(new_mode)) {
-                                                        terminal::disable_raw_mode()?;
-                                                        eprintln!("Error setting permissions: {}", e);
-                                                        std::thread::sleep(std::time::Duration::from_secs(2));
-                                                        terminal::enable_raw_mode()?;
+                                                    let mut result = fs::set_permissions(&selected_path, Permissions::from_mode(new_mode));
+                                                    if let Err(e) = &result {
+                                                        let mode = format!("{:o}", new_mode);
+                                                        let command = ["chmod".as_ref(), mode.as_ref(), "--".as_ref(), selected_path.as_os_str()];
+                                                        if browser.retry_elevated("Setting permissions", e, &command)? {
+                                                            result = Ok(());
+                                                        }
+                                                    }
+                                                    if let Err(e) = result {
+                                                        browser.notify(Severity::Error, format!("Error setting permissions: {}", e));
+                                                    } else {
+                                                        browser.notify(Severity::Success, format!("Permissions set to {:o}", new_mode));
                                                     }
                                                 }  else {
-                                                    terminal::disable_raw_mode()?;
-                                                    eprintln!("Invalid permissions value");
-                                                    std::thread::sleep(std::time::Duration::from_secs(2));
-                                                    terminal::enable_raw_mode()?;
+                                                    browser.notify(Severity::Error, "Invalid permissions value");
                                                 }
                                             }
                                         }
@@ -3801,87 +14794,166 @@ fn run_browser(browser: &mut FileBrowser) -> io::Result<ExitAction> {
                             }
                         } else if browser.list_info_mode == 3 {
                             // Calculate directory sizes when in size mode
-                            browser.calculate_all_dir_sizes()?;
+                            browser.calculate_all_dir_sizes();
                         }
                         continue;
                     }
-                    // Handle both 'c' and 'C' for copy operations
-                    if browser.keybindings.contains(&browser.keybindings.copy, ch) ||
-                       (ch == 'C' && browser.keybindings.copy.contains(&'c')) {
-                        if ch == 'C' || modifiers.contains(KeyModifiers::SHIFT) {
-                            // Shift+C: Clear multi-copy selection
-                            browser.clear_copy_selection();
-                        } else {
-                            // c: Toggle item in multi-copy selection
-                            browser.toggle_copy_selection();
-                        }
+                    if browser.keybindings.matches(&browser.keybindings.dir_size, &key) {
+                        browser.calculate_selected_dir_size();
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.clear_copy, &key) {
+                        // Clear multi-copy selection
+                        browser.clear_copy_selection();
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.mark, &key) {
+                        browser.toggle_mark();
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.copy, &key) {
+                        // Toggle item in multi-copy selection
+                        browser.toggle_copy_selection();
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.paste, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.paste, &key) {
                         browser.paste_from_clipboard()?;
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.trash, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.trash, &key) {
                         browser.move_to_trash()?;
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.delete, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.trash_browse, &key) {
+                        browser.open_trash()?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.empty_trash, &key) {
+                        browser.empty_trash()?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.delete, &key) {
                         browser.delete_permanent()?;
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.undo, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.extract, &key) {
+                        browser.extract_selected()?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.compress, &key) {
+                        browser.compress_targets()?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.undo, &key) {
                         browser.undo()?;
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.redo, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.redo, &key) {
                         browser.redo()?;
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.create, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.create, &key) {
                         browser.create_new()?;
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.quicklook, ch) {
+                    if browser.preview_mode && browser.keybindings.matches(&browser.keybindings.preview_page_up, &key) {
+                        browser.scroll_preview(false, true)?;
+                        continue;
+                    }
+                    if browser.preview_mode && browser.keybindings.matches(&browser.keybindings.preview_page_down, &key) {
+                        browser.scroll_preview(true, true)?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.gitignore, &key) {
+                        browser.add_to_gitignore()?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.git_stage, &key) {
+                        browser.git_stage(true)?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.git_unstage, &key) {
+                        browser.git_stage(false)?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.git_diff, &key) {
+                        browser.toggle_diff_preview();
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.pager, &key) {
+                        browser.open_in_pager()?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.open_with, &key) {
+                        browser.show_open_with();
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.refresh, &key) {
+                        browser.refresh()?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.shell_command, &key) {
+                        browser.run_shell_command()?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.open_in_ide, &key) {
+                        browser.open_in_ide()?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.quicklook, &key) {
                         browser.open_quicklook()?;
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.rename, ch) {
-                        // Rename functionality
-                        if let Some(selected_path) = browser.get_selected_path() {
-                            if let Some(old_name) = selected_path.file_name().and_then(|n| n.to_str()) {
-                                if let Ok(Some(new_name)) = FileBrowser::read_input_with_escape(&format!("\nRename '{}' to: ", old_name)) {
-                                    let new_name = new_name.trim();
-
-                                    if !new_name.is_empty() && new_name != old_name {
-                                        let new_path = selected_path.parent().unwrap().join(new_name);
-                                        if let Err(e) = fs::rename(&selected_path, &new_path) {
-                                            terminal::disable_raw_mode()?;
-                                            eprintln!("Error renaming: {}", e);
-                                            std::thread::sleep(std::time::Duration::from_secs(2));
-                                            terminal::enable_raw_mode()?;
-                                        } else {
-                                            browser.undo_stack.push(UndoAction::Rename {
-                                                old_path: selected_path.clone(),
-                                                new_path: new_path.clone()
-                                            });
-                                            browser.redo_stack.clear();
-                                            browser.load_entries()?;
-                                        }
-                                    }
-                                }
-                            }
-                        }
+                    if browser.keybindings.matches(&browser.keybindings.rename, &key) {
+                        browser.rename_selected()?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.edit_tags, &key) {
+                        browser.edit_tags()?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.bulk_rename, &key) {
+                        browser.bulk_rename()?;
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.pattern_rename, &key) {
+                        browser.pattern_rename()?;
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.fuzzy_find, ch) || browser.keybindings.contains(&browser.keybindings.fuzzy_home, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.fuzzy_find, &key) || browser.keybindings.matches(&browser.keybindings.fuzzy_home, &key) {
                         browser.fuzzy_mode = true;
+                        browser.sync_collapsed();
                         browser.fuzzy_query.clear();
                         browser.fuzzy_prev_count = browser.entries.len();
                         // fuzzy_home (?) always uses stay mode, fuzzy_find (/) uses jump mode unless Shift is held
-                        browser.fuzzy_jump_mode = browser.keybindings.contains(&browser.keybindings.fuzzy_find, ch) && !modifiers.contains(KeyModifiers::SHIFT);
+                        browser.fuzzy_jump_mode = browser.keybindings.matches(&browser.keybindings.fuzzy_find, &key) && !modifiers.contains(KeyModifiers::SHIFT);
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.sort, &key) {
+                        browser.change_sort(false);
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.sort_reverse, &key) {
+                        browser.change_sort(true);
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.collapse_group, &key) {
+                        browser.collapse_group();
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.expand_groups, &key) {
+                        browser.expand_groups();
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.history_jump, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.grep, &key) {
+                        browser.start_grep(false);
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.find_files, &key) {
+                        browser.start_grep(true);
+                        continue;
+                    }
+                    if browser.keybindings.matches(&browser.keybindings.history_jump, &key) {
                         // Enter history navigation mode (zoxide-style)
                         browser.history_mode = true;
                         browser.history_query.clear();
@@ -3890,142 +14962,76 @@ fn run_browser(browser: &mut FileBrowser) -> io::Result<ExitAction> {
                         browser.scroll_offset = 0;
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.preview_height_decrease, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.preview_height_decrease, &key) {
                         if browser.preview_mode {
                             browser.preview_split_ratio = (browser.preview_split_ratio - 0.1).max(0.2);
                             let _ = browser.save_preview_ratio();
                         }
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.preview_height_increase, ch) {
+                    if browser.keybindings.matches(&browser.keybindings.preview_height_increase, &key) {
                         if browser.preview_mode {
                             browser.preview_split_ratio = (browser.preview_split_ratio + 0.1).min(1.0);
                             let _ = browser.save_preview_ratio();
                         }
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.preview_up, ch) || ch == 'I' {
-                        // Scroll preview up - shift for visible lines (uppercase), otherwise configured amount
-                        if browser.preview_mode {
-                            if let Some(selected) = browser.get_selected_path() {
-                                let (_, height) = terminal::size()?;
-                                let split_line = browser.start_row + ((height - browser.start_row) as f32 * (1.0 - browser.preview_split_ratio)) as u16;
-                                let preview_lines = (height - split_line - 3) as usize;
-
-                                let scroll_amount = if ch == 'I' || modifiers.contains(KeyModifiers::SHIFT) {
-                                    preview_lines
-                                } else {
-                                    browser.settings.preview_scroll_amount
-                                };
-
-                                let current = browser.preview_scroll_map.get(&selected).copied().unwrap_or(0);
-                                let new_scroll = current.saturating_sub(scroll_amount);
-                                browser.preview_scroll_map.insert(selected, new_scroll);
-                            }
+                    let next_image = browser.keybindings.matches(&browser.keybindings.next_image, &key);
+                    if next_image || browser.keybindings.matches(&browser.keybindings.prev_image, &key) {
+                        // Step through the directory's images without leaving the preview
+                        let previewing_image = browser.preview_mode
+                            && browser.get_selected_path().is_some_and(|path| is_image(&path));
+                        if previewing_image && !browser.select_adjacent_image(next_image) {
+                            browser.notify(Severity::Info, "No other images in this directory");
                         }
                         continue;
                     }
-                    if browser.keybindings.contains(&browser.keybindings.preview_down, ch) || ch == 'O' {
-                        // Scroll preview down - shift for visible lines (uppercase), otherwise configured amount
+                    if browser.keybindings.matches(&browser.keybindings.preview_up, &key) {
+                        // Scroll preview up - Shift scrolls a page, otherwise the configured amount
                         if browser.preview_mode {
-                            if let Some(selected) = browser.get_selected_path() {
-                                if selected.is_file() {
-                                    let (_, height) = terminal::size()?;
-                                    let split_line = browser.start_row + ((height - browser.start_row) as f32 * (1.0 - browser.preview_split_ratio)) as u16;
-                                    let preview_lines = (height - split_line - 3) as usize;
-
-                                    let scroll_amount = if ch == 'O' || modifiers.contains(KeyModifiers::SHIFT) {
-                                        preview_lines
-                                    } else {
-                                        browser.settings.preview_scroll_amount
-                                    };
-
-                                    // Get file line count to bound scroll
-                                    if let Ok(file) = fs::File::open(&selected) {
-                                        use io::BufRead;
-                                        let line_count = io::BufReader::new(file).lines().count();
-
-                                        let current = browser.preview_scroll_map.get(&selected).copied().unwrap_or(0);
-                                        // Don't scroll past the last visible line
-                                        let max_scroll = line_count.saturating_sub(preview_lines);
-                                        let new_scroll = (current + scroll_amount).min(max_scroll);
-                                        browser.preview_scroll_map.insert(selected, new_scroll);
-                                    }
-                                }
-                            }
+                            browser.scroll_preview(false, modifiers.contains(KeyModifiers::SHIFT))?;
                         }
                         continue;
                     }
-                }
-
-                // Handle arrow keys and special keys
-                match code {
-                    KeyCode::Esc => {
-                        // Esc: quit without cd
-                        return Ok(ExitAction::None);
+                    if browser.keybindings.matches(&browser.keybindings.preview_left, &key) {
+                        if browser.preview_mode {
+                            browser.scroll_table(false);
+                        }
+                        continue;
                     }
-                    KeyCode::Up => browser.select_up(),
-                    KeyCode::Down => browser.select_down(),
-                    KeyCode::Left => browser.select_left(),
-                    KeyCode::Right => browser.select_right(),
-                    KeyCode::Char(' ') => {
-                        // Space: Page down in preview mode, Quick Look otherwise
+                    if browser.keybindings.matches(&browser.keybindings.preview_right, &key) {
                         if browser.preview_mode {
-                            // Page down in preview (same as 'O')
-                            if let Some(selected) = browser.get_selected_path() {
-                                if selected.is_file() {
-                                    let (_, height) = terminal::size()?;
-                                    let split_line = browser.start_row + ((height - browser.start_row) as f32 * (1.0 - browser.preview_split_ratio)) as u16;
-                                    let preview_lines = (height - split_line - 3) as usize;
-
-                                    // Get file line count to bound scroll
-                                    if let Ok(file) = fs::File::open(&selected) {
-                                        use io::BufRead;
-                                        let line_count = io::BufReader::new(file).lines().count();
-
-                                        let current = browser.preview_scroll_map.get(&selected).copied().unwrap_or(0);
-                                        let new_scroll = (current + preview_lines).min(line_count.saturating_sub(preview_lines));
-                                        browser.preview_scroll_map.insert(selected, new_scroll);
-                                    }
-                                }
-                            }
-                        } else {
-                            // Not in preview mode: open Quick Look
-                            browser.open_quicklook()?;
+                            browser.scroll_table(true);
                         }
+                        continue;
                     }
-                    KeyCode::Tab => {
-                        // Tab: Cycle list info mode in list mode, toggle line numbers in preview mode
+                    if browser.keybindings.matches(&browser.keybindings.preview_down, &key) {
+                        // Scroll preview down - Shift scrolls a page, otherwise the configured amount
                         if browser.preview_mode {
-                            browser.show_line_numbers = !browser.show_line_numbers;
-                        } else if browser.list_mode {
-                            browser.list_info_mode = (browser.list_info_mode + 1) % 4;
+                            browser.scroll_preview(true, modifiers.contains(KeyModifiers::SHIFT))?;
                         }
+                        continue;
                     }
-                    KeyCode::Enter | KeyCode::Char('k') => {
-                        // Enter: Select item - if file, open in editor; if directory, cd to it
+                    if browser.keybindings.matches(&browser.keybindings.select, &key) {
+                        // Select item - if file, open in editor; if directory, cd to it
                         if let Some(selected_path) = browser.get_selected_path() {
                             if selected_path.is_file() {
+                                if !browser.confirm_edit(&selected_path)? {
+                                    continue;
+                                }
                                 // Write current directory to temp file for shell wrapper
                                 let _ = fs::write("/tmp/ils_cd", browser.get_current_dir().display().to_string());
 
-                                // Disable raw mode and open in default editor
-                                terminal::disable_raw_mode()?;
-                                execute!(io::stdout(), cursor::Show)?;
-
-                                let editor = env::var("EDITOR").unwrap_or_else(|_| "vim".to_string());
-                                let _ = std::process::Command::new(editor)
-                                    .arg(&selected_path)
-                                    .status();
+                                // Open with its configured opener or the editor
+                                let opened = run_suspended(&mut browser.open_command(&selected_path));
 
                                 // Check if we should exit after editing
                                 if browser.settings.exit_after_edit {
                                     return Ok(ExitAction::Cd(browser.get_current_dir().clone()));
                                 }
-
-                                // Re-enable raw mode
-                                execute!(io::stdout(), cursor::Hide)?;
-                                terminal::enable_raw_mode()?;
+                                if let Err(e) = opened {
+                                    browser.notify(Severity::Error, format!("Could not open {}: {}", selected_path.display(), e));
+                                }
                             } else {
                                 // It's a directory, exit with it
                                 return Ok(ExitAction::Cd(selected_path));
@@ -4034,6 +15040,34 @@ fn run_browser(browser: &mut FileBrowser) -> io::Result<ExitAction> {
                             // No selection, return current directory
                             return Ok(ExitAction::Cd(browser.get_current_dir().clone()));
                         }
+                        continue;
+                    }
+                    if let KeyCode::Char(ch) = code {
+                        if ch.is_alphanumeric() && key == ch.to_string() && !browser.keybindings.is_bound(ch) {
+                            // Type-ahead: unbound letters jump to the next matching entry
+                            browser.jump_to_first_letter(ch);
+                            continue;
+                        }
+                    }
+                }
+
+                // Handle arrow keys and special keys
+                match code {
+                    KeyCode::Esc if browser.cancel_pdf_extraction() => {}
+                    KeyCode::Esc => {
+                        return Ok(browser.exit_action(&browser.settings.escape_action));
+                    }
+                    KeyCode::Up => browser.select_up(),
+                    KeyCode::Down => browser.select_down(),
+                    KeyCode::Left => browser.select_left(),
+                    KeyCode::Right => browser.select_right(),
+                    KeyCode::Tab => {
+                        // Tab: Cycle list info mode in list mode, toggle line numbers in preview mode
+                        if browser.preview_mode {
+                            browser.show_line_numbers = !browser.show_line_numbers;
+                        } else if browser.list_mode {
+                            browser.list_info_mode = (browser.list_info_mode + 1) % 5;
+                        }
                     }
                     KeyCode::Backspace => { browser.go_back()?; }
                     _ => {}
@@ -4042,7 +15076,235 @@ fn run_browser(browser: &mut FileBrowser) -> io::Result<ExitAction> {
             Event::Resize(_, _) => {
                 browser.update_layout()?; // Recalculate columns on resize
             }
+            Event::FocusGained => browser.focused = true,
+            Event::FocusLost => browser.focused = false,
             _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn mp4_box(kind: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(body);
+        data
+    }
+
+    /// An MP4 with a 5 second, 640x480 H.264 track.
+    fn sample_mp4() -> Vec<u8> {
+        let mut mvhd = vec![0u8; 12];
+        mvhd.extend_from_slice(&1000u32.to_be_bytes());
+        mvhd.extend_from_slice(&5000u32.to_be_bytes());
+        let mut hdlr = vec![0u8; 8];
+        hdlr.extend_from_slice(b"vide");
+        let mut entry = vec![0, 0, 0, 86];
+        entry.extend_from_slice(b"avc1");
+        entry.extend_from_slice(&[0; 24]);
+        entry.extend_from_slice(&640u16.to_be_bytes());
+        entry.extend_from_slice(&480u16.to_be_bytes());
+        entry.extend_from_slice(&[0; 50]);
+        let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stsd.extend_from_slice(&entry);
+        let stbl = mp4_box(b"stbl", &mp4_box(b"stsd", &stsd));
+        let mdia = [mp4_box(b"hdlr", &hdlr), mp4_box(b"minf", &stbl)].concat();
+        let trak = mp4_box(b"trak", &mp4_box(b"mdia", &mdia));
+        let moov = mp4_box(b"moov", &[mp4_box(b"mvhd", &mvhd), trak].concat());
+        [mp4_box(b"ftyp", b"isom\0\0\0\0"), mp4_box(b"mdat", &[0; 32]), moov].concat()
+    }
+
+    fn ebml(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut data = id.to_vec();
+        data.push(0x01);
+        data.extend_from_slice(&(body.len() as u64).to_be_bytes()[1..]);
+        data.extend_from_slice(body);
+        data
+    }
+
+    /// A Matroska file with a 2.5 second, 1280x720 H.264 track.
+    fn sample_matroska() -> Vec<u8> {
+        let info = [ebml(&[0x2A, 0xD7, 0xB1], &[0x0F, 0x42, 0x40]), ebml(&[0x44, 0x89], &2500f64.to_be_bytes())].concat();
+        let video = [ebml(&[0xB0], &1280u16.to_be_bytes()), ebml(&[0xBA], &720u16.to_be_bytes())].concat();
+        let track = [ebml(&[0x83], &[1]), ebml(&[0x86], b"V_MPEG4/ISO/AVC"), ebml(&[0xE0], &video)].concat();
+        let tracks = ebml(&[0x16, 0x54, 0xAE, 0x6B], &ebml(&[0xAE], &track));
+        let segment = [ebml(&[0x15, 0x49, 0xA9, 0x66], &info), tracks].concat();
+        [ebml(&[0x1A, 0x45, 0xDF, 0xA3], &[]), ebml(&[0x18, 0x53, 0x80, 0x67], &segment)].concat()
+    }
+
+    /// A 3 second 16-bit stereo 44.1 kHz WAV, without its samples.
+    fn sample_wav() -> Vec<u8> {
+        let mut data = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        data.extend_from_slice(&16u32.to_le_bytes());
+        data.extend_from_slice(&[1, 0, 2, 0]);
+        data.extend_from_slice(&44100u32.to_le_bytes());
+        data.extend_from_slice(&176400u32.to_le_bytes());
+        data.extend_from_slice(&[4, 0, 16, 0]);
+        data.extend_from_slice(b"data");
+        data.extend_from_slice(&(176400u32 * 3).to_le_bytes());
+        data
+    }
+
+    /// A 2 second stereo 44.1 kHz FLAC, without its frames.
+    fn sample_flac() -> Vec<u8> {
+        let mut data = b"fLaC\x80\0\0\x22".to_vec();
+        data.extend_from_slice(&[0; 10]);
+        data.extend_from_slice(&((44100u64 << 44) | (1 << 41) | (15 << 36) | 88200).to_be_bytes());
+        data.extend_from_slice(&[0; 16]);
+        data
+    }
+
+    /// A variable bitrate MP3 of 100 frames behind an ID3 tag.
+    fn sample_mp3() -> Vec<u8> {
+        let mut data = b"ID3\x04\0\0\0\0\0\x04".to_vec();
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x64]);
+        data.extend_from_slice(&[0; 32]);
+        data.extend_from_slice(b"Xing\0\0\0\x01");
+        data.extend_from_slice(&100u32.to_be_bytes());
+        data.extend_from_slice(&[0; 64]);
+        data
+    }
+
+    /// Run `parse` on every truncation of `data`, none of which may panic.
+    fn parse_prefixes(data: &[u8], parse: fn(&mut Cursor<&[u8]>) -> Option<MediaInfo>) {
+        for end in 0..data.len() {
+            parse(&mut Cursor::new(&data[..end]));
+        }
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        assert!(actual.is_some_and(|actual| (actual - expected).abs() < 0.01), "duration {:?}, expected {}", actual, expected);
+    }
+
+    #[test]
+    fn provisioning_profile_plist_is_found_after_its_start() {
+        let path = env::temp_dir().join(format!("ils-test-{}.mobileprovision", std::process::id()));
+        let plist = "<?xml version=\"1.0\"?><plist version=\"1.0\"><dict><key>Name</key><string>Dev</string></dict></plist>";
+        fs::write(&path, format!("</plist>signature{}trailer", plist)).unwrap();
+        let lines = decode_apple_file(&path);
+        let _ = fs::remove_file(&path);
+        assert!(lines.unwrap().iter().any(|line| line.contains("<string>Dev</string>")));
+    }
+
+    #[test]
+    fn mp4_headers_are_read() {
+        let info = mp4_info(&mut Cursor::new(sample_mp4())).unwrap();
+        assert_close(info.duration, 5.0);
+        assert_eq!(info.resolution(), Some((640, 480)));
+    }
+
+    #[test]
+    fn mp4_survives_truncation() {
+        parse_prefixes(&sample_mp4(), |file| mp4_info(file));
+    }
+
+    #[test]
+    fn mp4_rejects_oversized_boxes() {
+        // 64-bit sizes that would overflow or wrap the offset back to the start
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        for size in [u64::MAX, u64::MAX - ftyp.len() as u64 + 1, 0, 15] {
+            let mut data = ftyp.clone();
+            data.extend_from_slice(&[0, 0, 0, 1]);
+            data.extend_from_slice(b"free");
+            data.extend_from_slice(&size.to_be_bytes());
+            data.extend_from_slice(&sample_mp4());
+            assert!(mp4_info(&mut Cursor::new(data)).is_none());
+        }
+        // A 32-bit size past the end, hiding the movie box behind it
+        let mut data = ftyp.clone();
+        data.extend_from_slice(&u32::MAX.to_be_bytes());
+        data.extend_from_slice(b"free");
+        data.extend_from_slice(&sample_mp4());
+        assert!(mp4_info(&mut Cursor::new(data)).is_none());
+
+        for size in [u64::MAX, u64::MAX - 7, 17] {
+            let mut data = vec![0, 0, 0, 1];
+            data.extend_from_slice(b"trak");
+            data.extend_from_slice(&size.to_be_bytes());
+            assert!(mp4_boxes(&data).is_empty());
+        }
+    }
+
+    #[test]
+    fn matroska_headers_are_read() {
+        let info = matroska_info(&mut Cursor::new(sample_matroska())).unwrap();
+        assert_close(info.duration, 2.5);
+        assert_eq!(info.resolution(), Some((1280, 720)));
+    }
+
+    #[test]
+    fn matroska_survives_truncation() {
+        parse_prefixes(&sample_matroska(), |file| matroska_info(file));
+    }
+
+    #[test]
+    fn matroska_survives_oversized_elements() {
+        // Sizes longer than the data, up to the largest an 8-byte number holds
+        let mut data = ebml(&[0x1A, 0x45, 0xDF, 0xA3], &[]);
+        data.extend_from_slice(&[0x18, 0x53, 0x80, 0x67, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        data.extend_from_slice(&[0x15, 0x49, 0xA9, 0x66, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE]);
+        data.extend_from_slice(&[0x44, 0x89, 0x88]);
+        assert!(matroska_info(&mut Cursor::new(data)).is_some());
+        assert!(ebml_vint(&[0x00, 0xFF], 0, false).is_none());
+        assert!(ebml_elements(&[0x1A, 0x45, 0xDF, 0xA3, 0x01, 0xFF]).is_empty());
+    }
+
+    #[test]
+    fn wav_headers_are_read() {
+        let info = wav_info(&mut Cursor::new(sample_wav())).unwrap();
+        assert_close(info.duration, 3.0);
+        assert!(matches!(info.streams[..], [MediaStream::Audio { sample_rate: 44100, channels: 2, .. }]));
+    }
+
+    #[test]
+    fn wav_survives_truncation_and_bad_chunk_sizes() {
+        parse_prefixes(&sample_wav(), |file| wav_info(file));
+        for size in [0, 3, u32::MAX] {
+            let mut data = sample_wav();
+            data[16..20].copy_from_slice(&size.to_le_bytes());
+            wav_info(&mut Cursor::new(data));
+        }
+        let mut data = b"RIFF\0\0\0\0WAVELIST".to_vec();
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(wav_info(&mut Cursor::new(data)).is_some_and(|info| info.streams.is_empty()));
+    }
+
+    #[test]
+    fn flac_headers_are_read() {
+        let info = flac_info(&mut Cursor::new(sample_flac())).unwrap();
+        assert_close(info.duration, 2.0);
+        assert!(matches!(info.streams[..], [MediaStream::Audio { sample_rate: 44100, channels: 2, .. }]));
+    }
+
+    #[test]
+    fn flac_survives_truncation() {
+        parse_prefixes(&sample_flac(), |file| flac_info(file));
+        let mut data = sample_flac();
+        data[18..26].copy_from_slice(&u64::MAX.to_be_bytes());
+        flac_info(&mut Cursor::new(data));
+    }
+
+    #[test]
+    fn mp3_headers_are_read() {
+        let info = mp3_info(&mut Cursor::new(sample_mp3())).unwrap();
+        assert_close(info.duration, 100.0 * 1152.0 / 44100.0);
+        assert!(matches!(info.streams[..], [MediaStream::Audio { sample_rate: 44100, channels: 2, .. }]));
+    }
+
+    #[test]
+    fn mp3_survives_truncation_and_oversized_tags() {
+        parse_prefixes(&sample_mp3(), |file| mp3_info(file));
+        let mut data = sample_mp3();
+        data[6..10].copy_from_slice(&[0x7F; 4]);
+        assert!(mp3_info(&mut Cursor::new(data)).is_none());
+        for header in [[0xFF, 0xFB, 0xF0, 0x64], [0xFF, 0xFB, 0x9C, 0x64], [0xFF, 0xE3, 0x00, 0x00]] {
+            let mut data = sample_mp3();
+            data[14..18].copy_from_slice(&header);
+            mp3_info(&mut Cursor::new(data));
+        }
+    }
+}